@@ -0,0 +1,44 @@
+CREATE TABLE IF NOT EXISTS tb_mcp_aggregates
+(
+    id          UUID PRIMARY KEY,
+    name        TEXT      NOT NULL,
+    description TEXT      NOT NULL DEFAULT '',
+    members     JSONB     NOT NULL DEFAULT '[]',
+    created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at  TIMESTAMP
+);
+
+-- Unique index to avoid duplicate name
+CREATE UNIQUE INDEX IF NOT EXISTS uq_mcp_aggregates_name_not_deleted
+    ON tb_mcp_aggregates (name)
+    WHERE deleted_at IS NULL;
+
+-- Table comment
+COMMENT ON TABLE tb_mcp_aggregates IS 'Aggregated MCP servers merging several registered MCP servers';
+
+-- Column comments
+COMMENT ON COLUMN tb_mcp_aggregates.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_mcp_aggregates.name IS 'Aggregate name, used in /proxy/aggregate/{name}';
+COMMENT ON COLUMN tb_mcp_aggregates.description IS 'Aggregate description';
+COMMENT ON COLUMN tb_mcp_aggregates.members IS 'Member MCP servers, e.g. [{"name": "github", "tag": "1.0.0", "namespace": "github"}]';
+COMMENT ON COLUMN tb_mcp_aggregates.created_at IS 'Record creation time';
+COMMENT ON COLUMN tb_mcp_aggregates.updated_at IS 'Last update time';
+COMMENT ON COLUMN tb_mcp_aggregates.deleted_at IS 'Logical deletion time (NULL means not deleted)';
+
+-- Create trigger for tb_mcp_aggregates table (reusing existing function)
+DO
+$$
+    BEGIN
+        IF NOT EXISTS (SELECT 1
+                       FROM pg_trigger
+                       WHERE tgname = 'set_updated_at_trigger'
+                         AND tgrelid = 'tb_mcp_aggregates'::regclass) THEN
+            CREATE TRIGGER set_updated_at_trigger
+                BEFORE UPDATE
+                ON tb_mcp_aggregates
+                FOR EACH ROW
+            EXECUTE FUNCTION update_updated_at_column();
+        END IF;
+    END
+$$;
@@ -0,0 +1,49 @@
+CREATE TABLE IF NOT EXISTS tb_api_key_policies
+(
+    id           UUID PRIMARY KEY,
+    api_key_name TEXT      NOT NULL,
+    server_name  TEXT      NOT NULL,
+    tag_pattern  TEXT      NOT NULL DEFAULT '*',
+    allow_tools  TEXT[]    NOT NULL DEFAULT '{}',
+    deny_tools   TEXT[]    NOT NULL DEFAULT '{}',
+    description  TEXT      NOT NULL DEFAULT '',
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at   TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_key_policies_api_key_name
+    ON tb_api_key_policies (api_key_name)
+    WHERE deleted_at IS NULL;
+
+-- Table comment
+COMMENT ON TABLE tb_api_key_policies IS 'Grants of API keys to MCP servers and tools, keys without grants are unrestricted';
+
+-- Column comments
+COMMENT ON COLUMN tb_api_key_policies.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_api_key_policies.api_key_name IS 'Name of the API key (tb_api_keys.name) the grant belongs to';
+COMMENT ON COLUMN tb_api_key_policies.server_name IS 'MCP server name pattern, * matches any characters';
+COMMENT ON COLUMN tb_api_key_policies.tag_pattern IS 'MCP server tag pattern, * matches any characters';
+COMMENT ON COLUMN tb_api_key_policies.allow_tools IS 'Tool name patterns allowed, empty allows every tool';
+COMMENT ON COLUMN tb_api_key_policies.deny_tools IS 'Tool name patterns denied, takes precedence over allow_tools';
+COMMENT ON COLUMN tb_api_key_policies.description IS 'Policy description';
+COMMENT ON COLUMN tb_api_key_policies.created_at IS 'Record creation time';
+COMMENT ON COLUMN tb_api_key_policies.updated_at IS 'Last update time';
+COMMENT ON COLUMN tb_api_key_policies.deleted_at IS 'Logical deletion time (NULL means not deleted)';
+
+-- Create trigger for tb_api_key_policies table (reusing existing function)
+DO
+$$
+    BEGIN
+        IF NOT EXISTS (SELECT 1
+                       FROM pg_trigger
+                       WHERE tgname = 'set_updated_at_trigger'
+                         AND tgrelid = 'tb_api_key_policies'::regclass) THEN
+            CREATE TRIGGER set_updated_at_trigger
+                BEFORE UPDATE
+                ON tb_api_key_policies
+                FOR EACH ROW
+            EXECUTE FUNCTION update_updated_at_column();
+        END IF;
+    END
+$$;
@@ -0,0 +1,40 @@
+-- API keys are identified by id, only a hash and a display prefix of the key are stored
+ALTER TABLE tb_api_keys
+    ADD COLUMN IF NOT EXISTS id           UUID      NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN IF NOT EXISTS key_hash     TEXT,
+    ADD COLUMN IF NOT EXISTS key_prefix   TEXT,
+    ADD COLUMN IF NOT EXISTS scopes       TEXT[]    NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS expires_at   TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS last_used_at TIMESTAMP;
+
+-- existing keys keep the access they had before scopes existed
+UPDATE tb_api_keys
+SET key_hash   = encode(sha256(convert_to(apikey, 'UTF8')), 'hex'),
+    key_prefix = left(apikey, 8),
+    scopes     = ARRAY ['proxy', 'registry:read', 'registry:write']
+WHERE key_hash IS NULL;
+
+ALTER TABLE tb_api_keys
+    DROP CONSTRAINT IF EXISTS tb_api_keys_pkey,
+    DROP CONSTRAINT IF EXISTS tb_api_keys_name_key,
+    DROP COLUMN IF EXISTS apikey,
+    ALTER COLUMN key_hash SET NOT NULL,
+    ALTER COLUMN key_prefix SET NOT NULL,
+    ADD PRIMARY KEY (id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_api_keys_key_hash
+    ON tb_api_keys (key_hash);
+
+-- a revoked key frees its name
+CREATE UNIQUE INDEX IF NOT EXISTS idx_api_keys_name
+    ON tb_api_keys (name)
+    WHERE deleted_at IS NULL;
+
+-- Column comments
+COMMENT ON COLUMN tb_api_keys.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_api_keys.key_hash IS 'SHA-256 hex digest of the API key, the key itself is never stored';
+COMMENT ON COLUMN tb_api_keys.key_prefix IS 'First characters of the API key, displayed to identify the key';
+COMMENT ON COLUMN tb_api_keys.scopes IS 'Scopes granted: proxy, registry:read, registry:write';
+COMMENT ON COLUMN tb_api_keys.expires_at IS 'Expiry time, NULL means the key never expires';
+COMMENT ON COLUMN tb_api_keys.last_used_at IS 'Last time the key authenticated a request';
+COMMENT ON COLUMN tb_api_keys.deleted_at IS 'Revocation time for soft delete, NULL means active, value means revoked';
@@ -0,0 +1,58 @@
+CREATE TABLE IF NOT EXISTS tb_users
+(
+    id            UUID PRIMARY KEY,
+    username      TEXT      NOT NULL,
+    password_hash TEXT      NOT NULL,
+    role          TEXT      NOT NULL DEFAULT 'read-only',
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at    TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username
+    ON tb_users (username)
+    WHERE deleted_at IS NULL;
+
+-- Table comment
+COMMENT ON TABLE tb_users IS 'Users logging in to MCP Center with JWT access and refresh tokens';
+
+-- Column comments
+COMMENT ON COLUMN tb_users.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_users.username IS 'Login name, unique among active users';
+COMMENT ON COLUMN tb_users.password_hash IS 'Argon2 hash of the password in PHC string format';
+COMMENT ON COLUMN tb_users.role IS 'Role claimed by the tokens of the user: admin or read-only';
+COMMENT ON COLUMN tb_users.created_at IS 'Record creation time';
+COMMENT ON COLUMN tb_users.updated_at IS 'Last update time';
+COMMENT ON COLUMN tb_users.deleted_at IS 'Logical deletion time (NULL means not deleted)';
+
+-- Create trigger for tb_users table (reusing existing function)
+DO
+$$
+    BEGIN
+        IF NOT EXISTS (SELECT 1
+                       FROM pg_trigger
+                       WHERE tgname = 'set_updated_at_trigger'
+                         AND tgrelid = 'tb_users'::regclass) THEN
+            CREATE TRIGGER set_updated_at_trigger
+                BEFORE UPDATE
+                ON tb_users
+                FOR EACH ROW
+            EXECUTE FUNCTION update_updated_at_column();
+        END IF;
+    END
+$$;
+
+CREATE TABLE IF NOT EXISTS tb_revoked_tokens
+(
+    jti        UUID PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Table comment
+COMMENT ON TABLE tb_revoked_tokens IS 'JWTs revoked by logout or refresh before they expire';
+
+-- Column comments
+COMMENT ON COLUMN tb_revoked_tokens.jti IS 'Token id (jti claim) of the revoked token';
+COMMENT ON COLUMN tb_revoked_tokens.expires_at IS 'Expiry of the token, the row is useless and purged afterward';
+COMMENT ON COLUMN tb_revoked_tokens.created_at IS 'Revocation time';
@@ -0,0 +1,6 @@
+ALTER TABLE tb_mcp_servers
+    ADD COLUMN IF NOT EXISTS credential_type TEXT,
+    ADD COLUMN IF NOT EXISTS credential      BYTEA;
+
+COMMENT ON COLUMN tb_mcp_servers.credential_type IS 'Kind of the upstream credential, e.g. header, bearer or basic (NULL means none)';
+COMMENT ON COLUMN tb_mcp_servers.credential IS 'Upstream credential encrypted with the credential key, never returned by the API';
@@ -0,0 +1,47 @@
+CREATE TABLE IF NOT EXISTS tb_audit_logs
+(
+    id          UUID PRIMARY KEY,
+    actor_type  TEXT      NOT NULL,
+    actor       TEXT      NOT NULL,
+    action      TEXT      NOT NULL,
+    server_name TEXT,
+    server_tag  TEXT,
+    method      TEXT      NOT NULL,
+    tool        TEXT,
+    path        TEXT      NOT NULL,
+    arguments   JSONB,
+    status      INTEGER   NOT NULL,
+    error_code  BIGINT,
+    error       TEXT,
+    latency_ms  BIGINT    NOT NULL,
+    created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_logs_created_at
+    ON tb_audit_logs (created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_audit_logs_actor
+    ON tb_audit_logs (actor, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_audit_logs_server
+    ON tb_audit_logs (server_name, server_tag, created_at DESC);
+
+-- Table comment
+COMMENT ON TABLE tb_audit_logs IS 'Audit log of MCP requests and admin API changes, rows are never updated';
+
+-- Column comments
+COMMENT ON COLUMN tb_audit_logs.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_audit_logs.actor_type IS 'Kind of credentials: admin, user, oauth, api_key or anonymous';
+COMMENT ON COLUMN tb_audit_logs.actor IS 'Username, OAuth subject or API key name of the caller';
+COMMENT ON COLUMN tb_audit_logs.action IS 'mcp for JSON-RPC requests to MCP servers, api for admin API changes';
+COMMENT ON COLUMN tb_audit_logs.server_name IS 'Name of the MCP server or aggregate, NULL for api entries';
+COMMENT ON COLUMN tb_audit_logs.server_tag IS 'Tag of the MCP server, NULL for api entries and aggregates';
+COMMENT ON COLUMN tb_audit_logs.method IS 'JSON-RPC method of mcp entries, HTTP method of api entries';
+COMMENT ON COLUMN tb_audit_logs.tool IS 'Tool name of tools/call requests';
+COMMENT ON COLUMN tb_audit_logs.path IS 'Request path';
+COMMENT ON COLUMN tb_audit_logs.arguments IS 'Redacted arguments of tool calls or params of other requests, when captured';
+COMMENT ON COLUMN tb_audit_logs.status IS 'HTTP status of the response';
+COMMENT ON COLUMN tb_audit_logs.error_code IS 'Code of the JSON-RPC error answering the request';
+COMMENT ON COLUMN tb_audit_logs.error IS 'Reason of a failed request';
+COMMENT ON COLUMN tb_audit_logs.latency_ms IS 'Milliseconds until the response was complete';
+COMMENT ON COLUMN tb_audit_logs.created_at IS 'Time the request was received';
@@ -0,0 +1,51 @@
+CREATE TABLE IF NOT EXISTS tb_rate_limits
+(
+    id           UUID PRIMARY KEY,
+    scope        TEXT      NOT NULL,
+    kind         TEXT      NOT NULL,
+    api_key_name TEXT      NOT NULL DEFAULT '*',
+    server_name  TEXT      NOT NULL DEFAULT '*',
+    tag_pattern  TEXT      NOT NULL DEFAULT '*',
+    max_requests BIGINT    NOT NULL,
+    period_secs  BIGINT    NOT NULL DEFAULT 60,
+    burst        BIGINT,
+    description  TEXT      NOT NULL DEFAULT '',
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    deleted_at   TIMESTAMP
+);
+
+-- Table comment
+COMMENT ON TABLE tb_rate_limits IS 'Request rates and open sessions permitted to API keys, MCP servers or everyone';
+
+-- Column comments
+COMMENT ON COLUMN tb_rate_limits.id IS 'Unique identifier (UUID)';
+COMMENT ON COLUMN tb_rate_limits.scope IS 'Budget holder: global (one budget), server (one per server and tag) or api_key (one per API key)';
+COMMENT ON COLUMN tb_rate_limits.kind IS 'Limited requests: connect (new sessions), message (message posts) or sessions (open streams)';
+COMMENT ON COLUMN tb_rate_limits.api_key_name IS 'API key name pattern, * matches any characters, other patterns only match API keys';
+COMMENT ON COLUMN tb_rate_limits.server_name IS 'MCP server or aggregate name pattern, * matches any characters';
+COMMENT ON COLUMN tb_rate_limits.tag_pattern IS 'MCP server tag pattern, * matches any characters';
+COMMENT ON COLUMN tb_rate_limits.max_requests IS 'Requests per period, the number of open streams for the sessions kind';
+COMMENT ON COLUMN tb_rate_limits.period_secs IS 'Period of max_requests in seconds';
+COMMENT ON COLUMN tb_rate_limits.burst IS 'Requests allowed at once, NULL means max_requests';
+COMMENT ON COLUMN tb_rate_limits.description IS 'Rate limit description';
+COMMENT ON COLUMN tb_rate_limits.created_at IS 'Record creation time';
+COMMENT ON COLUMN tb_rate_limits.updated_at IS 'Last update time';
+COMMENT ON COLUMN tb_rate_limits.deleted_at IS 'Logical deletion time (NULL means not deleted)';
+
+-- Create trigger for tb_rate_limits table (reusing existing function)
+DO
+$$
+    BEGIN
+        IF NOT EXISTS (SELECT 1
+                       FROM pg_trigger
+                       WHERE tgname = 'set_updated_at_trigger'
+                         AND tgrelid = 'tb_rate_limits'::regclass) THEN
+            CREATE TRIGGER set_updated_at_trigger
+                BEFORE UPDATE
+                ON tb_rate_limits
+                FOR EACH ROW
+            EXECUTE FUNCTION update_updated_at_column();
+        END IF;
+    END
+$$;
@@ -0,0 +1,34 @@
+-- Notify every mcp-center instance of changed rows of tb_mcp_servers, the payload
+-- only names the row so that credentials are never sent over the channel
+CREATE OR REPLACE FUNCTION notify_mcp_server_changed()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    -- identical payloads of a transaction are delivered once
+    IF TG_OP <> 'INSERT' THEN
+        PERFORM pg_notify('mcp_server_changed',
+                          json_build_object('name', OLD.name, 'tag', OLD.tag)::text);
+    END IF;
+    IF TG_OP <> 'DELETE' THEN
+        PERFORM pg_notify('mcp_server_changed',
+                          json_build_object('name', NEW.name, 'tag', NEW.tag)::text);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DO
+$$
+    BEGIN
+        IF NOT EXISTS (SELECT 1
+                       FROM pg_trigger
+                       WHERE tgname = 'notify_mcp_server_changed_trigger'
+                         AND tgrelid = 'tb_mcp_servers'::regclass) THEN
+            CREATE TRIGGER notify_mcp_server_changed_trigger
+                AFTER INSERT OR UPDATE OR DELETE
+                ON tb_mcp_servers
+                FOR EACH ROW
+            EXECUTE FUNCTION notify_mcp_server_changed();
+        END IF;
+    END
+$$;
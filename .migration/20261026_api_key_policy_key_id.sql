@@ -0,0 +1,18 @@
+-- grants of an API key follow the key, not its name which a revoked key frees
+ALTER TABLE tb_api_key_policies
+    ADD COLUMN IF NOT EXISTS api_key_id UUID;
+
+UPDATE tb_api_key_policies p
+SET api_key_id = k.id
+FROM tb_api_keys k
+WHERE p.api_key_id IS NULL
+  AND k.name = p.api_key_name
+  AND k.deleted_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_api_key_policies_api_key_id
+    ON tb_api_key_policies (api_key_id)
+    WHERE deleted_at IS NULL;
+
+-- Column comments
+COMMENT ON COLUMN tb_api_key_policies.api_key_id IS 'API key (tb_api_keys.id) the grant belongs to, NULL for grants of OAuth principals';
+COMMENT ON COLUMN tb_api_key_policies.api_key_name IS 'Name of the API key when the grant was created, or the OAuth principal the grant belongs to';
@@ -0,0 +1,15 @@
+-- limits of a single API key follow the key, not its name which a revoked key frees
+ALTER TABLE tb_rate_limits
+    ADD COLUMN IF NOT EXISTS api_key_id UUID;
+
+UPDATE tb_rate_limits r
+SET api_key_id = k.id
+FROM tb_api_keys k
+WHERE r.api_key_id IS NULL
+  AND r.api_key_name NOT LIKE '%*%'
+  AND k.name = r.api_key_name
+  AND k.deleted_at IS NULL;
+
+-- Column comments
+COMMENT ON COLUMN tb_rate_limits.api_key_id IS 'API key (tb_api_keys.id) a limit of a single key belongs to, NULL for patterns';
+COMMENT ON COLUMN tb_rate_limits.api_key_name IS 'API key name pattern, * matches any characters, other patterns only match API keys; a name without * is bound to api_key_id';
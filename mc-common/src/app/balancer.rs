@@ -0,0 +1,558 @@
+use crate::app::cache::McpServerInfo;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How a request without session affinity picks an instance.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    WeightedRandom,
+}
+
+/// Load balancing of a server, read from `load_balancing` of the registry `extra` column.
+/// The registry `endpoint` is always an instance, `instances` adds the others.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoadBalancing {
+    pub strategy: Strategy,
+    pub instances: Vec<InstanceConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InstanceConfig {
+    pub endpoint: String,
+    /// Relative share of the traffic, only used by `weighted-random`.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl LoadBalancing {
+    pub fn from_extra(extra: Option<&serde_json::Value>) -> Result<Self, String> {
+        let config: Self = match extra.and_then(|extra| extra.get("load_balancing")) {
+            None => return Ok(Self::default()),
+            Some(config) => serde_json::from_value(config.clone())
+                .map_err(|err| format!("invalid load balancing config: {err}"))?,
+        };
+        if let Some(instance) = config.instances.iter().find(|i| i.weight == 0) {
+            return Err(format!(
+                "weight of instance {} must be positive",
+                instance.endpoint
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// When an instance is taken out of rotation and put back, counted over consecutive
+/// results of health probes and proxied requests.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            unhealthy_threshold: default_unhealthy_threshold(),
+            healthy_threshold: default_healthy_threshold(),
+        }
+    }
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+fn default_healthy_threshold() -> u32 {
+    2
+}
+
+/// Health of a server as reported by the registry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerHealth {
+    /// Whether any instance is in rotation.
+    pub healthy: bool,
+    pub instances: Vec<InstanceHealth>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstanceHealth {
+    pub endpoint: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub active_connections: usize,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct HealthState {
+    healthy: bool,
+    failures: u32,
+    successes: u32,
+    last_error: Option<String>,
+}
+
+/// State of an instance shared by the leases, kept while the config of the server changes.
+#[derive(Debug)]
+pub struct InstanceState {
+    endpoint: String,
+    thresholds: HealthThresholds,
+    active: AtomicUsize,
+    health: Mutex<HealthState>,
+}
+
+impl InstanceState {
+    fn new(endpoint: &str, thresholds: HealthThresholds) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            thresholds,
+            active: AtomicUsize::new(0),
+            // instances start in rotation until they are seen failing
+            health: Mutex::new(HealthState {
+                healthy: true,
+                failures: 0,
+                successes: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.health.lock().unwrap().healthy
+    }
+
+    /// Records the result of a probe or a proxied request.
+    pub fn record(&self, result: Result<(), String>) {
+        let mut health = self.health.lock().unwrap();
+        match result {
+            Ok(()) => {
+                health.failures = 0;
+                health.successes = health.successes.saturating_add(1);
+                if !health.healthy && health.successes >= self.thresholds.healthy_threshold {
+                    health.healthy = true;
+                    health.last_error = None;
+                    tracing::info!("Instance {} is healthy again", self.endpoint);
+                }
+            }
+            Err(err) => {
+                health.successes = 0;
+                health.failures = health.failures.saturating_add(1);
+                if health.healthy && health.failures >= self.thresholds.unhealthy_threshold {
+                    health.healthy = false;
+                    tracing::warn!(
+                        "Instance {} is unhealthy after {} failures, error: {}",
+                        self.endpoint,
+                        health.failures,
+                        err
+                    );
+                }
+                health.last_error = Some(err);
+            }
+        }
+    }
+
+    fn report(&self) -> InstanceHealth {
+        let health = self.health.lock().unwrap();
+        InstanceHealth {
+            endpoint: self.endpoint.clone(),
+            healthy: health.healthy,
+            consecutive_failures: health.failures,
+            active_connections: self.active.load(Ordering::Relaxed),
+            last_error: health.last_error.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Instance {
+    server: McpServerInfo,
+    weight: u32,
+    state: Arc<InstanceState>,
+}
+
+/// The instances serving one name/tag.
+#[derive(Debug)]
+pub struct Upstream {
+    strategy: Strategy,
+    instances: Vec<Instance>,
+    cursor: AtomicUsize,
+}
+
+impl PartialEq for Upstream {
+    fn eq(&self, other: &Self) -> bool {
+        self.strategy == other.strategy
+            && self.instances.len() == other.instances.len()
+            && self.instances.iter().zip(&other.instances).all(|(a, b)| {
+                a.server.endpoint == b.server.endpoint
+                    && a.server == b.server
+                    && a.weight == b.weight
+                    && a.state.thresholds == b.state.thresholds
+            })
+    }
+}
+
+impl Upstream {
+    /// `instances` must not be empty.
+    pub fn new(
+        strategy: Strategy,
+        instances: Vec<(McpServerInfo, u32)>,
+        thresholds: HealthThresholds,
+    ) -> Self {
+        assert!(!instances.is_empty(), "an upstream needs an instance");
+        Self {
+            strategy,
+            instances: instances
+                .into_iter()
+                .map(|(server, weight)| Instance {
+                    state: Arc::new(InstanceState::new(&server.endpoint, thresholds)),
+                    server,
+                    weight,
+                })
+                .collect(),
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn single(server: McpServerInfo, thresholds: HealthThresholds) -> Self {
+        Self::new(
+            Strategy::default(),
+            vec![(server, default_weight())],
+            thresholds,
+        )
+    }
+
+    /// Takes over the connection counts and health of the instances which remain.
+    pub fn inherit(&mut self, previous: &Upstream) {
+        for instance in &mut self.instances {
+            if let Some(old) = previous
+                .instances
+                .iter()
+                .find(|old| old.server.endpoint == instance.server.endpoint)
+                && old.state.thresholds == instance.state.thresholds
+            {
+                instance.state = old.state.clone();
+            }
+        }
+    }
+
+    /// Picks a healthy instance by the strategy, any instance when none is healthy.
+    /// The connection counts as active until the lease is dropped.
+    pub fn select(&self) -> (McpServerInfo, Lease) {
+        let mut candidates: Vec<usize> = (0..self.instances.len())
+            .filter(|&index| self.instances[index].state.is_healthy())
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..self.instances.len()).collect();
+        }
+
+        let index = match self.strategy {
+            _ if candidates.len() == 1 => candidates[0],
+            Strategy::RoundRobin => candidates[self.next_index(candidates.len())],
+            Strategy::LeastConnections => {
+                // ties are broken round robin, so idle instances share the load
+                let start = self.next_index(candidates.len());
+                (0..candidates.len())
+                    .map(|offset| candidates[(start + offset) % candidates.len()])
+                    .min_by_key(|&index| self.instances[index].state.active.load(Ordering::Relaxed))
+                    .unwrap_or(candidates[start])
+            }
+            Strategy::WeightedRandom => {
+                let weights: Vec<u32> = candidates
+                    .iter()
+                    .map(|&index| self.instances[index].weight)
+                    .collect();
+                let total: u64 = weights.iter().map(|&weight| weight as u64).sum();
+                candidates[pick_weighted(weights.into_iter(), random_u64() % total.max(1))]
+            }
+        };
+        self.lease(index)
+    }
+
+    /// The instance with the given endpoint, used to keep a session on its instance.
+    pub fn find(&self, endpoint: &str) -> Option<(McpServerInfo, Lease)> {
+        self.instances
+            .iter()
+            .position(|instance| instance.server.endpoint == endpoint)
+            .map(|index| self.lease(index))
+    }
+
+    /// Every instance with its state, for the health checker.
+    pub fn instances(&self) -> impl Iterator<Item = (&McpServerInfo, &Arc<InstanceState>)> {
+        self.instances
+            .iter()
+            .map(|instance| (&instance.server, &instance.state))
+    }
+
+    pub fn health(&self) -> ServerHealth {
+        let instances: Vec<InstanceHealth> = self
+            .instances
+            .iter()
+            .map(|instance| instance.state.report())
+            .collect();
+        ServerHealth {
+            healthy: instances.iter().any(|instance| instance.healthy),
+            instances,
+        }
+    }
+
+    fn next_index(&self, len: usize) -> usize {
+        self.cursor.fetch_add(1, Ordering::Relaxed) % len
+    }
+
+    fn lease(&self, index: usize) -> (McpServerInfo, Lease) {
+        let instance = &self.instances[index];
+        (instance.server.clone(), Lease::new(instance.state.clone()))
+    }
+}
+
+/// An active connection to an instance, counted for `least-connections`.
+#[derive(Debug)]
+pub struct Lease {
+    state: Arc<InstanceState>,
+}
+
+impl Lease {
+    fn new(state: Arc<InstanceState>) -> Self {
+        state.active.fetch_add(1, Ordering::Relaxed);
+        Self { state }
+    }
+
+    /// Counts the outcome of the proxied request towards the health of the instance.
+    pub fn report(&self, result: Result<(), String>) {
+        self.state.record(result);
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.state.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// `point` is below the sum of the weights
+fn pick_weighted(weights: impl Iterator<Item = u32>, mut point: u64) -> usize {
+    let mut last = 0;
+    for (index, weight) in weights.enumerate() {
+        if point < weight as u64 {
+            return index;
+        }
+        point -= weight as u64;
+        last = index;
+    }
+    last
+}
+
+fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    // the system random source doesn't fail on supported platforms
+    let _ = SystemRandom::new().fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HttpScheme, TransportType};
+    use serde_json::json;
+
+    fn server(endpoint: &str) -> McpServerInfo {
+        McpServerInfo {
+            endpoint: endpoint.to_string(),
+            host: endpoint.to_string(),
+            port: "80".to_string(),
+            path: "/sse".to_string(),
+            scheme: HttpScheme::Http,
+            transport_type: TransportType::Sse,
+            extra: None,
+            credential: None,
+        }
+    }
+
+    fn upstream(strategy: Strategy, weights: &[u32]) -> Upstream {
+        Upstream::new(
+            strategy,
+            weights
+                .iter()
+                .enumerate()
+                .map(|(index, weight)| (server(&format!("instance-{index}")), *weight))
+                .collect(),
+            HealthThresholds::default(),
+        )
+    }
+
+    #[test]
+    fn test_from_extra() {
+        assert_eq!(
+            LoadBalancing::from_extra(None).unwrap(),
+            LoadBalancing::default()
+        );
+
+        let config = LoadBalancing::from_extra(Some(&json!({"load_balancing": {
+            "strategy": "least-connections",
+            "instances": [{"endpoint": "http://b/sse"}, {"endpoint": "http://c/sse", "weight": 3}]
+        }})))
+        .unwrap();
+        assert_eq!(config.strategy, Strategy::LeastConnections);
+        assert_eq!(config.instances[0].weight, 1);
+        assert_eq!(config.instances[1].weight, 3);
+
+        let invalid = [
+            json!({"load_balancing": {"strategy": "random"}}),
+            json!({"load_balancing": {"instances": [{"endpoint": "http://b/sse", "weight": 0}]}}),
+            json!({"load_balancing": {"instances": [{"weight": 1}]}}),
+        ];
+        for extra in invalid {
+            assert!(LoadBalancing::from_extra(Some(&extra)).is_err(), "{extra}");
+        }
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let upstream = upstream(Strategy::RoundRobin, &[1, 1, 1]);
+        let picked: Vec<String> = (0..4).map(|_| upstream.select().0.endpoint).collect();
+        assert_eq!(
+            picked,
+            vec!["instance-0", "instance-1", "instance-2", "instance-0"]
+        );
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let upstream = upstream(Strategy::LeastConnections, &[1, 1, 1]);
+
+        let (first, first_lease) = upstream.select();
+        let (second, _second_lease) = upstream.select();
+        let (third, _third_lease) = upstream.select();
+        assert_ne!(first.endpoint, second.endpoint);
+        assert_ne!(second.endpoint, third.endpoint);
+        assert_ne!(first.endpoint, third.endpoint);
+
+        // the instance whose connection ended is the least loaded
+        drop(first_lease);
+        assert_eq!(upstream.select().0.endpoint, first.endpoint);
+    }
+
+    #[test]
+    fn test_pick_weighted() {
+        let cases = [
+            (vec![1, 1], 0, 0),
+            (vec![1, 1], 1, 1),
+            (vec![1, 3], 1, 1),
+            (vec![1, 3], 3, 1),
+            (vec![2, 1, 1], 1, 0),
+            (vec![2, 1, 1], 3, 2),
+        ];
+        for (weights, point, want) in cases {
+            assert_eq!(
+                pick_weighted(weights.clone().into_iter(), point),
+                want,
+                "{weights:?} {point}"
+            );
+        }
+
+        let upstream = upstream(Strategy::WeightedRandom, &[1, 1000]);
+        let heavy = (0..100)
+            .filter(|_| upstream.select().0.endpoint == "instance-1")
+            .count();
+        assert!(heavy > 80, "{heavy}");
+    }
+
+    #[test]
+    fn test_find() {
+        let upstream = upstream(Strategy::RoundRobin, &[1, 1]);
+        assert_eq!(
+            upstream.find("instance-1").unwrap().0.endpoint,
+            "instance-1"
+        );
+        assert!(upstream.find("instance-2").is_none());
+        assert_eq!(upstream, self::upstream(Strategy::RoundRobin, &[1, 1]));
+        assert_ne!(
+            upstream,
+            self::upstream(Strategy::LeastConnections, &[1, 1])
+        );
+    }
+
+    #[test]
+    fn test_health() {
+        let upstream = upstream(Strategy::RoundRobin, &[1, 1]);
+        let (_, lease) = upstream.find("instance-0").unwrap();
+
+        // taken out of rotation after `unhealthy_threshold` failures in a row
+        for _ in 0..2 {
+            lease.report(Err("refused".to_string()));
+        }
+        lease.report(Ok(()));
+        for _ in 0..2 {
+            lease.report(Err("refused".to_string()));
+        }
+        assert!(upstream.health().instances[0].healthy);
+        lease.report(Err("refused".to_string()));
+
+        let health = upstream.health();
+        assert!(health.healthy);
+        assert!(!health.instances[0].healthy);
+        assert_eq!(health.instances[0].consecutive_failures, 3);
+        assert_eq!(health.instances[0].last_error.as_deref(), Some("refused"));
+        assert_eq!(health.instances[0].active_connections, 1);
+        for _ in 0..4 {
+            assert_eq!(upstream.select().0.endpoint, "instance-1");
+        }
+        // a bound session stays on its instance
+        assert!(upstream.find("instance-0").is_some());
+
+        // back after `healthy_threshold` successes
+        lease.report(Ok(()));
+        assert!(!upstream.health().instances[0].healthy);
+        lease.report(Ok(()));
+        assert!(upstream.health().instances[0].healthy);
+        assert_eq!(upstream.health().instances[0].last_error, None);
+    }
+
+    #[test]
+    fn test_all_unhealthy() {
+        let upstream = upstream(Strategy::RoundRobin, &[1, 1]);
+        for (_, state) in upstream.instances() {
+            for _ in 0..3 {
+                state.record(Err("refused".to_string()));
+            }
+        }
+        assert!(!upstream.health().healthy);
+
+        // better to try an instance than to fail every request
+        let picked: Vec<String> = (0..2).map(|_| upstream.select().0.endpoint).collect();
+        assert_eq!(picked, vec!["instance-0", "instance-1"]);
+    }
+
+    #[test]
+    fn test_inherit() {
+        let previous = upstream(Strategy::RoundRobin, &[1, 1]);
+        let (_, lease) = previous.find("instance-1").unwrap();
+        for _ in 0..3 {
+            lease.report(Err("refused".to_string()));
+        }
+
+        let mut upstream = Upstream::new(
+            Strategy::RoundRobin,
+            vec![(server("instance-1"), 1), (server("instance-2"), 1)],
+            HealthThresholds::default(),
+        );
+        upstream.inherit(&previous);
+        let health = upstream.health();
+        assert!(!health.instances[0].healthy);
+        assert_eq!(health.instances[0].active_connections, 1);
+        assert!(health.instances[1].healthy);
+    }
+}
@@ -1,22 +1,37 @@
+use crate::app::balancer::{HealthThresholds, Lease, LoadBalancing, ServerHealth, Upstream};
 use crate::app::event::Event;
-use crate::types::HttpScheme;
-use mc_db::{DBClient, McpDBHandler};
+use crate::app::stdio::StdioConfig;
+use crate::credential::{CredentialCipher, UpstreamCredential};
+use crate::metrics;
+use crate::types::{HttpScheme, TransportType};
+use mc_db::{DBClient, McpDBHandler, McpListFilter, McpServerListener};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
-use tokio::sync::RwLock;
 use tokio::sync::broadcast::Receiver;
-use tokio::time::interval;
+use tokio::sync::{Notify, RwLock};
+use tokio::time::{interval, sleep};
 
 static REGEX_ENDPOINT: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^(?P<scheme>https?)://(?P<host>[^/:]+)(?::(?P<port>\d+))?(?P<path>/.*)?$").unwrap()
 });
 
+/// Session bindings idle for longer are dropped, the next request picks an instance anew.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const LISTEN_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+type ServerCache = HashMap<String, HashMap<String, Arc<Upstream>>>;
+// servers of the registry by name and tag, `Err` when they can't be proxied
+type Desired = HashMap<(String, String), Result<Upstream, String>>;
+// (name, tag, session id) to the endpoint of the instance serving the session
+type SessionBindings = HashMap<(String, String, String), (String, Instant)>;
+
+/// One instance of an MCP server.
 #[derive(Debug, Clone)]
 pub struct McpServerInfo {
     pub endpoint: String,
@@ -24,115 +39,293 @@ pub struct McpServerInfo {
     pub port: String,
     pub path: String,
     pub scheme: HttpScheme,
+    pub transport_type: TransportType,
+    pub extra: Option<serde_json::Value>,
+    pub credential: Option<UpstreamCredential>,
 }
 
 impl PartialEq for McpServerInfo {
     fn eq(&self, other: &Self) -> bool {
-        self.host == other.host && self.port == other.port && self.path == other.path
+        self.host == other.host
+            && self.port == other.port
+            && self.path == other.path
+            && self.transport_type == other.transport_type
+            && self.extra == other.extra
+            && self.credential == other.credential
+    }
+}
+
+/// Servers changed by events while a full sync reads the registry. The sync keeps their
+/// cached state, its snapshot may predate the change.
+#[derive(Debug, Default)]
+struct SyncChanges {
+    reading: bool,
+    changed: HashSet<(String, String)>,
+}
+
+impl SyncChanges {
+    fn start(&mut self) {
+        self.reading = true;
+        self.changed.clear();
+    }
+
+    fn record(&mut self, mcp_name: &str, tag: &str) {
+        if self.reading {
+            self.changed.insert((mcp_name.to_string(), tag.to_string()));
+        }
+    }
+
+    fn finish(&mut self) -> HashSet<(String, String)> {
+        self.reading = false;
+        std::mem::take(&mut self.changed)
+    }
+}
+
+/// Keeps the sessions opened on a connection on its instance until the connection ends.
+pub struct SessionAffinity {
+    cache: Cache,
+    mcp_name: String,
+    tag: String,
+    endpoint: String,
+    sessions: Vec<String>,
+    _lease: Lease,
+}
+
+impl SessionAffinity {
+    pub fn bind(&mut self, session: &str) {
+        self.cache
+            .bind_session(&self.mcp_name, &self.tag, session, &self.endpoint);
+        self.sessions.push(session.to_string());
+    }
+}
+
+impl Drop for SessionAffinity {
+    fn drop(&mut self) {
+        for session in &self.sessions {
+            self.cache
+                .unbind_session(&self.mcp_name, &self.tag, session);
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct Cache {
     db_client: Arc<DBClient>,
-    server_cache: Arc<RwLock<HashMap<String, HashMap<String, McpServerInfo>>>>,
+    server_cache: Arc<RwLock<ServerCache>>,
+    sync_changes: Arc<Mutex<SyncChanges>>,
+    sessions: Arc<Mutex<SessionBindings>>,
     runtime: Arc<Runtime>,
+    cipher: Option<Arc<CredentialCipher>>,
+    thresholds: HealthThresholds,
+    /// Wakes the full sync before its interval.
+    resync: Arc<Notify>,
 }
 
 impl Cache {
+    /// Loads the servers every `interval` seconds and applies the events of this instance,
+    /// with `listen` also the changes of other instances notified by Postgres.
     pub fn new(
         db_client: Arc<DBClient>,
         receiver: Receiver<Event>,
         runtime: Arc<Runtime>,
         interval: u64,
+        listen: bool,
+        cipher: Option<Arc<CredentialCipher>>,
+        thresholds: HealthThresholds,
     ) -> Self {
         let cache = Self {
             db_client,
             server_cache: Arc::new(RwLock::new(HashMap::new())),
+            sync_changes: Arc::new(Mutex::new(SyncChanges::default())),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
             runtime,
+            cipher,
+            thresholds,
+            resync: Arc::new(Notify::new()),
         };
         cache.async_cache(interval);
         cache.handle_event(receiver);
+        if listen {
+            cache.listen_changes();
+        }
         cache
     }
+    /// Periodically reconciles the cache with every server which can be proxied.
     fn async_cache(&self, cache_interval: u64) {
         let cache = self.server_cache.clone();
+        let sync_changes = self.sync_changes.clone();
         let db_client = self.db_client.clone();
+        let cipher = self.cipher.clone();
+        let thresholds = self.thresholds;
+        let resync = self.resync.clone();
 
         self.runtime.spawn(async move {
-            let mut ticker = interval(Duration::from_secs(cache_interval));
+            let mut ticker = interval(Duration::from_secs(cache_interval.max(1)));
             loop {
-                ticker.tick().await;
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = resync.notified() => {}
+                }
+                let started = Instant::now();
 
+                sync_changes.lock().unwrap().start();
                 let handler = McpDBHandler::new(db_client.clone());
-                let mcp_servers = match handler.list_all().await {
+                let mcp_servers = match handler.list_all(McpListFilter::default()).await {
                     Ok(results) => results,
                     Err(err) => {
                         tracing::error!("Can't list mcp servers, error: {}", err);
+                        sync_changes.lock().unwrap().finish();
+                        metrics::cache_sync_failed(started);
                         continue;
                     }
                 };
 
-                let mut updated_count: usize = 0;
-                let all_count: usize = mcp_servers.len();
-
-                for server in mcp_servers {
-                    let r_cache = cache.read().await;
-
-                    let tag = &server.tag;
-
-                    let mcp_server = match parse_endpoint(server.endpoint.as_str()) {
-                        Ok(p) => p,
-                        Err(err) => {
-                            tracing::error!("Failed to parse endpoint, error: {}", err);
-                            return;
-                        }
-                    };
-
-                    if let Some(tags) = r_cache.get(&server.name)
-                        && let Some(item) = tags.get(&server.tag)
-                        && item == &mcp_server
-                    {
-                        return;
-                    }
-
-                    // unlock the read lock
-                    drop(r_cache);
-
-                    updated_count += 1;
-
-                    let mut w_cache = cache.write().await;
+                let desired: Desired = mcp_servers
+                    .into_iter()
+                    .map(|server| {
+                        let credential = decrypt_credential(
+                            cipher.as_deref(),
+                            &server.name,
+                            &server.tag,
+                            server.credential.as_deref(),
+                        );
+                        let upstream = parse_upstream(
+                            server.endpoint.as_str(),
+                            server.transport_type.as_str(),
+                            server.extra,
+                            credential,
+                            thresholds,
+                        )
+                        .map_err(|err| err.to_string());
+                        ((server.name, server.tag), upstream)
+                    })
+                    .collect();
 
-                    w_cache
-                        .entry(server.name.clone())
-                        .or_insert_with(HashMap::new)
-                        .insert(tag.clone(), mcp_server);
-
-                    tracing::info!(
-                        "Load mcp server {}/{} success, endpoint: {}",
-                        server.name,
-                        tag,
-                        server.endpoint
-                    );
-                }
+                // servers changed by events since the read keep their cached state, the
+                // snapshot may be older; events arriving from here on wait for the swap
+                let reconciled = {
+                    let mut cache = cache.write().await;
+                    let changed = sync_changes.lock().unwrap().finish();
+                    let (next, reconciled) = reconcile(&cache, desired, &changed);
+                    *cache = next;
+                    reconciled
+                };
 
+                metrics::cache_sync(
+                    started,
+                    reconciled.added + reconciled.updated,
+                    reconciled.removed,
+                );
                 tracing::info!(
-                    updated_count = updated_count,
-                    no_need_update = all_count - updated_count,
+                    added_count = reconciled.added,
+                    updated_count = reconciled.updated,
+                    evicted_count = reconciled.removed,
+                    no_need_update = reconciled.unchanged,
+                    failed_count = reconciled.failed,
                     "sync mcp servers done"
                 );
             }
         });
     }
 
+    /// Picks an instance without tracking the connection.
     pub async fn load_server_info(&self, mcp_name: &str, tag: &str) -> Option<McpServerInfo> {
-        let cache = self.server_cache.read().await;
-        if let Some(tags) = cache.get(mcp_name)
-            && let Some(info) = tags.get(tag)
+        self.select(mcp_name, tag, None)
+            .await
+            .map(|(server, _)| server)
+    }
+
+    /// Picks an instance of the server, the one serving `session` when it is bound.
+    /// The connection counts as active until the lease is dropped.
+    #[tracing::instrument(name = "cache.select", skip(self))]
+    pub async fn select(
+        &self,
+        mcp_name: &str,
+        tag: &str,
+        session: Option<&str>,
+    ) -> Option<(McpServerInfo, Lease)> {
+        let upstream = {
+            let cache = self.server_cache.read().await;
+            cache.get(mcp_name)?.get(tag)?.clone()
+        };
+
+        if let Some(session) = session
+            && let Some(endpoint) = self.bound_endpoint(mcp_name, tag, session)
         {
-            return Some(info.clone());
+            if let Some(found) = upstream.find(&endpoint) {
+                return Some(found);
+            }
+            tracing::warn!(
+                "Instance {} of session {} of mcp server {}/{} is gone",
+                endpoint,
+                session,
+                mcp_name,
+                tag
+            );
         }
-        None
+        Some(upstream.select())
+    }
+
+    /// Every cached server, for the health checker.
+    pub async fn upstreams(&self) -> Vec<(String, String, Arc<Upstream>)> {
+        let cache = self.server_cache.read().await;
+        cache
+            .iter()
+            .flat_map(|(name, tags)| {
+                tags.iter()
+                    .map(|(tag, upstream)| (name.clone(), tag.clone(), upstream.clone()))
+            })
+            .collect()
+    }
+
+    pub async fn health(&self, mcp_name: &str, tag: &str) -> Option<ServerHealth> {
+        let cache = self.server_cache.read().await;
+        Some(cache.get(mcp_name)?.get(tag)?.health())
+    }
+
+    /// Keeps the requests of a session on the instance which opened it.
+    pub fn bind_session(&self, mcp_name: &str, tag: &str, session: &str, endpoint: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, (_, used)| now.duration_since(*used) < SESSION_IDLE_TIMEOUT);
+        sessions.insert(
+            (mcp_name.to_string(), tag.to_string(), session.to_string()),
+            (endpoint.to_string(), now),
+        );
+    }
+
+    pub fn unbind_session(&self, mcp_name: &str, tag: &str, session: &str) {
+        self.sessions.lock().unwrap().remove(&(
+            mcp_name.to_string(),
+            tag.to_string(),
+            session.to_string(),
+        ));
+    }
+
+    /// Holds `lease` and binds the sessions of the connection to `server`.
+    pub fn affinity(
+        &self,
+        mcp_name: &str,
+        tag: &str,
+        server: &McpServerInfo,
+        lease: Lease,
+    ) -> SessionAffinity {
+        SessionAffinity {
+            cache: self.clone(),
+            mcp_name: mcp_name.to_string(),
+            tag: tag.to_string(),
+            endpoint: server.endpoint.clone(),
+            sessions: vec![],
+            _lease: lease,
+        }
+    }
+
+    fn bound_endpoint(&self, mcp_name: &str, tag: &str, session: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (mcp_name.to_string(), tag.to_string(), session.to_string());
+        let (endpoint, used) = sessions.get_mut(&key)?;
+        *used = Instant::now();
+        Some(endpoint.clone())
     }
 
     #[allow(dead_code)]
@@ -143,6 +336,7 @@ impl Cache {
         server: McpServerInfo,
     ) -> Result<(), Box<dyn Error>> {
         let mut cache = self.server_cache.write().await;
+        let server = Arc::new(Upstream::single(server, self.thresholds));
 
         match cache.get_mut(mcp_name) {
             None => {
@@ -160,47 +354,328 @@ impl Cache {
 
     fn handle_event(&self, mut receiver: Receiver<Event>) {
         let cache = self.server_cache.clone();
+        let sync_changes = self.sync_changes.clone();
+        let cipher = self.cipher.clone();
+        let thresholds = self.thresholds;
         self.runtime.spawn(async move {
             while let Ok(event) = receiver.recv().await {
-                match event {
-                    Event::Delete { mcp_name, tag } => {
-                        let mut cache = cache.write().await;
-                        if let Some(tags) = cache.get_mut(&mcp_name) {
-                            tags.remove(tag.as_str());
-                        }
-                        tracing::info!("Remove mcp server {}/{} from cache", mcp_name, tag);
-                    }
-                    Event::CreateOrUpdate {
-                        mcp_name,
-                        tag,
-                        endpoint,
-                    } => {
-                        let server = parse_endpoint(endpoint.as_str())
-                            .map_err(|err| {
-                                tracing::error!("Failed to parse endpoint, error: {}", err);
-                            })
-                            .unwrap();
-
-                        let mut cache = cache.write().await;
-
-                        match cache.get_mut(&mcp_name) {
-                            None => {
-                                let mut tags = HashMap::new();
-                                tags.insert(tag.to_string(), server);
-                                cache.insert(mcp_name.to_string(), tags);
-                            }
-                            Some(tags) => {
-                                tags.insert(tag.to_string(), server);
-                            }
-                        };
-                        tracing::info!("update or create mcp server {}/{} success", mcp_name, tag);
+                apply_event(&cache, &sync_changes, cipher.as_deref(), thresholds, event).await;
+            }
+        });
+    }
+
+    /// Applies the changes of `tb_mcp_servers` committed by any instance. Changes missed
+    /// while the listener reconnects are caught up by a full sync.
+    fn listen_changes(&self) {
+        let cache = self.server_cache.clone();
+        let sync_changes = self.sync_changes.clone();
+        let db_client = self.db_client.clone();
+        let cipher = self.cipher.clone();
+        let thresholds = self.thresholds;
+        let resync = self.resync.clone();
+
+        self.runtime.spawn(async move {
+            let handler = McpDBHandler::new(db_client.clone());
+            let mut connected = false;
+            loop {
+                let mut listener = match McpServerListener::connect(&db_client).await {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        tracing::error!(
+                            "Can't listen to mcp server changes, retrying in {:?}, error: {}",
+                            LISTEN_RETRY_INTERVAL,
+                            err
+                        );
+                        sleep(LISTEN_RETRY_INTERVAL).await;
+                        continue;
                     }
+                };
+                tracing::info!("Listening to mcp server changes");
+                if connected {
+                    resync.notify_one();
+                }
+                connected = true;
+
+                loop {
+                    let change = match listener.recv().await {
+                        Ok(Some(change)) => change,
+                        Ok(None) => {
+                            tracing::warn!("Lost the connection listening to mcp server changes");
+                            resync.notify_one();
+                            continue;
+                        }
+                        Err(err) => {
+                            tracing::error!("Failed to receive mcp server changes, error: {}", err);
+                            break;
+                        }
+                    };
+
+                    // the row is read again, the notification only names it
+                    let event = match handler.get(&change.name, &change.tag).await {
+                        Ok(Some(server)) => Event::from(&server),
+                        Ok(None) => Event::Delete {
+                            mcp_name: change.name,
+                            tag: change.tag,
+                        },
+                        Err(err) => {
+                            tracing::error!(
+                                "Can't load changed mcp server {}/{}, error: {}",
+                                change.name,
+                                change.tag,
+                                err
+                            );
+                            resync.notify_one();
+                            continue;
+                        }
+                    };
+                    apply_event(&cache, &sync_changes, cipher.as_deref(), thresholds, event).await;
                 }
+                sleep(LISTEN_RETRY_INTERVAL).await;
             }
         });
     }
 }
-fn parse_endpoint(endpoint: &str) -> Result<McpServerInfo, Box<dyn Error>> {
+
+async fn apply_event(
+    cache: &RwLock<ServerCache>,
+    sync_changes: &Mutex<SyncChanges>,
+    cipher: Option<&CredentialCipher>,
+    thresholds: HealthThresholds,
+    event: Event,
+) {
+    match event {
+        Event::Delete { mcp_name, tag } => {
+            let mut cache = cache.write().await;
+            sync_changes.lock().unwrap().record(&mcp_name, &tag);
+            if let Some(tags) = cache.get_mut(&mcp_name)
+                && tags.remove(tag.as_str()).is_some()
+            {
+                tracing::info!("Remove mcp server {}/{} from cache", mcp_name, tag);
+            }
+        }
+        Event::CreateOrUpdate {
+            mcp_name,
+            tag,
+            endpoint,
+            transport_type,
+            extra,
+            credential,
+        } => {
+            let credential = decrypt_credential(cipher, &mcp_name, &tag, credential.as_deref());
+            let mut server = match parse_upstream(
+                endpoint.as_str(),
+                transport_type.as_str(),
+                extra,
+                credential,
+                thresholds,
+            ) {
+                Ok(upstream) => upstream,
+                Err(err) => {
+                    tracing::error!("Failed to parse endpoint, error: {}", err);
+                    return;
+                }
+            };
+
+            let mut cache = cache.write().await;
+            sync_changes.lock().unwrap().record(&mcp_name, &tag);
+            if let Some(previous) = cache.get(&mcp_name).and_then(|tags| tags.get(&tag)) {
+                // the same change arrives as event and as notification
+                if previous.as_ref() == &server {
+                    return;
+                }
+                server.inherit(previous);
+            }
+            cache
+                .entry(mcp_name.clone())
+                .or_insert_with(HashMap::new)
+                .insert(tag.clone(), Arc::new(server));
+            tracing::info!("update or create mcp server {}/{} success", mcp_name, tag);
+        }
+    }
+}
+
+/// Counts of a sync by what happened to the servers.
+#[derive(Debug, Default, PartialEq)]
+struct Reconciled {
+    added: usize,
+    updated: usize,
+    removed: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+/// Builds the cache holding exactly the desired servers. Unchanged servers keep their
+/// upstream, changed ones take over the health of their instances. A server which can't
+/// be parsed keeps its last upstream, or stays out of the cache. `changed` servers keep
+/// their cached state whatever `desired` holds.
+fn reconcile(
+    current: &ServerCache,
+    desired: Desired,
+    changed: &HashSet<(String, String)>,
+) -> (ServerCache, Reconciled) {
+    let mut next = ServerCache::new();
+    let mut reconciled = Reconciled::default();
+
+    for (name, tag) in changed {
+        if let Some(upstream) = current.get(name).and_then(|tags| tags.get(tag)) {
+            reconciled.unchanged += 1;
+            next.entry(name.clone())
+                .or_default()
+                .insert(tag.clone(), upstream.clone());
+        }
+    }
+
+    for ((name, tag), upstream) in desired {
+        if changed.contains(&(name.clone(), tag.clone())) {
+            continue;
+        }
+        let previous = current.get(&name).and_then(|tags| tags.get(&tag));
+        let upstream = match (upstream, previous) {
+            (Ok(upstream), Some(previous)) if previous.as_ref() == &upstream => {
+                reconciled.unchanged += 1;
+                previous.clone()
+            }
+            (Ok(mut upstream), Some(previous)) => {
+                upstream.inherit(previous);
+                reconciled.updated += 1;
+                tracing::info!("Update mcp server {}/{} in cache", name, tag);
+                Arc::new(upstream)
+            }
+            (Ok(upstream), None) => {
+                reconciled.added += 1;
+                tracing::info!("Load mcp server {}/{} success", name, tag);
+                Arc::new(upstream)
+            }
+            (Err(err), previous) => {
+                reconciled.failed += 1;
+                tracing::error!(
+                    "Failed to parse mcp server {}/{}, error: {}",
+                    name,
+                    tag,
+                    err
+                );
+                match previous {
+                    Some(previous) => previous.clone(),
+                    None => continue,
+                }
+            }
+        };
+        next.entry(name).or_default().insert(tag, upstream);
+    }
+
+    // evict servers which have been deleted or disabled since the last sync
+    for (name, tags) in current {
+        for tag in tags.keys() {
+            if !next.get(name).is_some_and(|tags| tags.contains_key(tag)) {
+                reconciled.removed += 1;
+                tracing::info!("Remove mcp server {}/{} from cache", name, tag);
+            }
+        }
+    }
+
+    (next, reconciled)
+}
+
+// a credential which can't be decrypted is dropped, the upstream then rejects the requests
+fn decrypt_credential(
+    cipher: Option<&CredentialCipher>,
+    name: &str,
+    tag: &str,
+    sealed: Option<&[u8]>,
+) -> Option<UpstreamCredential> {
+    let sealed = sealed?;
+    let Some(cipher) = cipher else {
+        tracing::error!(
+            "Can't decrypt credential of mcp server {}/{}, credential key is not configured",
+            name,
+            tag
+        );
+        return None;
+    };
+    cipher
+        .decrypt(name, tag, sealed)
+        .map_err(|err| {
+            tracing::error!(
+                "Failed to decrypt credential of mcp server {}/{}, error: {}",
+                name,
+                tag,
+                err
+            );
+        })
+        .ok()
+}
+
+/// Checks that a server can be proxied, with the same parsing the cache applies to its row.
+pub fn check_upstream(
+    endpoint: &str,
+    transport_type: &str,
+    extra: Option<&serde_json::Value>,
+) -> Result<(), String> {
+    parse_upstream(
+        endpoint,
+        transport_type,
+        extra.cloned(),
+        None,
+        HealthThresholds::default(),
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// the registry endpoint is the first instance, stdio servers run locally and have one
+fn parse_upstream(
+    endpoint: &str,
+    transport_type: &str,
+    extra: Option<serde_json::Value>,
+    credential: Option<UpstreamCredential>,
+    thresholds: HealthThresholds,
+) -> Result<Upstream, Box<dyn Error>> {
+    let primary = parse_endpoint(endpoint, transport_type, extra.clone(), credential.clone())?;
+    if primary.transport_type.is_stdio() {
+        return Ok(Upstream::single(primary, thresholds));
+    }
+
+    let config = LoadBalancing::from_extra(extra.as_ref())?;
+    let mut instances = vec![(primary, 1)];
+    for instance in config.instances {
+        if instance.endpoint == endpoint {
+            instances[0].1 = instance.weight;
+            continue;
+        }
+        let server = parse_endpoint(
+            &instance.endpoint,
+            transport_type,
+            extra.clone(),
+            credential.clone(),
+        )?;
+        instances.push((server, instance.weight));
+    }
+    Ok(Upstream::new(config.strategy, instances, thresholds))
+}
+
+fn parse_endpoint(
+    endpoint: &str,
+    transport_type: &str,
+    extra: Option<serde_json::Value>,
+    credential: Option<UpstreamCredential>,
+) -> Result<McpServerInfo, Box<dyn Error>> {
+    let transport_type = TransportType::from_str(transport_type)?;
+
+    // stdio servers are spawned locally from `extra`, there is no upstream address
+    if transport_type.is_stdio() {
+        StdioConfig::from_extra(extra.as_ref())?;
+        return Ok(McpServerInfo {
+            endpoint: endpoint.to_string(),
+            host: String::new(),
+            port: String::new(),
+            path: String::new(),
+            scheme: HttpScheme::Http,
+            transport_type,
+            extra,
+            credential,
+        });
+    }
+
     if let Some(caps) = REGEX_ENDPOINT.captures(endpoint) {
         let scheme = caps.name("scheme").map(|m| m.as_str()).unwrap_or("");
         let host = caps.name("host").map(|m| m.as_str()).unwrap_or("");
@@ -221,8 +696,165 @@ fn parse_endpoint(endpoint: &str) -> Result<McpServerInfo, Box<dyn Error>> {
             port: port.to_string(),
             path: path.to_string(),
             scheme: HttpScheme::from_str(scheme)?,
+            transport_type,
+            extra,
+            credential,
         })
     } else {
         Err(format!("Failed to parse endpoint {endpoint}").into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(endpoint: &str) -> Upstream {
+        parse_upstream(endpoint, "sse", None, None, HealthThresholds::default()).unwrap()
+    }
+
+    fn key(name: &str, tag: &str) -> (String, String) {
+        (name.to_string(), tag.to_string())
+    }
+
+    fn cached(servers: &[(&str, &str, &str)]) -> ServerCache {
+        let mut cache = ServerCache::new();
+        for (name, tag, endpoint) in servers {
+            cache
+                .entry(name.to_string())
+                .or_default()
+                .insert(tag.to_string(), Arc::new(upstream(endpoint)));
+        }
+        cache
+    }
+
+    fn tags(cache: &ServerCache) -> Vec<(String, String)> {
+        let mut tags: Vec<(String, String)> = cache
+            .iter()
+            .flat_map(|(name, tags)| tags.keys().map(|tag| key(name, tag)))
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let current = cached(&[
+            ("github", "1.0.0", "http://github:8080/sse"),
+            ("github", "2.0.0", "http://github2:8080/sse"),
+            ("files", "latest", "http://files:8080/sse"),
+            ("broken", "latest", "http://broken:8080/sse"),
+        ]);
+        let desired = Desired::from([
+            (
+                key("github", "1.0.0"),
+                Ok(upstream("http://github:8080/sse")),
+            ),
+            (
+                key("files", "latest"),
+                Ok(upstream("http://files:9090/sse")),
+            ),
+            (
+                key("broken", "latest"),
+                Err("Unsupported scheme".to_string()),
+            ),
+            (
+                key("invalid", "latest"),
+                Err("Unsupported scheme".to_string()),
+            ),
+            (
+                key("slack", "latest"),
+                Ok(upstream("http://slack:8080/sse")),
+            ),
+        ]);
+
+        let (next, reconciled) = reconcile(&current, desired, &HashSet::new());
+        assert_eq!(
+            reconciled,
+            Reconciled {
+                added: 1,
+                updated: 1,
+                removed: 1,
+                unchanged: 1,
+                failed: 2,
+            }
+        );
+        assert_eq!(
+            tags(&next),
+            vec![
+                key("broken", "latest"),
+                key("files", "latest"),
+                key("github", "1.0.0"),
+                key("slack", "latest"),
+            ]
+        );
+
+        // unchanged and unparsable servers keep their upstream, changed ones are replaced
+        let same = |name: &str, tag: &str| Arc::ptr_eq(&current[name][tag], &next[name][tag]);
+        assert!(same("github", "1.0.0"));
+        assert!(same("broken", "latest"));
+        assert!(!same("files", "latest"));
+    }
+
+    #[test]
+    fn test_reconcile_deleted() {
+        let current = cached(&[
+            ("github", "1.0.0", "http://github:8080/sse"),
+            ("files", "latest", "http://files:8080/sse"),
+        ]);
+
+        let (next, reconciled) = reconcile(&current, Desired::new(), &HashSet::new());
+        assert!(next.is_empty());
+        assert_eq!(reconciled.removed, 2);
+
+        let (next, reconciled) = reconcile(&next, Desired::new(), &HashSet::new());
+        assert!(next.is_empty());
+        assert_eq!(reconciled, Reconciled::default());
+    }
+
+    #[test]
+    fn test_reconcile_changed_during_read() {
+        let mut sync_changes = SyncChanges::default();
+        sync_changes.record("ignored", "latest");
+        sync_changes.start();
+        // read from the registry before "slack" was registered and "files" deleted
+        let desired = Desired::from([
+            (
+                key("github", "1.0.0"),
+                Ok(upstream("http://github:9090/sse")),
+            ),
+            (
+                key("files", "latest"),
+                Ok(upstream("http://files:8080/sse")),
+            ),
+        ]);
+        // the events of both changes are applied before the swap
+        let current = cached(&[
+            ("github", "1.0.0", "http://github:8080/sse"),
+            ("slack", "latest", "http://slack:8080/sse"),
+        ]);
+        sync_changes.record("slack", "latest");
+        sync_changes.record("files", "latest");
+
+        let changed = sync_changes.finish();
+        assert_eq!(
+            changed,
+            HashSet::from([key("slack", "latest"), key("files", "latest")])
+        );
+        let (next, reconciled) = reconcile(&current, desired, &changed);
+        assert_eq!(
+            tags(&next),
+            vec![key("github", "1.0.0"), key("slack", "latest")]
+        );
+        assert!(Arc::ptr_eq(
+            &current["slack"]["latest"],
+            &next["slack"]["latest"]
+        ));
+        assert_eq!(reconciled.updated, 1);
+        assert_eq!(reconciled.removed, 0);
+
+        // changes outside of a read are left to the next sync
+        sync_changes.record("slack", "latest");
+        assert!(sync_changes.finish().is_empty());
+    }
+}
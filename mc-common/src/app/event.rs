@@ -1,10 +1,36 @@
+use mc_db::model::McpServers;
+
 #[derive(Clone)]
 pub enum Event {
-    #[allow(dead_code)]
     Delete { mcp_name: String, tag: String },
     CreateOrUpdate {
         mcp_name: String,
         tag: String,
         endpoint: String,
+        transport_type: String,
+        extra: Option<serde_json::Value>,
+        /// The upstream credential, still encrypted.
+        credential: Option<Vec<u8>>,
     },
 }
+
+// disabled servers are dropped from the proxy cache, others are refreshed
+impl From<&McpServers> for Event {
+    fn from(server: &McpServers) -> Self {
+        if server.disabled {
+            Event::Delete {
+                mcp_name: server.name.clone(),
+                tag: server.tag.clone(),
+            }
+        } else {
+            Event::CreateOrUpdate {
+                mcp_name: server.name.clone(),
+                tag: server.tag.clone(),
+                endpoint: server.endpoint.clone(),
+                transport_type: server.transport_type.clone(),
+                extra: server.extra.clone(),
+                credential: server.credential.clone(),
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
+pub mod balancer;
 pub mod cache;
 pub mod event;
+pub mod stdio;
 
 use crate::app::cache::Cache;
 use crate::app::event::Event;
+use crate::credential::CredentialCipher;
 use axum::body::Body;
 use hyper_rustls::HttpsConnector;
 use hyper_util::client::legacy::Client;
@@ -18,6 +21,8 @@ pub struct AppState {
     pub event_sender: Sender<Event>,
     pub https_client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
     pub mcp_cache: Arc<Cache>,
+    /// Encrypts upstream credentials, `None` when no credential key is configured.
+    pub credential_cipher: Option<Arc<CredentialCipher>>,
     handler_manager: HandlerManager,
 }
 impl AppState {
@@ -26,6 +31,7 @@ impl AppState {
         event_sender: Sender<Event>,
         https_client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
         mcp_cache: Arc<Cache>,
+        credential_cipher: Option<Arc<CredentialCipher>>,
         handler_manager: HandlerManager,
     ) -> Self {
         Self {
@@ -33,6 +39,7 @@ impl AppState {
             event_sender,
             https_client,
             mcp_cache,
+            credential_cipher,
             handler_manager,
         }
     }
@@ -46,6 +53,11 @@ pub struct HandlerManager {
     pub mcp_handler: Option<Arc<mc_db::McpDBHandler>>,
     pub system_settings_handler: Option<Arc<mc_db::SystemSettingsDBHandler>>,
     pub api_keys_handler: Option<Arc<mc_db::ApiKeyDBHandler>>,
+    pub aggregate_handler: Option<Arc<mc_db::AggregateDBHandler>>,
+    pub policy_handler: Option<Arc<mc_db::PolicyDBHandler>>,
+    pub user_handler: Option<Arc<mc_db::UserDBHandler>>,
+    pub audit_handler: Option<Arc<mc_db::AuditDBHandler>>,
+    pub rate_limit_handler: Option<Arc<mc_db::RateLimitDBHandler>>,
     db: Arc<DBClient>,
 }
 
@@ -56,6 +68,11 @@ impl HandlerManager {
             mcp_handler: None,
             system_settings_handler: None,
             api_keys_handler: None,
+            aggregate_handler: None,
+            policy_handler: None,
+            user_handler: None,
+            audit_handler: None,
+            rate_limit_handler: None,
         }
     }
 
@@ -74,8 +91,37 @@ impl HandlerManager {
         self.api_keys_handler = Some(Arc::new(mc_db::ApiKeyDBHandler::new(self.db.clone())));
         self
     }
+
+    pub fn with_aggregate_handler(mut self) -> Self {
+        self.aggregate_handler = Some(Arc::new(mc_db::AggregateDBHandler::new(self.db.clone())));
+        self
+    }
+
+    pub fn with_policy_handler(mut self) -> Self {
+        self.policy_handler = Some(Arc::new(mc_db::PolicyDBHandler::new(self.db.clone())));
+        self
+    }
+
+    pub fn with_user_handler(mut self) -> Self {
+        self.user_handler = Some(Arc::new(mc_db::UserDBHandler::new(self.db.clone())));
+        self
+    }
+
+    pub fn with_audit_handler(mut self) -> Self {
+        self.audit_handler = Some(Arc::new(mc_db::AuditDBHandler::new(self.db.clone())));
+        self
+    }
+
+    pub fn with_rate_limit_handler(mut self) -> Self {
+        self.rate_limit_handler = Some(Arc::new(mc_db::RateLimitDBHandler::new(self.db.clone())));
+        self
+    }
 }
 
+/// Attached by the authorization middleware to requests of the admin token and admin users.
+#[derive(Clone, Copy, Debug)]
+pub struct Admin;
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Response {
     data: Option<serde_json::Value>,
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// a crashing process is restarted no faster than this, a zero backoff would never grow
+const MIN_RESTART_BACKOFF_MS: u64 = 100;
+
+/// Process configuration of a `stdio` server, read from the registry `extra` column.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StdioConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub mode: ProcessMode,
+    #[serde(default = "default_restart_backoff_ms")]
+    pub restart_backoff_ms: u64,
+    #[serde(default = "default_max_restart_backoff_ms")]
+    pub max_restart_backoff_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessMode {
+    /// One process per name/tag, shared by every session.
+    #[default]
+    Shared,
+    /// A dedicated process for each session, stopped when the session ends.
+    Session,
+}
+
+fn default_restart_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_restart_backoff_ms() -> u64 {
+    30_000
+}
+
+impl StdioConfig {
+    pub fn from_extra(extra: Option<&Value>) -> Result<Self, String> {
+        let extra = extra.ok_or_else(|| "stdio server requires `extra`".to_string())?;
+        let config: Self = serde_json::from_value(extra.clone())
+            .map_err(|err| format!("invalid stdio server config: {err}"))?;
+        config
+            .validate()
+            .map_err(|err| format!("invalid stdio server config: {err}"))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err("command must not be empty".to_string());
+        }
+        if self.restart_backoff_ms < MIN_RESTART_BACKOFF_MS {
+            return Err(format!(
+                "restart_backoff_ms must be at least {MIN_RESTART_BACKOFF_MS}"
+            ));
+        }
+        if self.max_restart_backoff_ms < self.restart_backoff_ms {
+            return Err(
+                "max_restart_backoff_ms must not be less than restart_backoff_ms".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_stdio_config_from_extra() {
+        let config = StdioConfig::from_extra(Some(&json!({
+            "command": "npx",
+            "args": ["-y", "@modelcontextprotocol/server-everything"],
+            "env": {"DEBUG": "1"},
+        })))
+        .unwrap();
+        assert_eq!(config.command, "npx");
+        assert_eq!(config.args.len(), 2);
+        assert_eq!(config.env.get("DEBUG"), Some(&"1".to_string()));
+        assert_eq!(config.working_dir, None);
+        assert_eq!(config.mode, ProcessMode::Shared);
+        assert_eq!(config.restart_backoff_ms, 500);
+        assert_eq!(config.max_restart_backoff_ms, 30_000);
+
+        let config = StdioConfig::from_extra(Some(&json!({
+            "command": "uvx",
+            "working_dir": "/tmp",
+            "mode": "session",
+        })))
+        .unwrap();
+        assert_eq!(config.working_dir, Some("/tmp".to_string()));
+        assert_eq!(config.mode, ProcessMode::Session);
+
+        assert!(StdioConfig::from_extra(None).is_err());
+        assert!(StdioConfig::from_extra(Some(&json!({"args": []}))).is_err());
+        assert!(StdioConfig::from_extra(Some(&json!({"command": "x", "mode": "pool"}))).is_err());
+
+        let config = StdioConfig::from_extra(Some(&json!({
+            "command": "npx",
+            "restart_backoff_ms": 100,
+            "max_restart_backoff_ms": 100,
+        })))
+        .unwrap();
+        assert_eq!(config.restart_backoff_ms, 100);
+
+        let invalid = [
+            (json!({"command": ""}), "command must not be empty"),
+            (json!({"command": "  "}), "command must not be empty"),
+            (
+                json!({"command": "npx", "restart_backoff_ms": 0}),
+                "restart_backoff_ms must be at least 100",
+            ),
+            (
+                json!({"command": "npx", "restart_backoff_ms": 99}),
+                "restart_backoff_ms must be at least 100",
+            ),
+            (
+                json!({"command": "npx", "restart_backoff_ms": 1000, "max_restart_backoff_ms": 500}),
+                "max_restart_backoff_ms must not be less than restart_backoff_ms",
+            ),
+            (
+                json!({"command": "npx", "max_restart_backoff_ms": 100}),
+                "max_restart_backoff_ms must not be less than restart_backoff_ms",
+            ),
+        ];
+        for (extra, want) in invalid {
+            assert_eq!(
+                StdioConfig::from_extra(Some(&extra)),
+                Err(format!("invalid stdio server config: {want}")),
+                "{extra}"
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Credential MCP Center presents to an upstream MCP server in place of the
+/// credentials of the client.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UpstreamCredential {
+    Header { name: String, value: String },
+    Bearer { token: String },
+    Basic { username: String, password: String },
+}
+
+// secrets must not end up in logs
+impl fmt::Debug for UpstreamCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamCredential::Header { name, .. } => {
+                write!(f, "Header {{ name: {name:?}, value: \"***\" }}")
+            }
+            UpstreamCredential::Bearer { .. } => write!(f, "Bearer {{ token: \"***\" }}"),
+            UpstreamCredential::Basic { username, .. } => {
+                write!(f, "Basic {{ username: {username:?}, password: \"***\" }}")
+            }
+        }
+    }
+}
+
+impl UpstreamCredential {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UpstreamCredential::Header { .. } => "header",
+            UpstreamCredential::Bearer { .. } => "bearer",
+            UpstreamCredential::Basic { .. } => "basic",
+        }
+    }
+
+    /// The header the credential is sent in.
+    pub fn header(&self) -> Result<(HeaderName, HeaderValue), String> {
+        let (name, value) = match self {
+            UpstreamCredential::Header { name, value } => (
+                HeaderName::try_from(name.as_str())
+                    .map_err(|err| format!("Invalid header name {name}: {err}"))?,
+                value.clone(),
+            ),
+            UpstreamCredential::Bearer { token } => (AUTHORIZATION, format!("Bearer {token}")),
+            UpstreamCredential::Basic { username, password } => (
+                AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{username}:{password}"))
+                ),
+            ),
+        };
+        let mut value = HeaderValue::from_str(&value)
+            .map_err(|err| format!("Invalid value of header {name}: {err}"))?;
+        value.set_sensitive(true);
+        Ok((name, value))
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        match self.header() {
+            Ok((name, value)) => {
+                headers.insert(name, value);
+            }
+            Err(err) => tracing::error!("Failed to apply upstream credential, error {err}"),
+        }
+    }
+}
+
+/// Encrypts upstream credentials at rest with AES-256-GCM. The server name
+/// and tag are authenticated along, a credential can't be moved to another server.
+pub struct CredentialCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl CredentialCipher {
+    /// `key` is the base64 encoding of 32 random bytes.
+    pub fn new(key: &str) -> Result<Self, String> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|err| format!("Invalid credential key: {err}"))?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| "Credential key must be 32 bytes".to_string())?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Returns the nonce followed by the sealed credential.
+    pub fn encrypt(
+        &self,
+        name: &str,
+        tag: &str,
+        credential: &UpstreamCredential,
+    ) -> Result<Vec<u8>, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| "Failed to generate nonce".to_string())?;
+
+        let mut data = serde_json::to_vec(credential).map_err(|err| err.to_string())?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad(name, tag)),
+                &mut data,
+            )
+            .map_err(|_| "Failed to encrypt credential".to_string())?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        Ok(sealed)
+    }
+
+    pub fn decrypt(
+        &self,
+        name: &str,
+        tag: &str,
+        sealed: &[u8],
+    ) -> Result<UpstreamCredential, String> {
+        if sealed.len() < NONCE_LEN {
+            return Err("Encrypted credential is too short".to_string());
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+
+        let mut data = data.to_vec();
+        let plain = self
+            .key
+            .open_in_place(nonce, Aad::from(aad(name, tag)), &mut data)
+            .map_err(|_| "Failed to decrypt credential".to_string())?;
+        serde_json::from_slice(plain).map_err(|err| err.to_string())
+    }
+}
+
+fn aad(name: &str, tag: &str) -> Vec<u8> {
+    format!("{name}/{tag}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_cipher() {
+        let cipher = CredentialCipher::new(KEY).unwrap();
+        let credential = UpstreamCredential::Bearer {
+            token: "secret".to_string(),
+        };
+
+        let sealed = cipher.encrypt("github", "1.0.0", &credential).unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("secret"));
+        assert_ne!(
+            sealed,
+            cipher.encrypt("github", "1.0.0", &credential).unwrap()
+        );
+        assert_eq!(
+            cipher.decrypt("github", "1.0.0", &sealed).unwrap(),
+            credential
+        );
+
+        // bound to the server
+        assert!(cipher.decrypt("github", "2.0.0", &sealed).is_err());
+        // and to the key
+        let other = CredentialCipher::new(&STANDARD.encode([7u8; 32])).unwrap();
+        assert!(other.decrypt("github", "1.0.0", &sealed).is_err());
+
+        assert!(CredentialCipher::new("c2hvcnQ=").is_err());
+        assert!(cipher.decrypt("github", "1.0.0", &sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let cases = [
+            (
+                r#"{"type": "header", "name": "X-Api-Key", "value": "secret"}"#,
+                ("x-api-key", "secret"),
+            ),
+            (
+                r#"{"type": "bearer", "token": "secret"}"#,
+                ("authorization", "Bearer secret"),
+            ),
+            (
+                r#"{"type": "basic", "username": "user", "password": "pass"}"#,
+                ("authorization", "Basic dXNlcjpwYXNz"),
+            ),
+        ];
+
+        for (input, (name, value)) in cases {
+            let credential: UpstreamCredential = serde_json::from_str(input).unwrap();
+            let (header, header_value) = credential.header().unwrap();
+            assert_eq!(header.as_str(), name, "{input}");
+            assert_eq!(header_value.to_str().unwrap(), value, "{input}");
+            assert!(!format!("{credential:?}").contains("secret"));
+            assert!(!format!("{credential:?}").contains("pass\""));
+        }
+
+        let invalid = UpstreamCredential::Header {
+            name: "bad header".to_string(),
+            value: "secret".to_string(),
+        };
+        assert!(invalid.header().is_err());
+    }
+}
@@ -1,5 +1,7 @@
 extern crate core;
 
 pub mod app;
+pub mod credential;
+pub mod metrics;
 pub mod router;
 pub mod types;
@@ -0,0 +1,264 @@
+use crate::app::AppState;
+use crate::router::RouterHandler;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use mc_db::DBClient;
+use once_cell::sync::Lazy;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TEXT_FORMAT, TextEncoder,
+};
+use std::time::Instant;
+
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Metrics of MCP Center, all prefixed with `mcp_center_`.
+static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    Registry::new_custom(Some("mcp_center".to_string()), None).expect("the metric prefix is valid")
+});
+
+static PROXY_CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "proxy_connections_total",
+            "Requests to the connect endpoint",
+        ),
+        &["name", "tag", "transport"],
+    ))
+});
+
+static SSE_SESSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "sse_sessions_active",
+            "Open SSE streams of the SSE transport",
+        ),
+        &["name", "tag"],
+    ))
+});
+
+static MESSAGE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "message_duration_seconds",
+            "Time until the response headers of message posts",
+        ),
+        &["name", "tag", "status"],
+    ))
+});
+
+static UPSTREAM_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "upstream_errors_total",
+            "Failed requests to upstream servers",
+        ),
+        &["name", "tag", "kind"],
+    ))
+});
+
+static CACHE_SYNC_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register(Histogram::with_opts(HistogramOpts::new(
+        "cache_sync_duration_seconds",
+        "Duration of the syncs of the server cache from the database",
+    )))
+});
+
+static CACHE_SYNCS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("cache_syncs_total", "Syncs of the server cache by result"),
+        &["result"],
+    ))
+});
+
+static CACHE_SYNC_SERVERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "cache_sync_servers_total",
+            "Servers updated in or evicted from the cache by syncs",
+        ),
+        &["change"],
+    ))
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Connections of the database pool"),
+        &["state"],
+    ))
+});
+
+static AUTH_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("auth_failures_total", "Rejected requests by reason"),
+        &["reason"],
+    ))
+});
+
+static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "rate_limited_total",
+            "Proxied requests rejected by rate limits",
+        ),
+        &["scope", "kind"],
+    ))
+});
+
+fn register<T: Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric is registered once");
+    metric
+}
+
+pub fn proxy_connection(name: &str, tag: &str, transport: &str) {
+    PROXY_CONNECTIONS
+        .with_label_values(&[name, tag, transport])
+        .inc();
+}
+
+pub fn message(name: &str, tag: &str, status: u16, started: Instant) {
+    MESSAGE_DURATION
+        .with_label_values(&[name, tag, &status.to_string()])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+pub fn upstream_error(name: &str, tag: &str, kind: &str) {
+    UPSTREAM_ERRORS.with_label_values(&[name, tag, kind]).inc();
+}
+
+pub fn cache_sync(started: Instant, updated: usize, evicted: usize) {
+    CACHE_SYNC_DURATION.observe(started.elapsed().as_secs_f64());
+    CACHE_SYNCS.with_label_values(&["ok"]).inc();
+    CACHE_SYNC_SERVERS
+        .with_label_values(&["updated"])
+        .inc_by(updated as u64);
+    CACHE_SYNC_SERVERS
+        .with_label_values(&["evicted"])
+        .inc_by(evicted as u64);
+}
+
+pub fn cache_sync_failed(started: Instant) {
+    CACHE_SYNC_DURATION.observe(started.elapsed().as_secs_f64());
+    CACHE_SYNCS.with_label_values(&["error"]).inc();
+}
+
+pub fn auth_failure(reason: &str) {
+    AUTH_FAILURES.with_label_values(&[reason]).inc();
+}
+
+pub fn rate_limited(scope: &str, kind: &str) {
+    RATE_LIMITED.with_label_values(&[scope, kind]).inc();
+}
+
+/// Counts an open SSE stream until dropped.
+pub struct SseSession {
+    name: String,
+    tag: String,
+}
+
+impl SseSession {
+    pub fn new(name: &str, tag: &str) -> Self {
+        SSE_SESSIONS.with_label_values(&[name, tag]).inc();
+        Self {
+            name: name.to_string(),
+            tag: tag.to_string(),
+        }
+    }
+}
+
+impl Drop for SseSession {
+    fn drop(&mut self) {
+        SSE_SESSIONS
+            .with_label_values(&[&self.name, &self.tag])
+            .dec();
+    }
+}
+
+/// Encodes all metrics in the Prometheus text format, sampling the database pool first.
+pub fn gather(db_client: &DBClient) -> Result<String, String> {
+    let size = db_client.pool.size() as i64;
+    let idle = db_client.pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(size - idle);
+
+    // metrics appear before their first sample
+    Lazy::force(&PROXY_CONNECTIONS);
+    Lazy::force(&SSE_SESSIONS);
+    Lazy::force(&MESSAGE_DURATION);
+    Lazy::force(&UPSTREAM_ERRORS);
+    Lazy::force(&CACHE_SYNC_DURATION);
+    Lazy::force(&CACHE_SYNCS);
+    Lazy::force(&CACHE_SYNC_SERVERS);
+    Lazy::force(&AUTH_FAILURES);
+    Lazy::force(&RATE_LIMITED);
+
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .map_err(|err| err.to_string())?;
+    String::from_utf8(buffer).map_err(|err| err.to_string())
+}
+
+pub fn register_router() -> RouterHandler<AppState> {
+    Box::new(|router| router.route(METRICS_PATH, get(serve)))
+}
+
+async fn serve(State(state): State<AppState>) -> Result<Response, (StatusCode, String)> {
+    let body = gather(&state.db).map_err(|err| {
+        tracing::error!("Failed to encode metrics, error: {}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to encode metrics".to_string(),
+        )
+    })?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        proxy_connection("github", "1.0.0", "sse");
+        upstream_error("github", "1.0.0", "timeout");
+        auth_failure("invalid_api_key");
+        rate_limited("api_key", "message");
+        message("github", "1.0.0", 202, Instant::now());
+        {
+            let _session = SseSession::new("github", "1.0.0");
+            assert_eq!(
+                SSE_SESSIONS.with_label_values(&["github", "1.0.0"]).get(),
+                1
+            );
+        }
+        assert_eq!(
+            SSE_SESSIONS.with_label_values(&["github", "1.0.0"]).get(),
+            0
+        );
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&REGISTRY.gather(), &mut buffer)
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        for line in [
+            r#"mcp_center_proxy_connections_total{name="github",tag="1.0.0",transport="sse"} 1"#,
+            r#"mcp_center_upstream_errors_total{kind="timeout",name="github",tag="1.0.0"} 1"#,
+            r#"mcp_center_auth_failures_total{reason="invalid_api_key"} 1"#,
+            r#"mcp_center_rate_limited_total{kind="message",scope="api_key"} 1"#,
+            r#"mcp_center_message_duration_seconds_count{name="github",status="202",tag="1.0.0"} 1"#,
+        ] {
+            assert!(text.contains(line), "{line}\n{text}");
+        }
+    }
+}
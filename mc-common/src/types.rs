@@ -34,3 +34,44 @@ impl HttpScheme {
         self == &HttpScheme::Https
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransportType {
+    Sse,
+    Streamable,
+    Stdio,
+}
+
+impl FromStr for TransportType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sse" => Ok(TransportType::Sse),
+            "streamable" | "streamable-http" | "streamable_http" => Ok(TransportType::Streamable),
+            "stdio" => Ok(TransportType::Stdio),
+            _ => Err(format!("Unknown transport type: {s}")),
+        }
+    }
+}
+
+impl TransportType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportType::Sse => "sse",
+            TransportType::Streamable => "streamable",
+            TransportType::Stdio => "stdio",
+        }
+    }
+
+    pub fn is_sse(&self) -> bool {
+        self == &TransportType::Sse
+    }
+
+    pub fn is_streamable(&self) -> bool {
+        self == &TransportType::Streamable
+    }
+
+    pub fn is_stdio(&self) -> bool {
+        self == &TransportType::Stdio
+    }
+}
@@ -0,0 +1,82 @@
+use crate::DBClient;
+use crate::model::McpAggregates;
+use std::sync::Arc;
+
+pub struct AggregateDBHandler {
+    client: Arc<DBClient>,
+}
+
+impl AggregateDBHandler {
+    pub fn new(client: Arc<DBClient>) -> Self {
+        AggregateDBHandler { client }
+    }
+
+    pub async fn list_all(&self) -> Result<Vec<McpAggregates>, sqlx::Error> {
+        sqlx::query_as::<_, McpAggregates>(
+            "SELECT * FROM tb_mcp_aggregates WHERE deleted_at IS NULL ORDER BY name",
+        )
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, name: &str) -> Result<Option<McpAggregates>, sqlx::Error> {
+        sqlx::query_as::<_, McpAggregates>(
+            "SELECT * FROM tb_mcp_aggregates WHERE name = $1 AND deleted_at IS NULL",
+        )
+        .bind(name)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn create(&self, aggregate: &McpAggregates) -> Result<McpAggregates, sqlx::Error> {
+        sqlx::query_as::<_, McpAggregates>(
+            r#"
+        INSERT INTO tb_mcp_aggregates
+            (id, name, description, members)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+        )
+        .bind(aggregate.id)
+        .bind(&aggregate.name)
+        .bind(&aggregate.description)
+        .bind(&aggregate.members)
+        .fetch_one(&self.client.pool)
+        .await
+    }
+
+    pub async fn update(
+        &self,
+        aggregate: &McpAggregates,
+    ) -> Result<Option<McpAggregates>, sqlx::Error> {
+        sqlx::query_as::<_, McpAggregates>(
+            r#"
+        UPDATE tb_mcp_aggregates
+        SET description = $2,
+            members = $3
+        WHERE name = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(&aggregate.name)
+        .bind(&aggregate.description)
+        .bind(&aggregate.members)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Soft deletes the aggregate by setting `deleted_at`.
+    pub async fn delete(&self, name: &str) -> Result<Option<McpAggregates>, sqlx::Error> {
+        sqlx::query_as::<_, McpAggregates>(
+            r#"
+        UPDATE tb_mcp_aggregates
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE name = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+}
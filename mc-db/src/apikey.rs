@@ -1,5 +1,12 @@
 use crate::{DBClient, model};
+use chrono::NaiveDateTime;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use uuid::Uuid;
+
+const API_KEY_PREFIX: &str = "mck_";
+// characters of the key kept to display it
+const DISPLAY_PREFIX_LEN: usize = 12;
 
 pub struct ApiKeyDBHandler {
     client: Arc<DBClient>,
@@ -10,27 +17,167 @@ impl ApiKeyDBHandler {
         ApiKeyDBHandler { client }
     }
 
-    pub async fn create(&self, name: &str) -> Result<model::ApiKeys, sqlx::Error> {
+    /// Creates a key, the generated key is only returned here and never stored.
+    pub async fn create(
+        &self,
+        name: &str,
+        scopes: &[String],
+        expires_at: Option<NaiveDateTime>,
+    ) -> Result<Option<(model::ApiKeys, String)>, sqlx::Error> {
+        let key = generate_api_key();
         let api_key = sqlx::query_as::<_, model::ApiKeys>(
             r#"
         INSERT INTO tb_api_keys
-            (name)
-        VALUES ($1)
+            (name, key_hash, key_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) WHERE deleted_at IS NULL DO NOTHING
         RETURNING *
         "#,
         )
         .bind(name)
-        .fetch_one(&self.client.pool)
+        .bind(hash_api_key(&key))
+        .bind(display_prefix(&key))
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_optional(&self.client.pool)
         .await?;
-        Ok(api_key)
+        Ok(api_key.map(|api_key| (api_key, key)))
     }
 
+    /// Finds the active key, revoked and expired keys are not found.
     pub async fn find(&self, api_key: &str) -> Result<model::ApiKeys, sqlx::Error> {
-        let api_key =
-            sqlx::query_as::<_, model::ApiKeys>(r#"SELECT * FROM tb_api_keys WHERE apikey = $1"#)
-                .bind(api_key)
-                .fetch_one(&self.client.pool)
-                .await?;
+        let api_key = sqlx::query_as::<_, model::ApiKeys>(
+            r#"
+        SELECT * FROM tb_api_keys
+        WHERE key_hash = $1
+          AND deleted_at IS NULL
+          AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        "#,
+        )
+        .bind(hash_api_key(api_key))
+        .fetch_one(&self.client.pool)
+        .await?;
         Ok(api_key)
     }
+
+    /// The active key of a name, revoked keys free their names.
+    pub async fn find_by_name(&self, name: &str) -> Result<Option<model::ApiKeys>, sqlx::Error> {
+        sqlx::query_as::<_, model::ApiKeys>(
+            "SELECT * FROM tb_api_keys WHERE name = $1 AND deleted_at IS NULL",
+        )
+        .bind(name)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn list(&self, include_revoked: bool) -> Result<Vec<model::ApiKeys>, sqlx::Error> {
+        sqlx::query_as::<_, model::ApiKeys>(
+            r#"
+        SELECT * FROM tb_api_keys
+        WHERE $1 OR deleted_at IS NULL
+        ORDER BY created_at
+        "#,
+        )
+        .bind(include_revoked)
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<model::ApiKeys>, sqlx::Error> {
+        sqlx::query_as::<_, model::ApiKeys>("SELECT * FROM tb_api_keys WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.client.pool)
+            .await
+    }
+
+    /// Replaces the key of an active record, the previous key stops working at once.
+    pub async fn rotate(&self, id: Uuid) -> Result<Option<(model::ApiKeys, String)>, sqlx::Error> {
+        let key = generate_api_key();
+        let api_key = sqlx::query_as::<_, model::ApiKeys>(
+            r#"
+        UPDATE tb_api_keys
+        SET key_hash = $2,
+            key_prefix = $3,
+            last_used_at = NULL
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(id)
+        .bind(hash_api_key(&key))
+        .bind(display_prefix(&key))
+        .fetch_optional(&self.client.pool)
+        .await?;
+        Ok(api_key.map(|api_key| (api_key, key)))
+    }
+
+    /// Revokes the key by setting `deleted_at`.
+    pub async fn revoke(&self, id: Uuid) -> Result<Option<model::ApiKeys>, sqlx::Error> {
+        sqlx::query_as::<_, model::ApiKeys>(
+            r#"
+        UPDATE tb_api_keys
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Records the use of a key, at most once a minute to spare writes on busy keys.
+    pub async fn touch(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+        UPDATE tb_api_keys
+        SET last_used_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+          AND (last_used_at IS NULL OR last_used_at < CURRENT_TIMESTAMP - INTERVAL '1 minute')
+        "#,
+        )
+        .bind(id)
+        .execute(&self.client.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+fn generate_api_key() -> String {
+    format!("{API_KEY_PREFIX}{}", Uuid::new_v4().simple())
+}
+
+/// SHA-256 hex digest under which a key is stored.
+pub fn hash_api_key(api_key: &str) -> String {
+    format!("{:x}", Sha256::digest(api_key.as_bytes()))
+}
+
+fn display_prefix(api_key: &str) -> String {
+    api_key.chars().take(DISPLAY_PREFIX_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_api_key() {
+        assert_eq!(
+            hash_api_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(hash_api_key("abc"), hash_api_key("abd"));
+    }
+
+    #[test]
+    fn test_generate_api_key() {
+        let key = generate_api_key();
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(key.len(), API_KEY_PREFIX.len() + 32);
+        assert_ne!(key, generate_api_key());
+
+        let prefix = display_prefix(&key);
+        assert_eq!(prefix.len(), DISPLAY_PREFIX_LEN);
+        assert!(key.starts_with(&prefix));
+    }
 }
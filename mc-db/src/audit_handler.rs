@@ -0,0 +1,109 @@
+use crate::DBClient;
+use crate::model::AuditLogs;
+use chrono::NaiveDateTime;
+use std::sync::Arc;
+
+/// Narrows the listed entries, unset fields match every entry.
+#[derive(Debug, Default, Clone)]
+pub struct AuditListFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub server_name: Option<String>,
+    pub server_tag: Option<String>,
+    pub tool: Option<String>,
+    /// Entries created at or after.
+    pub since: Option<NaiveDateTime>,
+    /// Entries created before.
+    pub until: Option<NaiveDateTime>,
+}
+
+const FILTER_CONDITION: &str = r#"
+        WHERE ($1::TEXT IS NULL OR actor = $1)
+          AND ($2::TEXT IS NULL OR action = $2)
+          AND ($3::TEXT IS NULL OR server_name = $3)
+          AND ($4::TEXT IS NULL OR server_tag = $4)
+          AND ($5::TEXT IS NULL OR tool = $5)
+          AND ($6::TIMESTAMP IS NULL OR created_at >= $6)
+          AND ($7::TIMESTAMP IS NULL OR created_at < $7)
+        "#;
+
+pub struct AuditDBHandler {
+    client: Arc<DBClient>,
+}
+
+impl AuditDBHandler {
+    pub fn new(client: Arc<DBClient>) -> Self {
+        AuditDBHandler { client }
+    }
+
+    pub async fn insert(&self, entry: &AuditLogs) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO tb_audit_logs
+            (id, actor_type, actor, action, server_name, server_tag, method, tool, path,
+             arguments, status, error_code, error, latency_ms, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+        "#,
+        )
+        .bind(entry.id)
+        .bind(&entry.actor_type)
+        .bind(&entry.actor)
+        .bind(&entry.action)
+        .bind(&entry.server_name)
+        .bind(&entry.server_tag)
+        .bind(&entry.method)
+        .bind(&entry.tool)
+        .bind(&entry.path)
+        .bind(&entry.arguments)
+        .bind(entry.status)
+        .bind(entry.error_code)
+        .bind(&entry.error)
+        .bind(entry.latency_ms)
+        .bind(entry.created_at)
+        .execute(&self.client.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Lists the newest entries first.
+    pub async fn list_with_limit(
+        &self,
+        filter: &AuditListFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditLogs>, sqlx::Error> {
+        sqlx::query_as::<_, AuditLogs>(
+            format!(
+                "SELECT * FROM tb_audit_logs {FILTER_CONDITION} ORDER BY created_at DESC, id LIMIT $8 OFFSET $9"
+            )
+            .as_str(),
+        )
+        .bind(&filter.actor)
+        .bind(&filter.action)
+        .bind(&filter.server_name)
+        .bind(&filter.server_tag)
+        .bind(&filter.tool)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn count(&self, filter: &AuditListFilter) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            format!("SELECT COUNT(*) FROM tb_audit_logs {FILTER_CONDITION}").as_str(),
+        )
+        .bind(&filter.actor)
+        .bind(&filter.action)
+        .bind(&filter.server_name)
+        .bind(&filter.server_tag)
+        .bind(&filter.tool)
+        .bind(filter.since)
+        .bind(filter.until)
+        .fetch_one(&self.client.pool)
+        .await?;
+        Ok(count)
+    }
+}
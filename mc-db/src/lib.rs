@@ -1,11 +1,23 @@
+mod aggregate_handler;
 mod apikey;
+mod audit_handler;
 mod mcp_handler;
+mod mcp_listener;
 pub mod model;
+mod policy_handler;
+mod rate_limit_handler;
 mod settings_handler;
+mod user_handler;
 
+pub use aggregate_handler::*;
 pub use apikey::*;
+pub use audit_handler::*;
 pub use mcp_handler::*;
+pub use mcp_listener::*;
+pub use policy_handler::*;
+pub use rate_limit_handler::*;
 pub use settings_handler::*;
+pub use user_handler::*;
 
 use sqlx::migrate::Migrator;
 use sqlx::postgres::PgPoolOptions;
@@ -2,6 +2,25 @@ use crate::DBClient;
 use crate::model::McpServers;
 use std::sync::Arc;
 
+/// Selects which rows the list queries return. The default only returns servers that
+/// can be proxied, i.e. neither soft-deleted nor disabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct McpListFilter {
+    pub include_deleted: bool,
+    pub include_disabled: bool,
+}
+
+impl McpListFilter {
+    fn condition(&self) -> &'static str {
+        match (self.include_deleted, self.include_disabled) {
+            (false, false) => "WHERE deleted_at IS NULL AND disabled = false",
+            (false, true) => "WHERE deleted_at IS NULL",
+            (true, false) => "WHERE disabled = false",
+            (true, true) => "",
+        }
+    }
+}
+
 pub struct McpDBHandler {
     client: Arc<DBClient>,
 }
@@ -11,19 +30,30 @@ impl McpDBHandler {
         McpDBHandler { client }
     }
 
-    pub async fn list_all(&self) -> Result<Vec<McpServers>, sqlx::Error> {
-        sqlx::query_as::<_, McpServers>("SELECT * FROM tb_mcp_servers ORDER BY id")
-            .fetch_all(&self.client.pool)
-            .await
+    pub async fn list_all(&self, filter: McpListFilter) -> Result<Vec<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            format!(
+                "SELECT * FROM tb_mcp_servers {} ORDER BY id",
+                filter.condition()
+            )
+            .as_str(),
+        )
+        .fetch_all(&self.client.pool)
+        .await
     }
 
     pub async fn list_with_limit(
         &self,
+        filter: McpListFilter,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<McpServers>, sqlx::Error> {
         sqlx::query_as::<_, McpServers>(
-            "SELECT * FROM tb_mcp_servers ORDER BY id LIMIT $1 OFFSET $2",
+            format!(
+                "SELECT * FROM tb_mcp_servers {} ORDER BY id LIMIT $1 OFFSET $2",
+                filter.condition()
+            )
+            .as_str(),
         )
         .bind(limit)
         .bind(offset)
@@ -31,10 +61,12 @@ impl McpDBHandler {
         .await
     }
 
-    pub async fn count(&self) -> Result<i64, sqlx::Error> {
-        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM tb_mcp_servers")
-            .fetch_one(&self.client.pool)
-            .await?;
+    pub async fn count(&self, filter: McpListFilter) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            format!("SELECT COUNT(*) FROM tb_mcp_servers {}", filter.condition()).as_str(),
+        )
+        .fetch_one(&self.client.pool)
+        .await?;
         Ok(count)
     }
 
@@ -80,4 +112,144 @@ impl McpDBHandler {
 
         Ok(res)
     }
+
+    pub async fn list_by_create_from(
+        &self,
+        create_from: &str,
+    ) -> Result<Vec<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            "SELECT * FROM tb_mcp_servers WHERE create_from = $1 AND deleted_at IS NULL ORDER BY id",
+        )
+        .bind(create_from)
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    /// Inserts the server, or updates the live row with the same name and tag if it was
+    /// created from the same source. Returns `None` when the name and tag are owned by
+    /// a row created from another source, which is left untouched.
+    pub async fn upsert(&self, server: &McpServers) -> Result<Option<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            r#"
+        INSERT INTO tb_mcp_servers
+            (id, name, tag, endpoint, transport_type, description, create_from, extra)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE($8, '{}'::jsonb))
+        ON CONFLICT (name, tag) WHERE deleted_at IS NULL
+        DO UPDATE SET
+            endpoint = EXCLUDED.endpoint,
+            transport_type = EXCLUDED.transport_type,
+            description = EXCLUDED.description,
+            extra = EXCLUDED.extra
+        WHERE tb_mcp_servers.create_from = EXCLUDED.create_from
+        RETURNING *
+        "#,
+        )
+        .bind(server.id)
+        .bind(&server.name)
+        .bind(&server.tag)
+        .bind(&server.endpoint)
+        .bind(&server.transport_type)
+        .bind(&server.description)
+        .bind(&server.create_from)
+        .bind(&server.extra)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Soft deletes the live row with the given name and tag by setting `deleted_at`.
+    pub async fn delete(&self, name: &str, tag: &str) -> Result<Option<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            r#"
+        UPDATE tb_mcp_servers
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE name = $1 AND tag = $2 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(name)
+        .bind(tag)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, name: &str, tag: &str) -> Result<Option<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            "SELECT * FROM tb_mcp_servers WHERE name = $1 AND tag = $2 AND deleted_at IS NULL",
+        )
+        .bind(name)
+        .bind(tag)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Updates the mutable fields of the live row identified by the server's name and tag.
+    pub async fn update(&self, server: &McpServers) -> Result<Option<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            r#"
+        UPDATE tb_mcp_servers
+        SET endpoint = $3,
+            transport_type = $4,
+            description = $5,
+            extra = COALESCE($6, '{}'::jsonb),
+            disabled = $7
+        WHERE name = $1 AND tag = $2 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(&server.name)
+        .bind(&server.tag)
+        .bind(&server.endpoint)
+        .bind(&server.transport_type)
+        .bind(&server.description)
+        .bind(&server.extra)
+        .bind(server.disabled)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn set_disabled(
+        &self,
+        name: &str,
+        tag: &str,
+        disabled: bool,
+    ) -> Result<Option<McpServers>, sqlx::Error> {
+        sqlx::query_as::<_, McpServers>(
+            r#"
+        UPDATE tb_mcp_servers
+        SET disabled = $3
+        WHERE name = $1 AND tag = $2 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(name)
+        .bind(tag)
+        .bind(disabled)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Replaces the encrypted upstream credential of the live row, `None` removes it.
+    pub async fn set_credential(
+        &self,
+        name: &str,
+        tag: &str,
+        credential: Option<(&str, &[u8])>,
+    ) -> Result<Option<McpServers>, sqlx::Error> {
+        let (credential_type, credential) = credential.unzip();
+        sqlx::query_as::<_, McpServers>(
+            r#"
+        UPDATE tb_mcp_servers
+        SET credential_type = $3,
+            credential = $4
+        WHERE name = $1 AND tag = $2 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(name)
+        .bind(tag)
+        .bind(credential_type)
+        .bind(credential)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
 }
@@ -0,0 +1,65 @@
+use crate::DBClient;
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+
+/// Channel notified by the trigger of `tb_mcp_servers`.
+pub const MCP_SERVER_CHANNEL: &str = "mcp_server_changed";
+
+/// A row of `tb_mcp_servers` which was inserted, updated or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct McpServerChange {
+    pub name: String,
+    pub tag: String,
+}
+
+/// Receives the changes of `tb_mcp_servers` committed by any mcp-center instance.
+pub struct McpServerListener {
+    listener: PgListener,
+}
+
+impl McpServerListener {
+    pub async fn connect(client: &DBClient) -> Result<Self, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&client.pool).await?;
+        listener.listen(MCP_SERVER_CHANNEL).await?;
+        Ok(Self { listener })
+    }
+
+    /// Waits for the next change. `None` means the connection was lost, the next call
+    /// reconnects and the changes in between are missed. Payloads which don't name a row
+    /// were not sent by the trigger and are skipped.
+    pub async fn recv(&mut self) -> Result<Option<McpServerChange>, sqlx::Error> {
+        loop {
+            match self.listener.try_recv().await? {
+                None => return Ok(None),
+                Some(notification) => {
+                    if let Ok(change) = parse_change(notification.payload()) {
+                        return Ok(Some(change));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_change(payload: &str) -> Result<McpServerChange, serde_json::Error> {
+    serde_json::from_str(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_change() {
+        // the payload built by notify_mcp_server_changed()
+        let change = parse_change(r#"{"name" : "github", "tag" : "1.0.0"}"#).unwrap();
+        assert_eq!(
+            change,
+            McpServerChange {
+                name: "github".to_string(),
+                tag: "1.0.0".to_string(),
+            }
+        );
+        assert!(parse_change(r#"{"name" : "github"}"#).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Separates the member namespace from the tool or prompt name, e.g. `github__create_issue`.
+pub const NAMESPACE_SEPARATOR: &str = "__";
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct McpAggregates {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub members: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+impl McpAggregates {
+    pub fn members(&self) -> Result<Vec<AggregateMember>, serde_json::Error> {
+        serde_json::from_value(self.members.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateMember {
+    pub name: String,
+    pub tag: String,
+    /// Prefix of the member's tools and prompts, defaults to the server name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl AggregateMember {
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or(self.name.as_str())
+    }
+}
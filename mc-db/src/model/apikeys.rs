@@ -1,12 +1,34 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Scope of the MCP proxy endpoints.
+pub const SCOPE_PROXY: &str = "proxy";
+/// Scope of the read only registry endpoints.
+pub const SCOPE_REGISTRY_READ: &str = "registry:read";
+/// Scope of the registry endpoints changing servers and aggregates.
+pub const SCOPE_REGISTRY_WRITE: &str = "registry:write";
+
+pub const SCOPES: [&str; 3] = [SCOPE_PROXY, SCOPE_REGISTRY_READ, SCOPE_REGISTRY_WRITE];
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct ApiKeys {
-    pub apikey: String,
+    pub id: Uuid,
     pub name: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
 }
+
+impl ApiKeys {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
@@ -0,0 +1,28 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A JSON-RPC request to an MCP server or aggregate.
+pub const AUDIT_ACTION_MCP: &str = "mcp";
+/// A change through the admin API.
+pub const AUDIT_ACTION_API: &str = "api";
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct AuditLogs {
+    pub id: Uuid,
+    pub actor_type: String,
+    pub actor: String,
+    pub action: String,
+    pub server_name: Option<String>,
+    pub server_tag: Option<String>,
+    pub method: String,
+    pub tool: Option<String>,
+    pub path: String,
+    pub arguments: Option<serde_json::Value>,
+    pub status: i32,
+    pub error_code: Option<i64>,
+    pub error: Option<String>,
+    pub latency_ms: i64,
+    pub created_at: NaiveDateTime,
+}
@@ -45,6 +45,11 @@ pub struct McpServers {
     pub create_from: String,
     pub extra: Option<serde_json::Value>,
     pub disabled: bool,
+    /// Kind of the upstream credential, `None` when the server has none.
+    pub credential_type: Option<String>,
+    /// The upstream credential encrypted with the credential key.
+    #[serde(skip_serializing, default)]
+    pub credential: Option<Vec<u8>>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
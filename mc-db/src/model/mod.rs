@@ -1,7 +1,17 @@
+mod aggregates;
 mod apikeys;
+mod audit_logs;
 mod mcp_servers;
+mod policies;
+mod rate_limits;
 mod system_settings;
+mod users;
 
+pub use aggregates::*;
 pub use apikeys::*;
+pub use audit_logs::*;
 pub use mcp_servers::*;
+pub use policies::*;
+pub use rate_limits::*;
 pub use system_settings::*;
+pub use users::*;
@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKeyPolicies {
+    pub id: Uuid,
+    /// The API key of the grant, `None` for grants of OAuth principals matched by name.
+    pub api_key_id: Option<Uuid>,
+    pub api_key_name: String,
+    pub server_name: String,
+    pub tag_pattern: String,
+    pub allow_tools: Vec<String>,
+    pub deny_tools: Vec<String>,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+}
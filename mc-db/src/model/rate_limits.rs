@@ -0,0 +1,23 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RateLimits {
+    pub id: Uuid,
+    pub scope: String,
+    pub kind: String,
+    pub api_key_name: String,
+    /// The API key a limit of a single key belongs to, `None` for patterns.
+    pub api_key_id: Option<Uuid>,
+    pub server_name: String,
+    pub tag_pattern: String,
+    pub max_requests: i64,
+    pub period_secs: i64,
+    pub burst: Option<i64>,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+}
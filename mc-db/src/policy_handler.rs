@@ -0,0 +1,127 @@
+use crate::DBClient;
+use crate::model::ApiKeyPolicies;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub struct PolicyDBHandler {
+    client: Arc<DBClient>,
+}
+
+impl PolicyDBHandler {
+    pub fn new(client: Arc<DBClient>) -> Self {
+        PolicyDBHandler { client }
+    }
+
+    pub async fn list(
+        &self,
+        api_key_name: Option<&str>,
+    ) -> Result<Vec<ApiKeyPolicies>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            r#"
+        SELECT * FROM tb_api_key_policies
+        WHERE deleted_at IS NULL AND ($1::TEXT IS NULL OR api_key_name = $1)
+        ORDER BY api_key_name, created_at
+        "#,
+        )
+        .bind(api_key_name)
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    /// Policies of a caller: the grants of its API key, or those of any of the names of an
+    /// OAuth principal, which may hold the policies of several names.
+    pub async fn list_for_caller(
+        &self,
+        api_key_id: Option<Uuid>,
+        names: &[String],
+    ) -> Result<Vec<ApiKeyPolicies>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            r#"
+        SELECT * FROM tb_api_key_policies
+        WHERE deleted_at IS NULL
+          AND (api_key_id = $1 OR (api_key_id IS NULL AND api_key_name = ANY($2)))
+        ORDER BY api_key_name, created_at
+        "#,
+        )
+        .bind(api_key_id)
+        .bind(names)
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<ApiKeyPolicies>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            "SELECT * FROM tb_api_key_policies WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn create(&self, policy: &ApiKeyPolicies) -> Result<ApiKeyPolicies, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            r#"
+        INSERT INTO tb_api_key_policies
+            (id, api_key_name, server_name, tag_pattern, allow_tools, deny_tools, description,
+             api_key_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING *
+        "#,
+        )
+        .bind(policy.id)
+        .bind(&policy.api_key_name)
+        .bind(&policy.server_name)
+        .bind(&policy.tag_pattern)
+        .bind(&policy.allow_tools)
+        .bind(&policy.deny_tools)
+        .bind(&policy.description)
+        .bind(policy.api_key_id)
+        .fetch_one(&self.client.pool)
+        .await
+    }
+
+    pub async fn update(
+        &self,
+        policy: &ApiKeyPolicies,
+    ) -> Result<Option<ApiKeyPolicies>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            r#"
+        UPDATE tb_api_key_policies
+        SET api_key_name = $2,
+            server_name = $3,
+            tag_pattern = $4,
+            allow_tools = $5,
+            deny_tools = $6,
+            description = $7,
+            api_key_id = $8
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(policy.id)
+        .bind(&policy.api_key_name)
+        .bind(&policy.server_name)
+        .bind(&policy.tag_pattern)
+        .bind(&policy.allow_tools)
+        .bind(&policy.deny_tools)
+        .bind(&policy.description)
+        .bind(policy.api_key_id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Soft deletes the policy by setting `deleted_at`.
+    pub async fn delete(&self, id: Uuid) -> Result<Option<ApiKeyPolicies>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyPolicies>(
+            r#"
+        UPDATE tb_api_key_policies
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+}
@@ -0,0 +1,108 @@
+use crate::DBClient;
+use crate::model::RateLimits;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub struct RateLimitDBHandler {
+    client: Arc<DBClient>,
+}
+
+impl RateLimitDBHandler {
+    pub fn new(client: Arc<DBClient>) -> Self {
+        RateLimitDBHandler { client }
+    }
+
+    pub async fn list(&self) -> Result<Vec<RateLimits>, sqlx::Error> {
+        sqlx::query_as::<_, RateLimits>(
+            r#"
+        SELECT * FROM tb_rate_limits
+        WHERE deleted_at IS NULL
+        ORDER BY scope, kind, created_at
+        "#,
+        )
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<RateLimits>, sqlx::Error> {
+        sqlx::query_as::<_, RateLimits>(
+            "SELECT * FROM tb_rate_limits WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn create(&self, limit: &RateLimits) -> Result<RateLimits, sqlx::Error> {
+        sqlx::query_as::<_, RateLimits>(
+            r#"
+        INSERT INTO tb_rate_limits
+            (id, scope, kind, api_key_name, server_name, tag_pattern, max_requests, period_secs,
+             burst, description, api_key_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING *
+        "#,
+        )
+        .bind(limit.id)
+        .bind(&limit.scope)
+        .bind(&limit.kind)
+        .bind(&limit.api_key_name)
+        .bind(&limit.server_name)
+        .bind(&limit.tag_pattern)
+        .bind(limit.max_requests)
+        .bind(limit.period_secs)
+        .bind(limit.burst)
+        .bind(&limit.description)
+        .bind(limit.api_key_id)
+        .fetch_one(&self.client.pool)
+        .await
+    }
+
+    pub async fn update(&self, limit: &RateLimits) -> Result<Option<RateLimits>, sqlx::Error> {
+        sqlx::query_as::<_, RateLimits>(
+            r#"
+        UPDATE tb_rate_limits
+        SET scope = $2,
+            kind = $3,
+            api_key_name = $4,
+            server_name = $5,
+            tag_pattern = $6,
+            max_requests = $7,
+            period_secs = $8,
+            burst = $9,
+            description = $10,
+            api_key_id = $11
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(limit.id)
+        .bind(&limit.scope)
+        .bind(&limit.kind)
+        .bind(&limit.api_key_name)
+        .bind(&limit.server_name)
+        .bind(&limit.tag_pattern)
+        .bind(limit.max_requests)
+        .bind(limit.period_secs)
+        .bind(limit.burst)
+        .bind(&limit.description)
+        .bind(limit.api_key_id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Soft deletes the rate limit by setting `deleted_at`.
+    pub async fn delete(&self, id: Uuid) -> Result<Option<RateLimits>, sqlx::Error> {
+        sqlx::query_as::<_, RateLimits>(
+            r#"
+        UPDATE tb_rate_limits
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+}
@@ -0,0 +1,122 @@
+use crate::DBClient;
+use crate::model::Users;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub struct UserDBHandler {
+    client: Arc<DBClient>,
+}
+
+impl UserDBHandler {
+    pub fn new(client: Arc<DBClient>) -> Self {
+        UserDBHandler { client }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Users>, sqlx::Error> {
+        sqlx::query_as::<_, Users>(
+            "SELECT * FROM tb_users WHERE deleted_at IS NULL ORDER BY username",
+        )
+        .fetch_all(&self.client.pool)
+        .await
+    }
+
+    pub async fn get(&self, username: &str) -> Result<Option<Users>, sqlx::Error> {
+        sqlx::query_as::<_, Users>(
+            "SELECT * FROM tb_users WHERE username = $1 AND deleted_at IS NULL",
+        )
+        .bind(username)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    pub async fn create(&self, user: &Users) -> Result<Users, sqlx::Error> {
+        sqlx::query_as::<_, Users>(
+            r#"
+        INSERT INTO tb_users
+            (id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+        )
+        .bind(user.id)
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(&user.role)
+        .fetch_one(&self.client.pool)
+        .await
+    }
+
+    /// Updates the password and role, `None` keeps the current value.
+    pub async fn update(
+        &self,
+        username: &str,
+        password_hash: Option<&str>,
+        role: Option<&str>,
+    ) -> Result<Option<Users>, sqlx::Error> {
+        sqlx::query_as::<_, Users>(
+            r#"
+        UPDATE tb_users
+        SET password_hash = COALESCE($2, password_hash),
+            role = COALESCE($3, role)
+        WHERE username = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(role)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Soft deletes the user by setting `deleted_at`.
+    pub async fn delete(&self, username: &str) -> Result<Option<Users>, sqlx::Error> {
+        sqlx::query_as::<_, Users>(
+            r#"
+        UPDATE tb_users
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE username = $1 AND deleted_at IS NULL
+        RETURNING *
+        "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.client.pool)
+        .await
+    }
+
+    /// Revokes the token until `expires_at`, a unix timestamp. `false` when the
+    /// token was already revoked, so of concurrent calls only one gets `true`.
+    pub async fn revoke_token(&self, jti: Uuid, expires_at: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+        INSERT INTO tb_revoked_tokens
+            (jti, expires_at)
+        VALUES ($1, to_timestamp($2))
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+        )
+        .bind(jti)
+        .bind(expires_at as f64)
+        .execute(&self.client.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Drops the rows of tokens which expired, they are rejected anyway.
+    pub async fn purge_revoked_tokens(&self) -> Result<u64, sqlx::Error> {
+        let result =
+            sqlx::query("DELETE FROM tb_revoked_tokens WHERE expires_at < CURRENT_TIMESTAMP")
+                .execute(&self.client.pool)
+                .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn is_token_revoked(&self, jti: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM tb_revoked_tokens WHERE jti = $1)",
+        )
+        .bind(jti)
+        .fetch_one(&self.client.pool)
+        .await
+    }
+}
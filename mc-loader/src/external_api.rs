@@ -1,19 +1,388 @@
 use crate::{Loader, McpServer};
-use reqwest::Client;
+use reqwest::header::{
+    AUTHORIZATION, ETAG, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Client, Request, Response, StatusCode};
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::sleep;
 
-#[derive(Debug, Default, Clone)]
+// a catalog that keeps returning pages is rejected rather than synced partially
+const MAX_PAGES: u64 = 1000;
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Servers listed by an HTTP API, e.g. an internal catalog.
+///
+/// Pages are requested one after another and every server entry is mapped on its own,
+/// invalid entries are reported and skipped. Responses are cached by their `ETag` and
+/// `Last-Modified` headers and requested conditionally.
+#[derive(Debug)]
 pub struct ExternalApiLoader {
     url: String,
     authorization: Option<String>,
+    client: Client,
+    retries: u32,
+    pagination: Pagination,
+    fields: Fields,
+    cache: Mutex<HashMap<String, CachedPage>>,
 }
 
-impl ExternalApiLoader {
-    pub fn new(url: &str, authorization: Option<String>) -> Self {
+/// Settings of the `external` registry besides its url.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ExternalApiOptions {
+    /// Seconds a request may take.
+    pub timeout: u64,
+    /// Retries of requests which failed or were answered with 429 or 5xx.
+    pub retries: u32,
+    pub pagination: Pagination,
+    pub mapping: FieldMapping,
+}
+
+impl Default for ExternalApiOptions {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            retries: 2,
+            pagination: Pagination::default(),
+            mapping: FieldMapping::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PaginationKind {
+    /// A single request.
+    #[default]
+    None,
+    /// `page_param` counts pages from `first_page`.
+    Page,
+    /// `offset_param` counts the entries received before.
+    Offset,
+    /// `cursor_param` is read from `next_cursor` of the previous response.
+    Cursor,
+}
+
+/// How further pages are requested. The query parameters are added to the url, a page
+/// shorter than `page_size` or a response without next cursor is the last.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Pagination {
+    #[serde(rename = "type")]
+    pub kind: PaginationKind,
+    pub page_size: u64,
+    pub size_param: String,
+    pub page_param: String,
+    pub first_page: u64,
+    pub offset_param: String,
+    pub cursor_param: String,
+    /// JSONPath of the next cursor in a response.
+    pub next_cursor: String,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            kind: PaginationKind::None,
+            page_size: 100,
+            size_param: "limit".to_string(),
+            page_param: "page".to_string(),
+            first_page: 1,
+            offset_param: "offset".to_string(),
+            cursor_param: "cursor".to_string(),
+            next_cursor: "$.next_cursor".to_string(),
+        }
+    }
+}
+
+impl Pagination {
+    fn query(&self, page: u64, offset: u64, cursor: Option<&str>) -> Vec<(String, String)> {
+        let size = (self.size_param.clone(), self.page_size.to_string());
+        match self.kind {
+            PaginationKind::None => vec![],
+            PaginationKind::Page => vec![
+                (
+                    self.page_param.clone(),
+                    (self.first_page + page).to_string(),
+                ),
+                size,
+            ],
+            PaginationKind::Offset => vec![(self.offset_param.clone(), offset.to_string()), size],
+            PaginationKind::Cursor => match cursor {
+                Some(cursor) => vec![(self.cursor_param.clone(), cursor.to_string()), size],
+                None => vec![size],
+            },
+        }
+    }
+}
+
+/// Where the servers are found in a response and their fields in a server entry, as
+/// JSONPath like `$.data.list` or `$.spec.endpoints[0].url`. Optional fields are not
+/// read when empty.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FieldMapping {
+    pub items: String,
+    pub name: String,
+    pub endpoint: String,
+    pub tag: String,
+    pub version: String,
+    pub transport_type: String,
+    pub description: String,
+    pub extra: String,
+    /// Entries with `false` are left out.
+    pub is_published: String,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
         Self {
+            items: "$.data.list".to_string(),
+            name: "name".to_string(),
+            endpoint: "endpoint".to_string(),
+            tag: "tag".to_string(),
+            version: "version".to_string(),
+            transport_type: "transport_type".to_string(),
+            description: "description".to_string(),
+            extra: "extra".to_string(),
+            is_published: "is_published".to_string(),
+        }
+    }
+}
+
+/// Result of listing the servers of the API.
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub servers: Vec<McpServer>,
+    /// Entries left out as not published.
+    pub unpublished: usize,
+    /// Entries left out as invalid.
+    pub errors: Vec<EntryError>,
+    /// Whether every page was answered with 304 Not Modified.
+    pub unchanged: bool,
+}
+
+/// An entry of the API which can't be synced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryError {
+    /// Position of the entry over all pages.
+    pub index: usize,
+    pub name: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "entry {} ({name}): {}", self.index, self.message),
+            None => write!(f, "entry {}: {}", self.index, self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedPage {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: Value,
+}
+
+struct Fetched {
+    url: String,
+    body: Value,
+    unchanged: bool,
+}
+
+impl ExternalApiLoader {
+    pub fn new(
+        url: &str,
+        authorization: Option<String>,
+        options: ExternalApiOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(options.timeout.max(1)))
+            .build()?;
+        Ok(Self {
             url: url.to_string(),
             authorization,
+            client,
+            retries: options.retries,
+            fields: Fields::new(&options.mapping, &options.pagination)?,
+            pagination: options.pagination,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Requests every page and maps the entries to servers. Fails only when a page can't
+    /// be requested or has no list of servers, a partial list would remove servers.
+    pub async fn load(&self) -> Result<Listing, Box<dyn Error>> {
+        let mut entries = vec![];
+        let mut urls = HashSet::new();
+        let mut unchanged = true;
+        let mut offset = 0;
+        let mut cursor = None;
+
+        for page in 0..MAX_PAGES {
+            let query = self.pagination.query(page, offset, cursor.as_deref());
+            let fetched = self.fetch(&query).await?;
+            let items = self
+                .fields
+                .items
+                .select(&fetched.body)
+                .and_then(Value::as_array)
+                .ok_or_else(|| {
+                    format!(
+                        "{} has no list of servers at {}",
+                        fetched.url, self.fields.items
+                    )
+                })?;
+            let count = items.len() as u64;
+            entries.extend(items.iter().cloned());
+            offset += count;
+
+            let more = match self.pagination.kind {
+                PaginationKind::None => false,
+                PaginationKind::Page | PaginationKind::Offset => {
+                    count > 0 && count >= self.pagination.page_size
+                }
+                PaginationKind::Cursor => {
+                    cursor = self
+                        .fields
+                        .next_cursor
+                        .select(&fetched.body)
+                        .and_then(cursor_value);
+                    cursor.is_some()
+                }
+            };
+            unchanged &= fetched.unchanged;
+            urls.insert(fetched.url);
+
+            if !more {
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .retain(|url, _| urls.contains(url));
+                let mut listing = self.fields.listing(&entries);
+                listing.unchanged = unchanged;
+                return Ok(listing);
+            }
+        }
+
+        Err(format!("{} has more than {MAX_PAGES} pages", self.url).into())
+    }
+
+    async fn fetch(&self, query: &[(String, String)]) -> Result<Fetched, Box<dyn Error>> {
+        let mut request = self.client.get(self.url.as_str()).query(query).build()?;
+        let url = request.url().to_string();
+        if let Some(auth) = &self.authorization {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, HeaderValue::from_str(auth)?);
+        }
+        let validators = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&url)
+            .map(|page| (page.etag.clone(), page.last_modified.clone()));
+        if let Some((etag, last_modified)) = validators {
+            if let Some(etag) = etag {
+                request
+                    .headers_mut()
+                    .insert(IF_NONE_MATCH, HeaderValue::from_str(&etag)?);
+            }
+            if let Some(last_modified) = last_modified {
+                request
+                    .headers_mut()
+                    .insert(IF_MODIFIED_SINCE, HeaderValue::from_str(&last_modified)?);
+            }
+        }
+
+        let response = self.send(request, &url).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            let body = self
+                .cache
+                .lock()
+                .unwrap()
+                .get(&url)
+                .map(|page| page.body.clone());
+            return match body {
+                Some(body) => Ok(Fetched {
+                    url,
+                    body,
+                    unchanged: true,
+                }),
+                None => Err(format!("{url} answered 304 to an unconditional request").into()),
+            };
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body: Value = serde_json::from_str(&response.text().await?)?;
+
+        let mut cache = self.cache.lock().unwrap();
+        if etag.is_some() || last_modified.is_some() {
+            let page = CachedPage {
+                etag,
+                last_modified,
+                body: body.clone(),
+            };
+            cache.insert(url.clone(), page);
+        } else {
+            cache.remove(&url);
+        }
+        Ok(Fetched {
+            url,
+            body,
+            unchanged: false,
+        })
+    }
+
+    async fn send(&self, request: Request, url: &str) -> Result<Response, Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone().ok_or("request can't be retried")?;
+            let error = match self.client.execute(retry).await {
+                Ok(response)
+                    if response.status().is_success()
+                        || response.status() == StatusCode::NOT_MODIFIED =>
+                {
+                    return Ok(response);
+                }
+                Ok(response)
+                    if response.status().is_server_error()
+                        || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    format!("status {}", response.status())
+                }
+                Ok(response) => return Err(format!("{url} answered {}", response.status()).into()),
+                Err(err) => err.to_string(),
+            };
+
+            if attempt >= self.retries {
+                return Err(format!(
+                    "{url} failed after {} attempts, error: {error}",
+                    attempt + 1
+                )
+                .into());
+            }
+            let backoff = RETRY_BACKOFF * 2u32.pow(attempt.min(5));
+            tracing::warn!("Request to {url} failed, retrying in {backoff:?}, error: {error}");
+            sleep(backoff).await;
+            attempt += 1;
         }
     }
 }
@@ -21,43 +390,505 @@ impl ExternalApiLoader {
 #[async_trait::async_trait]
 impl Loader for ExternalApiLoader {
     async fn list_mcp(&self) -> Result<Vec<McpServer>, Box<dyn Error>> {
-        let client = Client::new();
-        let mut builder = client.get(self.url.as_str());
-        if let Some(auth) = &self.authorization {
-            builder = builder.header("Authorization", auth.as_str());
+        let listing = self.load().await?;
+
+        if listing.unchanged {
+            tracing::debug!("mcp servers of {} are not modified", self.url);
+        } else {
+            for error in &listing.errors {
+                tracing::warn!("Skip mcp server of {}, {error}", self.url);
+            }
+            tracing::info!(
+                "all mcp number: {}, unpublished: {}, invalid: {}, {} mcp servers will be proxy",
+                listing.servers.len() + listing.unpublished + listing.errors.len(),
+                listing.unpublished,
+                listing.errors.len(),
+                listing.servers.len()
+            );
+        }
+
+        Ok(listing.servers)
+    }
+}
+
+fn cursor_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(cursor) if !cursor.is_empty() => Some(cursor.clone()),
+        Value::Number(cursor) => Some(cursor.to_string()),
+        _ => None,
+    }
+}
+
+/// The paths of [`FieldMapping`], parsed once.
+#[derive(Debug)]
+struct Fields {
+    items: JsonPath,
+    next_cursor: JsonPath,
+    name: JsonPath,
+    endpoint: JsonPath,
+    tag: Option<JsonPath>,
+    version: Option<JsonPath>,
+    transport_type: Option<JsonPath>,
+    description: Option<JsonPath>,
+    extra: Option<JsonPath>,
+    is_published: Option<JsonPath>,
+}
+
+impl Fields {
+    fn new(mapping: &FieldMapping, pagination: &Pagination) -> Result<Self, String> {
+        let path = |field: &str, path: &str| {
+            JsonPath::parse(path).map_err(|err| format!("invalid {field} path {path:?}, {err}"))
+        };
+        let required = |field: &str, value: &str| match value.is_empty() {
+            true => Err(format!("{field} path must not be empty")),
+            false => path(field, value),
+        };
+        let optional = |field: &str, value: &str| match value.is_empty() {
+            true => Ok(None),
+            false => path(field, value).map(Some),
+        };
+
+        Ok(Self {
+            items: path("items", &mapping.items)?,
+            next_cursor: path("next_cursor", &pagination.next_cursor)?,
+            name: required("name", &mapping.name)?,
+            endpoint: required("endpoint", &mapping.endpoint)?,
+            tag: optional("tag", &mapping.tag)?,
+            version: optional("version", &mapping.version)?,
+            transport_type: optional("transport_type", &mapping.transport_type)?,
+            description: optional("description", &mapping.description)?,
+            extra: optional("extra", &mapping.extra)?,
+            is_published: optional("is_published", &mapping.is_published)?,
+        })
+    }
+
+    fn listing(&self, entries: &[Value]) -> Listing {
+        let mut listing = Listing::default();
+        let mut indexes = HashMap::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            let error = |message| EntryError {
+                index,
+                name: self
+                    .name
+                    .select(entry)
+                    .and_then(Value::as_str)
+                    .map(|name| name.to_string()),
+                message,
+            };
+            let server = match self.server(entry) {
+                Ok(Some(server)) => server,
+                Ok(None) => {
+                    listing.unpublished += 1;
+                    continue;
+                }
+                Err(message) => {
+                    listing.errors.push(error(message));
+                    continue;
+                }
+            };
+            if let Err(message) = server.validate() {
+                listing.errors.push(error(message));
+                continue;
+            }
+            let key = (server.name.clone(), server.resolved_tag());
+            if let Some(first) = indexes.get(&key) {
+                let message = format!("{}/{} is already listed by entry {first}", key.0, key.1);
+                listing.errors.push(error(message));
+                continue;
+            }
+            indexes.insert(key, index);
+            listing.servers.push(server);
+        }
+
+        listing
+    }
+
+    /// Maps an entry, `None` when it isn't published.
+    fn server(&self, entry: &Value) -> Result<Option<McpServer>, String> {
+        let is_published = match field(self.is_published.as_ref(), entry) {
+            None => None,
+            Some(Value::Bool(published)) => Some(*published),
+            Some(_) => return Err("is_published must be a boolean".to_string()),
+        };
+        if is_published == Some(false) {
+            return Ok(None);
         }
-        let response = builder.send().await?;
-        let raw = response.text().await?;
 
-        let res: ListResponse = serde_json::from_str(raw.as_str())?;
+        let required = |path: &JsonPath, name: &str| {
+            string_field(Some(path), entry, name)?
+                .ok_or_else(|| format!("{name} is missing at {path}"))
+        };
+        Ok(Some(McpServer {
+            name: required(&self.name, "name")?,
+            endpoint: required(&self.endpoint, "endpoint")?,
+            tag: string_field(self.tag.as_ref(), entry, "tag")?,
+            version: string_field(self.version.as_ref(), entry, "version")?,
+            transport_type: string_field(self.transport_type.as_ref(), entry, "transport_type")?,
+            description: string_field(self.description.as_ref(), entry, "description")?,
+            extra: field(self.extra.as_ref(), entry).cloned(),
+            is_published,
+        }))
+    }
+}
+
+fn field<'a>(path: Option<&JsonPath>, entry: &'a Value) -> Option<&'a Value> {
+    path?.select(entry).filter(|value| !value.is_null())
+}
+
+fn string_field(
+    path: Option<&JsonPath>,
+    entry: &Value,
+    name: &str,
+) -> Result<Option<String>, String> {
+    match field(path, entry) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(Value::Number(value)) => Ok(Some(value.to_string())),
+        Some(_) => Err(format!("{name} must be a string")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
 
-        tracing::info!("all mcp number: {}", res.data.list.len());
+/// The subset of JSONPath selecting a single value: `$`, `.key`, `['key']` and `[0]`.
+/// The leading `$.` may be left out.
+#[derive(Debug, Clone, PartialEq)]
+struct JsonPath {
+    path: String,
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    fn parse(path: &str) -> Result<Self, String> {
+        let mut rest = path.strip_prefix('$').unwrap_or(path);
+        let mut segments = vec![];
+        let mut first = rest.len() == path.len();
 
-        let mut servers = vec![];
-        let mut remove_count = 0;
-        res.data.list.iter().for_each(|server| {
-            if server.is_published.is_none() || server.is_published == Some(true) {
-                servers.push(server.clone());
-                return;
+        while !rest.is_empty() {
+            if let Some(bracket) = rest.strip_prefix('[') {
+                let end = bracket.find(']').ok_or("unclosed [")?;
+                let inner = bracket[..end].trim();
+                let quoted = ['\'', '"'].iter().find(|quote| {
+                    inner.len() >= 2 && inner.starts_with(**quote) && inner.ends_with(**quote)
+                });
+                segments.push(match quoted {
+                    Some(_) => Segment::Key(inner[1..inner.len() - 1].to_string()),
+                    None => {
+                        Segment::Index(inner.parse().map_err(|_| {
+                            format!("{inner:?} is neither an index nor a quoted key")
+                        })?)
+                    }
+                });
+                rest = &bracket[end + 1..];
+            } else {
+                let key = match rest.strip_prefix('.') {
+                    Some(key) => key,
+                    None if first => rest,
+                    None => return Err(format!("expected . or [ before {rest:?}")),
+                };
+                let end = key.find(['.', '[']).unwrap_or(key.len());
+                if end == 0 {
+                    return Err("empty key".to_string());
+                }
+                segments.push(Segment::Key(key[..end].to_string()));
+                rest = &key[end..];
             }
-            remove_count += 1;
-        });
+            first = false;
+        }
 
-        tracing::info!(
-            "{} mcp servers will be proxy",
-            res.data.list.len() - remove_count
-        );
+        Ok(Self {
+            path: path.to_string(),
+            segments,
+        })
+    }
 
-        Ok(servers)
+    fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key.as_str()),
+                Segment::Index(index) => value.get(*index),
+            })
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ListResponse {
-    pub data: ListData,
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path)
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ListData {
-    pub list: Vec<McpServer>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_json_path() {
+        struct TestCase {
+            path: &'static str,
+            want: Result<Option<Value>, ()>,
+        }
+
+        let value = json!({
+            "data": {"list": [{"url": "http://a/sse"}, {"url": "http://b/sse"}]},
+            "a.b": 1,
+        });
+        let tests = vec![
+            TestCase {
+                path: "$",
+                want: Ok(Some(value.clone())),
+            },
+            TestCase {
+                path: "$.data.list[1].url",
+                want: Ok(Some(json!("http://b/sse"))),
+            },
+            TestCase {
+                path: "data.list[0]['url']",
+                want: Ok(Some(json!("http://a/sse"))),
+            },
+            TestCase {
+                path: "$[\"a.b\"]",
+                want: Ok(Some(json!(1))),
+            },
+            TestCase {
+                path: "$.data.items",
+                want: Ok(None),
+            },
+            TestCase {
+                path: "$.data[0]",
+                want: Ok(None),
+            },
+            TestCase {
+                path: "$..data",
+                want: Err(()),
+            },
+            TestCase {
+                path: "$.data[x]",
+                want: Err(()),
+            },
+            TestCase {
+                path: "$.data[0",
+                want: Err(()),
+            },
+            TestCase {
+                path: "$data",
+                want: Err(()),
+            },
+        ];
+
+        for t in tests {
+            let got = JsonPath::parse(t.path)
+                .map(|path| path.select(&value).cloned())
+                .map_err(|_| ());
+            assert_eq!(got, t.want, "path: {}", t.path);
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let fields = Fields::new(&FieldMapping::default(), &Pagination::default()).unwrap();
+        let listing = fields.listing(&[
+            json!({"name": "github", "endpoint": "http://github/sse", "version": 1}),
+            json!({"name": "draft", "endpoint": "http://draft/sse", "is_published": false}),
+            json!({"name": "files", "transport_type": "stdio", "endpoint": "stdio://files",
+                "extra": {"command": "npx"}, "is_published": true}),
+            json!({"name": "missing"}),
+            json!({"name": "ws", "endpoint": "ws://ws/mcp"}),
+            json!({"name": "github", "endpoint": "http://github2/sse", "tag": "1"}),
+            json!({"name": "flag", "endpoint": "http://flag/sse", "is_published": "yes"}),
+            json!({"name": ["list"], "endpoint": "http://list/sse"}),
+        ]);
+
+        let servers: Vec<(&str, String)> = listing
+            .servers
+            .iter()
+            .map(|server| (server.name.as_str(), server.resolved_tag()))
+            .collect();
+        assert_eq!(servers, vec![("github", "1".to_string())]);
+        assert_eq!(listing.unpublished, 1);
+        let errors: Vec<String> = listing.errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "entry 2 (files): stdio server files can only be defined in the local file",
+                "entry 3 (missing): endpoint is missing at endpoint",
+                "entry 4 (ws): endpoint \"ws://ws/mcp\" of ws is not an http(s) URL",
+                "entry 5 (github): github/1 is already listed by entry 0",
+                "entry 6 (flag): is_published must be a boolean",
+                "entry 7: name must be a string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_mapping() {
+        let mapping = FieldMapping {
+            name: "$.metadata.id".to_string(),
+            endpoint: "$.spec.endpoints[0].url".to_string(),
+            tag: "$.metadata['release']".to_string(),
+            is_published: String::new(),
+            ..Default::default()
+        };
+        let fields = Fields::new(&mapping, &Pagination::default()).unwrap();
+        let listing = fields.listing(&[json!({
+            "metadata": {"id": "github", "release": "2.0.0"},
+            "spec": {"endpoints": [{"url": "http://github/sse"}]},
+            "is_published": false,
+        })]);
+        assert!(listing.errors.is_empty(), "{:?}", listing.errors);
+        assert_eq!(listing.servers[0].name, "github");
+        assert_eq!(listing.servers[0].tag.as_deref(), Some("2.0.0"));
+        assert_eq!(listing.servers[0].endpoint, "http://github/sse");
+
+        let mapping = FieldMapping {
+            name: String::new(),
+            ..Default::default()
+        };
+        assert!(Fields::new(&mapping, &Pagination::default()).is_err());
+    }
+
+    async fn fake_api(
+        respond: impl Fn(&str, usize) -> String + Send + 'static,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let n = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_lowercase();
+                let response = respond(&request, counter.fetch_add(1, Ordering::SeqCst));
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (format!("http://{addr}/servers"), requests)
+    }
+
+    fn ok(headers: &str, body: Value) -> String {
+        format!("HTTP/1.1 200 OK\r\n{headers}connection: close\r\n\r\n{body}")
+    }
+
+    fn server(name: &str) -> Value {
+        json!({"name": name, "endpoint": format!("http://{name}/sse")})
+    }
+
+    #[tokio::test]
+    async fn test_load_pages() {
+        let (url, requests) = fake_api(|request, count| {
+            // the first request fails and is retried
+            if count == 0 {
+                return "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\n\r\n".to_string();
+            }
+            assert!(request.contains("authorization: bearer token"));
+            assert!(request.contains("limit=2"));
+            if request.contains("page=1") {
+                if request.contains("if-none-match: \"v1\"") {
+                    return "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n".to_string();
+                }
+                ok(
+                    "etag: \"v1\"\r\n",
+                    json!({"data": {"list": [server("a"), server("b")]}}),
+                )
+            } else {
+                assert!(request.contains("page=2"));
+                ok("", json!({"data": {"list": [server("c")]}}))
+            }
+        })
+        .await;
+        let options = ExternalApiOptions {
+            retries: 1,
+            pagination: Pagination {
+                kind: PaginationKind::Page,
+                page_size: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let loader =
+            ExternalApiLoader::new(&url, Some("Bearer token".to_string()), options).unwrap();
+
+        let listing = loader.load().await.unwrap();
+        assert_eq!(listing.servers.len(), 3);
+        assert!(!listing.unchanged);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // the first page is cached, the second without validators is requested again
+        let listing = loader.load().await.unwrap();
+        assert_eq!(listing.servers.len(), 3);
+        assert!(!listing.unchanged);
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_load_cursor() {
+        let (url, _) = fake_api(|request, _| {
+            if request.contains("if-modified-since: wed, 21 oct 2026 07:28:00 gmt") {
+                return "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n".to_string();
+            }
+            let headers = "last-modified: Wed, 21 Oct 2026 07:28:00 GMT\r\n";
+            match request.contains("cursor=next") {
+                false => ok(headers, json!({"items": [server("a")], "next": "next"})),
+                true => ok(headers, json!({"items": [server("b")], "next": null})),
+            }
+        })
+        .await;
+        let options = ExternalApiOptions {
+            pagination: Pagination {
+                kind: PaginationKind::Cursor,
+                next_cursor: "$.next".to_string(),
+                ..Default::default()
+            },
+            mapping: FieldMapping {
+                items: "$.items".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let loader = ExternalApiLoader::new(&url, None, options).unwrap();
+
+        let listing = loader.load().await.unwrap();
+        assert_eq!(listing.servers.len(), 2);
+        assert!(!listing.unchanged);
+
+        let listing = loader.load().await.unwrap();
+        assert_eq!(listing.servers.len(), 2);
+        assert!(listing.unchanged);
+    }
+
+    #[tokio::test]
+    async fn test_load_errors() {
+        let (url, requests) = fake_api(|request, _| match request.contains("offset=0") {
+            true => ok("", json!({"data": {"list": [server("a")]}})),
+            false => "HTTP/1.1 404 Not Found\r\nconnection: close\r\n\r\n".to_string(),
+        })
+        .await;
+        let options = ExternalApiOptions {
+            pagination: Pagination {
+                kind: PaginationKind::Offset,
+                page_size: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let loader = ExternalApiLoader::new(&url, None, options).unwrap();
+
+        // a missing page fails the load without retries
+        let err = loader.load().await.unwrap_err().to_string();
+        assert!(err.contains("offset=1"), "{err}");
+        assert!(err.contains("404"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
 }
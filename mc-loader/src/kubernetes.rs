@@ -0,0 +1,658 @@
+use crate::{Loader, McpServer};
+use reqwest::{Certificate, Client, Response};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout};
+
+/// Name of the MCP server, Services without it are not registered.
+pub const ANNOTATION_NAME: &str = "mcp-center/name";
+/// Tag of the MCP server, `latest` when not set.
+pub const ANNOTATION_TAG: &str = "mcp-center/tag";
+/// Path of the MCP endpoint, `/sse` or `/mcp` depending on the transport when not set.
+pub const ANNOTATION_PATH: &str = "mcp-center/path";
+/// `sse` or `streamable`, `sse` when not set.
+pub const ANNOTATION_TRANSPORT: &str = "mcp-center/transport";
+/// Name or number of the Service port, the first port when not set.
+pub const ANNOTATION_PORT: &str = "mcp-center/port";
+
+const SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+const SERVICE_ACCOUNT_CA: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
+// the API server ends watches after this many seconds, they resume from the last version
+const WATCH_TIMEOUT_SECS: u64 = 300;
+// a watch without events or bookmarks for longer is considered broken
+const WATCH_READ_TIMEOUT: Duration = Duration::from_secs(WATCH_TIMEOUT_SECS + 60);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Registers the Kubernetes Services annotated with `mcp-center/name`. The
+/// Services are listed once and then watched, changes wake the registry sync.
+pub struct KubernetesLoader {
+    watcher: Arc<Watcher>,
+    watching: AtomicBool,
+}
+
+impl KubernetesLoader {
+    /// Watches the Services of `namespace`, of all namespaces when empty. The
+    /// API server of the cluster running MCP Center is used when `api_server`
+    /// is empty, authenticated by the service account.
+    pub fn new(
+        api_server: &str,
+        namespace: &str,
+        label_selector: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let api_server = match api_server {
+            "" => in_cluster_api_server()?,
+            url => url.trim_end_matches('/').to_string(),
+        };
+
+        let mut builder = Client::builder().connect_timeout(Duration::from_secs(10));
+        if let Ok(ca) = std::fs::read(SERVICE_ACCOUNT_CA) {
+            builder = builder.add_root_certificate(Certificate::from_pem(&ca)?);
+        }
+
+        Ok(Self {
+            watcher: Arc::new(Watcher {
+                client: builder.build()?,
+                api_server,
+                namespace: namespace.to_string(),
+                label_selector: label_selector.to_string(),
+                token_file: SERVICE_ACCOUNT_TOKEN.to_string(),
+                state: Mutex::new(State::default()),
+                notify: Notify::new(),
+            }),
+            watching: AtomicBool::new(false),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader for KubernetesLoader {
+    async fn list_mcp(&self) -> Result<Vec<McpServer>, Box<dyn Error>> {
+        if !self.watcher.is_listed() {
+            self.watcher.list().await?;
+        }
+        if !self.watching.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.watcher.clone().run());
+        }
+        Ok(self.watcher.servers())
+    }
+
+    async fn changed(&self) {
+        self.watcher.notify.notified().await
+    }
+}
+
+fn in_cluster_api_server() -> Result<String, Box<dyn Error>> {
+    let host = std::env::var("KUBERNETES_SERVICE_HOST")
+        .map_err(|_| "KUBERNETES_SERVICE_HOST is not set, configure the API server")?;
+    let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+    match host.contains(':') {
+        true => Ok(format!("https://[{host}]:{port}")),
+        false => Ok(format!("https://{host}:{port}")),
+    }
+}
+
+struct Watcher {
+    client: Client,
+    api_server: String,
+    namespace: String,
+    label_selector: String,
+    token_file: String,
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+#[derive(Debug)]
+enum WatchError {
+    /// The version watched from is too old, the Services have to be listed again.
+    Gone,
+    Failed(String),
+}
+
+impl Watcher {
+    fn is_listed(&self) -> bool {
+        self.state.lock().unwrap().resource_version.is_some()
+    }
+
+    fn servers(&self) -> Vec<McpServer> {
+        self.state
+            .lock()
+            .unwrap()
+            .servers
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let result = match self.is_listed() {
+                true => self.watch().await,
+                false => self.list().await.map_err(WatchError::Failed),
+            };
+            match result {
+                Ok(()) => {}
+                Err(WatchError::Gone) => {
+                    tracing::info!("Kubernetes watch of services expired, listing again");
+                    self.state.lock().unwrap().resource_version = None;
+                }
+                Err(WatchError::Failed(err)) => {
+                    tracing::error!("Failed to watch kubernetes services, error: {err}");
+                    sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    async fn list(&self) -> Result<(), String> {
+        let response = self.get(vec![]).await?;
+        let body = response.bytes().await.map_err(|err| err.to_string())?;
+        let list: ServiceList = serde_json::from_slice(&body).map_err(|err| err.to_string())?;
+        tracing::info!("Listed {} kubernetes services", list.items.len());
+
+        if self.state.lock().unwrap().replace(list) {
+            self.notify.notify_one();
+        }
+        Ok(())
+    }
+
+    async fn watch(&self) -> Result<(), WatchError> {
+        let Some(resource_version) = self.state.lock().unwrap().resource_version.clone() else {
+            return Err(WatchError::Gone);
+        };
+        let mut response = self
+            .get(vec![
+                ("watch", "1".to_string()),
+                ("allowWatchBookmarks", "true".to_string()),
+                ("resourceVersion", resource_version),
+                ("timeoutSeconds", WATCH_TIMEOUT_SECS.to_string()),
+            ])
+            .await
+            .map_err(WatchError::Failed)?;
+
+        let mut buffer = vec![];
+        loop {
+            let chunk = match timeout(WATCH_READ_TIMEOUT, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => chunk,
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Err(err)) => return Err(WatchError::Failed(err.to_string())),
+                Err(_) => return Err(WatchError::Failed("the watch stalled".to_string())),
+            };
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(pos) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let event: WatchEvent = serde_json::from_slice(&line)
+                    .map_err(|err| WatchError::Failed(err.to_string()))?;
+                if self.state.lock().unwrap().apply(event)? {
+                    self.notify.notify_one();
+                }
+            }
+        }
+    }
+
+    async fn get(&self, mut query: Vec<(&str, String)>) -> Result<Response, String> {
+        if !self.label_selector.is_empty() {
+            query.push(("labelSelector", self.label_selector.clone()));
+        }
+        let path = match self.namespace.as_str() {
+            "" => "/api/v1/services".to_string(),
+            namespace => format!("/api/v1/namespaces/{namespace}/services"),
+        };
+
+        let mut builder = self
+            .client
+            .get(format!("{}{path}", self.api_server))
+            .query(&query);
+        // bound service account tokens are rotated, the file is read for every request
+        if let Ok(token) = std::fs::read_to_string(&self.token_file) {
+            builder = builder.bearer_auth(token.trim());
+        }
+
+        let response = builder.send().await.map_err(|err| err.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("the API server responded {status}: {body}"));
+        }
+        Ok(response)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Servers by the namespace and name of their Service.
+    servers: BTreeMap<String, McpServer>,
+    /// Version to watch from, `None` until the Services are listed.
+    resource_version: Option<String>,
+}
+
+impl State {
+    /// Replaces the servers by the ones listed, returns whether they changed.
+    fn replace(&mut self, list: ServiceList) -> bool {
+        let servers: BTreeMap<String, McpServer> = list
+            .items
+            .iter()
+            .filter_map(|service| Some((service.key(), service_server(service)?)))
+            .collect();
+        let changed = servers != self.servers;
+        self.servers = servers;
+        self.resource_version = Some(list.metadata.resource_version);
+        changed
+    }
+
+    /// Applies an event of a watch, returns whether the servers changed.
+    fn apply(&mut self, event: WatchEvent) -> Result<bool, WatchError> {
+        match event.kind.as_str() {
+            "ADDED" | "MODIFIED" | "DELETED" => {
+                let service: Service = serde_json::from_value(event.object)
+                    .map_err(|err| WatchError::Failed(err.to_string()))?;
+                self.resource_version = Some(service.metadata.resource_version.clone());
+
+                let key = service.key();
+                let server = match event.kind.as_str() {
+                    "DELETED" => None,
+                    _ => service_server(&service),
+                };
+                let changed = match server {
+                    Some(server) => self.servers.insert(key, server.clone()) != Some(server),
+                    None => self.servers.remove(&key).is_some(),
+                };
+                Ok(changed)
+            }
+            "BOOKMARK" => {
+                let service: Service = serde_json::from_value(event.object)
+                    .map_err(|err| WatchError::Failed(err.to_string()))?;
+                self.resource_version = Some(service.metadata.resource_version);
+                Ok(false)
+            }
+            "ERROR" => {
+                let status: Status = serde_json::from_value(event.object)
+                    .map_err(|err| WatchError::Failed(err.to_string()))?;
+                match status.code {
+                    410 => Err(WatchError::Gone),
+                    _ => Err(WatchError::Failed(status.message)),
+                }
+            }
+            kind => {
+                tracing::warn!("Unknown kubernetes watch event {kind}");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// The server a Service registers, `None` when it is not annotated or the
+/// annotations are invalid.
+fn service_server(service: &Service) -> Option<McpServer> {
+    let annotations = &service.metadata.annotations;
+    let name = annotations
+        .get(ANNOTATION_NAME)
+        .filter(|name| !name.is_empty())?;
+    let key = service.key();
+
+    let transport = annotations
+        .get(ANNOTATION_TRANSPORT)
+        .map(|transport| transport.to_lowercase())
+        .unwrap_or_else(|| "sse".to_string());
+    let (transport, default_path) = match transport.as_str() {
+        "sse" => ("sse", "/sse"),
+        "streamable" | "streamable-http" | "streamable_http" => ("streamable", "/mcp"),
+        transport => {
+            tracing::warn!("Skip kubernetes service {key}, unsupported transport {transport}");
+            return None;
+        }
+    };
+
+    let ports = &service.spec.ports;
+    let port = match annotations.get(ANNOTATION_PORT) {
+        Some(port) => ports
+            .iter()
+            .find(|candidate| candidate.name == *port || candidate.port.to_string() == *port),
+        None => ports.first(),
+    };
+    let Some(port) = port else {
+        tracing::warn!("Skip kubernetes service {key}, no matching port");
+        return None;
+    };
+
+    let path = annotations
+        .get(ANNOTATION_PATH)
+        .map(String::as_str)
+        .unwrap_or(default_path);
+    let separator = if path.starts_with('/') { "" } else { "/" };
+
+    Some(McpServer {
+        endpoint: format!(
+            "http://{}.{}.svc:{}{separator}{path}",
+            service.metadata.name, service.metadata.namespace, port.port
+        ),
+        name: name.clone(),
+        tag: annotations
+            .get(ANNOTATION_TAG)
+            .filter(|tag| !tag.is_empty())
+            .cloned(),
+        transport_type: Some(transport.to_string()),
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceList {
+    metadata: ListMeta,
+    #[serde(default)]
+    items: Vec<Service>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListMeta {
+    #[serde(rename = "resourceVersion", default)]
+    resource_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Service {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: ServiceSpec,
+}
+
+impl Service {
+    fn key(&self) -> String {
+        format!("{}/{}", self.metadata.namespace, self.metadata.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    namespace: String,
+    #[serde(rename = "resourceVersion", default)]
+    resource_version: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ServiceSpec {
+    #[serde(default)]
+    ports: Vec<ServicePort>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServicePort {
+    #[serde(default)]
+    name: String,
+    port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct Status {
+    #[serde(default)]
+    code: u16,
+    #[serde(default)]
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn service(name: &str, version: &str, annotations: Value) -> Value {
+        json!({
+            "metadata": {
+                "name": name,
+                "namespace": "mcp",
+                "resourceVersion": version,
+                "annotations": annotations,
+            },
+            "spec": {"ports": [
+                {"name": "metrics", "port": 9090},
+                {"name": "http", "port": 8080},
+            ]},
+        })
+    }
+
+    fn server(name: &str, tag: Option<&str>, endpoint: &str, transport: &str) -> McpServer {
+        McpServer {
+            endpoint: endpoint.to_string(),
+            name: name.to_string(),
+            tag: tag.map(str::to_string),
+            transport_type: Some(transport.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn event(kind: &str, object: Value) -> WatchEvent {
+        WatchEvent {
+            kind: kind.to_string(),
+            object,
+        }
+    }
+
+    #[test]
+    fn test_service_server() {
+        struct TestCase {
+            name: &'static str,
+            annotations: Value,
+            want: Option<McpServer>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "services without the name annotation are skipped",
+                annotations: json!({"mcp-center/tag": "1.0.0"}),
+                want: None,
+            },
+            TestCase {
+                name: "defaults",
+                annotations: json!({"mcp-center/name": "github"}),
+                want: Some(server(
+                    "github",
+                    None,
+                    "http://github-svc.mcp.svc:9090/sse",
+                    "sse",
+                )),
+            },
+            TestCase {
+                name: "streamable on a named port",
+                annotations: json!({
+                    "mcp-center/name": "github",
+                    "mcp-center/tag": "1.0.0",
+                    "mcp-center/transport": "streamable-http",
+                    "mcp-center/port": "http",
+                }),
+                want: Some(server(
+                    "github",
+                    Some("1.0.0"),
+                    "http://github-svc.mcp.svc:8080/mcp",
+                    "streamable",
+                )),
+            },
+            TestCase {
+                name: "port number and path",
+                annotations: json!({
+                    "mcp-center/name": "github",
+                    "mcp-center/port": "8080",
+                    "mcp-center/path": "v1/sse",
+                }),
+                want: Some(server(
+                    "github",
+                    None,
+                    "http://github-svc.mcp.svc:8080/v1/sse",
+                    "sse",
+                )),
+            },
+            TestCase {
+                name: "unknown port",
+                annotations: json!({"mcp-center/name": "github", "mcp-center/port": "grpc"}),
+                want: None,
+            },
+            TestCase {
+                name: "stdio can't be reached",
+                annotations: json!({"mcp-center/name": "github", "mcp-center/transport": "stdio"}),
+                want: None,
+            },
+        ];
+
+        for t in tests {
+            let service: Service =
+                serde_json::from_value(service("github-svc", "1", t.annotations)).unwrap();
+            assert_eq!(service_server(&service), t.want, "case: {}", t.name);
+        }
+    }
+
+    #[test]
+    fn test_apply_watch_stream() {
+        let mut state = State::default();
+        let list: ServiceList = serde_json::from_value(json!({
+            "metadata": {"resourceVersion": "10"},
+            "items": [
+                service("github-svc", "9", json!({"mcp-center/name": "github"})),
+                service("other", "8", json!({})),
+            ],
+        }))
+        .unwrap();
+        assert!(state.replace(list));
+        assert_eq!(state.resource_version.as_deref(), Some("10"));
+        assert_eq!(state.servers.len(), 1);
+
+        // recorded from `kubectl get --raw '/api/v1/namespaces/mcp/services?watch=1'`
+        let stream = [
+            event(
+                "ADDED",
+                service("gitlab-svc", "11", json!({"mcp-center/name": "gitlab"})),
+            ),
+            event(
+                "MODIFIED",
+                service(
+                    "github-svc",
+                    "12",
+                    json!({"mcp-center/name": "github", "mcp-center/tag": "2.0.0"}),
+                ),
+            ),
+            event(
+                "MODIFIED",
+                service(
+                    "other",
+                    "13",
+                    json!({"kubectl.kubernetes.io/restartedAt": "now"}),
+                ),
+            ),
+            event(
+                "BOOKMARK",
+                json!({"kind": "Service", "metadata": {"resourceVersion": "14"}}),
+            ),
+            // the annotation was removed
+            event("MODIFIED", service("gitlab-svc", "15", json!({}))),
+            event(
+                "DELETED",
+                service("github-svc", "16", json!({"mcp-center/name": "github"})),
+            ),
+        ];
+        let want_changed = [true, true, false, false, true, true];
+        let want_servers = [2, 2, 2, 2, 1, 0];
+
+        for (i, event) in stream.into_iter().enumerate() {
+            assert_eq!(state.apply(event).unwrap(), want_changed[i], "event {i}");
+            assert_eq!(state.servers.len(), want_servers[i], "event {i}");
+        }
+        assert_eq!(state.resource_version.as_deref(), Some("16"));
+
+        let gone = event(
+            "ERROR",
+            json!({"kind": "Status", "code": 410, "message": "too old resource version"}),
+        );
+        assert!(matches!(state.apply(gone), Err(WatchError::Gone)));
+        let forbidden = event("ERROR", json!({"kind": "Status", "code": 403}));
+        assert!(matches!(state.apply(forbidden), Err(WatchError::Failed(_))));
+    }
+
+    // serves the list, then a watch with one change, later watches stay open
+    async fn fake_api_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut watches = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let n = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                assert!(request.contains("/api/v1/namespaces/mcp/services"));
+                assert!(request.contains("labelSelector=app%3Dmcp"));
+
+                let body = if !request.contains("watch=1") {
+                    json!({
+                        "metadata": {"resourceVersion": "10"},
+                        "items": [service("github-svc", "9", json!({"mcp-center/name": "github"}))],
+                    })
+                    .to_string()
+                } else {
+                    watches += 1;
+                    if watches > 1 {
+                        tokio::spawn(async move {
+                            sleep(Duration::from_secs(60)).await;
+                            drop(socket);
+                        });
+                        continue;
+                    }
+                    assert!(request.contains("resourceVersion=10"));
+                    json!({"type": "MODIFIED", "object": service(
+                        "github-svc",
+                        "11",
+                        json!({"mcp-center/name": "github", "mcp-center/tag": "2.0.0"}),
+                    )})
+                    .to_string()
+                        + "\n"
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nconnection: close\r\n\r\n{body}"
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_list_and_watch() {
+        let loader = KubernetesLoader::new(&fake_api_server().await, "mcp", "app=mcp").unwrap();
+
+        let servers = loader.list_mcp().await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].tag, None);
+
+        timeout(Duration::from_secs(5), async {
+            loop {
+                loader.changed().await;
+                let servers = loader.list_mcp().await.unwrap();
+                if servers[0].tag.as_deref() == Some("2.0.0") {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("the watched change is listed");
+    }
+}
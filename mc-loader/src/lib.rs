@@ -2,9 +2,19 @@ use serde::Deserialize;
 use std::error::Error;
 
 pub mod external_api;
+pub mod kubernetes;
 pub mod local;
 
-#[derive(Default, Debug, Deserialize, Clone)]
+pub const DEFAULT_TAG: &str = "latest";
+const TRANSPORT_TYPES: [&str; 5] = [
+    "sse",
+    "streamable",
+    "streamable-http",
+    "streamable_http",
+    "stdio",
+];
+
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
 pub struct McpServer {
     pub endpoint: String,
     pub name: String,
@@ -12,9 +22,85 @@ pub struct McpServer {
     pub tag: Option<String>,
     #[serde(rename = "is_published")]
     pub is_published: Option<bool>,
+    /// `sse` when not set.
+    pub transport_type: Option<String>,
+    pub description: Option<String>,
+    /// Settings of the server kept in the `extra` column, e.g. the command of stdio servers.
+    pub extra: Option<serde_json::Value>,
+}
+
+impl McpServer {
+    /// The tag, or the version for registries without tags, `latest` when neither is set.
+    pub fn resolved_tag(&self) -> String {
+        self.tag
+            .clone()
+            .or_else(|| self.version.clone())
+            .unwrap_or_else(|| DEFAULT_TAG.to_string())
+    }
+
+    pub fn is_stdio(&self) -> bool {
+        self.transport_type
+            .as_deref()
+            .is_some_and(|transport_type| transport_type.eq_ignore_ascii_case("stdio"))
+    }
+
+    /// Checks a server read from a remote registry before it is synced. Stdio servers
+    /// are refused, their command would run on the host of the proxy.
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_local()?;
+        if self.is_stdio() {
+            return Err(format!(
+                "stdio server {} can only be defined in the local file",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks a server of the local file, which is the only source of stdio servers.
+    pub fn validate_local(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if self.name.contains('/') {
+            return Err(format!("name {} must not contain /", self.name));
+        }
+        let transport_type = self
+            .transport_type
+            .as_deref()
+            .unwrap_or("sse")
+            .to_lowercase();
+        if !TRANSPORT_TYPES.contains(&transport_type.as_str()) {
+            return Err(format!(
+                "unknown transport_type {}, expected one of {}",
+                self.transport_type.as_deref().unwrap_or_default(),
+                TRANSPORT_TYPES.join(", ")
+            ));
+        }
+        // stdio servers are spawned from `extra`
+        if transport_type != "stdio"
+            && !self.endpoint.starts_with("http://")
+            && !self.endpoint.starts_with("https://")
+        {
+            return Err(format!(
+                "endpoint {:?} of {} is not an http(s) URL",
+                self.endpoint, self.name
+            ));
+        }
+        if self.extra.as_ref().is_some_and(|extra| !extra.is_object()) {
+            return Err(format!("extra of {} must be an object", self.name));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
 pub trait Loader: Send + Sync {
     async fn list_mcp(&self) -> Result<Vec<McpServer>, Box<dyn Error>>;
+
+    /// Resolves when the servers may have changed since the last list. Loaders
+    /// without notifications never resolve, they are polled.
+    async fn changed(&self) {
+        std::future::pending::<()>().await
+    }
 }
@@ -1,53 +1,398 @@
 use crate::{Loader, McpServer};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::interval;
+use toml::Spanned;
 
-#[derive(Deserialize, Default, Clone)]
+// saved edits are picked up within this time
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize, Default)]
 struct McpServers {
-    mcp_servers: Vec<McpServer>,
+    #[serde(default)]
+    mcp_servers: Vec<Spanned<McpServer>>,
 }
 
-#[derive(Debug, Default, Clone)]
+/// Servers by name and tag.
+type Servers = BTreeMap<(String, String), McpServer>;
+
+/// Servers defined in a TOML file, reloaded whenever the file changes. Edits
+/// which fail to parse or validate are rejected, the last good servers stay.
+#[derive(Debug)]
 pub struct LocalFileLoader {
-    mcp_servers: Vec<McpServer>,
+    file: Arc<WatchedFile>,
+    watching: AtomicBool,
 }
 
 impl LocalFileLoader {
     pub fn new(path: String) -> Self {
-        let mut registry = Self {
-            mcp_servers: Vec::new(),
+        let file = WatchedFile {
+            path,
+            state: Mutex::new(FileState::default()),
+            notify: Notify::new(),
         };
-        let mcp_servers = load(path.as_str());
-        registry.mcp_servers = mcp_servers;
-        registry
+        file.reload();
+        Self {
+            file: Arc::new(file),
+            watching: AtomicBool::new(false),
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl Loader for LocalFileLoader {
     async fn list_mcp(&self) -> Result<Vec<McpServer>, Box<dyn Error>> {
-        Ok(self.mcp_servers.clone())
+        if !self.watching.swap(true, Ordering::SeqCst) {
+            let file = self.file.clone();
+            tokio::spawn(async move {
+                let mut ticker = interval(POLL_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    if file.reload() {
+                        file.notify.notify_one();
+                    }
+                }
+            });
+        }
+
+        // without any good content the registry is left as it is
+        match &self.file.state.lock().unwrap().servers {
+            Some(servers) => Ok(servers.values().cloned().collect()),
+            None => Err(format!("{} has no valid servers", self.file.path).into()),
+        }
+    }
+
+    async fn changed(&self) {
+        self.file.notify.notified().await
+    }
+}
+
+#[derive(Debug)]
+struct WatchedFile {
+    path: String,
+    state: Mutex<FileState>,
+    notify: Notify,
+}
+
+#[derive(Debug, Default)]
+struct FileState {
+    /// Content last read, the file is parsed again when it differs.
+    content: Option<String>,
+    /// Servers of the last good content, `None` before there was any.
+    servers: Option<Servers>,
+    /// Last failure to read the file, logged once.
+    read_error: Option<String>,
+}
+
+impl WatchedFile {
+    /// Reads the file and applies changed content, returns whether the servers changed.
+    fn reload(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) => {
+                let err = err.to_string();
+                if state.read_error.as_ref() != Some(&err) {
+                    match state.servers {
+                        Some(_) => tracing::error!(
+                            "Failed to read {}, keeping the last servers, error: {err}",
+                            self.path
+                        ),
+                        // an unreadable file is no reason to remove the registered servers
+                        None => tracing::error!(
+                            "Failed to read {}, the registry is left unchanged, error: {err}",
+                            self.path
+                        ),
+                    }
+                    state.read_error = Some(err);
+                }
+                return false;
+            }
+        };
+        state.read_error = None;
+        if state.content.as_ref() == Some(&content) {
+            return false;
+        }
+
+        let servers = parse(&content);
+        state.content = Some(content);
+        let servers = match servers {
+            Ok(servers) => servers,
+            Err(errors) => {
+                for error in errors {
+                    tracing::error!("Rejected {}, {error}", self.path);
+                }
+                if state.servers.is_some() {
+                    tracing::error!("Keeping the last servers of {}", self.path);
+                }
+                return false;
+            }
+        };
+
+        let changes = diff(state.servers.as_ref(), &servers);
+        tracing::info!(
+            "Loaded {} servers from {}, added: {:?}, updated: {:?}, removed: {:?}",
+            servers.len(),
+            self.path,
+            changes.added,
+            changes.updated,
+            changes.removed
+        );
+        // the first content is reported even without servers, the registry is synced to it
+        let changed = state.servers.is_none() || !changes.is_empty();
+        state.servers = Some(servers);
+        changed
+    }
+}
+
+/// Parses and validates the servers of a file, every error names its line.
+fn parse(content: &str) -> Result<Servers, Vec<String>> {
+    let file: McpServers = toml::from_str(content).map_err(|err| {
+        let message = err.message().to_string();
+        match err.span() {
+            Some(span) => vec![format!("line {}: {message}", line(content, span.start))],
+            None => vec![message],
+        }
+    })?;
+
+    let mut servers = Servers::new();
+    let mut lines = BTreeMap::new();
+    let mut errors = vec![];
+    for entry in file.mcp_servers {
+        let line = line(content, entry.span().start);
+        let server = entry.into_inner();
+        match server.validate_local() {
+            Err(err) => errors.push(format!("line {line}: {err}")),
+            Ok(()) => {
+                let key = (server.name.clone(), server.resolved_tag());
+                if let Some(first) = lines.get(&key) {
+                    errors.push(format!(
+                        "line {line}: {}/{} is already defined on line {first}",
+                        key.0, key.1
+                    ));
+                    continue;
+                }
+                lines.insert(key.clone(), line);
+                servers.insert(key, server);
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(servers),
+        false => Err(errors),
+    }
+}
+
+fn line(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())]
+        .bytes()
+        .filter(|byte| *byte == b'\n')
+        .count()
+        + 1
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    added: Vec<String>,
+    updated: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
     }
 }
 
-fn load(path: &str) -> Vec<McpServer> {
-    let content = fs::read_to_string(path)
-        .map_err(|e| {
-            tracing::error!("Failed to read config file {}: {}", path, e);
-            e
-        })
-        .unwrap_or_default();
+fn diff(previous: Option<&Servers>, servers: &Servers) -> Changes {
+    let empty = Servers::new();
+    let previous = previous.unwrap_or(&empty);
+    let mut changes = Changes::default();
+    for (key, server) in servers {
+        match previous.get(key) {
+            None => changes.added.push(format!("{}/{}", key.0, key.1)),
+            Some(before) if before != server => {
+                changes.updated.push(format!("{}/{}", key.0, key.1))
+            }
+            Some(_) => {}
+        }
+    }
+    for key in previous.keys() {
+        if !servers.contains_key(key) {
+            changes.removed.push(format!("{}/{}", key.0, key.1));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SERVERS: &str = r#"
+[[mcp_servers]]
+endpoint = "http://127.0.0.1:8080/sse"
+name = "github"
+tag = "1.0.0"
+
+[[mcp_servers]]
+endpoint = "stdio://files"
+name = "files"
+transport_type = "stdio"
+description = "Local files"
+[mcp_servers.extra]
+command = "npx"
+args = ["-y", "@modelcontextprotocol/server-filesystem", "/data"]
+"#;
+
+    #[test]
+    fn test_parse() {
+        let servers = parse(SERVERS).unwrap();
+        assert_eq!(servers.len(), 2);
+
+        let files = &servers[&("files".to_string(), "latest".to_string())];
+        assert_eq!(files.description.as_deref(), Some("Local files"));
+        assert_eq!(
+            files.extra,
+            Some(json!({
+                "command": "npx",
+                "args": ["-y", "@modelcontextprotocol/server-filesystem", "/data"],
+            }))
+        );
+
+        // transport types are case-insensitive, stdio ones have no http endpoint either way
+        let servers = parse(&SERVERS.replace(r#""stdio""#, r#""STDIO""#)).unwrap();
+        assert!(servers[&("files".to_string(), "latest".to_string())].is_stdio());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        struct TestCase {
+            content: &'static str,
+            want: Vec<&'static str>,
+        }
+
+        let tests = vec![
+            TestCase {
+                content: "[[mcp_servers]]\nendpoint = \"http://a/sse\"\nname = \"a\nname = \"b\"\n",
+                want: vec!["line 3: "],
+            },
+            TestCase {
+                content: "[[mcp_servers]]\nendpoint = \"http://a/sse\"\nname = 1\n",
+                want: vec!["line 3: "],
+            },
+            TestCase {
+                content: r#"
+[[mcp_servers]]
+endpoint = "http://a/sse"
+name = "a"
 
-    let servers: McpServers = toml::from_str(&content)
-        .map_err(|e| {
-            tracing::error!("Failed to parse TOML config: {}", e);
-            e
-        })
-        .unwrap_or_default();
+[[mcp_servers]]
+endpoint = "a:8080"
+name = "b"
 
-    tracing::info!("Loaded config file {}", path);
-    tracing::info!("Loaded servers {:?}", servers.mcp_servers);
+[[mcp_servers]]
+endpoint = "http://a/mcp"
+name = "c"
+transport_type = "websocket"
 
-    servers.mcp_servers.clone()
+[[mcp_servers]]
+endpoint = "http://a2/sse"
+name = "a"
+tag = "latest"
+"#,
+                want: vec![
+                    "line 6: endpoint \"a:8080\" of b is not an http(s) URL",
+                    "line 10: unknown transport_type websocket",
+                    "line 15: a/latest is already defined on line 2",
+                ],
+            },
+        ];
+
+        for t in tests {
+            let errors = parse(t.content).unwrap_err();
+            assert_eq!(errors.len(), t.want.len(), "{errors:?}");
+            for (error, want) in errors.iter().zip(t.want) {
+                assert!(error.starts_with(want), "{error} should start with {want}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut previous = parse(SERVERS).unwrap();
+        assert_eq!(
+            diff(None, &previous),
+            Changes {
+                added: vec!["files/latest".to_string(), "github/1.0.0".to_string()],
+                ..Default::default()
+            }
+        );
+        assert!(diff(Some(&previous), &previous).is_empty());
+
+        let mut servers = previous.clone();
+        servers.remove(&("files".to_string(), "latest".to_string()));
+        let github = servers
+            .get_mut(&("github".to_string(), "1.0.0".to_string()))
+            .unwrap();
+        github.endpoint = "http://127.0.0.1:8081/sse".to_string();
+        previous.remove(&("github".to_string(), "1.0.0".to_string()));
+        assert_eq!(
+            diff(Some(&previous), &servers),
+            Changes {
+                added: vec!["github/1.0.0".to_string()],
+                updated: vec![],
+                removed: vec!["files/latest".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("mcp_servers_{}.toml", std::process::id()));
+        let file = WatchedFile {
+            path: path.to_string_lossy().to_string(),
+            state: Mutex::new(FileState::default()),
+            notify: Notify::new(),
+        };
+        let servers = |file: &WatchedFile| {
+            file.state
+                .lock()
+                .unwrap()
+                .servers
+                .as_ref()
+                .map(|servers| servers.len())
+        };
+
+        // a missing file loads nothing, the registry isn't synced to it
+        assert!(!file.reload());
+        assert_eq!(servers(&file), None);
+
+        fs::write(&path, SERVERS).unwrap();
+        assert!(file.reload());
+        assert_eq!(servers(&file), Some(2));
+        assert!(!file.reload());
+
+        // a bad edit keeps the last servers
+        fs::write(&path, "[[mcp_servers]]\nname = ").unwrap();
+        assert!(!file.reload());
+        assert_eq!(servers(&file), Some(2));
+
+        fs::write(&path, SERVERS.replace("8080", "8081")).unwrap();
+        assert!(file.reload());
+        assert_eq!(servers(&file), Some(2));
+
+        fs::remove_file(&path).unwrap();
+        assert!(!file.reload());
+        assert_eq!(servers(&file), Some(2));
+    }
 }
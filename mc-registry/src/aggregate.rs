@@ -0,0 +1,231 @@
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use mc_common::app::{AppState, Response};
+use mc_db::AggregateDBHandler;
+use mc_db::model::{AggregateMember, McpAggregates, NAMESPACE_SEPARATOR};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListAggregatesResponse {
+    aggregates: Vec<McpAggregates>,
+    count: usize,
+}
+
+pub async fn list_aggregates(
+    State(state): State<AppState>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let aggregate_handler = get_aggregate_handler(&state)?;
+
+    let aggregates = aggregate_handler.list_all().await.map_err(|e| {
+        tracing::error!("Failed to list aggregates {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to list aggregates".to_string(),
+        )
+    })?;
+
+    let count = aggregates.len();
+    let data = serde_json::to_value(ListAggregatesResponse { aggregates, count }).map_err(|e| {
+        tracing::error!("Failed to parse aggregates {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AggregateCreateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub members: Vec<AggregateMember>,
+}
+
+pub async fn create_aggregate(
+    State(state): State<AppState>,
+    Json(request): Json<AggregateCreateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let aggregate_handler = get_aggregate_handler(&state)?;
+    validate_members(&request.members)?;
+
+    let res = aggregate_handler
+        .create(&McpAggregates {
+            id: Uuid::new_v4(),
+            name: request.name.clone(),
+            description: request.description,
+            members: members_value(&request.members)?,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create aggregate {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create aggregate".to_string(),
+            )
+        })?;
+    tracing::info!("Aggregate {} created", request.name);
+
+    build_response(res)
+}
+
+pub async fn get_aggregate(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let aggregate_handler = get_aggregate_handler(&state)?;
+
+    let res = aggregate_handler
+        .get(&name)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get aggregate {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get aggregate".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name))?;
+
+    build_response(res)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AggregateUpdateRequest {
+    #[serde(default)]
+    pub description: String,
+    pub members: Vec<AggregateMember>,
+}
+
+pub async fn update_aggregate(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<AggregateUpdateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let aggregate_handler = get_aggregate_handler(&state)?;
+    validate_members(&request.members)?;
+
+    let res = aggregate_handler
+        .update(&McpAggregates {
+            id: Default::default(),
+            name: name.clone(),
+            description: request.description,
+            members: members_value(&request.members)?,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update aggregate {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update aggregate".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name))?;
+    tracing::info!("Aggregate {} updated", name);
+
+    build_response(res)
+}
+
+pub async fn delete_aggregate(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let aggregate_handler = get_aggregate_handler(&state)?;
+
+    let res = aggregate_handler
+        .delete(&name)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete aggregate {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete aggregate".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name))?;
+    tracing::info!("Aggregate {} deleted", name);
+
+    build_response(res)
+}
+
+// namespaces route `{namespace}__{tool}` back to a member, so they must be unique
+// and must not contain the separator themselves
+fn validate_members(members: &[AggregateMember]) -> Result<(), (StatusCode, String)> {
+    if members.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Aggregate requires at least one member".to_string(),
+        ));
+    }
+
+    let mut namespaces = HashSet::new();
+    for member in members {
+        let namespace = member.namespace();
+        if namespace.is_empty() || namespace.contains(NAMESPACE_SEPARATOR) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Invalid namespace {namespace:?} of member {}/{}, it must be non-empty and must not contain {NAMESPACE_SEPARATOR:?}",
+                    member.name, member.tag
+                ),
+            ));
+        }
+        if !namespaces.insert(namespace) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Duplicate member namespace {namespace:?}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn members_value(members: &[AggregateMember]) -> Result<serde_json::Value, (StatusCode, String)> {
+    serde_json::to_value(members).map_err(|e| {
+        tracing::error!("Failed to serialize aggregate members {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })
+}
+
+fn get_aggregate_handler(
+    state: &AppState,
+) -> Result<&Arc<AggregateDBHandler>, (StatusCode, String)> {
+    state.handlers().aggregate_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get aggregate handler not found".to_string(),
+        )
+    })
+}
+
+fn not_found(name: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Aggregate {name} not found"))
+}
+
+fn build_response(aggregate: McpAggregates) -> Result<Json<Response>, (StatusCode, String)> {
+    let data = serde_json::to_value(aggregate).map_err(|e| {
+        tracing::error!("Failed to parse aggregate {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
@@ -1,6 +1,8 @@
+mod aggregate;
 mod mcp_server;
 
-use axum::routing::{get, post};
+pub use aggregate::*;
+use axum::routing::{get, post, put};
 use mc_common::app::AppState;
 use mc_common::router;
 pub use mcp_server::*;
@@ -10,5 +12,34 @@ pub fn register_router() -> router::RouterHandler<AppState> {
         router
             .route("/api/registry/mcp-server", get(list_all))
             .route("/api/registry/mcp-server", post(register_mcp_server))
+            .route(
+                "/api/registry/mcp-server/{name}/{tag}",
+                get(get_mcp_server)
+                    .put(update_mcp_server)
+                    .patch(patch_mcp_server)
+                    .delete(delete_mcp_server),
+            )
+            .route(
+                "/api/registry/mcp-server/{name}/{tag}/enable",
+                post(enable_mcp_server),
+            )
+            .route(
+                "/api/registry/mcp-server/{name}/{tag}/disable",
+                post(disable_mcp_server),
+            )
+            .route(
+                "/api/registry/mcp-server/{name}/{tag}/credential",
+                put(set_mcp_server_credential).delete(delete_mcp_server_credential),
+            )
+            .route(
+                "/api/registry/aggregate",
+                get(list_aggregates).post(create_aggregate),
+            )
+            .route(
+                "/api/registry/aggregate/{name}",
+                get(get_aggregate)
+                    .put(update_aggregate)
+                    .delete(delete_aggregate),
+            )
     })
 }
@@ -1,10 +1,17 @@
-use axum::Json;
-use axum::extract::{Query, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
+use axum::{Extension, Json};
+use mc_common::app::balancer::ServerHealth;
+use mc_common::app::cache::check_upstream;
 use mc_common::app::event::Event;
-use mc_common::app::{AppState, Response};
+use mc_common::app::{Admin, AppState, Response};
+use mc_common::credential::UpstreamCredential;
+use mc_common::types::TransportType;
 use mc_db::model::{CreateFrom, McpServers, SettingKey};
+use mc_db::{McpDBHandler, McpListFilter};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
@@ -12,16 +19,27 @@ pub struct ListAllRequest {
     use_raw_endpoint: Option<bool>,
     page_size: Option<i64>,
     page_num: Option<i64>,
+    include_deleted: Option<bool>,
+    include_disabled: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ListAllResponse {
-    servers: Vec<McpServers>,
+    servers: Vec<McpServerEntry>,
     count: i64,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct McpServerEntry {
+    #[serde(flatten)]
+    server: McpServers,
+    /// Absent for servers which are not served, deleted or disabled ones.
+    health: Option<ServerHealth>,
+}
+
 pub async fn list_all(
     State(state): State<AppState>,
+    admin: Option<Extension<Admin>>,
     Query(request): Query<ListAllRequest>,
 ) -> Result<Json<Response>, (StatusCode, String)> {
     if request.page_size.is_some() ^ request.page_num.is_some() {
@@ -33,6 +51,12 @@ pub async fn list_all(
 
     let page_size = request.page_size.unwrap_or(0);
     let page_num = request.page_num.unwrap_or(0);
+    // deleted and disabled servers are only listed to admins
+    let admin = admin.is_some();
+    let filter = McpListFilter {
+        include_deleted: admin && request.include_deleted.unwrap_or(false),
+        include_disabled: admin && request.include_disabled.unwrap_or(false),
+    };
 
     let mcp_handler = match &state.handlers().mcp_handler {
         None => {
@@ -61,7 +85,7 @@ pub async fn list_all(
     // select mcp servers
     let mut servers = if page_size > 0 && page_num > 0 {
         mcp_handler
-            .list_with_limit(page_size, (page_num - 1) * page_size)
+            .list_with_limit(filter, page_size, (page_num - 1) * page_size)
             .await
             .map_err(|e| {
                 tracing::error!("Failed to list mcp servers {}", e);
@@ -71,7 +95,7 @@ pub async fn list_all(
                 )
             })?
     } else {
-        mcp_handler.list_all().await.map_err(|e| {
+        mcp_handler.list_all(filter).await.map_err(|e| {
             tracing::error!("Failed to list mcp servers {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -90,7 +114,7 @@ pub async fn list_all(
         })
     }
 
-    let count = mcp_handler.count().await.map_err(|e| {
+    let count = mcp_handler.count(filter).await.map_err(|e| {
         tracing::error!("Failed to count mcp servers {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -98,7 +122,17 @@ pub async fn list_all(
         )
     })?;
 
-    let data = serde_json::to_value(ListAllResponse { servers, count }).map_err(|e| {
+    let mut entries = Vec::with_capacity(servers.len());
+    for server in servers {
+        let health = state.mcp_cache.health(&server.name, &server.tag).await;
+        entries.push(McpServerEntry { server, health });
+    }
+
+    let data = serde_json::to_value(ListAllResponse {
+        servers: entries,
+        count,
+    })
+    .map_err(|e| {
         tracing::error!("Failed to parse mcp servers {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -122,8 +156,16 @@ pub struct McpRegisterRequest {
 
 pub async fn register_mcp_server(
     State(state): State<AppState>,
+    admin: Option<Extension<Admin>>,
     Json(server): Json<McpRegisterRequest>,
 ) -> Result<Json<Response>, (StatusCode, String)> {
+    validate_server(
+        &server.endpoint,
+        &server.transport_type,
+        server.extra.as_ref(),
+        admin.is_some(),
+    )?;
+
     let mcp_handler = match &state.handlers().mcp_handler {
         None => {
             return Err((
@@ -142,13 +184,14 @@ pub async fn register_mcp_server(
             endpoint: server.endpoint.clone(),
             transport_type: server.transport_type.clone(),
             description: server.description.clone(),
-            create_from: if server.create_from.is_some() {
-                server.create_from.unwrap().clone()
-            } else {
-                CreateFrom::Register.to_string()
-            },
+            create_from: server
+                .create_from
+                .clone()
+                .unwrap_or_else(|| CreateFrom::Register.to_string()),
             extra: server.extra.clone(),
             disabled: Default::default(),
+            credential_type: None,
+            credential: None,
             created_at: Default::default(),
             updated_at: Default::default(),
             deleted_at: None,
@@ -167,6 +210,9 @@ pub async fn register_mcp_server(
             mcp_name: server.name.clone(),
             tag: server.tag.clone(),
             endpoint: server.endpoint.clone(),
+            transport_type: server.transport_type.clone(),
+            extra: server.extra.clone(),
+            credential: None,
         }) {
             tracing::error!("Failed to send event {}", err);
         }
@@ -183,3 +229,507 @@ pub async fn register_mcp_server(
 
     Ok(Json(Response::new(Some(data))))
 }
+
+pub async fn get_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    let server = mcp_handler
+        .get(&name, &tag)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get mcp server {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get mcp server".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name, &tag))?;
+
+    build_response(server)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct McpUpdateRequest {
+    pub endpoint: String,
+    pub transport_type: String,
+    pub description: String,
+    pub extra: Option<serde_json::Value>,
+    pub disabled: Option<bool>,
+}
+
+pub async fn update_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+    admin: Option<Extension<Admin>>,
+    Json(request): Json<McpUpdateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    validate_server(
+        &request.endpoint,
+        &request.transport_type,
+        request.extra.as_ref(),
+        admin.is_some(),
+    )?;
+
+    let mut server = find_mcp_server(mcp_handler, &name, &tag).await?;
+    server.endpoint = request.endpoint;
+    server.transport_type = request.transport_type;
+    server.description = request.description;
+    server.extra = request.extra;
+    server.disabled = request.disabled.unwrap_or(server.disabled);
+
+    let res = save_mcp_server(mcp_handler, &server).await?;
+
+    send_event(&state, Event::from(&res));
+    tracing::info!("MCP server {}/{} updated", name, tag);
+
+    build_response(res)
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct McpPatchRequest {
+    pub endpoint: Option<String>,
+    pub transport_type: Option<String>,
+    pub description: Option<String>,
+    pub extra: Option<serde_json::Value>,
+    pub disabled: Option<bool>,
+}
+
+impl McpPatchRequest {
+    fn apply(self, server: &mut McpServers) {
+        if let Some(endpoint) = self.endpoint {
+            server.endpoint = endpoint;
+        }
+        if let Some(transport_type) = self.transport_type {
+            server.transport_type = transport_type;
+        }
+        if let Some(description) = self.description {
+            server.description = description;
+        }
+        if let Some(extra) = self.extra {
+            server.extra = Some(extra);
+        }
+        if let Some(disabled) = self.disabled {
+            server.disabled = disabled;
+        }
+    }
+}
+
+pub async fn patch_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+    admin: Option<Extension<Admin>>,
+    Json(request): Json<McpPatchRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    let mut server = find_mcp_server(mcp_handler, &name, &tag).await?;
+    request.apply(&mut server);
+    // the merged row is checked, a new transport type may not fit the stored endpoint
+    validate_server(
+        &server.endpoint,
+        &server.transport_type,
+        server.extra.as_ref(),
+        admin.is_some(),
+    )?;
+
+    let res = save_mcp_server(mcp_handler, &server).await?;
+
+    send_event(&state, Event::from(&res));
+    tracing::info!("MCP server {}/{} patched", name, tag);
+
+    build_response(res)
+}
+
+pub async fn delete_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    let res = mcp_handler
+        .delete(&name, &tag)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete mcp server {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete mcp server".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name, &tag))?;
+
+    send_event(
+        &state,
+        Event::Delete {
+            mcp_name: name.clone(),
+            tag: tag.clone(),
+        },
+    );
+    tracing::info!("MCP server {}/{} deleted", name, tag);
+
+    build_response(res)
+}
+
+pub async fn enable_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    set_mcp_server_disabled(state, name, tag, false).await
+}
+
+pub async fn disable_mcp_server(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    set_mcp_server_disabled(state, name, tag, true).await
+}
+
+async fn set_mcp_server_disabled(
+    state: AppState,
+    name: String,
+    tag: String,
+    disabled: bool,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    let res = mcp_handler
+        .set_disabled(&name, &tag, disabled)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update mcp server {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update mcp server".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name, &tag))?;
+
+    send_event(&state, Event::from(&res));
+    tracing::info!("MCP server {}/{} disabled={}", name, tag, disabled);
+
+    build_response(res)
+}
+
+/// Stores the credential the proxy presents to the upstream server, encrypted.
+pub async fn set_mcp_server_credential(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+    Json(credential): Json<UpstreamCredential>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let cipher = state.credential_cipher.as_ref().ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            "Credential key is not configured".to_string(),
+        )
+    })?;
+    credential
+        .header()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let sealed = cipher.encrypt(&name, &tag, &credential).map_err(|e| {
+        tracing::error!("Failed to encrypt credential {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    save_mcp_server_credential(state, name, tag, Some((credential.kind(), &sealed))).await
+}
+
+pub async fn delete_mcp_server_credential(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    save_mcp_server_credential(state, name, tag, None).await
+}
+
+async fn save_mcp_server_credential(
+    state: AppState,
+    name: String,
+    tag: String,
+    credential: Option<(&str, &[u8])>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let mcp_handler = get_mcp_handler(&state)?;
+
+    let res = mcp_handler
+        .set_credential(&name, &tag, credential)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update mcp server credential {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update mcp server credential".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&name, &tag))?;
+
+    send_event(&state, Event::from(&res));
+    tracing::info!(
+        "MCP server {}/{} credential set to {:?}",
+        name,
+        tag,
+        res.credential_type
+    );
+
+    build_response(res)
+}
+
+fn get_mcp_handler(state: &AppState) -> Result<&Arc<McpDBHandler>, (StatusCode, String)> {
+    state.handlers().mcp_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get MCP handler not found".to_string(),
+        )
+    })
+}
+
+async fn find_mcp_server(
+    mcp_handler: &McpDBHandler,
+    name: &str,
+    tag: &str,
+) -> Result<McpServers, (StatusCode, String)> {
+    mcp_handler
+        .get(name, tag)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get mcp server {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get mcp server".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(name, tag))
+}
+
+async fn save_mcp_server(
+    mcp_handler: &McpDBHandler,
+    server: &McpServers,
+) -> Result<McpServers, (StatusCode, String)> {
+    mcp_handler
+        .update(server)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update mcp server {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update mcp server".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&server.name, &server.tag))
+}
+
+// rows the proxy can't parse would be dropped from the cache, they are rejected up front
+fn validate_server(
+    endpoint: &str,
+    transport_type: &str,
+    extra: Option<&serde_json::Value>,
+    admin: bool,
+) -> Result<(), (StatusCode, String)> {
+    check_upstream(endpoint, transport_type, extra)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid mcp server: {e}")))?;
+
+    // stdio servers run their command on the proxy host
+    if !admin && TransportType::from_str(transport_type).is_ok_and(|t| t.is_stdio()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only admins may register stdio servers".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn not_found(name: &str, tag: &str) -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        format!("MCP server {name}/{tag} not found"),
+    )
+}
+
+fn send_event(state: &AppState, event: Event) {
+    if let Err(err) = state.event_sender.send(event) {
+        tracing::error!("Failed to send event {}", err);
+    }
+}
+
+fn build_response(server: McpServers) -> Result<Json<Response>, (StatusCode, String)> {
+    let data = serde_json::to_value(server).map_err(|e| {
+        tracing::error!("Failed to parse mcp servers {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stored(endpoint: &str, transport_type: &str) -> McpServers {
+        McpServers {
+            id: Uuid::new_v4(),
+            name: "example".to_string(),
+            tag: "latest".to_string(),
+            endpoint: endpoint.to_string(),
+            transport_type: transport_type.to_string(),
+            description: String::new(),
+            create_from: CreateFrom::Register.to_string(),
+            extra: None,
+            disabled: false,
+            credential_type: None,
+            credential: None,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_server() {
+        struct TestCase {
+            name: &'static str,
+            endpoint: &'static str,
+            transport_type: &'static str,
+            extra: Option<serde_json::Value>,
+            admin: bool,
+            want: Option<(StatusCode, &'static str)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "sse server",
+                endpoint: "http://127.0.0.1:8080/sse",
+                transport_type: "sse",
+                extra: None,
+                admin: false,
+                want: None,
+            },
+            TestCase {
+                name: "streamable server",
+                endpoint: "https://mcp.example.com/mcp",
+                transport_type: "streamable-http",
+                extra: None,
+                admin: false,
+                want: None,
+            },
+            TestCase {
+                name: "unknown transport type",
+                endpoint: "http://127.0.0.1:8080/ws",
+                transport_type: "websocket",
+                extra: None,
+                admin: false,
+                want: Some((StatusCode::BAD_REQUEST, "Unknown transport type: websocket")),
+            },
+            TestCase {
+                name: "endpoint without scheme",
+                endpoint: "127.0.0.1:8080/sse",
+                transport_type: "sse",
+                extra: None,
+                admin: false,
+                want: Some((
+                    StatusCode::BAD_REQUEST,
+                    "Failed to parse endpoint 127.0.0.1:8080/sse",
+                )),
+            },
+            TestCase {
+                name: "unsupported scheme",
+                endpoint: "ftp://127.0.0.1/sse",
+                transport_type: "sse",
+                extra: None,
+                admin: false,
+                want: Some((
+                    StatusCode::BAD_REQUEST,
+                    "Failed to parse endpoint ftp://127.0.0.1/sse",
+                )),
+            },
+            TestCase {
+                name: "invalid load balancing instance",
+                endpoint: "http://a:8080/sse",
+                transport_type: "sse",
+                extra: Some(json!({"load_balancing": {"instances": [{"endpoint": "b:8080"}]}})),
+                admin: false,
+                want: Some((StatusCode::BAD_REQUEST, "Failed to parse endpoint b:8080")),
+            },
+            TestCase {
+                name: "stdio server of an admin",
+                endpoint: "stdio://files",
+                transport_type: "stdio",
+                extra: Some(json!({"command": "npx", "args": ["-y", "server-filesystem"]})),
+                admin: true,
+                want: None,
+            },
+            TestCase {
+                name: "stdio server of another caller",
+                endpoint: "stdio://files",
+                transport_type: "stdio",
+                extra: Some(json!({"command": "npx"})),
+                admin: false,
+                want: Some((StatusCode::FORBIDDEN, "Only admins")),
+            },
+            TestCase {
+                name: "stdio server without command",
+                endpoint: "stdio://files",
+                transport_type: "stdio",
+                extra: Some(json!({"args": ["-y"]})),
+                admin: true,
+                want: Some((StatusCode::BAD_REQUEST, "invalid stdio server config")),
+            },
+            TestCase {
+                name: "stdio server without extra",
+                endpoint: "stdio://files",
+                transport_type: "stdio",
+                extra: None,
+                admin: true,
+                want: Some((StatusCode::BAD_REQUEST, "stdio server requires `extra`")),
+            },
+        ];
+
+        for t in tests {
+            let res = validate_server(t.endpoint, t.transport_type, t.extra.as_ref(), t.admin);
+            match t.want {
+                None => assert!(res.is_ok(), "case: {}, {:?}", t.name, res),
+                Some((want_status, want)) => {
+                    let (status, message) = res.unwrap_err();
+                    assert_eq!(status, want_status, "case: {}", t.name);
+                    assert!(message.contains(want), "case: {}, {message}", t.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_patch_validation() {
+        // the patched row is validated as a whole
+        let mut server = stored("http://127.0.0.1:8080/sse", "sse");
+        McpPatchRequest {
+            transport_type: Some("grpc".to_string()),
+            ..Default::default()
+        }
+        .apply(&mut server);
+        assert_eq!(
+            validate_server(&server.endpoint, &server.transport_type, None, false)
+                .unwrap_err()
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+
+        let mut server = stored("http://127.0.0.1:8080/sse", "sse");
+        McpPatchRequest {
+            endpoint: Some("http://127.0.0.1:8080/mcp".to_string()),
+            transport_type: Some("streamable".to_string()),
+            ..Default::default()
+        }
+        .apply(&mut server);
+        assert_eq!(server.endpoint, "http://127.0.0.1:8080/mcp");
+        assert!(validate_server(&server.endpoint, &server.transport_type, None, false).is_ok());
+    }
+}
@@ -0,0 +1,564 @@
+use crate::reverse_proxy::headers::HeaderRules;
+use crate::reverse_proxy::set_upstream_credentials;
+use crate::reverse_proxy::sse::{SseEvent, SseParser};
+use crate::reverse_proxy::streamable::{HEADER_MCP_SESSION_ID, is_event_stream, session_id};
+use crate::stdio::{StdioManager, is_response};
+use crate::telemetry;
+use axum::body::Body;
+use http::{HeaderMap, Method, Request, StatusCode};
+use http_body_util::{BodyDataStream, BodyExt};
+use hyper::body::Incoming;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use mc_common::app::cache::McpServerInfo;
+use mc_common::types::TransportType;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+pub const CODE_METHOD_NOT_FOUND: i64 = -32601;
+pub const CODE_INVALID_PARAMS: i64 = -32602;
+pub const CODE_INTERNAL_ERROR: i64 = -32603;
+
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+// every call runs on its own upstream session, so ids are fixed
+const INITIALIZE_ID: i64 = 0;
+const REQUEST_ID: i64 = 1;
+
+// upper bound of a whole call, handshake included
+const CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(CODE_INTERNAL_ERROR, message)
+    }
+
+    pub fn to_value(&self) -> Value {
+        json!({"code": self.code, "message": self.message})
+    }
+}
+
+/// A minimal MCP client calling a single method on a registered server,
+/// speaking whichever transport the server is registered with.
+#[derive(Clone)]
+pub struct McpClient {
+    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    stdio: StdioManager,
+}
+
+impl McpClient {
+    pub fn new(
+        client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+        stdio: StdioManager,
+    ) -> Self {
+        Self { client, stdio }
+    }
+
+    /// Initializes a session, sends `method` and returns the JSON-RPC `result`.
+    pub async fn call(
+        &self,
+        name: &str,
+        tag: &str,
+        server: &McpServerInfo,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        let result = match server.transport_type {
+            TransportType::Sse => with_timeout(self.call_sse(server, method, params)).await,
+            TransportType::Streamable => {
+                with_timeout(self.call_streamable(server, method, params)).await
+            }
+            TransportType::Stdio => self.call_stdio(name, tag, server, method, params).await,
+        };
+
+        if let Err(err) = &result {
+            tracing::warn!(
+                "Failed to call {} on {}/{}, code {}, error {}",
+                method,
+                name,
+                tag,
+                err.code,
+                err.message
+            );
+        }
+        result
+    }
+
+    async fn call_streamable(
+        &self,
+        server: &McpServerInfo,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        let (sid, response) = self
+            .post_streamable(server, None, &initialize_request())
+            .await?;
+        into_result(response.ok_or_else(|| RpcError::internal("No response to initialize"))?)?;
+        self.post_streamable(server, sid.as_deref(), &initialized_notification())
+            .await?;
+
+        let result = self
+            .post_streamable(
+                server,
+                sid.as_deref(),
+                &build_request(REQUEST_ID, method, params),
+            )
+            .await;
+        if let Some(sid) = sid {
+            self.terminate(server, sid);
+        }
+
+        let (_, response) = result?;
+        into_result(response.ok_or_else(|| RpcError::internal(format!("No response to {method}")))?)
+    }
+
+    // returns the session id of the response and, for requests, the JSON-RPC response
+    async fn post_streamable(
+        &self,
+        server: &McpServerInfo,
+        sid: Option<&str>,
+        message: &Value,
+    ) -> Result<(Option<String>, Option<Value>), RpcError> {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri(server.endpoint.as_str())
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .header(
+                http::header::ACCEPT,
+                format!("{CONTENT_TYPE_JSON}, {CONTENT_TYPE_EVENT_STREAM}"),
+            );
+        if let Some(sid) = sid {
+            builder = builder.header(HEADER_MCP_SESSION_ID, sid);
+        }
+        let mut req = builder
+            .body(Body::from(message.to_string()))
+            .map_err(|err| RpcError::internal(format!("Failed to build request: {err}")))?;
+        prepare_headers(req.headers_mut(), server)?;
+
+        let response = self.client.request(req).await.map_err(|err| {
+            RpcError::internal(format!("Failed to request upstream server: {err}"))
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RpcError::internal(format!(
+                "Upstream server responded {status}"
+            )));
+        }
+
+        let sid = session_id(response.headers());
+        let id = match message.get("id") {
+            Some(id) if status != StatusCode::ACCEPTED => id,
+            _ => return Ok((sid, None)),
+        };
+
+        let response = if is_event_stream(response.headers()) {
+            SseReader::new(response.into_body())
+                .wait_response(id)
+                .await?
+        } else {
+            let body = response
+                .into_body()
+                .collect()
+                .await
+                .map_err(|err| RpcError::internal(format!("Failed to read response: {err}")))?
+                .to_bytes();
+            let message = serde_json::from_slice(&body)
+                .map_err(|err| RpcError::internal(format!("Failed to parse response: {err}")))?;
+            find_response(message, id)
+                .ok_or_else(|| RpcError::internal("Upstream response has no matching id"))?
+        };
+
+        Ok((sid, Some(response)))
+    }
+
+    // best effort, the upstream server expires the session otherwise
+    fn terminate(&self, server: &McpServerInfo, sid: String) {
+        let req = Request::builder()
+            .method(Method::DELETE)
+            .uri(server.endpoint.as_str())
+            .header(HEADER_MCP_SESSION_ID, sid.as_str())
+            .body(Body::empty())
+            .map_err(|err| RpcError::internal(format!("Failed to build request: {err}")))
+            .and_then(|mut req| {
+                prepare_headers(req.headers_mut(), server)?;
+                Ok(req)
+            });
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Ok(req) = req
+                && let Err(err) = client.request(req).await
+            {
+                tracing::debug!("Failed to terminate session {sid}, error {err}");
+            }
+        });
+    }
+
+    async fn call_sse(
+        &self,
+        server: &McpServerInfo,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        let mut req = Request::builder()
+            .method(Method::GET)
+            .uri(server.endpoint.as_str())
+            .header(http::header::ACCEPT, CONTENT_TYPE_EVENT_STREAM)
+            .body(Body::empty())
+            .map_err(|err| RpcError::internal(format!("Failed to build request: {err}")))?;
+        prepare_headers(req.headers_mut(), server)?;
+
+        let response = self.client.request(req).await.map_err(|err| {
+            RpcError::internal(format!("Failed to connect upstream server: {err}"))
+        })?;
+        if !response.status().is_success() {
+            return Err(RpcError::internal(format!(
+                "Upstream server responded {}",
+                response.status()
+            )));
+        }
+
+        // the connection stays open until `events` is dropped
+        let mut events = SseReader::new(response.into_body());
+        let endpoint = events.wait_event("endpoint").await?;
+        let message_url = resolve_message_url(server, endpoint.trim());
+
+        self.post_sse(server, &message_url, &initialize_request())
+            .await?;
+        into_result(events.wait_response(&json!(INITIALIZE_ID)).await?)?;
+        self.post_sse(server, &message_url, &initialized_notification())
+            .await?;
+
+        self.post_sse(
+            server,
+            &message_url,
+            &build_request(REQUEST_ID, method, params),
+        )
+        .await?;
+        into_result(events.wait_response(&json!(REQUEST_ID)).await?)
+    }
+
+    async fn post_sse(
+        &self,
+        server: &McpServerInfo,
+        url: &str,
+        message: &Value,
+    ) -> Result<(), RpcError> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .body(Body::from(message.to_string()))
+            .map_err(|err| RpcError::internal(format!("Failed to build request: {err}")))?;
+        prepare_headers(req.headers_mut(), server)?;
+
+        let response =
+            self.client.request(req).await.map_err(|err| {
+                RpcError::internal(format!("Failed to post upstream message: {err}"))
+            })?;
+        if !response.status().is_success() {
+            return Err(RpcError::internal(format!(
+                "Upstream server responded {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn call_stdio(
+        &self,
+        name: &str,
+        tag: &str,
+        server: &McpServerInfo,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        let session = self
+            .stdio
+            .open_session(name, tag, server)
+            .map_err(RpcError::internal)?;
+
+        let result = with_timeout(async {
+            let initialized = session.wait_response(&json!(INITIALIZE_ID));
+            session
+                .send(initialize_request())
+                .await
+                .map_err(RpcError::internal)?;
+            into_result(
+                initialized
+                    .await
+                    .map_err(|_| RpcError::internal("stdio session closed"))?,
+            )?;
+            session
+                .send(initialized_notification())
+                .await
+                .map_err(RpcError::internal)?;
+
+            let response = session.wait_response(&json!(REQUEST_ID));
+            session
+                .send(build_request(REQUEST_ID, method, params))
+                .await
+                .map_err(RpcError::internal)?;
+            into_result(
+                response
+                    .await
+                    .map_err(|_| RpcError::internal("stdio session closed"))?,
+            )
+        })
+        .await;
+
+        self.stdio.close_session(&session.id);
+        result
+    }
+}
+
+async fn with_timeout(
+    call: impl Future<Output = Result<Value, RpcError>>,
+) -> Result<Value, RpcError> {
+    tokio::time::timeout(CALL_TIMEOUT, call)
+        .await
+        .map_err(|_| RpcError::internal("Timed out waiting for upstream server"))?
+}
+
+struct SseReader {
+    body: BodyDataStream<Incoming>,
+    parser: SseParser,
+    pending: VecDeque<SseEvent>,
+}
+
+impl SseReader {
+    fn new(body: Incoming) -> Self {
+        Self {
+            body: body.into_data_stream(),
+            parser: SseParser::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    async fn next(&mut self) -> Result<SseEvent, RpcError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            match self.body.next().await {
+                Some(Ok(chunk)) => self.pending.extend(self.parser.push(&chunk)),
+                Some(Err(err)) => {
+                    return Err(RpcError::internal(format!(
+                        "Failed to read event stream: {err}"
+                    )));
+                }
+                None => return Err(RpcError::internal("Upstream event stream closed")),
+            }
+        }
+    }
+
+    async fn wait_event(&mut self, event: &str) -> Result<String, RpcError> {
+        loop {
+            let next = self.next().await?;
+            if next.event == event {
+                return Ok(next.data);
+            }
+        }
+    }
+
+    async fn wait_response(&mut self, id: &Value) -> Result<Value, RpcError> {
+        loop {
+            let data = self.wait_event("message").await?;
+            if let Ok(message) = serde_json::from_str::<Value>(&data)
+                && let Some(response) = find_response(message, id)
+            {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Returns the JSON-RPC `result` of a response, or its `error`.
+pub fn into_result(response: Value) -> Result<Value, RpcError> {
+    if let Some(error) = response.get("error") {
+        return Err(RpcError::new(
+            error
+                .get("code")
+                .and_then(Value::as_i64)
+                .unwrap_or(CODE_INTERNAL_ERROR),
+            error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Unknown error"),
+        ));
+    }
+    Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+fn find_response(message: Value, id: &Value) -> Option<Value> {
+    let matches = |message: &Value| is_response(message) && message.get("id") == Some(id);
+    match message {
+        Value::Array(messages) => messages.into_iter().find(matches),
+        message => matches(&message).then_some(message),
+    }
+}
+
+// the endpoint event of the SSE transport carries an absolute url or a path on the same origin
+// the aggregate calls members on its own behalf, there is no client to forward for
+fn prepare_headers(headers: &mut HeaderMap, server: &McpServerInfo) -> Result<(), RpcError> {
+    HeaderRules::from_server(server)
+        .map_err(RpcError::internal)?
+        .rewrite_request(headers, None);
+    set_upstream_credentials(headers, server);
+    telemetry::inject(headers);
+    Ok(())
+}
+
+fn resolve_message_url(server: &McpServerInfo, endpoint: &str) -> String {
+    if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+        return endpoint.to_string();
+    }
+
+    let mut origin = format!("{}://{}", server.scheme.as_str(), server.host);
+    if !server.port.is_empty() {
+        origin = format!("{origin}:{}", server.port);
+    }
+    if endpoint.starts_with('/') {
+        format!("{origin}{endpoint}")
+    } else {
+        format!("{origin}/{endpoint}")
+    }
+}
+
+fn build_request(id: i64, method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+}
+
+fn initialize_request() -> Value {
+    build_request(
+        INITIALIZE_ID,
+        "initialize",
+        json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": "mcp-center", "version": env!("CARGO_PKG_VERSION")},
+        }),
+    )
+}
+
+fn initialized_notification() -> Value {
+    json!({"jsonrpc": "2.0", "method": "notifications/initialized"})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::types::HttpScheme;
+
+    #[test]
+    fn test_into_result() {
+        assert_eq!(
+            into_result(json!({"jsonrpc": "2.0", "id": 1, "result": {"tools": []}})),
+            Ok(json!({"tools": []}))
+        );
+        assert_eq!(
+            into_result(
+                json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "Method not found"}})
+            ),
+            Err(RpcError::new(CODE_METHOD_NOT_FOUND, "Method not found"))
+        );
+        assert_eq!(
+            into_result(json!({"jsonrpc": "2.0", "id": 1, "error": {}})),
+            Err(RpcError::new(CODE_INTERNAL_ERROR, "Unknown error"))
+        );
+    }
+
+    #[test]
+    fn test_find_response() {
+        let id = json!(1);
+        assert_eq!(
+            find_response(json!({"jsonrpc": "2.0", "id": 1, "result": {}}), &id),
+            Some(json!({"jsonrpc": "2.0", "id": 1, "result": {}}))
+        );
+        assert_eq!(
+            find_response(json!({"jsonrpc": "2.0", "id": 2, "result": {}}), &id),
+            None
+        );
+        assert_eq!(
+            find_response(
+                json!({"jsonrpc": "2.0", "method": "notifications/progress"}),
+                &id
+            ),
+            None
+        );
+        assert_eq!(
+            find_response(
+                json!([
+                    {"jsonrpc": "2.0", "method": "notifications/progress"},
+                    {"jsonrpc": "2.0", "id": 1, "result": {}}
+                ]),
+                &id
+            ),
+            Some(json!({"jsonrpc": "2.0", "id": 1, "result": {}}))
+        );
+    }
+
+    #[test]
+    fn test_resolve_message_url() {
+        struct TestCase {
+            port: &'static str,
+            endpoint: &'static str,
+            want: &'static str,
+        }
+
+        let tests = vec![
+            TestCase {
+                port: "8080",
+                endpoint: "/message?sessionId=1",
+                want: "http://127.0.0.1:8080/message?sessionId=1",
+            },
+            TestCase {
+                port: "",
+                endpoint: "message?sessionId=1",
+                want: "http://127.0.0.1/message?sessionId=1",
+            },
+            TestCase {
+                port: "8080",
+                endpoint: "https://example.com/message?sessionId=1",
+                want: "https://example.com/message?sessionId=1",
+            },
+        ];
+
+        for t in tests {
+            let server = McpServerInfo {
+                endpoint: "http://127.0.0.1:8080/sse".to_string(),
+                host: "127.0.0.1".to_string(),
+                port: t.port.to_string(),
+                path: "/sse".to_string(),
+                scheme: HttpScheme::Http,
+                transport_type: TransportType::Sse,
+                extra: None,
+                credential: None,
+            };
+            assert_eq!(
+                resolve_message_url(&server, t.endpoint),
+                t.want,
+                "endpoint: {}",
+                t.endpoint
+            );
+        }
+    }
+}
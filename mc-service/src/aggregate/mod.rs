@@ -0,0 +1,475 @@
+pub mod client;
+
+use crate::aggregate::client::{
+    CODE_INVALID_PARAMS, CODE_METHOD_NOT_FOUND, McpClient, PROTOCOL_VERSION, RpcError,
+};
+use crate::reverse_proxy::ProxyResponse;
+use crate::reverse_proxy::policy::{AccessPolicy, CODE_ACCESS_DENIED, ServerPolicy};
+use crate::reverse_proxy::stdio::{build_body_response, error_response, read_messages};
+use crate::stdio::METHOD_INITIALIZE;
+use axum::body::Body;
+use axum::extract::Request;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use mc_common::app::cache::{Cache, McpServerInfo};
+use mc_db::AggregateDBHandler;
+use mc_db::model::{AggregateMember, NAMESPACE_SEPARATOR};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::task::JoinSet;
+use tower_service::Service;
+
+static REGEX_AGGREGATE_ROUTER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^/proxy/aggregate/([^/]+)/?$").unwrap());
+
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+// upper bound of pages followed when listing a single member
+const MAX_LIST_PAGES: usize = 10;
+
+/// A virtual MCP server speaking Streamable HTTP, merging the tools, prompts
+/// and resources of the aggregate members. Tools and prompts are exposed as
+/// `{namespace}__{name}` and routed back to the member they came from.
+#[derive(Clone)]
+pub struct AggregateService {
+    aggregates: Arc<AggregateDBHandler>,
+    cache: Arc<Cache>,
+    client: McpClient,
+}
+
+#[derive(Clone)]
+struct Member {
+    namespace: String,
+    name: String,
+    tag: String,
+    server: McpServerInfo,
+    policy: ServerPolicy,
+}
+
+impl AggregateService {
+    pub fn new(aggregates: Arc<AggregateDBHandler>, cache: Arc<Cache>, client: McpClient) -> Self {
+        Self {
+            aggregates,
+            cache,
+            client,
+        }
+    }
+
+    async fn handle(&self, req: Request<Body>) -> ProxyResponse {
+        let Some(name) = parse_aggregate_router(req.uri().path()) else {
+            return error_response(
+                "Failed to parse aggregate router".to_string(),
+                StatusCode::BAD_REQUEST,
+            );
+        };
+
+        // stateless server, there is no server-initiated stream nor session to terminate
+        if req.method() != Method::POST {
+            return error_response(
+                format!("Method {} is not allowed", req.method()),
+                StatusCode::METHOD_NOT_ALLOWED,
+            );
+        }
+
+        let aggregate = match self.aggregates.get(&name).await {
+            Ok(Some(aggregate)) => aggregate,
+            Ok(None) => {
+                return error_response(
+                    format!("Aggregate {name} not found"),
+                    StatusCode::NOT_FOUND,
+                );
+            }
+            Err(err) => {
+                tracing::error!("Failed to get aggregate {name}, error {err}");
+                return error_response(
+                    format!("Failed to get aggregate {name}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        };
+
+        let access = AccessPolicy::from_request(&req);
+        let members = match aggregate.members() {
+            Ok(members) => self.resolve_members(&name, members, &access).await,
+            Err(err) => {
+                tracing::error!("Invalid members of aggregate {name}, error {err}");
+                return error_response(
+                    format!("Invalid members of aggregate {name}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        };
+
+        let (messages, batch) = match read_messages(req).await {
+            Ok(res) => res,
+            Err(msg) => return error_response(msg, StatusCode::BAD_REQUEST),
+        };
+
+        let mut responses = vec![];
+        for message in messages {
+            // notifications and responses need no answer
+            let (Some(id), Some(method)) = (
+                message.get("id"),
+                message.get("method").and_then(Value::as_str),
+            ) else {
+                continue;
+            };
+            let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let response = match self.dispatch(&name, &members, method, params).await {
+                Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                Err(err) => json!({"jsonrpc": "2.0", "id": id, "error": err.to_value()}),
+            };
+            responses.push(response);
+        }
+
+        if responses.is_empty() {
+            return build_body_response(StatusCode::ACCEPTED, None, None, Bytes::new());
+        }
+
+        let body = if batch {
+            Value::Array(responses)
+        } else {
+            responses.remove(0)
+        };
+        build_body_response(
+            StatusCode::OK,
+            None,
+            Some(CONTENT_TYPE_JSON),
+            Bytes::from(body.to_string()),
+        )
+    }
+
+    // members missing from the cache are deleted or disabled and left out,
+    // so are the members the api key is not granted
+    async fn resolve_members(
+        &self,
+        aggregate: &str,
+        members: Vec<AggregateMember>,
+        access: &AccessPolicy,
+    ) -> Vec<Member> {
+        let mut resolved = vec![];
+        for member in members {
+            let Some(policy) = access.server(&member.name, &member.tag) else {
+                tracing::debug!(
+                    "Skip member {}/{} of aggregate {}, not granted",
+                    member.name,
+                    member.tag,
+                    aggregate
+                );
+                continue;
+            };
+            match self.cache.load_server_info(&member.name, &member.tag).await {
+                Some(server) => resolved.push(Member {
+                    namespace: member.namespace().to_string(),
+                    name: member.name,
+                    tag: member.tag,
+                    server,
+                    policy,
+                }),
+                None => tracing::warn!(
+                    "Skip member {}/{} of aggregate {}, server not found, deleted or disabled",
+                    member.name,
+                    member.tag,
+                    aggregate
+                ),
+            }
+        }
+        resolved
+    }
+
+    async fn dispatch(
+        &self,
+        aggregate: &str,
+        members: &[Member],
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        match method {
+            METHOD_INITIALIZE => Ok(initialize_result(aggregate, &params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.list(members, method, "tools").await),
+            "prompts/list" => Ok(self.list(members, method, "prompts").await),
+            "resources/list" => Ok(self.list(members, method, "resources").await),
+            "tools/call" | "prompts/get" => self.route(members, method, params).await,
+            "resources/read" => self.read_resource(members, params).await,
+            _ => Err(RpcError::new(
+                CODE_METHOD_NOT_FOUND,
+                format!("Method not found: {method}"),
+            )),
+        }
+    }
+
+    // members are listed concurrently, a failing member is left out of the result
+    async fn list(&self, members: &[Member], method: &str, key: &str) -> Value {
+        let mut tasks = JoinSet::new();
+        for (index, member) in members.iter().cloned().enumerate() {
+            let client = self.client.clone();
+            let method = method.to_string();
+            let key = key.to_string();
+            tasks.spawn(async move {
+                let items = list_member(&client, &member, &method, &key).await;
+                (index, items)
+            });
+        }
+
+        let mut lists = vec![Vec::new(); members.len()];
+        while let Some(res) = tasks.join_next().await {
+            match res {
+                Ok((index, Ok(mut items))) => {
+                    let member = &members[index];
+                    if key == "tools" {
+                        items.retain(|tool| {
+                            tool.get("name")
+                                .and_then(Value::as_str)
+                                .is_some_and(|name| member.policy.allows_tool(name))
+                        });
+                    }
+                    lists[index] = namespace_items(items, &member.namespace);
+                }
+                Ok((index, Err(_))) => {
+                    tracing::warn!(
+                        "Skip {} of member {}/{}",
+                        key,
+                        members[index].name,
+                        members[index].tag
+                    );
+                }
+                Err(err) => tracing::error!("Failed to join list task, error {err}"),
+            }
+        }
+
+        json!({ key: lists.concat() })
+    }
+
+    async fn route(
+        &self,
+        members: &[Member],
+        method: &str,
+        mut params: Value,
+    ) -> Result<Value, RpcError> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::new(CODE_INVALID_PARAMS, "Missing name"))?;
+        let (member, original) = split_namespaced(name)
+            .and_then(|(namespace, original)| {
+                members
+                    .iter()
+                    .find(|member| member.namespace == namespace)
+                    .map(|member| (member, original.to_string()))
+            })
+            .ok_or_else(|| RpcError::new(CODE_INVALID_PARAMS, format!("Unknown name: {name}")))?;
+
+        if method == "tools/call" && !member.policy.allows_tool(&original) {
+            return Err(RpcError::new(
+                CODE_ACCESS_DENIED,
+                format!("Tool {name} is not permitted"),
+            ));
+        }
+
+        params["name"] = Value::String(original);
+        self.client
+            .call(&member.name, &member.tag, &member.server, method, params)
+            .await
+    }
+
+    // resource uris are not namespaced, the owner is the member listing the uri
+    async fn read_resource(&self, members: &[Member], params: Value) -> Result<Value, RpcError> {
+        let uri = params
+            .get("uri")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::new(CODE_INVALID_PARAMS, "Missing uri"))?;
+
+        for member in members {
+            let Ok(resources) =
+                list_member(&self.client, member, "resources/list", "resources").await
+            else {
+                continue;
+            };
+            if resources
+                .iter()
+                .any(|resource| resource.get("uri").and_then(Value::as_str) == Some(uri))
+            {
+                return self
+                    .client
+                    .call(
+                        &member.name,
+                        &member.tag,
+                        &member.server,
+                        "resources/read",
+                        params,
+                    )
+                    .await;
+            }
+        }
+
+        Err(RpcError::new(
+            CODE_INVALID_PARAMS,
+            format!("Unknown resource: {uri}"),
+        ))
+    }
+}
+
+impl Service<Request<Body>> for AggregateService {
+    type Response = ProxyResponse;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { Ok(service.handle(req).await) })
+    }
+}
+
+async fn list_member(
+    client: &McpClient,
+    member: &Member,
+    method: &str,
+    key: &str,
+) -> Result<Vec<Value>, RpcError> {
+    let mut items = vec![];
+    let mut cursor: Option<Value> = None;
+
+    for _ in 0..MAX_LIST_PAGES {
+        let params = match &cursor {
+            Some(cursor) => json!({"cursor": cursor}),
+            None => json!({}),
+        };
+        let result = client
+            .call(&member.name, &member.tag, &member.server, method, params)
+            .await?;
+
+        if let Some(Value::Array(page)) = result.get(key) {
+            items.extend(page.iter().cloned());
+        }
+        cursor = result.get("nextCursor").filter(|c| !c.is_null()).cloned();
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    Ok(items)
+}
+
+fn namespace_items(items: Vec<Value>, namespace: &str) -> Vec<Value> {
+    items
+        .into_iter()
+        .map(|mut item| {
+            if let Some(name) = item.get("name").and_then(Value::as_str) {
+                item["name"] = Value::String(format!("{namespace}{NAMESPACE_SEPARATOR}{name}"));
+            }
+            item
+        })
+        .collect()
+}
+
+fn split_namespaced(name: &str) -> Option<(&str, &str)> {
+    name.split_once(NAMESPACE_SEPARATOR)
+        .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+}
+
+fn initialize_result(aggregate: &str, params: &Value) -> Value {
+    json!({
+        "protocolVersion": params
+            .get("protocolVersion")
+            .and_then(Value::as_str)
+            .unwrap_or(PROTOCOL_VERSION),
+        "capabilities": {"tools": {}, "prompts": {}, "resources": {}},
+        "serverInfo": {
+            "name": format!("mcp-center/{aggregate}"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+fn parse_aggregate_router(path: &str) -> Option<String> {
+    REGEX_AGGREGATE_ROUTER
+        .captures(path)
+        .and_then(|captures| captures.get(1))
+        .map(|name| name.as_str().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_namespaced() {
+        struct TestCase {
+            name: &'static str,
+            want: Option<(&'static str, &'static str)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "github__create_issue",
+                want: Some(("github", "create_issue")),
+            },
+            TestCase {
+                name: "github__create__issue",
+                want: Some(("github", "create__issue")),
+            },
+            TestCase {
+                name: "create_issue",
+                want: None,
+            },
+            TestCase {
+                name: "__create_issue",
+                want: None,
+            },
+            TestCase {
+                name: "github__",
+                want: None,
+            },
+        ];
+
+        for t in tests {
+            assert_eq!(split_namespaced(t.name), t.want, "name: {}", t.name);
+        }
+    }
+
+    #[test]
+    fn test_namespace_items() {
+        let items = vec![
+            json!({"name": "create_issue", "inputSchema": {"type": "object"}}),
+            json!({"uri": "file:///readme.md"}),
+        ];
+        assert_eq!(
+            namespace_items(items, "github"),
+            vec![
+                json!({"name": "github__create_issue", "inputSchema": {"type": "object"}}),
+                json!({"uri": "file:///readme.md"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_aggregate_router() {
+        assert_eq!(
+            parse_aggregate_router("/proxy/aggregate/dev-tools"),
+            Some("dev-tools".to_string())
+        );
+        assert_eq!(
+            parse_aggregate_router("/proxy/aggregate/dev-tools/"),
+            Some("dev-tools".to_string())
+        );
+        assert_eq!(
+            parse_aggregate_router("/proxy/aggregate/dev-tools/mcp"),
+            None
+        );
+        assert_eq!(
+            parse_aggregate_router("/proxy/connect/dev-tools/1.0.0"),
+            None
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use crate::config::Audit;
+use crate::reverse_proxy;
+use crate::reverse_proxy::Target;
+use crate::reverse_proxy::inspect::{Inspection, MessageKind, StreamInspector, inspect_message};
+use crate::reverse_proxy::streamable::is_event_stream;
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Query, Request, State};
+use axum::middleware;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use bytes::Bytes;
+use chrono::{NaiveDateTime, Utc};
+use http::{Method, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Frame, SizeHint};
+use mc_common::app::AppState;
+use mc_common::router::RouterHandler;
+use mc_db::model::{AUDIT_ACTION_API, AUDIT_ACTION_MCP, AuditLogs};
+use mc_db::{AuditDBHandler, AuditListFilter};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+pub const AUDIT_PATH: &str = "/api/audit";
+
+const REDACTED: &str = "[REDACTED]";
+// entries waiting for the writer, more are dropped rather than delaying requests
+const QUEUE_SIZE: usize = 10_000;
+// bytes of an error response kept as the error of its entries
+const MAX_ERROR_LEN: usize = 512;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// The caller of a request, attached by the authorization middleware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub kind: &'static str,
+    pub id: String,
+    /// Id of the API key of the caller, its name may be reused once it is revoked.
+    pub api_key_id: Option<Uuid>,
+}
+
+impl Actor {
+    pub fn admin() -> Self {
+        Self {
+            kind: "admin",
+            id: "admin".to_string(),
+            api_key_id: None,
+        }
+    }
+
+    pub fn user(username: &str) -> Self {
+        Self {
+            kind: "user",
+            id: username.to_string(),
+            api_key_id: None,
+        }
+    }
+
+    pub fn oauth(subject: &str) -> Self {
+        Self {
+            kind: "oauth",
+            id: subject.to_string(),
+            api_key_id: None,
+        }
+    }
+
+    pub fn api_key(id: Uuid, name: &str) -> Self {
+        Self {
+            kind: "api_key",
+            id: name.to_string(),
+            api_key_id: Some(id),
+        }
+    }
+
+    /// Name of the API key of the caller, `None` for other callers.
+    pub fn api_key_name(&self) -> Option<&str> {
+        (self.kind == "api_key").then_some(self.id.as_str())
+    }
+
+    fn anonymous() -> Self {
+        Self {
+            kind: "anonymous",
+            id: String::new(),
+            api_key_id: None,
+        }
+    }
+}
+
+/// Records who called which method of which server, and the changes made through
+/// the admin API. Entries are written by a background task, to the database
+/// and/or a JSON lines file.
+pub struct AuditLog {
+    config: Audit,
+    handler: Option<Arc<AuditDBHandler>>,
+    runtime: Arc<Runtime>,
+    tx: mpsc::Sender<AuditLogs>,
+    rx: Mutex<Option<mpsc::Receiver<AuditLogs>>>,
+}
+
+impl AuditLog {
+    pub fn new(config: Audit, handler: Option<Arc<AuditDBHandler>>, runtime: Arc<Runtime>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        Self {
+            config,
+            handler,
+            runtime,
+            tx,
+            rx: Mutex::new(Some(rx)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.database || !self.config.file.is_empty()
+    }
+
+    pub fn start(&self) {
+        if !self.is_enabled() {
+            tracing::info!("Audit log is disabled");
+            return;
+        }
+        let Some(mut rx) = self.rx.lock().unwrap().take() else {
+            return;
+        };
+        let handler = self.handler.clone().filter(|_| self.config.database);
+        let path = self.config.file.clone();
+
+        self.runtime.spawn(async move {
+            let mut file = match path.as_str() {
+                "" => None,
+                path => match tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                {
+                    Ok(file) => Some(file),
+                    Err(err) => {
+                        tracing::error!("Failed to open audit log file {path}, error: {err}");
+                        None
+                    }
+                },
+            };
+
+            let mut entries = Vec::with_capacity(100);
+            while rx.recv_many(&mut entries, 100).await > 0 {
+                for entry in entries.drain(..) {
+                    if let Some(handler) = &handler
+                        && let Err(err) = handler.insert(&entry).await
+                    {
+                        tracing::error!(
+                            "Failed to store audit log entry {}, error: {err}",
+                            entry.id
+                        );
+                    }
+                    if let Some(file) = &mut file {
+                        let mut line = json!(entry).to_string();
+                        line.push('\n');
+                        if let Err(err) = file.write_all(line.as_bytes()).await {
+                            tracing::error!(
+                                "Failed to write audit log entry {}, error: {err}",
+                                entry.id
+                            );
+                        }
+                    }
+                }
+                if let Some(file) = &mut file
+                    && let Err(err) = file.flush().await
+                {
+                    tracing::error!("Failed to flush audit log file, error: {err}");
+                }
+            }
+        });
+    }
+
+    pub fn record(&self, entry: AuditLogs) {
+        if let Err(err) = self.tx.try_send(entry) {
+            tracing::error!("Audit log entry dropped, error: {err}");
+        }
+    }
+
+    fn audits(&self, method: &str) -> bool {
+        self.config
+            .methods
+            .iter()
+            .any(|audited| audited == "*" || audited == method)
+    }
+
+    /// Pending entries of the audited JSON-RPC requests in `body`.
+    fn calls(&self, actor: &Actor, path: &str, target: Target, body: &[u8]) -> Calls {
+        let created_at = Utc::now().naive_utc();
+        let messages = match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(messages)) => messages,
+            Ok(message) => vec![message],
+            Err(_) => vec![],
+        };
+
+        let mut calls = Calls::default();
+        for message in messages {
+            let Some(info) = inspect_message(&message) else {
+                continue;
+            };
+            let Some(method) = info.method.filter(|_| info.kind == MessageKind::Request) else {
+                continue;
+            };
+            if !self.audits(&method) {
+                continue;
+            }
+
+            let mut entry = new_entry(actor, AUDIT_ACTION_MCP, path, method, created_at);
+            entry.server_name = Some(target.name.clone());
+            entry.server_tag = target.tag.clone();
+            entry.arguments = self
+                .config
+                .capture_arguments
+                .then(|| arguments(&message, info.tool.is_some()))
+                .flatten()
+                .map(|mut arguments| {
+                    redact(&mut arguments, &self.config.redact_fields);
+                    arguments
+                });
+            entry.tool = info.tool;
+            calls.push(info.id, entry);
+        }
+        calls
+    }
+}
+
+pub fn layer(audit: Arc<AuditLog>) -> RouterHandler<AppState> {
+    Box::new(move |router| router.layer(middleware::from_fn_with_state(audit.clone(), audited)))
+}
+
+pub fn register_router() -> RouterHandler<AppState> {
+    Box::new(|router| router.route(AUDIT_PATH, get(list)))
+}
+
+async fn audited(State(audit): State<Arc<AuditLog>>, req: Request, next: Next) -> Response {
+    if !audit.is_enabled() {
+        return next.run(req).await;
+    }
+
+    let actor = req
+        .extensions()
+        .get::<Actor>()
+        .cloned()
+        .unwrap_or_else(Actor::anonymous);
+    let path = req.uri().path().to_string();
+    let started = Instant::now();
+
+    if req.method() == Method::POST
+        && let Some(target) = reverse_proxy::proxy_target(&path)
+    {
+        let (parts, body) = req.into_parts();
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(err) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to read request body: {err}"),
+                )
+                    .into_response();
+            }
+        };
+        let calls = audit.calls(&actor, &path, target, &body);
+
+        let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+        if calls.is_empty() {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let body = AuditBody {
+            inner: body,
+            inspector: Some(StreamInspector::new(is_event_stream(&parts.headers))),
+            error: (!parts.status.is_success()).then(Vec::new),
+            status: parts.status,
+            calls,
+            started,
+            audit,
+        };
+        return Response::from_parts(parts, Body::new(body));
+    }
+
+    if is_api_change(req.method(), &path) {
+        let mut entry = new_entry(
+            &actor,
+            AUDIT_ACTION_API,
+            &path,
+            req.method().to_string(),
+            Utc::now().naive_utc(),
+        );
+        let response = next.run(req).await;
+        entry.status = response.status().as_u16() as i32;
+        entry.latency_ms = started.elapsed().as_millis() as i64;
+        if !response.status().is_success() {
+            entry.error = response.status().canonical_reason().map(str::to_string);
+        }
+        audit.record(entry);
+        return response;
+    }
+
+    next.run(req).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListAuditRequest {
+    page_size: Option<i64>,
+    page_num: Option<i64>,
+    actor: Option<String>,
+    action: Option<String>,
+    server_name: Option<String>,
+    server_tag: Option<String>,
+    tool: Option<String>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+}
+
+async fn list(
+    State(state): State<AppState>,
+    Query(request): Query<ListAuditRequest>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let page_size = request.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    let page_num = request.page_num.unwrap_or(1);
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) || page_num < 1 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("page_size must be between 1 and {MAX_PAGE_SIZE}, page_num at least 1"),
+        ));
+    }
+
+    let handler = state.handlers().audit_handler.as_ref().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "no audit handler found".to_string(),
+    ))?;
+
+    let filter = AuditListFilter {
+        actor: request.actor,
+        action: request.action,
+        server_name: request.server_name,
+        server_tag: request.server_tag,
+        tool: request.tool,
+        since: request.since,
+        until: request.until,
+    };
+    let entries = handler
+        .list_with_limit(&filter, page_size, (page_num - 1) * page_size)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to list audit log entries {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to list audit log entries".to_string(),
+            )
+        })?;
+    let count = handler.count(&filter).await.map_err(|err| {
+        tracing::error!("Failed to count audit log entries {}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to count audit log entries".to_string(),
+        )
+    })?;
+
+    Ok(Json(mc_common::app::Response::new(Some(json!({
+        "entries": entries,
+        "count": count,
+    })))))
+}
+
+// reads of the admin API are not recorded
+fn is_api_change(method: &Method, path: &str) -> bool {
+    path.starts_with("/api/") && ![Method::GET, Method::HEAD, Method::OPTIONS].contains(method)
+}
+
+fn new_entry(
+    actor: &Actor,
+    action: &str,
+    path: &str,
+    method: String,
+    created_at: NaiveDateTime,
+) -> AuditLogs {
+    AuditLogs {
+        id: Uuid::new_v4(),
+        actor_type: actor.kind.to_string(),
+        actor: actor.id.clone(),
+        action: action.to_string(),
+        server_name: None,
+        server_tag: None,
+        method,
+        tool: None,
+        path: path.to_string(),
+        arguments: None,
+        status: 0,
+        error_code: None,
+        error: None,
+        latency_ms: 0,
+        created_at,
+    }
+}
+
+// the tool name has a column of its own, `_meta` is not part of the call
+fn arguments(message: &Value, tool_call: bool) -> Option<Value> {
+    let params = message.get("params")?;
+    if tool_call {
+        return params.get("arguments").cloned();
+    }
+    let mut params = params.clone();
+    if let Some(params) = params.as_object_mut() {
+        params.remove("_meta");
+    }
+    Some(params)
+}
+
+/// Replaces the values of `fields` at any depth.
+pub fn redact(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|field| field.eq_ignore_ascii_case(key)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value, fields);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| redact(item, fields)),
+        _ => {}
+    }
+}
+
+/// Entries of the requests of a body, completed by their answers.
+#[derive(Debug, Default)]
+struct Calls {
+    entries: Vec<(Option<String>, AuditLogs)>,
+}
+
+impl Calls {
+    fn push(&mut self, id: Option<String>, entry: AuditLogs) {
+        self.entries.push((id, entry));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn answer(&mut self, inspection: Inspection) {
+        let Inspection::JsonRpc { messages, .. } = inspection else {
+            return;
+        };
+        for message in messages {
+            if message.kind != MessageKind::Error {
+                continue;
+            }
+            if let Some((_, entry)) = self
+                .entries
+                .iter_mut()
+                .find(|(id, _)| id.is_some() && *id == message.id)
+            {
+                entry.error_code = message.error_code;
+            }
+        }
+    }
+
+    fn finish(self, status: StatusCode, error: Option<String>, started: Instant) -> Vec<AuditLogs> {
+        let latency_ms = started.elapsed().as_millis() as i64;
+        self.entries
+            .into_iter()
+            .map(|(_, mut entry)| {
+                entry.status = status.as_u16() as i32;
+                entry.latency_ms = latency_ms;
+                entry.error = error.clone();
+                entry
+            })
+            .collect()
+    }
+}
+
+/// Response of audited requests, the entries are recorded once it is complete
+/// or the client went away.
+struct AuditBody {
+    inner: Body,
+    inspector: Option<StreamInspector>,
+    /// Body of an error response, `None` for successful responses.
+    error: Option<Vec<u8>>,
+    status: StatusCode,
+    calls: Calls,
+    started: Instant,
+    audit: Arc<AuditLog>,
+}
+
+impl hyper::body::Body for AuditBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            if let Some(inspector) = &mut this.inspector {
+                for inspection in inspector.push(data) {
+                    this.calls.answer(inspection);
+                }
+            }
+            if let Some(error) = &mut this.error {
+                let keep = MAX_ERROR_LEN.saturating_sub(error.len()).min(data.len());
+                error.extend_from_slice(&data[..keep]);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for AuditBody {
+    fn drop(&mut self) {
+        if let Some(inspection) = self.inspector.take().and_then(StreamInspector::finish) {
+            self.calls.answer(inspection);
+        }
+        let error = self.error.take().map(|error| {
+            let error = String::from_utf8_lossy(&error).trim().to_string();
+            match error.is_empty() {
+                true => self.status.to_string(),
+                false => error,
+            }
+        });
+        let calls = std::mem::take(&mut self.calls);
+        for entry in calls.finish(self.status, error, self.started) {
+            self.audit.record(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audit_log(config: Audit) -> (AuditLog, Arc<Runtime>) {
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap(),
+        );
+        (AuditLog::new(config, None, runtime.clone()), runtime)
+    }
+
+    fn target() -> Target {
+        Target {
+            name: "github".to_string(),
+            tag: Some("1.0.0".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_redact() {
+        let fields = Audit::default().redact_fields;
+        let mut value = json!({
+            "query": "rust",
+            "Password": "secret",
+            "headers": [{"Authorization": "Bearer abc", "accept": "*/*"}],
+            "nested": {"token": {"value": 1}},
+        });
+        redact(&mut value, &fields);
+        assert_eq!(
+            value,
+            json!({
+                "query": "rust",
+                "Password": REDACTED,
+                "headers": [{"Authorization": REDACTED, "accept": "*/*"}],
+                "nested": {"token": REDACTED},
+            })
+        );
+    }
+
+    #[test]
+    fn test_calls() {
+        let body = json!([
+            {"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                "params": {"name": "search", "arguments": {"query": "rust", "api_key": "k"}}},
+            {"jsonrpc": "2.0", "id": "2", "method": "ping", "params": {"_meta": {"progressToken": 1}}},
+            {"jsonrpc": "2.0", "method": "notifications/initialized"},
+        ])
+        .to_string();
+
+        struct TestCase {
+            config: Audit,
+            want: Vec<(&'static str, Option<&'static str>, Option<Value>)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                config: Audit::default(),
+                want: vec![("tools/call", Some("search"), None)],
+            },
+            TestCase {
+                config: Audit {
+                    methods: vec!["*".to_string()],
+                    capture_arguments: true,
+                    ..Default::default()
+                },
+                want: vec![
+                    (
+                        "tools/call",
+                        Some("search"),
+                        Some(json!({"query": "rust", "api_key": REDACTED})),
+                    ),
+                    ("ping", None, Some(json!({}))),
+                ],
+            },
+        ];
+
+        for t in tests {
+            let (audit, _runtime) = audit_log(t.config);
+            let calls = audit.calls(
+                &Actor::api_key(Uuid::nil(), "ci"),
+                "/proxy/connect/github/1.0.0",
+                target(),
+                body.as_bytes(),
+            );
+            let got = calls
+                .entries
+                .iter()
+                .map(|(_, entry)| {
+                    assert_eq!(entry.actor, "ci");
+                    assert_eq!(entry.server_tag.as_deref(), Some("1.0.0"));
+                    (
+                        entry.method.as_str(),
+                        entry.tool.as_deref(),
+                        entry.arguments.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(got, t.want);
+        }
+    }
+
+    #[test]
+    fn test_audit_body() {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": "tools/call",
+            "params": {"name": "search", "arguments": {}}})
+        .to_string();
+        let denied = json!({"jsonrpc": "2.0", "id": 7,
+            "error": {"code": -32001, "message": "Tool search is not permitted"}})
+        .to_string();
+
+        let tests = vec![
+            (StatusCode::OK, denied, Some(-32001), None),
+            (
+                StatusCode::BAD_GATEWAY,
+                "Failed to request upstream server".to_string(),
+                None,
+                Some("Failed to request upstream server"),
+            ),
+        ];
+
+        for (status, response, error_code, error) in tests {
+            let (audit, runtime) = audit_log(Audit::default());
+            let mut rx = audit.rx.lock().unwrap().take().unwrap();
+            let audit = Arc::new(audit);
+            let body = AuditBody {
+                inner: Body::from(response),
+                inspector: Some(StreamInspector::new(false)),
+                error: (!status.is_success()).then(Vec::new),
+                status,
+                calls: audit.calls(
+                    &Actor::user("alice"),
+                    "/proxy/connect/github/1.0.0",
+                    target(),
+                    request.as_bytes(),
+                ),
+                started: Instant::now(),
+                audit,
+            };
+            runtime.block_on(body.collect()).unwrap();
+
+            let entry = rx.try_recv().unwrap();
+            assert_eq!(entry.action, AUDIT_ACTION_MCP);
+            assert_eq!(entry.actor_type, "user");
+            assert_eq!(entry.status, status.as_u16() as i32);
+            assert_eq!(entry.error_code, error_code);
+            assert_eq!(entry.error.as_deref(), error);
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_is_api_change() {
+        let tests = vec![
+            (Method::POST, "/api/registry/mcp", true),
+            (Method::DELETE, "/api/key/ci", true),
+            (Method::GET, "/api/registry/mcp", false),
+            (Method::POST, "/proxy/connect/github/1.0.0", false),
+        ];
+
+        for (method, path, want) in tests {
+            assert_eq!(is_api_change(&method, path), want, "{method} {path}");
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use crate::reverse_proxy::upstream::UpstreamPolicy;
+use mc_common::app::balancer::HealthThresholds;
+use mc_loader::external_api::ExternalApiOptions;
+use mc_token::jwt::JwtConfig;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -16,25 +20,312 @@ pub struct McpCenter {
     pub http_port: u16,
     #[serde(default)]
     pub admin_token: String,
+    /// Base64 encoded 32 byte key encrypting upstream credentials, credential
+    /// injection is unavailable while empty.
+    #[serde(default)]
+    pub credential_key: String,
+    #[serde(default)]
+    pub jwt: JwtConfig,
+    #[serde(default)]
+    pub oauth: OAuth,
+    #[serde(default)]
+    pub health_check: HealthCheck,
+    #[serde(default)]
+    pub upstream: Upstream,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub telemetry: Telemetry,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub stdio: Stdio,
+}
+
+/// Audit log of MCP requests and admin API changes, disabled while neither
+/// `database` nor `file` is set.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Audit {
+    /// Stores the entries in `tb_audit_logs`, queried on `/api/audit`.
+    #[serde(default = "default_true")]
+    pub database: bool,
+    /// JSON lines file the entries are appended to.
+    #[serde(default)]
+    pub file: String,
+    /// JSON-RPC methods whose requests are recorded, `*` records all.
+    #[serde(default = "default_audit_methods")]
+    pub methods: Vec<String>,
+    /// Records the arguments of tool calls and the params of other requests.
+    #[serde(default)]
+    pub capture_arguments: bool,
+    /// Fields of captured arguments replaced at any depth, matched case-insensitively.
+    #[serde(default = "default_redact_fields")]
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            database: default_true(),
+            file: String::new(),
+            methods: default_audit_methods(),
+            capture_arguments: false,
+            redact_fields: default_redact_fields(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_audit_methods() -> Vec<String> {
+    vec!["tools/call".to_string()]
+}
+
+fn default_redact_fields() -> Vec<String> {
+    ["password", "token", "secret", "api_key", "authorization"]
+        .map(str::to_string)
+        .to_vec()
+}
+
+/// OpenTelemetry tracing, exporting nothing while `otlp_endpoint` is empty.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Telemetry {
+    /// OTLP/HTTP traces endpoint of the collector, e.g. `http://localhost:4318/v1/traces`.
+    #[serde(default)]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Share of the traces started by MCP Center which are recorded, callers'
+    /// sampling decisions are kept.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: String::new(),
+            service_name: default_service_name(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+fn default_service_name() -> String {
+    "mcp-center".to_string()
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+/// Prometheus metrics on `/metrics`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Metrics {
+    /// Separate port serving only the metrics without authorization. While `0`
+    /// they are served on `http_port` to admins.
+    #[serde(default)]
+    pub port: u16,
+}
+
+/// Rate limits of the proxy, the limits themselves are kept in `tb_rate_limits`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimit {
+    /// Seconds between reloads of the limits, changes through this instance apply at once.
+    #[serde(default = "default_rate_limit_reload_interval")]
+    pub reload_interval: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            reload_interval: default_rate_limit_reload_interval(),
+        }
+    }
+}
+
+fn default_rate_limit_reload_interval() -> u64 {
+    10
+}
+
+/// The registry cache of the proxy, every instance keeps its own.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Cache {
+    /// Seconds between full loads of the registry, a safety net for missed changes.
+    #[serde(default = "default_cache_poll_interval")]
+    pub poll_interval: u64,
+    /// Applies the changes of all instances at once through Postgres `LISTEN/NOTIFY`.
+    #[serde(default = "default_true")]
+    pub listen: bool,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            poll_interval: default_cache_poll_interval(),
+            listen: true,
+        }
+    }
+}
+
+fn default_cache_poll_interval() -> u64 {
+    100
+}
+
+/// Stdio servers spawned by the proxy.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Stdio {
+    /// Seconds after which a session without an open stream, pending request or message
+    /// is closed, `0` keeps sessions until their client ends them.
+    #[serde(default = "default_stdio_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self {
+            idle_timeout: default_stdio_idle_timeout(),
+        }
+    }
+}
+
+fn default_stdio_idle_timeout() -> u64 {
+    1800
+}
+
+/// Requests of the proxy to upstream servers.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Upstream {
+    /// Seconds to establish a connection, `0` waits for the operating system.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Defaults of the per server policy.
+    #[serde(flatten)]
+    pub policy: UpstreamPolicy,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_connect_timeout(),
+            policy: UpstreamPolicy::default(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+/// Health checking of the instances of registered MCP servers.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheck {
+    /// Seconds between active probes, `0` leaves only the passive checks of proxied requests.
+    #[serde(default = "default_health_check_interval")]
+    pub interval: u64,
+    /// Seconds a probe may take before it counts as failed.
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout: u64,
+    #[serde(flatten)]
+    pub thresholds: HealthThresholds,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            interval: default_health_check_interval(),
+            timeout: default_health_check_timeout(),
+            thresholds: HealthThresholds::default(),
+        }
+    }
+}
+
+fn default_health_check_interval() -> u64 {
+    30
+}
+
+fn default_health_check_timeout() -> u64 {
+    5
+}
+
+/// OAuth protected resource settings, disabled while `issuer` is empty.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OAuth {
+    #[serde(default)]
+    pub issuer: String,
+    /// Public base url of MCP Center, advertised as the protected resource.
+    #[serde(default)]
+    pub resource: String,
+    /// Expected `aud` claim, defaults to `resource`.
+    #[serde(default)]
+    pub audience: String,
+    /// JWKS of the issuer, discovered from the OpenID configuration when empty.
+    #[serde(default)]
+    pub jwks_uri: String,
+    /// Local JWKS file used instead of fetching the keys.
+    #[serde(default)]
+    pub jwks_file: String,
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum McpRegistry {
     #[serde(rename = "memory")]
-    LocalMemory { mcp_definition_path: String },
+    LocalMemory {
+        mcp_definition_path: String,
+        #[serde(default = "default_sync_interval")]
+        sync_interval: u64,
+    },
     #[serde(rename = "external")]
-    External { url: String, token: Option<String> },
+    External {
+        url: String,
+        token: Option<String>,
+        #[serde(default = "default_sync_interval")]
+        sync_interval: u64,
+        #[serde(flatten)]
+        options: Box<ExternalApiOptions>,
+    },
+    /// Services annotated with `mcp-center/name`, watched through the API server.
+    #[serde(rename = "kubernetes")]
+    Kubernetes {
+        /// The API server of the cluster MCP Center runs in when empty.
+        #[serde(default)]
+        api_server: String,
+        /// All namespaces when empty.
+        #[serde(default)]
+        namespace: String,
+        #[serde(default)]
+        label_selector: String,
+        #[serde(default = "default_sync_interval")]
+        sync_interval: u64,
+    },
 }
 
 impl Default for McpRegistry {
     fn default() -> Self {
         McpRegistry::LocalMemory {
             mcp_definition_path: "mcp_servers".to_string(),
+            sync_interval: default_sync_interval(),
         }
     }
 }
 
+fn default_sync_interval() -> u64 {
+    60
+}
+
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct Postgres {
     pub host: String,
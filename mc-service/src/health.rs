@@ -0,0 +1,112 @@
+use crate::aggregate::client::{CODE_METHOD_NOT_FOUND, McpClient, RpcError};
+use crate::config::HealthCheck;
+use mc_common::app::cache::Cache;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::time::{interval, timeout};
+
+/// Periodically pings every instance of the cached MCP servers. Instances failing
+/// `unhealthy_threshold` probes in a row leave the rotation until they pass
+/// `healthy_threshold` probes. Proxied requests report to the same counters.
+pub struct HealthChecker {
+    cache: Arc<Cache>,
+    client: McpClient,
+    config: HealthCheck,
+    runtime: Arc<Runtime>,
+}
+
+impl HealthChecker {
+    pub fn new(
+        cache: Arc<Cache>,
+        client: McpClient,
+        config: HealthCheck,
+        runtime: Arc<Runtime>,
+    ) -> Self {
+        Self {
+            cache,
+            client,
+            config,
+            runtime,
+        }
+    }
+
+    pub fn start(&self) {
+        if self.config.interval == 0 {
+            tracing::info!("Active health checks are disabled");
+            return;
+        }
+
+        let cache = self.cache.clone();
+        let client = self.client.clone();
+        let check_interval = Duration::from_secs(self.config.interval);
+        let probe_timeout = Duration::from_secs(self.config.timeout.max(1));
+
+        self.runtime.spawn(async move {
+            let mut ticker = interval(check_interval);
+            loop {
+                ticker.tick().await;
+
+                let mut probes = tokio::task::JoinSet::new();
+                for (name, tag, upstream) in cache.upstreams().await {
+                    for (server, state) in upstream.instances() {
+                        // stdio servers run in process, their failures surface on use
+                        if server.transport_type.is_stdio() {
+                            continue;
+                        }
+                        let (client, name, tag) = (client.clone(), name.clone(), tag.clone());
+                        let (server, state) = (server.clone(), state.clone());
+                        probes.spawn(async move {
+                            let result = match timeout(
+                                probe_timeout,
+                                client.call(&name, &tag, &server, "ping", json!({})),
+                            )
+                            .await
+                            {
+                                Ok(result) => probe_result(result),
+                                Err(_) => Err("health check timed out".to_string()),
+                            };
+                            state.record(result);
+                        });
+                    }
+                }
+                probes.join_all().await;
+            }
+        });
+    }
+}
+
+// servers which don't implement ping still completed the handshake
+fn probe_result(result: Result<serde_json::Value, RpcError>) -> Result<(), String> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) if err.code == CODE_METHOD_NOT_FOUND => Ok(()),
+        Err(err) => Err(err.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::client::CODE_INTERNAL_ERROR;
+
+    #[test]
+    fn test_probe_result() {
+        let cases = [
+            (Ok(json!({})), Ok(())),
+            (
+                Err(RpcError::new(CODE_METHOD_NOT_FOUND, "Method not found")),
+                Ok(()),
+            ),
+            (
+                Err(RpcError::new(CODE_INTERNAL_ERROR, "connection refused")),
+                Err("connection refused".to_string()),
+            ),
+        ];
+
+        for (input, want) in cases {
+            assert_eq!(probe_result(input.clone()), want, "{input:?}");
+        }
+    }
+}
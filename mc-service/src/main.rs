@@ -5,15 +5,23 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry;
 use tracing_subscriber::util::SubscriberInitExt;
 
+mod aggregate;
+mod audit;
 mod config;
+mod health;
+mod oauth;
+mod rate_limit;
 mod reverse_proxy;
 mod server;
+mod stdio;
+mod sync;
+mod telemetry;
 
 fn main() -> Result<(), Box<dyn Error>> {
     registry()
+        .with(telemetry::layer())
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("info,{}=debug", env!("CARGO_CRATE_NAME")).into()),
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
@@ -0,0 +1,472 @@
+use crate::config::OAuth;
+use axum::Json;
+use axum::body::Body;
+use axum::routing::get;
+use http::{HeaderValue, Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use mc_common::app::AppState;
+use mc_common::router::RouterHandler;
+use mc_db::model::SCOPES;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OnceCell, RwLock};
+
+/// Protected resource metadata (RFC 9728), clients discover the authorization server here.
+pub const METADATA_PATH: &str = "/.well-known/oauth-protected-resource";
+
+// unknown key ids trigger a refetch of the keys, at most this often
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Validates bearer JWTs issued by the configured authorization server
+/// against its JWKS.
+pub struct ResourceServer {
+    config: OAuth,
+    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    jwks: RwLock<JwkSet>,
+    // time of the last fetch, the lock serializes fetches
+    fetched_at: Mutex<Option<Instant>>,
+    jwks_uri: OnceCell<String>,
+}
+
+#[derive(Deserialize)]
+struct OAuthClaims {
+    sub: String,
+    scope: Option<String>,
+    scp: Option<Vec<String>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// The caller a validated token stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub subject: String,
+    pub groups: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// Names the policies of the principal are stored under, `sub:{subject}`
+    /// and `group:{name}` for every group, apart from the names of API keys.
+    pub fn policy_names(&self) -> Vec<String> {
+        std::iter::once(format!("sub:{}", self.subject))
+            .chain(self.groups.iter().map(|group| format!("group:{group}")))
+            .collect()
+    }
+}
+
+impl ResourceServer {
+    /// `None` when no issuer is configured.
+    pub fn new(
+        config: OAuth,
+        client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    ) -> Result<Option<Self>, String> {
+        if config.issuer.is_empty() {
+            return Ok(None);
+        }
+        if config.resource.is_empty() {
+            return Err("OAuth resource must be set along with the issuer".to_string());
+        }
+
+        let mut jwks = JwkSet { keys: vec![] };
+        if !config.jwks_file.is_empty() {
+            let content = std::fs::read_to_string(&config.jwks_file)
+                .map_err(|err| format!("Failed to read {}: {err}", config.jwks_file))?;
+            jwks = serde_json::from_str(&content)
+                .map_err(|err| format!("Failed to parse {}: {err}", config.jwks_file))?;
+        }
+
+        Ok(Some(Self {
+            config,
+            client,
+            jwks: RwLock::new(jwks),
+            fetched_at: Mutex::new(None),
+            jwks_uri: OnceCell::new(),
+        }))
+    }
+
+    pub fn metadata(&self) -> Value {
+        json!({
+            "resource": self.config.resource,
+            "authorization_servers": [self.config.issuer],
+            "scopes_supported": SCOPES,
+            "bearer_methods_supported": ["header"],
+        })
+    }
+
+    pub fn metadata_url(&self) -> String {
+        format!(
+            "{}{METADATA_PATH}",
+            self.config.resource.trim_end_matches('/')
+        )
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<Principal, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|err| err.to_string())?;
+        // symmetric keys would let anyone knowing the public JWKS forge tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(format!("Algorithm {:?} is not accepted", header.alg));
+        }
+
+        let jwk = self.find_key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|err| err.to_string())?;
+
+        let audience = if self.config.audience.is_empty() {
+            &self.config.resource
+        } else {
+            &self.config.audience
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[self.config.issuer.as_str()]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<OAuthClaims>(token, &key, &validation)
+            .map_err(|err| err.to_string())?
+            .claims;
+        Ok(self.principal(claims))
+    }
+
+    fn principal(&self, claims: OAuthClaims) -> Principal {
+        let scopes = match (claims.scope, claims.scp) {
+            (Some(scope), _) => scope.split_whitespace().map(String::from).collect(),
+            (None, Some(scp)) => scp,
+            (None, None) => vec![],
+        };
+        let groups = match claims.extra.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => vec![],
+        };
+
+        Principal {
+            subject: claims.sub,
+            groups,
+            scopes,
+        }
+    }
+
+    async fn find_key(&self, kid: Option<&str>) -> Result<Jwk, String> {
+        if let Some(jwk) = self.cached_key(kid).await {
+            return Ok(jwk);
+        }
+        if self.config.jwks_file.is_empty() {
+            self.refresh().await?;
+            if let Some(jwk) = self.cached_key(kid).await {
+                return Ok(jwk);
+            }
+        }
+        Err(format!("No key found for kid {kid:?}"))
+    }
+
+    // a token without kid is only accepted when the set holds a single key
+    async fn cached_key(&self, kid: Option<&str>) -> Option<Jwk> {
+        let jwks = self.jwks.read().await;
+        match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        }
+    }
+
+    async fn refresh(&self) -> Result<(), String> {
+        let mut fetched_at = self.fetched_at.lock().await;
+        if fetched_at.is_some_and(|at| at.elapsed() < JWKS_REFRESH_INTERVAL) {
+            return Ok(());
+        }
+        *fetched_at = Some(Instant::now());
+
+        let uri = self
+            .jwks_uri
+            .get_or_try_init(|| self.discover_jwks_uri())
+            .await?;
+        let jwks: JwkSet = serde_json::from_value(self.fetch(uri).await?)
+            .map_err(|err| format!("Failed to parse JWKS: {err}"))?;
+        tracing::info!("Fetched {} keys from {uri}", jwks.keys.len());
+
+        *self.jwks.write().await = jwks;
+        Ok(())
+    }
+
+    async fn discover_jwks_uri(&self) -> Result<String, String> {
+        if !self.config.jwks_uri.is_empty() {
+            return Ok(self.config.jwks_uri.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        self.fetch(&url)
+            .await?
+            .get("jwks_uri")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| format!("No jwks_uri in {url}"))
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Value, String> {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(url)
+            .body(Body::empty())
+            .map_err(|err| format!("Failed to build request: {err}"))?;
+        let response = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| format!("Failed to request {url}: {err}"))?;
+        if !response.status().is_success() {
+            return Err(format!("{url} responded {}", response.status()));
+        }
+
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|err| format!("Failed to read {url}: {err}"))?
+            .to_bytes();
+        serde_json::from_slice(&body).map_err(|err| format!("Failed to parse {url}: {err}"))
+    }
+}
+
+/// Tokens signed with an asymmetric key come from the authorization server,
+/// the tokens MCP Center issues itself are signed with HMAC.
+pub fn is_oauth_token(token: &str) -> bool {
+    jsonwebtoken::decode_header(token).is_ok_and(|header| {
+        !matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        )
+    })
+}
+
+/// Bearer challenge (RFC 6750) pointing clients to the resource metadata.
+pub fn www_authenticate(
+    status: StatusCode,
+    has_credentials: bool,
+    metadata_url: &str,
+    scope: Option<&str>,
+) -> Option<HeaderValue> {
+    let challenge = match status {
+        StatusCode::UNAUTHORIZED if has_credentials => {
+            format!(r#"Bearer error="invalid_token", resource_metadata="{metadata_url}""#)
+        }
+        StatusCode::UNAUTHORIZED => format!(r#"Bearer resource_metadata="{metadata_url}""#),
+        StatusCode::FORBIDDEN => format!(
+            r#"Bearer error="insufficient_scope", scope="{}", resource_metadata="{metadata_url}""#,
+            scope.unwrap_or_default()
+        ),
+        _ => return None,
+    };
+    HeaderValue::from_str(&challenge).ok()
+}
+
+pub fn register_router(server: Arc<ResourceServer>) -> RouterHandler<AppState> {
+    Box::new(move |router| {
+        let metadata = Json(server.metadata());
+        let path_metadata = metadata.clone();
+        router
+            .route(METADATA_PATH, get(move || async move { metadata }))
+            // clients may ask for the metadata of the resource path
+            .route(
+                &format!("{METADATA_PATH}/{{*path}}"),
+                get(move || async move { path_metadata }),
+            )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper_rustls::HttpsConnectorBuilder;
+    use hyper_util::rt::TokioExecutor;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    const ISSUER: &str = "https://auth.example.com";
+    const RESOURCE: &str = "https://mcp.example.com";
+    const PRIVATE_KEY: &str = include_str!("../testdata/oauth/private.pem");
+
+    fn resource_server() -> ResourceServer {
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .unwrap()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let config = OAuth {
+            issuer: ISSUER.to_string(),
+            resource: RESOURCE.to_string(),
+            jwks_file: concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/oauth/jwks.json").to_string(),
+            groups_claim: "groups".to_string(),
+            ..Default::default()
+        };
+        ResourceServer::new(
+            config,
+            Arc::new(Client::builder(TokioExecutor::new()).build(https)),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    fn sign(claims: Value, kid: Option<&str>) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = kid.map(String::from);
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap();
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": RESOURCE,
+            "sub": "alice",
+            "exp": now() + 300,
+            "scope": "proxy registry:read",
+            "groups": ["dev", "ops"],
+        })
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let server = resource_server();
+
+        let principal = server
+            .verify(&sign(claims(), Some("test-key")))
+            .await
+            .unwrap();
+        assert_eq!(
+            principal,
+            Principal {
+                subject: "alice".to_string(),
+                groups: vec!["dev".to_string(), "ops".to_string()],
+                scopes: vec!["proxy".to_string(), "registry:read".to_string()],
+            }
+        );
+        assert!(principal.has_scope("proxy"));
+        assert!(!principal.has_scope("registry:write"));
+        assert_eq!(
+            principal.policy_names(),
+            vec!["sub:alice", "group:dev", "group:ops"]
+        );
+
+        // the single key of the set is used without kid
+        assert!(server.verify(&sign(claims(), None)).await.is_ok());
+
+        let mut scp = claims();
+        scp.as_object_mut().unwrap().remove("scope");
+        scp["scp"] = json!(["proxy"]);
+        let principal = server.verify(&sign(scp, None)).await.unwrap();
+        assert_eq!(principal.scopes, vec!["proxy"]);
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejected() {
+        let server = resource_server();
+
+        let cases = [
+            ("iss", json!("https://other.example.com")),
+            ("aud", json!("https://other.example.com")),
+            ("exp", json!(now() - 300)),
+        ];
+        for (claim, value) in cases {
+            let mut claims = claims();
+            claims[claim] = value;
+            assert!(
+                server
+                    .verify(&sign(claims, Some("test-key")))
+                    .await
+                    .is_err(),
+                "{claim}"
+            );
+        }
+
+        assert!(
+            server
+                .verify(&sign(claims(), Some("unknown")))
+                .await
+                .is_err()
+        );
+
+        let hmac = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims(),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(!is_oauth_token(&hmac));
+        assert!(server.verify(&hmac).await.is_err());
+        assert!(is_oauth_token(&sign(claims(), None)));
+    }
+
+    #[test]
+    fn test_www_authenticate() {
+        let url = "https://mcp.example.com/.well-known/oauth-protected-resource";
+        let cases = [
+            (
+                StatusCode::UNAUTHORIZED,
+                false,
+                Some(format!(r#"Bearer resource_metadata="{url}""#)),
+            ),
+            (
+                StatusCode::UNAUTHORIZED,
+                true,
+                Some(format!(
+                    r#"Bearer error="invalid_token", resource_metadata="{url}""#
+                )),
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                true,
+                Some(format!(
+                    r#"Bearer error="insufficient_scope", scope="proxy", resource_metadata="{url}""#
+                )),
+            ),
+            (StatusCode::INTERNAL_SERVER_ERROR, true, None),
+        ];
+
+        for (status, has_credentials, expected) in cases {
+            assert_eq!(
+                www_authenticate(status, has_credentials, url, Some("proxy"))
+                    .map(|value| value.to_str().unwrap().to_string()),
+                expected,
+                "{status}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let server = resource_server();
+        assert_eq!(
+            server.metadata_url(),
+            "https://mcp.example.com/.well-known/oauth-protected-resource"
+        );
+        assert_eq!(server.metadata()["authorization_servers"], json!([ISSUER]));
+        assert_eq!(server.metadata()["resource"], json!(RESOURCE));
+    }
+}
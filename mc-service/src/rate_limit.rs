@@ -0,0 +1,1238 @@
+use crate::audit::Actor;
+use crate::config::RateLimit;
+use crate::reverse_proxy;
+use crate::reverse_proxy::policy::{glob_match, merge_denied};
+use crate::reverse_proxy::stdio::parse_messages;
+use crate::reverse_proxy::streamable::HEADER_MCP_SESSION_ID;
+use axum::body::Body;
+use axum::extract::{Path, Query, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router, middleware};
+use bytes::Bytes;
+use http::header::{CONTENT_TYPE, RETRY_AFTER};
+use http::{Method, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Frame, SizeHint};
+use mc_common::app::AppState;
+use mc_common::metrics;
+use mc_common::router::RouterHandler;
+use mc_db::RateLimitDBHandler;
+use mc_db::model::RateLimits;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::time::interval;
+use uuid::Uuid;
+
+pub const RATE_LIMIT_PATH: &str = "/api/rate-limit";
+
+/// JSON-RPC error code of a request rejected by a rate limit.
+pub const CODE_RATE_LIMITED: i64 = -32002;
+
+pub const SCOPE_GLOBAL: &str = "global";
+pub const SCOPE_SERVER: &str = "server";
+pub const SCOPE_API_KEY: &str = "api_key";
+const SCOPES: [&str; 3] = [SCOPE_GLOBAL, SCOPE_SERVER, SCOPE_API_KEY];
+
+pub const KIND_CONNECT: &str = "connect";
+pub const KIND_MESSAGE: &str = "message";
+pub const KIND_SESSIONS: &str = "sessions";
+const KINDS: [&str; 3] = [KIND_CONNECT, KIND_MESSAGE, KIND_SESSIONS];
+
+const DEFAULT_PERIOD_SECS: i64 = 60;
+// open sessions end when their clients go away, there is no refill to wait for
+const SESSIONS_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// A budget of a limit, shared by the requests of one server or API key
+/// depending on the scope of the limit.
+type BudgetKey = (Uuid, String);
+
+/// Proxied request checked against the limits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Opens a session or a stream.
+    Connect,
+    /// Posts messages to a session.
+    Message,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Connect => KIND_CONNECT,
+            Kind::Message => KIND_MESSAGE,
+        }
+    }
+}
+
+/// Who calls which server, API key limits only apply to API keys.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub api_key: Option<CallerKey>,
+    pub name: String,
+    pub tag: String,
+}
+
+/// The API key of a caller. Budgets and usage are kept by id, a key reusing the
+/// name of a revoked one starts afresh.
+#[derive(Debug, Clone)]
+pub struct CallerKey {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub retry_after: Duration,
+    pub scope: String,
+    pub kind: &'static str,
+}
+
+impl Rejection {
+    /// Whole seconds for `Retry-After`, at least one.
+    pub fn retry_after_secs(&self) -> u64 {
+        (self.retry_after.as_secs_f64().ceil() as u64).max(1)
+    }
+}
+
+/// Requests of an API key since this instance started.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Usage {
+    pub connections: u64,
+    pub messages: u64,
+    pub rejected: u64,
+    pub open_sessions: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, per_sec: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(capacity);
+        self.updated = now;
+    }
+
+    /// Time until a token is available, `None` when there is one.
+    fn wait(&self, per_sec: f64) -> Option<Duration> {
+        (self.tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    buckets: HashMap<BudgetKey, Bucket>,
+    sessions: HashMap<BudgetKey, u64>,
+    usage: HashMap<Uuid, (String, Usage)>,
+}
+
+/// Token bucket rate limits of new sessions and message posts, and limits of
+/// open sessions. The limits are kept in `tb_rate_limits` and reloaded
+/// periodically; the budgets are kept per instance.
+pub struct RateLimiter {
+    config: RateLimit,
+    handler: Option<Arc<RateLimitDBHandler>>,
+    runtime: Arc<Runtime>,
+    limits: RwLock<Vec<RateLimits>>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        config: RateLimit,
+        handler: Option<Arc<RateLimitDBHandler>>,
+        runtime: Arc<Runtime>,
+    ) -> Self {
+        Self {
+            config,
+            handler,
+            runtime,
+            limits: RwLock::new(vec![]),
+            counters: Arc::new(Mutex::new(Counters::default())),
+        }
+    }
+
+    pub fn start(self: &Arc<Self>) {
+        let limiter = self.clone();
+        self.runtime.spawn(async move {
+            if limiter.config.reload_interval == 0 {
+                limiter.reload().await;
+                return;
+            }
+            let mut ticker = interval(Duration::from_secs(limiter.config.reload_interval));
+            loop {
+                ticker.tick().await;
+                limiter.reload().await;
+            }
+        });
+    }
+
+    /// Loads the limits from the database, the previous ones stay on failure.
+    pub async fn reload(&self) {
+        let Some(handler) = &self.handler else {
+            return;
+        };
+        match handler.list().await {
+            Ok(limits) => self.set_limits(limits),
+            Err(err) => tracing::error!("Failed to load rate limits, error: {err}"),
+        }
+    }
+
+    fn set_limits(&self, limits: Vec<RateLimits>) {
+        let ids: Vec<Uuid> = limits.iter().map(|limit| limit.id).collect();
+        *self.limits.write().unwrap() = limits;
+        // open sessions are released by their permits
+        self.counters
+            .lock()
+            .unwrap()
+            .buckets
+            .retain(|(id, _), _| ids.contains(id));
+    }
+
+    /// Takes a request from the budgets of the limits applying to it. A
+    /// request opening a stream also takes a session, returned as a permit
+    /// to hold while the stream is open.
+    pub fn acquire(
+        &self,
+        kind: Kind,
+        caller: &Caller,
+        stream: bool,
+        now: Instant,
+    ) -> Result<Option<SessionPermit>, Rejection> {
+        let limits = self.limits.read().unwrap();
+        let mut counters = self.counters.lock().unwrap();
+
+        let mut rates = vec![];
+        let mut sessions = vec![];
+        for limit in limits.iter().filter(|limit| applies(limit, caller)) {
+            let Some(budget) = budget(limit, caller) else {
+                continue;
+            };
+            if limit.kind == kind.as_str() {
+                rates.push((limit, (limit.id, budget)));
+            } else if stream && limit.kind == KIND_SESSIONS {
+                sessions.push((limit, (limit.id, budget)));
+            }
+        }
+
+        let mut rejection: Option<Rejection> = None;
+        let mut reject = |retry_after: Duration, scope: &str, kind: &'static str| {
+            if rejection
+                .as_ref()
+                .is_none_or(|rejection| rejection.retry_after < retry_after)
+            {
+                rejection = Some(Rejection {
+                    retry_after,
+                    scope: scope.to_string(),
+                    kind,
+                });
+            }
+        };
+        for (limit, key) in &rates {
+            let (capacity, per_sec) = rate(limit);
+            let bucket = counters
+                .buckets
+                .entry(key.clone())
+                .or_insert_with(|| Bucket::new(capacity, now));
+            bucket.refill(capacity, per_sec, now);
+            if let Some(wait) = bucket.wait(per_sec) {
+                reject(wait, &limit.scope, kind.as_str());
+            }
+        }
+        for (limit, key) in &sessions {
+            if counters.sessions.get(key).copied().unwrap_or(0) >= limit.max_requests as u64 {
+                reject(SESSIONS_RETRY_AFTER, &limit.scope, KIND_SESSIONS);
+            }
+        }
+
+        if let Some(api_key) = &caller.api_key {
+            let (_, usage) = counters
+                .usage
+                .entry(api_key.id)
+                .or_insert_with(|| (api_key.name.clone(), Usage::default()));
+            match (&rejection, kind) {
+                (Some(_), _) => usage.rejected += 1,
+                (None, Kind::Connect) => usage.connections += 1,
+                (None, Kind::Message) => usage.messages += 1,
+            }
+        }
+        if let Some(rejection) = rejection {
+            return Err(rejection);
+        }
+
+        for (_, key) in rates {
+            if let Some(bucket) = counters.buckets.get_mut(&key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        if !stream {
+            return Ok(None);
+        }
+        let sessions: Vec<BudgetKey> = sessions.into_iter().map(|(_, key)| key).collect();
+        for key in &sessions {
+            *counters.sessions.entry(key.clone()).or_default() += 1;
+        }
+        let api_key = caller.api_key.as_ref().map(|api_key| api_key.id);
+        if let Some((_, usage)) = api_key.and_then(|id| counters.usage.get_mut(&id)) {
+            usage.open_sessions += 1;
+        }
+        Ok(Some(SessionPermit {
+            counters: self.counters.clone(),
+            sessions,
+            api_key,
+        }))
+    }
+
+    /// Usage of the API keys by id and name, of the keys with the name `api_key` when set.
+    pub fn usage(&self, api_key: Option<&str>) -> Vec<(Uuid, String, Usage)> {
+        let counters = self.counters.lock().unwrap();
+        let mut usage: Vec<(Uuid, String, Usage)> = counters
+            .usage
+            .iter()
+            .filter(|(_, (name, _))| api_key.is_none_or(|api_key| api_key == name.as_str()))
+            .map(|(id, (name, usage))| (*id, name.clone(), usage.clone()))
+            .collect();
+        usage.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0)));
+        usage
+    }
+}
+
+/// A session taken from the open session limits, released when dropped.
+#[derive(Debug)]
+pub struct SessionPermit {
+    counters: Arc<Mutex<Counters>>,
+    sessions: Vec<BudgetKey>,
+    api_key: Option<Uuid>,
+}
+
+impl Drop for SessionPermit {
+    fn drop(&mut self) {
+        let mut counters = self.counters.lock().unwrap();
+        for key in &self.sessions {
+            if let Some(open) = counters.sessions.get_mut(key) {
+                *open = open.saturating_sub(1);
+                if *open == 0 {
+                    counters.sessions.remove(key);
+                }
+            }
+        }
+        if let Some((_, usage)) = self
+            .api_key
+            .and_then(|api_key| counters.usage.get_mut(&api_key))
+        {
+            usage.open_sessions = usage.open_sessions.saturating_sub(1);
+        }
+    }
+}
+
+// a limit of a single key applies to that key, a name the key left to another doesn't
+// matter; other patterns than `*` only match API keys
+fn applies(limit: &RateLimits, caller: &Caller) -> bool {
+    let api_key = match limit.api_key_id {
+        Some(id) => caller
+            .api_key
+            .as_ref()
+            .is_some_and(|api_key| api_key.id == id),
+        None if limit.api_key_name == "*" => true,
+        None => {
+            is_pattern(&limit.api_key_name)
+                && caller
+                    .api_key
+                    .as_ref()
+                    .is_some_and(|api_key| glob_match(&limit.api_key_name, &api_key.name))
+        }
+    };
+    api_key
+        && glob_match(&limit.server_name, &caller.name)
+        && glob_match(&limit.tag_pattern, &caller.tag)
+}
+
+fn is_pattern(api_key_name: &str) -> bool {
+    api_key_name.contains('*')
+}
+
+fn budget(limit: &RateLimits, caller: &Caller) -> Option<String> {
+    match limit.scope.as_str() {
+        SCOPE_GLOBAL => Some(String::new()),
+        SCOPE_SERVER => Some(format!("{}/{}", caller.name, caller.tag)),
+        SCOPE_API_KEY => caller
+            .api_key
+            .as_ref()
+            .map(|api_key| api_key.id.to_string()),
+        _ => None,
+    }
+}
+
+/// Capacity and refill per second of the buckets of a limit.
+fn rate(limit: &RateLimits) -> (f64, f64) {
+    let capacity = limit.burst.unwrap_or(limit.max_requests).max(1) as f64;
+    let per_sec = limit.max_requests.max(1) as f64 / limit.period_secs.max(1) as f64;
+    (capacity, per_sec)
+}
+
+pub fn layer(limiter: Arc<RateLimiter>) -> RouterHandler<AppState> {
+    Box::new(move |router| router.layer(middleware::from_fn_with_state(limiter.clone(), limited)))
+}
+
+pub fn register_router(limiter: Arc<RateLimiter>) -> RouterHandler<AppState> {
+    Box::new(move |router| {
+        let routes = Router::new()
+            .route(
+                RATE_LIMIT_PATH,
+                get(list_rate_limits).post(create_rate_limit),
+            )
+            .route(&format!("{RATE_LIMIT_PATH}/usage"), get(list_usage))
+            .route(
+                &format!("{RATE_LIMIT_PATH}/{{id}}"),
+                get(get_rate_limit)
+                    .put(update_rate_limit)
+                    .delete(delete_rate_limit),
+            )
+            .layer(Extension(limiter.clone()));
+        router.merge(routes)
+    })
+}
+
+async fn limited(State(limiter): State<Arc<RateLimiter>>, req: Request, next: Next) -> Response {
+    let Some(target) = reverse_proxy::proxy_target(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let in_session = req.headers().contains_key(HEADER_MCP_SESSION_ID);
+    // posts without a session open one, streams are opened by gets
+    let stream = req.method() == Method::GET;
+    let kind = if stream {
+        Kind::Connect
+    } else if req.method() != Method::POST {
+        return next.run(req).await;
+    } else if in_session || req.uri().path().starts_with("/proxy/message/") {
+        Kind::Message
+    } else {
+        Kind::Connect
+    };
+
+    let caller = Caller {
+        api_key: req.extensions().get::<Actor>().and_then(|actor| {
+            Some(CallerKey {
+                id: actor.api_key_id?,
+                name: actor.api_key_name()?.to_string(),
+            })
+        }),
+        name: target.name,
+        tag: target.tag.unwrap_or_default(),
+    };
+
+    match limiter.acquire(kind, &caller, stream, Instant::now()) {
+        Ok(None) => next.run(req).await,
+        Ok(Some(permit)) => {
+            let (parts, body) = next.run(req).await.into_parts();
+            let body = PermitBody {
+                inner: body,
+                _permit: permit,
+            };
+            Response::from_parts(parts, Body::new(body))
+        }
+        Err(rejection) => {
+            metrics::rate_limited(&rejection.scope, rejection.kind);
+            tracing::warn!(
+                "Rate limit of {} {} exceeded on {}/{} by {}",
+                rejection.scope,
+                rejection.kind,
+                caller.name,
+                caller.tag,
+                caller
+                    .api_key
+                    .as_ref()
+                    .map_or("a client without api key", |api_key| &api_key.name)
+            );
+            // clients of a session expect the answers of their requests
+            if kind == Kind::Message && in_session {
+                let body = match req.into_body().collect().await {
+                    Ok(body) => body.to_bytes(),
+                    Err(_) => Bytes::new(),
+                };
+                if let Some(body) = rejected_responses(&body, &rejection) {
+                    return (
+                        [
+                            (CONTENT_TYPE, "application/json".to_string()),
+                            (RETRY_AFTER, rejection.retry_after_secs().to_string()),
+                        ],
+                        body.to_string(),
+                    )
+                        .into_response();
+                }
+            }
+            too_many_requests(&rejection)
+        }
+    }
+}
+
+fn too_many_requests(rejection: &Rejection) -> Response {
+    let secs = rejection.retry_after_secs();
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, secs.to_string())],
+        format!("Rate limit exceeded, retry after {secs} seconds"),
+    )
+        .into_response()
+}
+
+/// Error responses of the requests of a body, `None` when it has none.
+fn rejected_responses(body: &[u8], rejection: &Rejection) -> Option<Value> {
+    let (messages, _) = parse_messages(body).ok()?;
+    let errors = messages
+        .iter()
+        .filter(|message| message.get("method").is_some())
+        .filter_map(|message| message.get("id").filter(|id| !id.is_null()))
+        .map(|id| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": CODE_RATE_LIMITED,
+                    "message": "Rate limit exceeded",
+                    "data": {"retry_after": rejection.retry_after_secs()},
+                },
+            })
+        })
+        .collect();
+    merge_denied(errors)
+}
+
+/// Response of a stream, holding its session until complete or the client went away.
+struct PermitBody {
+    inner: Body,
+    _permit: SessionPermit,
+}
+
+impl hyper::body::Body for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RateLimitRequest {
+    pub scope: String,
+    pub kind: String,
+    pub api_key_name: Option<String>,
+    pub server_name: Option<String>,
+    pub tag_pattern: Option<String>,
+    pub max_requests: i64,
+    pub period_secs: Option<i64>,
+    pub burst: Option<i64>,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl RateLimitRequest {
+    fn into_rate_limit(self, id: Uuid) -> Result<RateLimits, (StatusCode, String)> {
+        if !SCOPES.contains(&self.scope.as_str()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unknown scope {}, expected one of {}",
+                    self.scope,
+                    SCOPES.join(", ")
+                ),
+            ));
+        }
+        if !KINDS.contains(&self.kind.as_str()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unknown kind {}, expected one of {}",
+                    self.kind,
+                    KINDS.join(", ")
+                ),
+            ));
+        }
+        let period_secs = self.period_secs.unwrap_or(DEFAULT_PERIOD_SECS);
+        if self.max_requests < 1 || period_secs < 1 || self.burst.is_some_and(|burst| burst < 1) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "max_requests, period_secs and burst must be at least 1".to_string(),
+            ));
+        }
+        let pattern = |pattern: Option<String>| pattern.unwrap_or_else(|| "*".to_string());
+        let (api_key_name, server_name, tag_pattern) = (
+            pattern(self.api_key_name),
+            pattern(self.server_name),
+            pattern(self.tag_pattern),
+        );
+        if api_key_name.is_empty() || server_name.is_empty() || tag_pattern.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "api_key_name, server_name and tag_pattern must not be empty".to_string(),
+            ));
+        }
+
+        Ok(RateLimits {
+            id,
+            scope: self.scope,
+            kind: self.kind,
+            api_key_name,
+            api_key_id: None,
+            server_name,
+            tag_pattern,
+            max_requests: self.max_requests,
+            period_secs,
+            burst: self.burst,
+            description: self.description,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        })
+    }
+}
+
+async fn list_rate_limits(
+    State(state): State<AppState>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let handler = get_rate_limit_handler(&state)?;
+
+    let rate_limits = handler.list().await.map_err(|e| {
+        tracing::error!("Failed to list rate limits {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to list rate limits".to_string(),
+        )
+    })?;
+
+    let count = rate_limits.len();
+    Ok(Json(mc_common::app::Response::new(Some(json!({
+        "rate_limits": rate_limits,
+        "count": count,
+    })))))
+}
+
+async fn create_rate_limit(
+    State(state): State<AppState>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Json(request): Json<RateLimitRequest>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let handler = get_rate_limit_handler(&state)?;
+    let mut limit = request.into_rate_limit(Uuid::new_v4())?;
+    limit.api_key_id = find_api_key(&state, &limit.api_key_name).await?;
+
+    let res = handler.create(&limit).await.map_err(|e| {
+        tracing::error!("Failed to create rate limit {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create rate limit".to_string(),
+        )
+    })?;
+    tracing::info!("Rate limit {} of scope {} created", res.id, res.scope);
+    limiter.reload().await;
+
+    build_response(res)
+}
+
+async fn get_rate_limit(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let handler = get_rate_limit_handler(&state)?;
+
+    let res = handler
+        .get(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get rate limit {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get rate limit".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+
+    build_response(res)
+}
+
+async fn update_rate_limit(
+    State(state): State<AppState>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RateLimitRequest>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let handler = get_rate_limit_handler(&state)?;
+    let mut limit = request.into_rate_limit(id)?;
+    limit.api_key_id = find_api_key(&state, &limit.api_key_name).await?;
+
+    let res = handler
+        .update(&limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update rate limit {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update rate limit".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Rate limit {} of scope {} updated", res.id, res.scope);
+    limiter.reload().await;
+
+    build_response(res)
+}
+
+async fn delete_rate_limit(
+    State(state): State<AppState>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    let handler = get_rate_limit_handler(&state)?;
+
+    let res = handler
+        .delete(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete rate limit {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete rate limit".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Rate limit {} of scope {} deleted", res.id, res.scope);
+    limiter.reload().await;
+
+    build_response(res)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListUsageRequest {
+    api_key_name: Option<String>,
+}
+
+async fn list_usage(
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Query(request): Query<ListUsageRequest>,
+) -> Json<mc_common::app::Response> {
+    let usage: Vec<Value> = limiter
+        .usage(request.api_key_name.as_deref())
+        .into_iter()
+        .map(|(id, name, usage)| {
+            let mut entry = json!(usage);
+            entry["api_key_id"] = json!(id);
+            entry["api_key_name"] = json!(name);
+            entry
+        })
+        .collect();
+    let count = usage.len();
+    Json(mc_common::app::Response::new(Some(json!({
+        "usage": usage,
+        "count": count,
+    }))))
+}
+
+/// The active key a limit of a single key is bound to, `None` for patterns.
+async fn find_api_key(state: &AppState, name: &str) -> Result<Option<Uuid>, (StatusCode, String)> {
+    if is_pattern(name) {
+        return Ok(None);
+    }
+    let handler = state.handlers().api_keys_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "no api key handler found".to_string(),
+        )
+    })?;
+
+    let key = handler.find_by_name(name).await.map_err(|e| {
+        tracing::error!("Failed to find api key {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to find api key".to_string(),
+        )
+    })?;
+    match key {
+        Some(key) => Ok(Some(key.id)),
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            format!("No active api key named {name}"),
+        )),
+    }
+}
+
+fn get_rate_limit_handler(
+    state: &AppState,
+) -> Result<&Arc<RateLimitDBHandler>, (StatusCode, String)> {
+    state.handlers().rate_limit_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "no rate limit handler found".to_string(),
+        )
+    })
+}
+
+fn not_found(id: Uuid) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Rate limit {id} not found"))
+}
+
+fn build_response(
+    rate_limit: RateLimits,
+) -> Result<Json<mc_common::app::Response>, (StatusCode, String)> {
+    Ok(Json(mc_common::app::Response::new(Some(json!(rate_limit)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: Vec<RateLimits>) -> RateLimiter {
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap(),
+        );
+        let limiter = RateLimiter::new(RateLimit::default(), None, runtime);
+        limiter.set_limits(limits);
+        limiter
+    }
+
+    fn limit(scope: &str, kind: &str, max_requests: i64, period_secs: i64) -> RateLimits {
+        RateLimitRequest {
+            scope: scope.to_string(),
+            kind: kind.to_string(),
+            api_key_name: None,
+            server_name: None,
+            tag_pattern: None,
+            max_requests,
+            period_secs: Some(period_secs),
+            burst: None,
+            description: String::new(),
+        }
+        .into_rate_limit(Uuid::new_v4())
+        .unwrap()
+    }
+
+    // keys of the same name share the id, unless a test creates one anew
+    fn key(name: &str) -> CallerKey {
+        let id = name
+            .bytes()
+            .fold(0u64, |id, b| id.wrapping_mul(31).wrapping_add(b.into()));
+        CallerKey {
+            id: Uuid::from_u64_pair(0, id),
+            name: name.to_string(),
+        }
+    }
+
+    fn caller(api_key: Option<&str>, name: &str) -> Caller {
+        Caller {
+            api_key: api_key.map(key),
+            name: name.to_string(),
+            tag: "1.0.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut limit = limit(SCOPE_GLOBAL, KIND_MESSAGE, 2, 1);
+        limit.burst = Some(3);
+        let limiter = limiter(vec![limit]);
+        let caller = caller(None, "github");
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.acquire(Kind::Message, &caller, false, now).is_ok());
+        }
+        let rejection = limiter
+            .acquire(Kind::Message, &caller, false, now)
+            .unwrap_err();
+        assert_eq!(rejection.retry_after, Duration::from_millis(500));
+        assert_eq!(rejection.retry_after_secs(), 1);
+        assert_eq!(rejection.scope, SCOPE_GLOBAL);
+
+        // other kinds have budgets of their own
+        assert!(limiter.acquire(Kind::Connect, &caller, false, now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        assert!(
+            limiter
+                .acquire(Kind::Message, &caller, false, later)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire(Kind::Message, &caller, false, later)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut per_key = limit(SCOPE_API_KEY, KIND_CONNECT, 1, 60);
+        per_key.api_key_name = "ci-*".to_string();
+        let mut per_server = limit(SCOPE_SERVER, KIND_CONNECT, 2, 60);
+        per_server.server_name = "github".to_string();
+        let limiter = limiter(vec![per_key, per_server]);
+        let now = Instant::now();
+
+        struct TestCase {
+            caller: Caller,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                caller: caller(Some("ci-bot"), "gitlab"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(Some("ci-bot"), "gitlab"),
+                want: false,
+            },
+            // every key has a budget of its own
+            TestCase {
+                caller: caller(Some("ci-deploy"), "gitlab"),
+                want: true,
+            },
+            // keys not matched and other callers are not limited per key
+            TestCase {
+                caller: caller(Some("dev"), "gitlab"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(None, "gitlab"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(None, "github"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(Some("dev"), "github"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(None, "github"),
+                want: false,
+            },
+        ];
+
+        for (i, t) in tests.into_iter().enumerate() {
+            assert_eq!(
+                limiter
+                    .acquire(Kind::Connect, &t.caller, false, now)
+                    .is_ok(),
+                t.want,
+                "case {i}: {:?}",
+                t.caller
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejected_request_takes_no_budget() {
+        let limiter = limiter(vec![
+            limit(SCOPE_GLOBAL, KIND_CONNECT, 2, 60),
+            limit(SCOPE_API_KEY, KIND_CONNECT, 1, 60),
+        ]);
+        let now = Instant::now();
+
+        assert!(
+            limiter
+                .acquire(Kind::Connect, &caller(Some("ci-bot"), "github"), false, now)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire(Kind::Connect, &caller(Some("ci-bot"), "github"), false, now)
+                .is_err()
+        );
+        // the global budget was left to others
+        assert!(
+            limiter
+                .acquire(Kind::Connect, &caller(Some("dev"), "github"), false, now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_single_key() {
+        let mut bound = limit(SCOPE_API_KEY, KIND_CONNECT, 1, 60);
+        bound.api_key_name = "ci-bot".to_string();
+        bound.api_key_id = Some(key("ci-bot").id);
+        let mut unbound = limit(SCOPE_API_KEY, KIND_CONNECT, 1, 60);
+        unbound.api_key_name = "dev".to_string();
+        let limiter = limiter(vec![bound, unbound]);
+        let now = Instant::now();
+        // a key created under the name of a revoked one
+        let renewed = Caller {
+            api_key: Some(CallerKey {
+                id: Uuid::new_v4(),
+                name: "ci-bot".to_string(),
+            }),
+            ..caller(None, "github")
+        };
+
+        struct TestCase {
+            caller: Caller,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                caller: caller(Some("ci-bot"), "github"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(Some("ci-bot"), "github"),
+                want: false,
+            },
+            // neither the limit nor the spent budget pass to the new key
+            TestCase {
+                caller: renewed.clone(),
+                want: true,
+            },
+            TestCase {
+                caller: renewed.clone(),
+                want: true,
+            },
+            // a name bound to no key limits nobody
+            TestCase {
+                caller: caller(Some("dev"), "github"),
+                want: true,
+            },
+            TestCase {
+                caller: caller(Some("dev"), "github"),
+                want: true,
+            },
+        ];
+
+        for (i, t) in tests.into_iter().enumerate() {
+            assert_eq!(
+                limiter
+                    .acquire(Kind::Connect, &t.caller, false, now)
+                    .is_ok(),
+                t.want,
+                "case {i}: {:?}",
+                t.caller
+            );
+        }
+        assert_eq!(limiter.usage(Some("ci-bot")).len(), 2);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let limiter = limiter(vec![limit(SCOPE_API_KEY, KIND_SESSIONS, 1, 60)]);
+        let caller = caller(Some("ci-bot"), "github");
+        let now = Instant::now();
+
+        let permit = limiter
+            .acquire(Kind::Connect, &caller, true, now)
+            .unwrap()
+            .unwrap();
+        let rejection = limiter
+            .acquire(Kind::Connect, &caller, true, now)
+            .unwrap_err();
+        assert_eq!(rejection.kind, KIND_SESSIONS);
+        assert_eq!(rejection.retry_after, SESSIONS_RETRY_AFTER);
+        // posts to the open session are no sessions
+        assert!(
+            limiter
+                .acquire(Kind::Message, &caller, false, now)
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(
+            limiter.usage(Some("ci-bot")),
+            vec![(
+                key("ci-bot").id,
+                "ci-bot".to_string(),
+                Usage {
+                    connections: 1,
+                    messages: 1,
+                    rejected: 1,
+                    open_sessions: 1,
+                }
+            )]
+        );
+
+        drop(permit);
+        assert_eq!(limiter.usage(None)[0].2.open_sessions, 0);
+        assert!(limiter.acquire(Kind::Connect, &caller, true, now).is_ok());
+    }
+
+    #[test]
+    fn test_set_limits() {
+        let limit = limit(SCOPE_GLOBAL, KIND_MESSAGE, 1, 60);
+        let limiter = limiter(vec![limit.clone()]);
+        let caller = caller(None, "github");
+        let now = Instant::now();
+
+        assert!(limiter.acquire(Kind::Message, &caller, false, now).is_ok());
+        assert!(limiter.acquire(Kind::Message, &caller, false, now).is_err());
+
+        limiter.set_limits(vec![limit]);
+        assert!(limiter.acquire(Kind::Message, &caller, false, now).is_err());
+
+        limiter.set_limits(vec![]);
+        assert!(limiter.acquire(Kind::Message, &caller, false, now).is_ok());
+        assert!(limiter.counters.lock().unwrap().buckets.is_empty());
+    }
+
+    #[test]
+    fn test_into_rate_limit() {
+        struct TestCase {
+            scope: &'static str,
+            kind: &'static str,
+            max_requests: i64,
+            burst: Option<i64>,
+            server_name: Option<&'static str>,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                scope: SCOPE_SERVER,
+                kind: KIND_MESSAGE,
+                max_requests: 10,
+                burst: Some(20),
+                server_name: Some("github"),
+                want: true,
+            },
+            TestCase {
+                scope: "tenant",
+                kind: KIND_MESSAGE,
+                max_requests: 10,
+                burst: None,
+                server_name: None,
+                want: false,
+            },
+            TestCase {
+                scope: SCOPE_GLOBAL,
+                kind: "tools",
+                max_requests: 10,
+                burst: None,
+                server_name: None,
+                want: false,
+            },
+            TestCase {
+                scope: SCOPE_GLOBAL,
+                kind: KIND_SESSIONS,
+                max_requests: 0,
+                burst: None,
+                server_name: None,
+                want: false,
+            },
+            TestCase {
+                scope: SCOPE_GLOBAL,
+                kind: KIND_CONNECT,
+                max_requests: 10,
+                burst: Some(0),
+                server_name: None,
+                want: false,
+            },
+            TestCase {
+                scope: SCOPE_SERVER,
+                kind: KIND_CONNECT,
+                max_requests: 10,
+                burst: None,
+                server_name: Some(""),
+                want: false,
+            },
+        ];
+
+        for t in tests {
+            let request = RateLimitRequest {
+                scope: t.scope.to_string(),
+                kind: t.kind.to_string(),
+                api_key_name: None,
+                server_name: t.server_name.map(str::to_string),
+                tag_pattern: None,
+                max_requests: t.max_requests,
+                period_secs: None,
+                burst: t.burst,
+                description: String::new(),
+            };
+            let res = request.into_rate_limit(Uuid::new_v4());
+            assert_eq!(res.is_ok(), t.want, "scope: {}, kind: {}", t.scope, t.kind);
+            if let Ok(limit) = res {
+                assert_eq!(limit.period_secs, DEFAULT_PERIOD_SECS);
+                assert_eq!(limit.api_key_name, "*");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejected_responses() {
+        let rejection = Rejection {
+            retry_after: Duration::from_millis(1500),
+            scope: SCOPE_API_KEY.to_string(),
+            kind: KIND_MESSAGE,
+        };
+        let error = |id: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": CODE_RATE_LIMITED,
+                    "message": "Rate limit exceeded",
+                    "data": {"retry_after": 2},
+                },
+            })
+        };
+
+        let tests = vec![
+            (
+                json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call"}),
+                Some(error(json!(1))),
+            ),
+            (
+                json!([
+                    {"jsonrpc": "2.0", "id": "a", "method": "tools/list"},
+                    {"jsonrpc": "2.0", "method": "notifications/initialized"},
+                    {"jsonrpc": "2.0", "id": "b", "method": "ping"},
+                ]),
+                Some(json!([error(json!("a")), error(json!("b"))])),
+            ),
+            (
+                json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+                None,
+            ),
+            (json!({"jsonrpc": "2.0", "id": 1, "result": {}}), None),
+        ];
+
+        for (body, want) in tests {
+            assert_eq!(
+                rejected_responses(body.to_string().as_bytes(), &rejection),
+                want,
+                "body: {body}"
+            );
+        }
+        assert_eq!(rejected_responses(b"not json", &rejection), None);
+    }
+}
@@ -1,15 +1,22 @@
-use crate::reverse_proxy::{ProxyResponse, build_error_stream_response};
+use crate::reverse_proxy::headers::{HeaderRules, client_addr};
+use crate::reverse_proxy::inspect::StreamInspector;
+use crate::reverse_proxy::policy::{AccessPolicy, PolicyGuard, ServerPolicy, SessionGuards};
+use crate::reverse_proxy::sse::{SseEvent, SseParser};
+use crate::reverse_proxy::stdio::StdioService;
+use crate::reverse_proxy::streamable::{StreamableService, session_id};
+use crate::reverse_proxy::upstream::{UpstreamBody, UpstreamClient, UpstreamError};
+use crate::reverse_proxy::{
+    ProxyResponse, build_error_stream_response, set_upstream_credentials, upstream_result,
+};
 use axum::body::Body;
 use axum::extract::Request;
 use axum::response::Response;
 use bytes::Bytes;
 use http::{HeaderValue, StatusCode, Uri};
-use http_body_util::{BodyExt, StreamBody};
+use http_body_util::StreamBody;
 use hyper::body::Frame;
-use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::Client;
-use hyper_util::client::legacy::connect::HttpConnector;
-use mc_common::app::cache::Cache;
+use mc_common::app::cache::{Cache, SessionAffinity};
+use mc_common::metrics;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::convert::Infallible;
@@ -19,6 +26,7 @@ use std::task::Poll;
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
 use tower_service::Service;
+use tracing::Instrument;
 
 static REGEX_CONNECT_ROUTER: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^/proxy/connect/([^/]+)/([^/]+)(/.*)?$").unwrap());
@@ -30,16 +38,27 @@ const HEADER_HOST: &str = "Host";
 
 #[derive(Clone)]
 pub struct ConnectionService {
-    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    client: UpstreamClient,
     cache: Arc<Cache>,
+    streamable: StreamableService,
+    stdio: StdioService,
+    guards: SessionGuards,
 }
 
 impl ConnectionService {
     pub(crate) fn new(
-        client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+        client: UpstreamClient,
         cache: Arc<Cache>,
+        stdio: StdioService,
+        guards: SessionGuards,
     ) -> Self {
-        ConnectionService { client, cache }
+        ConnectionService {
+            streamable: StreamableService::new(client.clone(), cache.clone()),
+            client,
+            cache,
+            stdio,
+            guards,
+        }
     }
 }
 
@@ -55,6 +74,9 @@ impl Service<Request<Body>> for ConnectionService {
     fn call(&mut self, mut req: Request<Body>) -> Self::Future {
         let cache = self.cache.clone();
         let client = self.client.clone();
+        let streamable = self.streamable.clone();
+        let stdio = self.stdio.clone();
+        let guards = self.guards.clone();
 
         Box::pin(async move {
             let (tx, rx) = tokio::sync::mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(100);
@@ -75,14 +97,65 @@ impl Service<Request<Body>> for ConnectionService {
                 }
             };
 
-            let mcp_server = match cache.load_server_info(&name, &tag).await {
-                Some(ep) => ep,
+            let Some(server_policy) = AccessPolicy::from_request(&req).server(&name, &tag) else {
+                tracing::warn!("API key is not permitted to access MCP server {name}/{tag}");
+                return Ok(build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("API key is not permitted to access MCP server {name}/{tag}"),
+                    StatusCode::FORBIDDEN,
+                ));
+            };
+
+            // streamable sessions stay on the instance which opened them
+            let session = session_id(req.headers());
+            let (mcp_server, lease) = match cache.select(&name, &tag, session.as_deref()).await {
+                Some(selected) => selected,
                 None => {
-                    tracing::error!("Failed to find server info for '{name}'");
+                    tracing::warn!("MCP server {name}/{tag} not found, deleted or disabled");
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("MCP server {name}/{tag} not found, deleted or disabled"),
+                        StatusCode::NOT_FOUND,
+                    ));
+                }
+            };
+            metrics::proxy_connection(&name, &tag, mcp_server.transport_type.as_str());
+
+            if mcp_server.transport_type.is_streamable() {
+                return Ok(streamable
+                    .proxy(req, &name, &tag, &mcp_server, &server_policy, lease)
+                    .await);
+            }
+
+            if mcp_server.transport_type.is_stdio() {
+                return Ok(stdio
+                    .connect(req, &name, &tag, &mcp_server, &server_policy)
+                    .await);
+            }
+
+            let rules = match HeaderRules::from_server(&mcp_server) {
+                Ok(rules) => rules,
+                Err(err) => {
+                    tracing::error!("Failed to load header rules of {name} {tag}, error {err}");
                     return Ok(build_error_stream_response(
                         tx,
                         stream,
-                        format!("Failed to load server info for {name} {tag}"),
+                        format!("Invalid header rules of {name} {tag}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
+            };
+
+            let policy = match client.policy(&mcp_server) {
+                Ok(policy) => policy,
+                Err(err) => {
+                    tracing::error!("Failed to load upstream policy of {name} {tag}, error {err}");
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Invalid upstream policy of {name} {tag}"),
                         StatusCode::INTERNAL_SERVER_ERROR,
                     ));
                 }
@@ -103,79 +176,213 @@ impl Service<Request<Body>> for ConnectionService {
                 }
             };
 
+            let peer = client_addr(req.extensions());
+            rules.rewrite_request(req.headers_mut(), peer);
             if let Ok(host) = HeaderValue::from_str(mcp_server.host.as_str()) {
                 req.headers_mut().insert(HEADER_HOST, host);
             };
+            set_upstream_credentials(req.headers_mut(), &mcp_server);
 
-            let response = client
-                .request(req)
-                .await
-                .map_err(|err| {
-                    println!("request error: {:?}", err);
-                })
-                .unwrap();
+            let response = match client.request(req, &mcp_server, &policy, true).await {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::error!("Failed to request sse server {name} {tag}, error {err}");
+                    metrics::upstream_error(&name, &tag, err.kind());
+                    if err != UpstreamError::CircuitOpen {
+                        lease.report(Err(err.to_string()));
+                    }
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Failed to request upstream server for {name} {tag}: {err}"),
+                        err.status(),
+                    ));
+                }
+            };
 
             let status_code = response.status();
-            let headers = response.headers().clone();
-
-            tokio::task::spawn(async move {
-                let mut response_stream = response.into_data_stream();
+            lease.report(upstream_result(status_code));
+            let mut headers = response.headers().clone();
+            rules.rewrite_response(&mut headers);
+
+            // message posts of the session are sent to the instance holding the stream
+            let affinity = cache.affinity(&name, &tag, &mcp_server, lease);
+            let idle = policy.idle_timeout();
+            let stream_span = tracing::info_span!("stream", name = %name, tag = %tag);
+            if server_policy.is_unrestricted() {
+                tokio::task::spawn(
+                    async move {
+                        let _session = metrics::SseSession::new(&name, &tag);
+                        let response_stream = UpstreamBody::new(response.into_body(), idle);
+                        forward_sse(response_stream, tx, name, tag, affinity).await;
+                    }
+                    .instrument(stream_span),
+                );
+            } else {
+                tokio::task::spawn(
+                    async move {
+                        let _session = metrics::SseSession::new(&name, &tag);
+                        let response_stream = UpstreamBody::new(response.into_body(), idle);
+                        forward_guarded_sse(
+                            response_stream,
+                            tx,
+                            name,
+                            tag,
+                            affinity,
+                            guards,
+                            server_policy,
+                        )
+                        .await;
+                    }
+                    .instrument(stream_span),
+                );
+            }
 
-                while let Some(chunk_result) = response_stream.next().await {
-                    match chunk_result {
-                        Ok(mut chunk) => {
-                            let chunk_str = String::from_utf8_lossy(&chunk);
-                            tracing::info!("chunk: {:?}", chunk_str);
+            let mut response_builder = Response::builder().status(status_code);
 
-                            if let Some((path, session_id)) = parse_message(chunk_str.as_ref()) {
-                                tracing::info!(
-                                    "connect mcp success sessionId={}, name={}, tag={}",
-                                    session_id,
-                                    &name,
-                                    &tag
-                                );
+            for (name, value) in &headers {
+                response_builder = response_builder.header(name, value);
+            }
 
-                                let proxy_message_path =
-                                    build_proxy_message_path(&name, &tag, &path, &session_id);
+            response_builder = response_builder.header("transfer-encoding", "chunked");
 
-                                let mut proxy_body = String::from("event: endpoint\ndata: ");
-                                proxy_body.push_str(proxy_message_path.as_str());
-                                proxy_body.push_str("\r\n\r\n");
+            response_builder = response_builder.header("connection", "keep-alive");
 
-                                chunk = Bytes::from(proxy_body);
-                            }
+            Ok(response_builder.body(StreamBody::new(stream)).unwrap())
+        })
+    }
+}
 
-                            if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
-                                tracing::warn!("connection closed: {:?}", e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("connection error: {:?}", e);
-                            let _ = tx.send(Err(std::io::Error::other(e))).await;
-                            break;
-                        }
-                    }
+type FrameSender = tokio::sync::mpsc::Sender<Result<Frame<Bytes>, std::io::Error>>;
+
+async fn forward_sse(
+    mut response_stream: UpstreamBody,
+    tx: FrameSender,
+    name: String,
+    tag: String,
+    mut affinity: SessionAffinity,
+) {
+    // responses of the SSE transport arrive as message events on this stream
+    let mut inspector = StreamInspector::new(true);
+
+    while let Some(chunk_result) = response_stream.next().await {
+        match chunk_result {
+            Ok(mut chunk) => {
+                for inspection in inspector.push(&chunk) {
+                    inspection.log(&name, &tag, "response");
                 }
 
-                let _ = tx.send(Ok(Frame::trailers(http::HeaderMap::new()))).await;
-            });
+                let chunk_str = String::from_utf8_lossy(&chunk);
+                tracing::trace!("chunk: {:?}", chunk_str);
 
-            let mut response_builder = Response::builder().status(status_code);
+                if let Some((path, session_id)) = parse_message(chunk_str.as_ref()) {
+                    tracing::info!(
+                        "connect mcp success sessionId={}, name={}, tag={}",
+                        session_id,
+                        &name,
+                        &tag
+                    );
+                    affinity.bind(&session_id);
 
-            for (name, value) in &headers {
-                if name.as_str() != "content-length" && name.as_str() != "transfer-encoding" {
-                    response_builder = response_builder.header(name, value);
+                    let proxy_message_path =
+                        build_proxy_message_path(&name, &tag, &path, &session_id);
+
+                    let mut proxy_body = String::from("event: endpoint\ndata: ");
+                    proxy_body.push_str(proxy_message_path.as_str());
+                    proxy_body.push_str("\r\n\r\n");
+
+                    chunk = Bytes::from(proxy_body);
+                }
+
+                if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
+                    tracing::warn!("connection closed: {:?}", e);
+                    break;
                 }
             }
+            Err(e) => {
+                tracing::error!("connection error: {:?}", e);
+                let _ = tx.send(Err(e)).await;
+                break;
+            }
+        }
+    }
 
-            response_builder = response_builder.header("transfer-encoding", "chunked");
+    let _ = tx.send(Ok(Frame::trailers(http::HeaderMap::new()))).await;
+}
 
-            response_builder = response_builder.header("connection", "keep-alive");
+// re-encodes the stream event by event, so tools/list responses can be filtered
+// and denials of the message endpoint can be sent in between
+async fn forward_guarded_sse(
+    mut response_stream: UpstreamBody,
+    tx: FrameSender,
+    name: String,
+    tag: String,
+    mut affinity: SessionAffinity,
+    guards: SessionGuards,
+    policy: ServerPolicy,
+) {
+    let (guard, mut denials) = PolicyGuard::with_injector(policy);
+    let guard = Arc::new(guard);
+    let mut parser = SseParser::new();
+    let mut inspector = StreamInspector::new(true);
+    let mut session_id = None;
+
+    loop {
+        let chunk = tokio::select! {
+            chunk_result = response_stream.next() => match chunk_result {
+                Some(Ok(chunk)) => {
+                    for inspection in inspector.push(&chunk) {
+                        inspection.log(&name, &tag, "response");
+                    }
+                    tracing::trace!("chunk: {:?}", String::from_utf8_lossy(&chunk));
 
-            Ok(response_builder.body(StreamBody::new(stream)).unwrap())
-        })
+                    let mut encoded = vec![];
+                    for event in parser.push(&chunk) {
+                        let event = match parse_message(&event.data) {
+                            Some((path, sid)) if event.event == "endpoint" => {
+                                tracing::info!(
+                                    "connect mcp success sessionId={}, name={}, tag={}",
+                                    sid,
+                                    &name,
+                                    &tag
+                                );
+                                guards.insert(&sid, guard.clone());
+                                affinity.bind(&sid);
+                                let endpoint = build_proxy_message_path(&name, &tag, &path, &sid);
+                                session_id = Some(sid);
+                                SseEvent::new("endpoint", &endpoint)
+                            }
+                            _ => guard.filter_event(event),
+                        };
+                        encoded.extend_from_slice(&event.to_bytes());
+                    }
+                    Bytes::from(encoded)
+                }
+                Some(Err(e)) => {
+                    tracing::error!("connection error: {:?}", e);
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+                None => break,
+            },
+            Some(message) = denials.recv() => {
+                SseEvent::new("message", &message.to_string()).to_bytes()
+            }
+        };
+
+        if chunk.is_empty() {
+            continue;
+        }
+        if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
+            tracing::warn!("connection closed: {:?}", e);
+            break;
+        }
+    }
+
+    if let Some(sid) = session_id {
+        guards.remove(&sid);
     }
+    let _ = tx.send(Ok(Frame::trailers(http::HeaderMap::new()))).await;
 }
 
 // parse {name} {tag} from uri
@@ -201,7 +408,12 @@ fn parse_message(input: &str) -> Option<(String, String)> {
         .map(|caps| (caps["path"].to_string(), caps["sid"].to_string()))
 }
 
-fn build_proxy_message_path(name: &str, tag: &str, message_path: &str, session_id: &str) -> String {
+pub(crate) fn build_proxy_message_path(
+    name: &str,
+    tag: &str,
+    message_path: &str,
+    session_id: &str,
+) -> String {
     // build to /message/{name}/{tag}/{raw_message_path}?sessionId={session_id}
     let raw_message_path = message_path.trim_start_matches("/");
 
@@ -0,0 +1,393 @@
+use axum::extract::ConnectInfo;
+use http::header::{
+    CONNECTION, CONTENT_LENGTH, FORWARDED, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE,
+};
+use http::{Extensions, HeaderMap, HeaderName, HeaderValue};
+use mc_common::app::cache::McpServerInfo;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Hop-by-hop headers of RFC 9110 section 7.6.1, plus the legacy ones still in use.
+const HOP_BY_HOP: [HeaderName; 7] = [
+    CONNECTION,
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+];
+
+/// Header rewrites of a server, read from `headers` of the registry `extra` column:
+///
+/// ```json
+/// {"headers": {"request": {"set": {"X-Tenant": "a"}}, "response": {"remove": ["Server"]}}}
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaderRules {
+    pub request: HeaderRuleSet,
+    pub response: HeaderRuleSet,
+}
+
+/// Applied in field order: remove, rename, set, add.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaderRuleSet {
+    #[serde(deserialize_with = "deserialize_names")]
+    pub remove: Vec<HeaderName>,
+    /// Old name to new name, every value is moved.
+    #[serde(deserialize_with = "deserialize_renames")]
+    pub rename: Vec<(HeaderName, HeaderName)>,
+    /// Replaces any value of the header.
+    #[serde(deserialize_with = "deserialize_values")]
+    pub set: Vec<(HeaderName, HeaderValue)>,
+    /// Appends a value, keeping those already present.
+    #[serde(deserialize_with = "deserialize_values")]
+    pub add: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderRules {
+    pub fn from_server(server: &McpServerInfo) -> Result<Self, String> {
+        match server.extra.as_ref().and_then(|extra| extra.get("headers")) {
+            None => Ok(Self::default()),
+            Some(rules) => serde_json::from_value(rules.clone())
+                .map_err(|err| format!("invalid header rules: {err}")),
+        }
+    }
+
+    /// Prepares the headers of a request forwarded to the upstream server. `client` is
+    /// the peer address, the `Forwarded` headers are only added when it is known.
+    pub fn rewrite_request(&self, headers: &mut HeaderMap, client: Option<IpAddr>) {
+        remove_hop_by_hop(headers);
+        if let Some(client) = client {
+            set_forwarded(headers, client);
+        }
+        self.request.apply(headers);
+    }
+
+    /// Prepares the headers of an upstream response. The body is streamed again,
+    /// so `Content-Length` is dropped along the hop-by-hop headers.
+    pub fn rewrite_response(&self, headers: &mut HeaderMap) {
+        remove_hop_by_hop(headers);
+        headers.remove(CONTENT_LENGTH);
+        self.response.apply(headers);
+    }
+}
+
+impl HeaderRuleSet {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (from, to) in &self.rename {
+            let values: Vec<HeaderValue> = headers.get_all(from).iter().cloned().collect();
+            if values.is_empty() {
+                continue;
+            }
+            headers.remove(from);
+            headers.remove(to);
+            for value in values {
+                headers.append(to.clone(), value);
+            }
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in &self.add {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+/// The peer address, known when the server is started with connect info.
+pub fn client_addr(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+// the headers named by `Connection` are hop-by-hop too
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+    for name in listed.iter().chain(HOP_BY_HOP.iter()) {
+        headers.remove(name);
+    }
+}
+
+// appends to the chains of earlier proxies, protocol and host are those the client used
+fn set_forwarded(headers: &mut HeaderMap, client: IpAddr) {
+    let proto = headers
+        .get(&X_FORWARDED_PROTO)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|value| value.trim().to_string())
+        .unwrap_or_else(|| "http".to_string());
+    let host = headers
+        .get(&X_FORWARDED_HOST)
+        .or_else(|| headers.get(HOST))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let forwarded_for = match headers
+        .get(&X_FORWARDED_FOR)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(chain) => format!("{chain}, {client}"),
+        None => client.to_string(),
+    };
+    let node = match client {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    };
+    let mut forwarded = format!("for={node};proto={proto}");
+    if let Some(host) = &host {
+        forwarded.push_str(&format!(";host=\"{host}\""));
+    }
+    if let Some(chain) = headers.get(FORWARDED).and_then(|value| value.to_str().ok()) {
+        forwarded = format!("{chain}, {forwarded}");
+    }
+
+    for (name, value) in [
+        (X_FORWARDED_FOR, Some(forwarded_for)),
+        (X_FORWARDED_PROTO, Some(proto)),
+        (X_FORWARDED_HOST, host),
+        (FORWARDED, Some(forwarded)),
+    ] {
+        if let Some(value) = value.and_then(|value| HeaderValue::try_from(value).ok()) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+fn parse_name<E: serde::de::Error>(name: &str) -> Result<HeaderName, E> {
+    HeaderName::try_from(name).map_err(|err| E::custom(format!("header {name}: {err}")))
+}
+
+fn deserialize_names<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<HeaderName>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|name| parse_name(name))
+        .collect()
+}
+
+fn deserialize_renames<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Vec<(HeaderName, HeaderName)>, D::Error> {
+    BTreeMap::<String, String>::deserialize(d)?
+        .iter()
+        .map(|(from, to)| Ok((parse_name(from)?, parse_name(to)?)))
+        .collect()
+}
+
+fn deserialize_values<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Vec<(HeaderName, HeaderValue)>, D::Error> {
+    BTreeMap::<String, String>::deserialize(d)?
+        .iter()
+        .map(|(name, value)| {
+            let value = HeaderValue::try_from(value.as_str()).map_err(|err| {
+                serde::de::Error::custom(format!("value of header {name}: {err}"))
+            })?;
+            Ok((parse_name(name)?, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::types::{HttpScheme, TransportType};
+    use serde_json::{Value, json};
+
+    fn server(extra: Option<Value>) -> McpServerInfo {
+        McpServerInfo {
+            endpoint: "http://127.0.0.1:8080/mcp".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: "8080".to_string(),
+            path: "/mcp".to_string(),
+            scheme: HttpScheme::Http,
+            transport_type: TransportType::Streamable,
+            extra,
+            credential: None,
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::try_from(*name).unwrap(),
+                HeaderValue::try_from(*value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_from_server() {
+        assert_eq!(
+            HeaderRules::from_server(&server(None)).unwrap(),
+            HeaderRules::default()
+        );
+        assert_eq!(
+            HeaderRules::from_server(&server(Some(json!({"command": "npx"})))).unwrap(),
+            HeaderRules::default()
+        );
+
+        let rules = HeaderRules::from_server(&server(Some(json!({"headers": {
+            "request": {"set": {"X-Tenant": "a"}, "rename": {"X-User": "X-Upstream-User"}},
+            "response": {"remove": ["Server"]}
+        }}))))
+        .unwrap();
+        assert_eq!(rules.request.set.len(), 1);
+        assert_eq!(rules.request.rename.len(), 1);
+        assert_eq!(
+            rules.response.remove,
+            vec![HeaderName::from_static("server")]
+        );
+
+        let invalid = [
+            json!({"headers": {"request": {"set": {"bad header": "a"}}}}),
+            json!({"headers": {"request": {"set": {"X-Tenant": "a\nb"}}}}),
+            json!({"headers": {"request": {"replace": {}}}}),
+            json!({"headers": {"request": {"remove": "Server"}}}),
+        ];
+        for extra in invalid {
+            assert!(
+                HeaderRules::from_server(&server(Some(extra.clone()))).is_err(),
+                "{extra}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        struct TestCase {
+            name: &'static str,
+            rules: Value,
+            input: Vec<(&'static str, &'static str)>,
+            want: Vec<(&'static str, &'static str)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "remove",
+                rules: json!({"remove": ["X-Internal"]}),
+                input: vec![("x-internal", "1"), ("accept", "*/*")],
+                want: vec![("accept", "*/*")],
+            },
+            TestCase {
+                name: "rename moves every value",
+                rules: json!({"rename": {"X-User": "X-Upstream-User"}}),
+                input: vec![("x-user", "a"), ("x-user", "b"), ("x-upstream-user", "c")],
+                want: vec![("x-upstream-user", "a"), ("x-upstream-user", "b")],
+            },
+            TestCase {
+                name: "rename of a missing header",
+                rules: json!({"rename": {"X-User": "X-Upstream-User"}}),
+                input: vec![("x-upstream-user", "c")],
+                want: vec![("x-upstream-user", "c")],
+            },
+            TestCase {
+                name: "set replaces, add appends",
+                rules: json!({"set": {"X-Tenant": "a"}, "add": {"X-Tag": "b"}}),
+                input: vec![("x-tenant", "z"), ("x-tag", "y")],
+                want: vec![("x-tenant", "a"), ("x-tag", "y"), ("x-tag", "b")],
+            },
+        ];
+
+        for t in tests {
+            let rules: HeaderRuleSet = serde_json::from_value(t.rules).unwrap();
+            let mut input = headers(&t.input);
+            rules.apply(&mut input);
+            assert_eq!(input, headers(&t.want), "{}", t.name);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_request() {
+        let rules = HeaderRules::default();
+
+        let mut input = headers(&[
+            ("host", "center.example.com"),
+            ("connection", "keep-alive, x-hop"),
+            ("keep-alive", "timeout=5"),
+            ("x-hop", "1"),
+            ("te", "trailers"),
+            ("accept", "text/event-stream"),
+        ]);
+        rules.rewrite_request(&mut input, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(
+            input,
+            headers(&[
+                ("host", "center.example.com"),
+                ("accept", "text/event-stream"),
+                ("x-forwarded-for", "10.0.0.1"),
+                ("x-forwarded-proto", "http"),
+                ("x-forwarded-host", "center.example.com"),
+                (
+                    "forwarded",
+                    "for=10.0.0.1;proto=http;host=\"center.example.com\""
+                ),
+            ])
+        );
+
+        // behind another proxy the chains are extended
+        let mut input = headers(&[
+            ("host", "mcp-center:5432"),
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "center.example.com"),
+            ("forwarded", "for=203.0.113.7"),
+        ]);
+        rules.rewrite_request(&mut input, Some("::1".parse().unwrap()));
+        assert_eq!(input["x-forwarded-for"], "203.0.113.7, ::1");
+        assert_eq!(input["x-forwarded-proto"], "https");
+        assert_eq!(input["x-forwarded-host"], "center.example.com");
+        assert_eq!(
+            input["forwarded"],
+            "for=203.0.113.7, for=\"[::1]\";proto=https;host=\"center.example.com\""
+        );
+
+        // no forwarding headers without a known client
+        let mut input = headers(&[("host", "center.example.com")]);
+        rules.rewrite_request(&mut input, None);
+        assert_eq!(input, headers(&[("host", "center.example.com")]));
+    }
+
+    #[test]
+    fn test_rewrite_response() {
+        let rules = HeaderRules::from_server(&server(Some(json!({"headers": {
+            "response": {"remove": ["Server"], "set": {"X-Proxy": "mcp-center"}}
+        }}))))
+        .unwrap();
+
+        let mut input = headers(&[
+            ("content-type", "text/event-stream"),
+            ("content-length", "42"),
+            ("transfer-encoding", "chunked"),
+            ("connection", "close"),
+            ("server", "uvicorn"),
+        ]);
+        rules.rewrite_response(&mut input);
+        assert_eq!(
+            input,
+            headers(&[
+                ("content-type", "text/event-stream"),
+                ("x-proxy", "mcp-center"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::reverse_proxy::sse::SseParser;
+use axum::body::Body;
+use axum::extract::Request;
+use bytes::Bytes;
+use http::request::Parts;
+use http_body_util::BodyExt;
+use serde_json::Value;
+
+const METHOD_TOOLS_CALL: &str = "tools/call";
+
+// plain bodies larger than this are passed through without inspection
+const MAX_INSPECT_BODY: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Request,
+    Notification,
+    Response,
+    Error,
+}
+
+impl MessageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageKind::Request => "request",
+            MessageKind::Notification => "notification",
+            MessageKind::Response => "response",
+            MessageKind::Error => "error",
+        }
+    }
+}
+
+/// The JSON-RPC envelope of a single MCP message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageInfo {
+    pub kind: MessageKind,
+    pub method: Option<String>,
+    pub id: Option<String>,
+    /// Tool name of a `tools/call` request.
+    pub tool: Option<String>,
+    pub error_code: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inspection {
+    JsonRpc {
+        messages: Vec<MessageInfo>,
+        batch: bool,
+    },
+    /// Not a JSON-RPC body, forwarded untouched.
+    Opaque,
+}
+
+impl Inspection {
+    pub fn log(&self, name: &str, tag: &str, direction: &str) {
+        match self {
+            Inspection::JsonRpc { messages, batch } => {
+                for message in messages {
+                    tracing::info!(
+                        name,
+                        tag,
+                        direction,
+                        batch,
+                        kind = message.kind.as_str(),
+                        method = message.method.as_deref(),
+                        id = message.id.as_deref(),
+                        tool = message.tool.as_deref(),
+                        error_code = message.error_code,
+                        "mcp message"
+                    );
+                }
+            }
+            Inspection::Opaque => tracing::debug!(name, tag, direction, "opaque mcp message"),
+        }
+    }
+}
+
+/// Inspects a body holding a single JSON-RPC message or a batch. A batch with
+/// any entry that is not JSON-RPC is opaque as a whole.
+pub fn inspect(body: &[u8]) -> Inspection {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(items)) if !items.is_empty() => items
+            .iter()
+            .map(inspect_message)
+            .collect::<Option<Vec<_>>>()
+            .map_or(Inspection::Opaque, |messages| Inspection::JsonRpc {
+                messages,
+                batch: true,
+            }),
+        Ok(message) => {
+            inspect_message(&message).map_or(Inspection::Opaque, |message| Inspection::JsonRpc {
+                messages: vec![message],
+                batch: false,
+            })
+        }
+        Err(_) => Inspection::Opaque,
+    }
+}
+
+/// Buffers the request body to inspect and log it, returning the request parts
+/// and the body to be forwarded.
+pub async fn inspect_request(
+    req: Request<Body>,
+    name: &str,
+    tag: &str,
+) -> Result<(Parts, Bytes), String> {
+    let (parts, body) = req.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map_err(|err| format!("Failed to read request body: {err}"))?
+        .to_bytes();
+
+    inspect(&body).log(name, tag, "request");
+    Ok((parts, body))
+}
+
+pub fn inspect_message(message: &Value) -> Option<MessageInfo> {
+    let object = message.as_object()?;
+    if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return None;
+    }
+
+    let id = object
+        .get("id")
+        .filter(|id| !id.is_null())
+        .map(|id| match id {
+            Value::String(id) => id.clone(),
+            id => id.to_string(),
+        });
+    let method = object
+        .get("method")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let (kind, error_code) = if method.is_some() {
+        match id {
+            Some(_) => (MessageKind::Request, None),
+            None => (MessageKind::Notification, None),
+        }
+    } else if let Some(error) = object.get("error") {
+        (
+            MessageKind::Error,
+            error.get("code").and_then(Value::as_i64),
+        )
+    } else if object.contains_key("result") {
+        (MessageKind::Response, None)
+    } else {
+        return None;
+    };
+
+    let tool = match method.as_deref() {
+        Some(METHOD_TOOLS_CALL) => object
+            .get("params")
+            .and_then(|params| params.get("name"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        _ => None,
+    };
+
+    Some(MessageInfo {
+        kind,
+        method,
+        id,
+        tool,
+        error_code,
+    })
+}
+
+/// Inspects a response body while it is streamed, message events of an
+/// event stream one by one and plain bodies once complete.
+pub struct StreamInspector {
+    parser: Option<SseParser>,
+    buffer: Vec<u8>,
+    overflow: bool,
+}
+
+impl StreamInspector {
+    pub fn new(event_stream: bool) -> Self {
+        Self {
+            parser: event_stream.then(SseParser::new),
+            buffer: vec![],
+            overflow: false,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Inspection> {
+        match &mut self.parser {
+            Some(parser) => parser
+                .push(chunk)
+                .into_iter()
+                .filter(|event| event.event == "message")
+                .map(|event| inspect(event.data.as_bytes()))
+                .collect(),
+            None => {
+                if !self.overflow {
+                    self.overflow = self.buffer.len() + chunk.len() > MAX_INSPECT_BODY;
+                    if self.overflow {
+                        self.buffer = vec![];
+                    } else {
+                        self.buffer.extend_from_slice(chunk);
+                    }
+                }
+                vec![]
+            }
+        }
+    }
+
+    pub fn finish(self) -> Option<Inspection> {
+        match self.parser {
+            Some(_) => None,
+            None if self.overflow => Some(Inspection::Opaque),
+            None => Some(inspect(&self.buffer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(
+        kind: MessageKind,
+        method: Option<&str>,
+        id: Option<&str>,
+        tool: Option<&str>,
+        error_code: Option<i64>,
+    ) -> MessageInfo {
+        MessageInfo {
+            kind,
+            method: method.map(str::to_string),
+            id: id.map(str::to_string),
+            tool: tool.map(str::to_string),
+            error_code,
+        }
+    }
+
+    #[test]
+    fn test_inspect() {
+        struct TestCase {
+            body: &'static str,
+            want: Inspection,
+        }
+
+        let tests = vec![
+            TestCase {
+                body: r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"echo","arguments":{}}}"#,
+                want: Inspection::JsonRpc {
+                    messages: vec![info(
+                        MessageKind::Request,
+                        Some("tools/call"),
+                        Some("1"),
+                        Some("echo"),
+                        None,
+                    )],
+                    batch: false,
+                },
+            },
+            TestCase {
+                body: r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                want: Inspection::JsonRpc {
+                    messages: vec![info(
+                        MessageKind::Notification,
+                        Some("notifications/initialized"),
+                        None,
+                        None,
+                        None,
+                    )],
+                    batch: false,
+                },
+            },
+            TestCase {
+                body: r#"[{"jsonrpc":"2.0","id":"a","result":{}},{"jsonrpc":"2.0","id":"b","error":{"code":-32601,"message":"Method not found"}}]"#,
+                want: Inspection::JsonRpc {
+                    messages: vec![
+                        info(MessageKind::Response, None, Some("a"), None, None),
+                        info(MessageKind::Error, None, Some("b"), None, Some(-32601)),
+                    ],
+                    batch: true,
+                },
+            },
+            TestCase {
+                body: r#"[{"jsonrpc":"2.0","id":1,"method":"ping"},{"foo":"bar"}]"#,
+                want: Inspection::Opaque,
+            },
+            TestCase {
+                body: r#"{"id":1,"method":"ping"}"#,
+                want: Inspection::Opaque,
+            },
+            TestCase {
+                body: "Accepted",
+                want: Inspection::Opaque,
+            },
+            TestCase {
+                body: "[]",
+                want: Inspection::Opaque,
+            },
+        ];
+
+        for t in tests {
+            assert_eq!(inspect(t.body.as_bytes()), t.want, "body: {}", t.body);
+        }
+    }
+
+    #[test]
+    fn test_stream_inspector() {
+        let mut inspector = StreamInspector::new(true);
+        assert_eq!(
+            inspector.push(b"event: endpoint\ndata: /message?sessionId=1\n\n"),
+            vec![]
+        );
+        assert_eq!(
+            inspector.push(b"event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":1,"),
+            vec![]
+        );
+        assert_eq!(
+            inspector.push(b"\"result\":{}}\n\n"),
+            vec![Inspection::JsonRpc {
+                messages: vec![info(MessageKind::Response, None, Some("1"), None, None)],
+                batch: false,
+            }]
+        );
+        assert_eq!(inspector.finish(), None);
+
+        let mut inspector = StreamInspector::new(false);
+        assert_eq!(inspector.push(b"{\"jsonrpc\":\"2.0\","), vec![]);
+        assert_eq!(inspector.push(b"\"id\":2,\"result\":{}}"), vec![]);
+        assert_eq!(
+            inspector.finish(),
+            Some(Inspection::JsonRpc {
+                messages: vec![info(MessageKind::Response, None, Some("2"), None, None)],
+                batch: false,
+            })
+        );
+
+        let mut inspector = StreamInspector::new(false);
+        inspector.push(&vec![b' '; MAX_INSPECT_BODY + 1]);
+        assert_eq!(inspector.finish(), Some(Inspection::Opaque));
+    }
+}
@@ -1,40 +1,60 @@
-use crate::reverse_proxy::{ProxyResponse, build_error_stream_response};
+use crate::reverse_proxy::headers::{HeaderRules, client_addr};
+use crate::reverse_proxy::inspect::{StreamInspector, inspect_request};
+use crate::reverse_proxy::policy::{AccessPolicy, SessionGuards, guard_message, merge_denied};
+use crate::reverse_proxy::stdio::{StdioService, build_body_response, query_session_id};
+use crate::reverse_proxy::streamable::is_event_stream;
+use crate::reverse_proxy::upstream::{UpstreamBody, UpstreamClient, UpstreamError};
+use crate::reverse_proxy::{
+    ProxyResponse, build_error_stream_response, set_upstream_credentials, upstream_result,
+};
+use crate::telemetry;
 use axum::body::Body;
 use axum::extract::Request;
 use axum::response::Response;
 use bytes::Bytes;
 use http::{HeaderValue, StatusCode, Uri};
-use http_body_util::{BodyExt, StreamBody};
+use http_body_util::StreamBody;
 use hyper::body::Frame;
-use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::Client;
-use hyper_util::client::legacy::connect::HttpConnector;
 use mc_common::app::cache::{Cache, McpServerInfo};
+use mc_common::metrics;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::convert::Infallible;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Instant;
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
 use tower_service::Service;
+use tracing::Instrument;
+
+const CONTENT_TYPE_JSON: &str = "application/json";
 
 static REGEX_MESSAGE_ROUTER: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^/proxy/message/([^/]+)/([^/]+)(/.*)?$").unwrap());
 
 #[derive(Clone)]
 pub struct MessageService {
-    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    client: UpstreamClient,
     cache: Arc<Cache>,
+    stdio: StdioService,
+    guards: SessionGuards,
 }
 
 impl MessageService {
     pub fn new(
-        client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+        client: UpstreamClient,
         cache: Arc<Cache>,
+        stdio: StdioService,
+        guards: SessionGuards,
     ) -> Self {
-        Self { client, cache }
+        Self {
+            client,
+            cache,
+            stdio,
+            guards,
+        }
     }
 }
 
@@ -47,21 +67,22 @@ impl Service<Request<Body>> for MessageService {
         Ok(()).into()
     }
 
-    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
         let cache = self.cache.clone();
         let client = self.client.clone();
+        let stdio = self.stdio.clone();
+        let guards = self.guards.clone();
 
         Box::pin(async move {
             let (tx, rx) = tokio::sync::mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(100);
             let stream = ReceiverStream::new(rx);
 
-            let path = req.uri().path();
-            let path_query = req.uri().query();
+            let path = req.uri().path().to_string();
+            let path_query = req.uri().query().map(str::to_string);
 
-            tracing::info!("path ===> {path}");
-            // tracing::info!("path_query ===> {path_query}");
+            tracing::debug!("path ===> {path}");
 
-            let (name, tag, sub_path) = match parse_message_router(path) {
+            let (name, tag, sub_path) = match parse_message_router(&path) {
                 Ok(res) => res,
                 Err(err) => {
                     tracing::error!(error = ?err, "parse message router failed {path}");
@@ -74,81 +95,213 @@ impl Service<Request<Body>> for MessageService {
                 }
             };
 
-            let mcp_server = match cache.load_server_info(&name, &tag).await {
+            let Some(server_policy) = AccessPolicy::from_request(&req).server(&name, &tag) else {
+                tracing::warn!("API key is not permitted to access MCP server {name}/{tag}");
+                return Ok(build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("API key is not permitted to access MCP server {name}/{tag}"),
+                    StatusCode::FORBIDDEN,
+                ));
+            };
+
+            // the session lives on the instance holding its SSE stream
+            let session = query_session_id(path_query.as_deref());
+            let (mcp_server, lease) = match cache.select(&name, &tag, session.as_deref()).await {
                 None => {
-                    tracing::error!("Failed to find server info for '{name}'");
+                    tracing::warn!("MCP server {name}/{tag} not found, deleted or disabled");
                     return Ok(build_error_stream_response(
                         tx,
                         stream,
-                        format!("Failed to load server info for {name} {tag}"),
-                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("MCP server {name}/{tag} not found, deleted or disabled"),
+                        StatusCode::NOT_FOUND,
                     ));
                 }
-                Some(ep) => ep,
+                Some(selected) => selected,
             };
 
-            *req.uri_mut() =
-                match Uri::try_from(build_raw_message_path(&mcp_server, &sub_path, path_query)) {
-                    Ok(uri) => uri,
-                    Err(err) => {
-                        tracing::error!(
-                            "Failed to convert endpoint to uri for {name} {tag}, error {err}"
+            // non JSON-RPC bodies are inspected as opaque and forwarded as they are
+            let (mut parts, mut body) = match inspect_request(req, &name, &tag).await {
+                Ok(res) => res,
+                Err(err) => {
+                    tracing::error!("Failed to inspect message for {name} {tag}, error {err}");
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        err,
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+            };
+
+            // answers of a restricted session, denials included, go through the guard of its stream;
+            // the policy of the caller applies as well, on an instance not holding the stream alone
+            if !server_policy.is_unrestricted() {
+                let session_guard =
+                    query_session_id(path_query.as_deref()).and_then(|sid| guards.get(&sid));
+                let (forward, denied) =
+                    guard_message(&server_policy, session_guard.as_deref(), body);
+                match session_guard {
+                    Some(guard) => {
+                        guard.inject(denied).await;
+                    }
+                    None if !denied.is_empty() => {
+                        tracing::warn!(
+                            "Denied tool calls of {name}/{tag} without the session stream"
                         );
+                        let body = merge_denied(denied).unwrap_or_default();
+                        return Ok(build_body_response(
+                            StatusCode::FORBIDDEN,
+                            None,
+                            Some(CONTENT_TYPE_JSON),
+                            Bytes::from(body.to_string()),
+                        ));
+                    }
+                    None => {}
+                }
+                body = match forward {
+                    Some(forward) => forward,
+                    None => {
                         return Ok(build_error_stream_response(
                             tx,
                             stream,
-                            format!("Failed to convert endpoint to uri for {name} {tag}"),
-                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Accepted".to_string(),
+                            StatusCode::ACCEPTED,
                         ));
                     }
                 };
+            }
+            telemetry::link_meta(&body);
+            let body = telemetry::inject_meta(body);
+            // the body may have been rewritten, let the client compute its length
+            parts.headers.remove(http::header::CONTENT_LENGTH);
+            let mut req = Request::from_parts(parts, Body::from(body));
+
+            if mcp_server.transport_type.is_stdio() {
+                return Ok(stdio.message(req, &name, &tag).await);
+            }
 
-            if let Ok(host) = HeaderValue::from_str(mcp_server.host.as_str()) {
-                req.headers_mut().insert("host", host);
+            let rules = match HeaderRules::from_server(&mcp_server) {
+                Ok(rules) => rules,
+                Err(err) => {
+                    tracing::error!("Failed to load header rules of {name} {tag}, error {err}");
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Invalid header rules of {name} {tag}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
             };
 
-            let response = client
-                .request(req)
-                .await
-                .map_err(|err| {
-                    println!("request error: {:?}", err);
-                })
-                .unwrap();
+            let policy = match client.policy(&mcp_server) {
+                Ok(policy) => policy,
+                Err(err) => {
+                    tracing::error!("Failed to load upstream policy of {name} {tag}, error {err}");
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Invalid upstream policy of {name} {tag}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
+            };
 
-            let status_code = response.status();
-            let headers = response.headers().clone();
+            *req.uri_mut() = match Uri::try_from(build_raw_message_path(
+                &mcp_server,
+                &sub_path,
+                path_query.as_deref(),
+            )) {
+                Ok(uri) => uri,
+                Err(err) => {
+                    tracing::error!(
+                        "Failed to convert endpoint to uri for {name} {tag}, error {err}"
+                    );
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Failed to convert endpoint to uri for {name} {tag}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
+            };
 
-            tokio::task::spawn(async move {
-                let mut response_stream = response.into_data_stream();
+            let peer = client_addr(req.extensions());
+            rules.rewrite_request(req.headers_mut(), peer);
+            if let Ok(host) = HeaderValue::from_str(mcp_server.host.as_str()) {
+                req.headers_mut().insert("host", host);
+            };
+            set_upstream_credentials(req.headers_mut(), &mcp_server);
 
-                while let Some(chunk_result) = response_stream.next().await {
-                    match chunk_result {
-                        Ok(chunk) => {
-                            let chunk_str = String::from_utf8_lossy(&chunk);
-                            tracing::info!("chunk: {:?}", chunk_str);
+            let started = Instant::now();
+            let response = match client.request(req, &mcp_server, &policy, false).await {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::error!(
+                        "Failed to request message of server {name} {tag}, error {err}"
+                    );
+                    metrics::upstream_error(&name, &tag, err.kind());
+                    metrics::message(&name, &tag, err.status().as_u16(), started);
+                    if err != UpstreamError::CircuitOpen {
+                        lease.report(Err(err.to_string()));
+                    }
+                    return Ok(build_error_stream_response(
+                        tx,
+                        stream,
+                        format!("Failed to request upstream server for {name} {tag}: {err}"),
+                        err.status(),
+                    ));
+                }
+            };
 
-                            if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
-                                tracing::warn!("connection closed: {:?}", e);
+            let status_code = response.status();
+            metrics::message(&name, &tag, status_code.as_u16(), started);
+            lease.report(upstream_result(status_code));
+            let mut headers = response.headers().clone();
+            rules.rewrite_response(&mut headers);
+            let mut inspector = StreamInspector::new(is_event_stream(&headers));
+            let idle = policy.idle_timeout();
+
+            let stream_span = tracing::info_span!("stream", name = %name, tag = %tag);
+            tokio::task::spawn(
+                async move {
+                    let _lease = lease;
+                    let mut response_stream = UpstreamBody::new(response.into_body(), idle);
+
+                    while let Some(chunk_result) = response_stream.next().await {
+                        match chunk_result {
+                            Ok(chunk) => {
+                                tracing::trace!("chunk: {:?}", String::from_utf8_lossy(&chunk));
+                                for inspection in inspector.push(&chunk) {
+                                    inspection.log(&name, &tag, "response");
+                                }
+
+                                if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
+                                    tracing::warn!("connection closed: {:?}", e);
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                tracing::error!("connection error: {:?}", e);
+                                let _ = tx.send(Err(e)).await;
                                 break;
                             }
                         }
-                        Err(e) => {
-                            tracing::error!("connection error: {:?}", e);
-                            let _ = tx.send(Err(std::io::Error::other(e))).await;
-                            break;
-                        }
                     }
-                }
 
-                let _ = tx.send(Ok(Frame::trailers(http::HeaderMap::new()))).await;
-            });
+                    if let Some(inspection) = inspector.finish() {
+                        inspection.log(&name, &tag, "response");
+                    }
+
+                    let _ = tx.send(Ok(Frame::trailers(http::HeaderMap::new()))).await;
+                }
+                .instrument(stream_span),
+            );
 
             let mut response_builder = Response::builder().status(status_code);
 
             for (name, value) in &headers {
-                if name.as_str() != "content-length" && name.as_str() != "transfer-encoding" {
-                    response_builder = response_builder.header(name, value);
-                }
+                response_builder = response_builder.header(name, value);
             }
 
             response_builder = response_builder.header("transfer-encoding", "chunked");
@@ -198,7 +351,7 @@ pub fn parse_message_router(uri: &str) -> Result<(String, String, String), Strin
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mc_common::types::HttpScheme;
+    use mc_common::types::{HttpScheme, TransportType};
     #[test]
     fn test_parse_message_router() {
         struct TestCase {
@@ -243,6 +396,9 @@ mod tests {
             host: "example.com".to_string(),
             port: "".to_string(),
             path: "".to_string(),
+            transport_type: TransportType::Sse,
+            extra: None,
+            credential: None,
         };
 
         struct TestCase {
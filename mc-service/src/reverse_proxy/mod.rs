@@ -1,25 +1,46 @@
+use crate::aggregate::AggregateService;
+use crate::aggregate::client::McpClient;
+use crate::config;
 use crate::reverse_proxy::connection::ConnectionService;
 use crate::reverse_proxy::message::MessageService;
+use crate::reverse_proxy::policy::SessionGuards;
+use crate::reverse_proxy::stdio::StdioService;
+use crate::reverse_proxy::upstream::UpstreamClient;
+use crate::stdio::StdioManager;
 use axum::Router;
-use axum::body::Body;
-use axum::http::StatusCode;
+use axum::http::header::{AUTHORIZATION, PROXY_AUTHORIZATION};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::Response;
 use bytes::Bytes;
 use http_body_util::StreamBody;
 use hyper::body::Frame;
-use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::Client;
-use hyper_util::client::legacy::connect::HttpConnector;
-use mc_common::app::cache::Cache;
+use mc_common::app::cache::{Cache, McpServerInfo};
 use mc_common::router;
+use mc_db::AggregateDBHandler;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
 use tokio::sync::mpsc::Sender;
 use tokio_stream::wrappers::ReceiverStream;
 
 pub mod connection;
+pub mod headers;
+pub mod inspect;
 pub mod message;
+pub mod policy;
+pub mod sse;
+pub mod stdio;
+pub mod streamable;
+pub mod upstream;
 
-type ProxyResponse = Response<StreamBody<ReceiverStream<Result<Frame<Bytes>, std::io::Error>>>>;
+static REGEX_PROXY_TARGET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^/proxy/(?:(?:connect|message)/([^/]+)/([^/]+)|aggregate/([^/]+))").unwrap()
+});
+
+pub(crate) type ProxyResponse =
+    Response<StreamBody<ReceiverStream<Result<Frame<Bytes>, std::io::Error>>>>;
 
 pub fn build_error_stream_response(
     tx: Sender<Result<Frame<Bytes>, std::io::Error>>,
@@ -36,19 +57,162 @@ pub fn build_error_stream_response(
     response_builder.body(StreamBody::new(stream)).unwrap()
 }
 
+/// Drops the credentials of the client, they are meant for MCP Center, and
+/// presents the credential registered for the upstream server instead.
+pub fn set_upstream_credentials(headers: &mut HeaderMap, server: &McpServerInfo) {
+    headers.remove(AUTHORIZATION);
+    headers.remove(PROXY_AUTHORIZATION);
+    if let Some(credential) = &server.credential {
+        credential.apply(headers);
+    }
+}
+
+/// Outcome of a proxied request for the health of the instance, server errors count as failures.
+pub fn upstream_result(status: StatusCode) -> Result<(), String> {
+    if status.is_server_error() {
+        return Err(format!("upstream responded {status}"));
+    }
+    Ok(())
+}
+
+/// Server addressed by a proxy path, aggregates have no tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub name: String,
+    pub tag: Option<String>,
+}
+
+pub fn proxy_target(path: &str) -> Option<Target> {
+    let caps = REGEX_PROXY_TARGET.captures(path)?;
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(name), Some(tag), _) => Some(Target {
+            name: name.as_str().to_string(),
+            tag: Some(tag.as_str().to_string()),
+        }),
+        (_, _, Some(name)) => Some(Target {
+            name: name.as_str().to_string(),
+            tag: None,
+        }),
+        _ => None,
+    }
+}
+
 pub fn register_router<S: Clone + Send + Sync + 'static>(
-    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    upstream: UpstreamClient,
     cache: Arc<Cache>,
+    aggregates: Arc<AggregateDBHandler>,
+    stdio: &config::Stdio,
+    runtime: Arc<Runtime>,
 ) -> router::RouterHandler<S> {
+    // stdio processes are shared by the connect, message and aggregate endpoints
+    let manager = StdioManager::new();
+    let idle_timeout = (stdio.idle_timeout > 0).then(|| Duration::from_secs(stdio.idle_timeout));
+    manager.start_reaper(cache.clone(), idle_timeout, runtime);
+    // guards of restricted SSE sessions are shared by the connect and message endpoints
+    let guards = SessionGuards::default();
+    let stdio = StdioService::new(manager.clone(), guards.clone());
+    let aggregate = AggregateService::new(
+        aggregates,
+        cache.clone(),
+        McpClient::new(upstream.http_client(), manager),
+    );
+
     Box::new(move |router: Router<S>| {
         router
             .route_service(
                 "/proxy/connect/{name}/{tag}",
-                ConnectionService::new(client.clone(), cache.clone()),
+                ConnectionService::new(
+                    upstream.clone(),
+                    cache.clone(),
+                    stdio.clone(),
+                    guards.clone(),
+                ),
             )
             .route_service(
                 "/proxy/message/{name}/{tag}/{*subPath}",
-                MessageService::new(client.clone(), cache.clone()),
+                MessageService::new(
+                    upstream.clone(),
+                    cache.clone(),
+                    stdio.clone(),
+                    guards.clone(),
+                ),
             )
+            .route_service("/proxy/aggregate/{name}", aggregate.clone())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::credential::UpstreamCredential;
+    use mc_common::types::{HttpScheme, TransportType};
+
+    fn github() -> Target {
+        Target {
+            name: "github".to_string(),
+            tag: Some("1.0.0".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_proxy_target() {
+        let tests = vec![
+            ("/proxy/connect/github/1.0.0", Some(github())),
+            ("/proxy/message/github/1.0.0/message", Some(github())),
+            (
+                "/proxy/aggregate/dev",
+                Some(Target {
+                    name: "dev".to_string(),
+                    tag: None,
+                }),
+            ),
+            ("/proxy/connect/github", None),
+            ("/api/registry/mcp", None),
+        ];
+
+        for (path, want) in tests {
+            assert_eq!(proxy_target(path), want, "path: {path}");
+        }
+    }
+
+    #[test]
+    fn test_set_upstream_credentials() {
+        let mut server = McpServerInfo {
+            endpoint: "http://127.0.0.1:8080/mcp".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: "8080".to_string(),
+            path: "/mcp".to_string(),
+            scheme: HttpScheme::Http,
+            transport_type: TransportType::Streamable,
+            extra: None,
+            credential: None,
+        };
+        let inbound = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, "Bearer mck_client".parse().unwrap());
+            headers.insert(PROXY_AUTHORIZATION, "Basic client".parse().unwrap());
+            headers
+        };
+
+        let mut headers = inbound();
+        set_upstream_credentials(&mut headers, &server);
+        assert!(headers.is_empty());
+
+        server.credential = Some(UpstreamCredential::Header {
+            name: "X-Api-Key".to_string(),
+            value: "upstream".to_string(),
+        });
+        let mut headers = inbound();
+        set_upstream_credentials(&mut headers, &server);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-api-key"], "upstream");
+
+        server.credential = Some(UpstreamCredential::Bearer {
+            token: "upstream".to_string(),
+        });
+        let mut headers = inbound();
+        set_upstream_credentials(&mut headers, &server);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[AUTHORIZATION], "Bearer upstream");
+    }
+}
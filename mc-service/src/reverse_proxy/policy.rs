@@ -0,0 +1,667 @@
+use crate::reverse_proxy::sse::{SseEvent, SseParser};
+use crate::reverse_proxy::stdio::parse_messages;
+use crate::stdio::is_response;
+use bytes::Bytes;
+use mc_db::model::ApiKeyPolicies;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// JSON-RPC error code of a tool call denied by policy.
+pub const CODE_ACCESS_DENIED: i64 = -32001;
+
+const METHOD_TOOLS_CALL: &str = "tools/call";
+const METHOD_TOOLS_LIST: &str = "tools/list";
+
+/// What the API key of a request may access, attached to the request by the
+/// authorization middleware. Keys without any policy are unrestricted.
+#[derive(Debug, Clone)]
+pub enum AccessPolicy {
+    Unrestricted,
+    Restricted(Vec<Grant>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub server_name: String,
+    pub tag_pattern: String,
+    pub allow_tools: Vec<String>,
+    pub deny_tools: Vec<String>,
+}
+
+impl From<ApiKeyPolicies> for Grant {
+    fn from(policy: ApiKeyPolicies) -> Self {
+        Self {
+            server_name: policy.server_name,
+            tag_pattern: policy.tag_pattern,
+            allow_tools: policy.allow_tools,
+            deny_tools: policy.deny_tools,
+        }
+    }
+}
+
+impl Grant {
+    fn matches(&self, name: &str, tag: &str) -> bool {
+        glob_match(&self.server_name, name) && glob_match(&self.tag_pattern, tag)
+    }
+
+    // deny patterns take precedence, an empty allow list allows every tool
+    fn allows_tool(&self, tool: &str) -> bool {
+        (self.allow_tools.is_empty()
+            || self
+                .allow_tools
+                .iter()
+                .any(|pattern| glob_match(pattern, tool)))
+            && !self
+                .deny_tools
+                .iter()
+                .any(|pattern| glob_match(pattern, tool))
+    }
+}
+
+impl AccessPolicy {
+    pub fn from_policies(policies: Vec<ApiKeyPolicies>) -> Self {
+        if policies.is_empty() {
+            AccessPolicy::Unrestricted
+        } else {
+            AccessPolicy::Restricted(policies.into_iter().map(Grant::from).collect())
+        }
+    }
+
+    pub fn from_request<B>(req: &http::Request<B>) -> Self {
+        req.extensions()
+            .get::<AccessPolicy>()
+            .cloned()
+            .unwrap_or(AccessPolicy::Unrestricted)
+    }
+
+    /// Returns the policy of a server, `None` when the server is not granted at all.
+    pub fn server(&self, name: &str, tag: &str) -> Option<ServerPolicy> {
+        match self {
+            AccessPolicy::Unrestricted => Some(ServerPolicy::Unrestricted),
+            AccessPolicy::Restricted(grants) => {
+                let grants: Vec<Grant> = grants
+                    .iter()
+                    .filter(|grant| grant.matches(name, tag))
+                    .cloned()
+                    .collect();
+                (!grants.is_empty()).then_some(ServerPolicy::Restricted(grants))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerPolicy {
+    Unrestricted,
+    Restricted(Vec<Grant>),
+}
+
+impl ServerPolicy {
+    pub fn is_unrestricted(&self) -> bool {
+        matches!(self, ServerPolicy::Unrestricted)
+    }
+
+    /// A tool is allowed when any grant of the server allows it.
+    pub fn allows_tool(&self, tool: &str) -> bool {
+        match self {
+            ServerPolicy::Unrestricted => true,
+            ServerPolicy::Restricted(grants) => grants.iter().any(|grant| grant.allows_tool(tool)),
+        }
+    }
+}
+
+/// Enforces a [`ServerPolicy`] on the messages of one client session: denies
+/// tool calls and filters the `tools/list` responses the session receives.
+pub struct PolicyGuard {
+    policy: ServerPolicy,
+    list_ids: Mutex<HashSet<String>>,
+    injector: Option<mpsc::Sender<Value>>,
+}
+
+impl PolicyGuard {
+    pub fn new(policy: ServerPolicy) -> Self {
+        Self {
+            policy,
+            list_ids: Mutex::new(HashSet::new()),
+            injector: None,
+        }
+    }
+
+    /// A guard of a session whose answers arrive on a separate stream, the
+    /// receiver yields the denials to be sent on that stream.
+    pub fn with_injector(policy: ServerPolicy) -> (Self, mpsc::Receiver<Value>) {
+        let (tx, rx) = mpsc::channel(100);
+        let mut guard = Self::new(policy);
+        guard.injector = Some(tx);
+        (guard, rx)
+    }
+
+    /// Splits client messages into the ones to forward and the error responses
+    /// of denied tool calls.
+    pub fn check(&self, messages: Vec<Value>) -> (Vec<Value>, Vec<Value>) {
+        let mut forward = Vec::with_capacity(messages.len());
+        let mut denied = vec![];
+
+        for message in messages {
+            let id = message.get("id").filter(|id| !id.is_null());
+            match message.get("method").and_then(Value::as_str) {
+                Some(METHOD_TOOLS_CALL) => {
+                    let tool = message
+                        .get("params")
+                        .and_then(|params| params.get("name"))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    if !self.policy.allows_tool(tool) {
+                        tracing::warn!("Tool {tool} is not permitted for the api key");
+                        if let Some(id) = id {
+                            denied.push(denied_response(id, tool));
+                        }
+                        continue;
+                    }
+                }
+                Some(METHOD_TOOLS_LIST) => {
+                    if let Some(id) = id {
+                        self.list_ids.lock().unwrap().insert(id.to_string());
+                    }
+                }
+                _ => {}
+            }
+            forward.push(message);
+        }
+
+        (forward, denied)
+    }
+
+    /// Removes the tools not permitted from `tools/list` responses, batches included.
+    pub fn filter(&self, message: &mut Value) {
+        if let Value::Array(messages) = message {
+            messages.iter_mut().for_each(|message| self.filter(message));
+            return;
+        }
+
+        if !is_response(message) {
+            return;
+        }
+        let Some(id) = message.get("id").map(Value::to_string) else {
+            return;
+        };
+        // the requests of a session stream may be checked by another instance, which leaves
+        // no id here, so every tools list on the stream is filtered
+        if !self.list_ids.lock().unwrap().remove(&id) && self.injector.is_none() {
+            return;
+        }
+
+        if let Some(Value::Array(tools)) = message
+            .get_mut("result")
+            .and_then(|result| result.get_mut("tools"))
+        {
+            tools.retain(|tool| {
+                tool.get("name")
+                    .and_then(Value::as_str)
+                    .is_some_and(|name| self.policy.allows_tool(name))
+            });
+        }
+    }
+
+    /// Filters the data of a `message` event, other events are left as they are.
+    pub fn filter_event(&self, mut event: SseEvent) -> SseEvent {
+        if event.event == "message"
+            && let Ok(mut message) = serde_json::from_str::<Value>(&event.data)
+        {
+            self.filter(&mut message);
+            event.data = message.to_string();
+        }
+        event
+    }
+
+    /// Sends denials on the session stream, returns false without a stream.
+    pub async fn inject(&self, messages: Vec<Value>) -> bool {
+        let Some(injector) = &self.injector else {
+            return false;
+        };
+        for message in messages {
+            if injector.send(message).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Guards of restricted legacy SSE sessions, shared by the connect endpoint
+/// owning the stream and the message endpoint receiving the requests. Only the
+/// instance holding the stream has the guard of a session.
+#[derive(Clone, Default)]
+pub struct SessionGuards {
+    guards: Arc<Mutex<HashMap<String, Arc<PolicyGuard>>>>,
+}
+
+impl SessionGuards {
+    pub fn insert(&self, session_id: &str, guard: Arc<PolicyGuard>) {
+        self.guards
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), guard);
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<Arc<PolicyGuard>> {
+        self.guards.lock().unwrap().get(session_id).cloned()
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        self.guards.lock().unwrap().remove(session_id);
+    }
+}
+
+/// Rewrites a response body for a [`PolicyGuard`]: filters `tools/list`
+/// results and adds the denials of the request, as events of an event stream
+/// or merged into a JSON body.
+pub struct ResponseFilter {
+    guard: Arc<PolicyGuard>,
+    parser: Option<SseParser>,
+    buffer: Vec<u8>,
+    denied: Vec<Value>,
+}
+
+impl ResponseFilter {
+    pub fn new(guard: Arc<PolicyGuard>, event_stream: bool, denied: Vec<Value>) -> Self {
+        Self {
+            guard,
+            parser: event_stream.then(SseParser::new),
+            buffer: vec![],
+            denied,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Bytes {
+        match &mut self.parser {
+            Some(parser) => {
+                let mut encoded = take_denied_events(&mut self.denied);
+                for event in parser.push(chunk) {
+                    encoded.extend_from_slice(&self.guard.filter_event(event).to_bytes());
+                }
+                Bytes::from(encoded)
+            }
+            None => {
+                self.buffer.extend_from_slice(chunk);
+                Bytes::new()
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Bytes {
+        if self.parser.is_some() {
+            return Bytes::from(take_denied_events(&mut self.denied));
+        }
+
+        let message = match serde_json::from_slice::<Value>(&self.buffer) {
+            Ok(message) => Some(message),
+            Err(_) if self.buffer.is_empty() => None,
+            // not JSON-RPC, nothing to filter
+            Err(_) => return Bytes::from(self.buffer),
+        };
+
+        let body = match message {
+            Some(mut message) => {
+                self.guard.filter(&mut message);
+                merge_messages(message, self.denied)
+            }
+            None => merge_denied(self.denied),
+        };
+        body.map_or_else(Bytes::new, |body| Bytes::from(body.to_string()))
+    }
+}
+
+/// Applies a guard to a request body, returning the body left to forward, if
+/// any, and the denials. Bodies that are not JSON-RPC are forwarded as they are.
+pub fn guard_body(guard: &PolicyGuard, body: Bytes) -> (Option<Bytes>, Vec<Value>) {
+    match parse_messages(&body) {
+        Ok((messages, batch)) => {
+            let (forward, denied) = guard.check(messages);
+            let forward = match forward.len() {
+                0 => None,
+                _ if batch => Some(Value::Array(forward)),
+                _ => forward.into_iter().next(),
+            };
+            (
+                forward.map(|message| Bytes::from(message.to_string())),
+                denied,
+            )
+        }
+        Err(_) => (Some(body), vec![]),
+    }
+}
+
+/// Applies the policy of the caller and, on the instance holding the stream, the guard
+/// of the session to a posted message. The session may have been opened by another
+/// caller, its grants never widen those of the caller.
+pub fn guard_message(
+    policy: &ServerPolicy,
+    session: Option<&PolicyGuard>,
+    body: Bytes,
+) -> (Option<Bytes>, Vec<Value>) {
+    let (forward, mut denied) = guard_body(&PolicyGuard::new(policy.clone()), body);
+    let (Some(session), Some(forward)) = (session, forward.clone()) else {
+        return (forward, denied);
+    };
+    let (forward, session_denied) = guard_body(session, forward);
+    denied.extend(session_denied);
+    (forward, denied)
+}
+
+fn take_denied_events(denied: &mut Vec<Value>) -> Vec<u8> {
+    denied
+        .drain(..)
+        .flat_map(|message| SseEvent::new("message", &message.to_string()).to_bytes())
+        .collect()
+}
+
+fn merge_messages(message: Value, denied: Vec<Value>) -> Option<Value> {
+    if denied.is_empty() {
+        return Some(message);
+    }
+    let mut messages = match message {
+        Value::Array(messages) => messages,
+        message => vec![message],
+    };
+    messages.extend(denied);
+    Some(Value::Array(messages))
+}
+
+/// The body answering denied requests only, a single response for a single request.
+pub fn merge_denied(mut denied: Vec<Value>) -> Option<Value> {
+    match denied.len() {
+        0 => None,
+        1 => denied.pop(),
+        _ => Some(Value::Array(denied)),
+    }
+}
+
+pub fn denied_response(id: &Value, tool: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": CODE_ACCESS_DENIED,
+            "message": format!("Tool {tool} is not permitted"),
+        },
+    })
+}
+
+/// Matches `value` against a pattern where `*` matches any characters.
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if value.len() < first.len() + last.len() || !value.starts_with(first) || !value.ends_with(last)
+    {
+        return false;
+    }
+
+    let mut rest = &value[first.len()..value.len() - last.len()];
+
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(server_name: &str, tag_pattern: &str, allow: &[&str], deny: &[&str]) -> Grant {
+        Grant {
+            server_name: server_name.to_string(),
+            tag_pattern: tag_pattern.to_string(),
+            allow_tools: allow.iter().map(|s| s.to_string()).collect(),
+            deny_tools: deny.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        struct TestCase {
+            pattern: &'static str,
+            value: &'static str,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                pattern: "*",
+                value: "anything",
+                want: true,
+            },
+            TestCase {
+                pattern: "github",
+                value: "github",
+                want: true,
+            },
+            TestCase {
+                pattern: "github",
+                value: "github-enterprise",
+                want: false,
+            },
+            TestCase {
+                pattern: "1.*",
+                value: "1.2.0",
+                want: true,
+            },
+            TestCase {
+                pattern: "1.*",
+                value: "2.0.0",
+                want: false,
+            },
+            TestCase {
+                pattern: "*_issue",
+                value: "create_issue",
+                want: true,
+            },
+            TestCase {
+                pattern: "get_*_by_*",
+                value: "get_user_by_id",
+                want: true,
+            },
+            TestCase {
+                pattern: "get_*_by_*",
+                value: "get_user",
+                want: false,
+            },
+            TestCase {
+                pattern: "a*a",
+                value: "a",
+                want: false,
+            },
+        ];
+
+        for t in tests {
+            assert_eq!(
+                glob_match(t.pattern, t.value),
+                t.want,
+                "pattern: {}, value: {}",
+                t.pattern,
+                t.value
+            );
+        }
+    }
+
+    #[test]
+    fn test_access_policy_server() {
+        assert!(
+            AccessPolicy::Unrestricted
+                .server("github", "1.0.0")
+                .is_some_and(|policy| policy.is_unrestricted())
+        );
+
+        let policy = AccessPolicy::Restricted(vec![
+            grant("github", "1.*", &[], &["delete_*"]),
+            grant("github", "*", &["list_*"], &[]),
+        ]);
+        assert!(policy.server("gitlab", "1.0.0").is_none());
+
+        let server = policy.server("github", "1.0.0").unwrap();
+        assert!(server.allows_tool("create_issue"));
+        assert!(!server.allows_tool("delete_repo"));
+
+        let server = policy.server("github", "2.0.0").unwrap();
+        assert!(server.allows_tool("list_issues"));
+        assert!(!server.allows_tool("create_issue"));
+    }
+
+    #[test]
+    fn test_policy_guard() {
+        let guard = PolicyGuard::new(ServerPolicy::Restricted(vec![grant(
+            "*",
+            "*",
+            &[],
+            &["delete_*"],
+        )]));
+
+        let (forward, denied) = guard.check(vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {"name": "delete_repo"}}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {"name": "create_issue"}}),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        ]);
+        assert_eq!(forward.len(), 3);
+        assert_eq!(denied, vec![denied_response(&json!(2), "delete_repo")]);
+
+        // responses to other requests are left untouched
+        let mut other =
+            json!({"jsonrpc": "2.0", "id": 3, "result": {"tools": [{"name": "delete_repo"}]}});
+        guard.filter(&mut other);
+        assert_eq!(other["result"]["tools"].as_array().unwrap().len(), 1);
+
+        let mut list = json!([{"jsonrpc": "2.0", "id": 1, "result": {"tools": [
+            {"name": "create_issue"},
+            {"name": "delete_repo"},
+        ]}}]);
+        guard.filter(&mut list);
+        assert_eq!(
+            list[0]["result"]["tools"],
+            json!([{"name": "create_issue"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_guard() {
+        let (guard, mut denials) =
+            PolicyGuard::with_injector(ServerPolicy::Restricted(vec![grant(
+                "*",
+                "*",
+                &[],
+                &["delete_*"],
+            )]));
+
+        // a tools/list checked by another instance is filtered as well
+        let mut list = json!({"jsonrpc": "2.0", "id": 9, "result": {"tools": [
+            {"name": "create_issue"},
+            {"name": "delete_repo"},
+        ]}});
+        guard.filter(&mut list);
+        assert_eq!(list["result"]["tools"], json!([{"name": "create_issue"}]));
+
+        let denied = vec![denied_response(&json!(2), "delete_repo")];
+        assert!(guard.inject(denied.clone()).await);
+        assert_eq!(denials.try_recv().unwrap(), denied[0]);
+    }
+
+    #[test]
+    fn test_guard_message() {
+        let call = |id: i64, tool: &str| json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": tool}});
+        let body =
+            Bytes::from(json!([call(1, "list_issues"), call(2, "create_issue")]).to_string());
+        let narrow = ServerPolicy::Restricted(vec![grant("*", "*", &["list_*"], &[])]);
+        let wide = PolicyGuard::new(ServerPolicy::Restricted(vec![grant("*", "*", &[], &[])]));
+        let no_list = PolicyGuard::new(ServerPolicy::Restricted(vec![grant(
+            "*",
+            "*",
+            &[],
+            &["list_*"],
+        )]));
+
+        // the grants of the session owner don't widen those of the caller
+        let (forward, denied) = guard_message(&narrow, Some(&wide), body.clone());
+        let forward: Value = serde_json::from_slice(&forward.unwrap()).unwrap();
+        assert_eq!(forward, json!([call(1, "list_issues")]));
+        assert_eq!(denied, vec![denied_response(&json!(2), "create_issue")]);
+
+        // nor the other way around
+        let (forward, denied) = guard_message(&narrow, Some(&no_list), body.clone());
+        assert!(forward.is_none());
+        assert_eq!(
+            denied,
+            vec![
+                denied_response(&json!(2), "create_issue"),
+                denied_response(&json!(1), "list_issues"),
+            ]
+        );
+
+        let (_, denied) = guard_message(&narrow, None, body);
+        assert_eq!(denied, vec![denied_response(&json!(2), "create_issue")]);
+    }
+
+    #[test]
+    fn test_response_filter() {
+        let policy = ServerPolicy::Restricted(vec![grant("*", "*", &["create_*"], &[])]);
+        let guard = Arc::new(PolicyGuard::new(policy.clone()));
+        guard.check(vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+        ]);
+
+        let denied = vec![denied_response(&json!(2), "delete_repo")];
+        let mut filter = ResponseFilter::new(guard, false, denied.clone());
+        assert!(
+            filter
+                .push(br#"{"jsonrpc":"2.0","id":1,"result":"#)
+                .is_empty()
+        );
+        filter.push(br#"{"tools":[{"name":"create_issue"},{"name":"delete_repo"}]}}"#);
+        let body: Value = serde_json::from_slice(&filter.finish()).unwrap();
+        assert_eq!(
+            body,
+            json!([
+                {"jsonrpc": "2.0", "id": 1, "result": {"tools": [{"name": "create_issue"}]}},
+                denied[0],
+            ])
+        );
+
+        let guard = Arc::new(PolicyGuard::new(policy));
+        guard.check(vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+        ]);
+        let mut filter = ResponseFilter::new(guard, true, denied.clone());
+        let mut body = filter.push(
+            b"event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"tools\":[{\"name\":\"delete_repo\"}]}}\n\n",
+        )
+        .to_vec();
+        body.extend_from_slice(&filter.finish());
+
+        let events = SseParser::new().push(&body);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<Value>(&events[0].data).unwrap(),
+            denied[0]
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&events[1].data).unwrap(),
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"tools": []}})
+        );
+    }
+
+    #[test]
+    fn test_merge_denied() {
+        assert_eq!(merge_denied(vec![]), None);
+        assert_eq!(merge_denied(vec![json!(1)]), Some(json!(1)));
+        assert_eq!(merge_denied(vec![json!(1), json!(2)]), Some(json!([1, 2])));
+    }
+}
@@ -0,0 +1,188 @@
+use bytes::Bytes;
+
+/// A single Server-Sent Event, `event` defaults to `message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: String,
+    pub data: String,
+}
+
+impl SseEvent {
+    pub fn new(event: &str, data: &str) -> Self {
+        Self {
+            id: None,
+            event: event.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut encoded = String::new();
+        if let Some(id) = &self.id {
+            encoded.push_str(&format!("id: {id}\n"));
+        }
+        encoded.push_str(&format!("event: {}\n", self.event));
+        for line in self.data.split('\n') {
+            encoded.push_str(&format!("data: {line}\n"));
+        }
+        encoded.push('\n');
+        Bytes::from(encoded)
+    }
+}
+
+/// Incremental `text/event-stream` parser, chunks may split lines anywhere.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    id: Option<String>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            // an empty line dispatches the event
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    let event = std::mem::take(&mut self.event);
+                    events.push(SseEvent {
+                        id: self.id.take(),
+                        event: if event.is_empty() {
+                            "message".to_string()
+                        } else {
+                            event
+                        },
+                        data: std::mem::take(&mut self.data).join("\n"),
+                    });
+                }
+                self.id = None;
+                self.event.clear();
+                continue;
+            }
+
+            // comment, e.g. keep-alive
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+            match field {
+                "id" => self.id = Some(value.to_string()),
+                "event" => self.event = value.to_string(),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        struct TestCase {
+            chunks: Vec<&'static str>,
+            want: Vec<(&'static str, &'static str)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                chunks: vec!["event: endpoint\ndata: /message?sessionId=1\n\n"],
+                want: vec![("endpoint", "/message?sessionId=1")],
+            },
+            TestCase {
+                chunks: vec!["event: endpoint\r\ndata: /message\r\n\r\n"],
+                want: vec![("endpoint", "/message")],
+            },
+            TestCase {
+                chunks: vec!["data: {\"id\"", ":1}\n", "\n"],
+                want: vec![("message", "{\"id\":1}")],
+            },
+            TestCase {
+                chunks: vec![": ping\n\ndata: a\ndata: b\n\nevent: x\n\n"],
+                want: vec![("message", "a\nb")],
+            },
+            TestCase {
+                chunks: vec!["data: 1\n\ndata:2\n\n"],
+                want: vec![("message", "1"), ("message", "2")],
+            },
+            TestCase {
+                chunks: vec!["id: 7\ndata: 1\n\n"],
+                want: vec![("message", "1")],
+            },
+            TestCase {
+                chunks: vec!["data: incomplete\n"],
+                want: vec![],
+            },
+        ];
+
+        for t in tests {
+            let mut parser = SseParser::new();
+            let got: Vec<SseEvent> = t
+                .chunks
+                .iter()
+                .flat_map(|chunk| parser.push(chunk.as_bytes()))
+                .collect();
+            let want: Vec<SseEvent> = t
+                .want
+                .iter()
+                .map(|(event, data)| SseEvent::new(event, data))
+                .collect();
+            let got: Vec<SseEvent> = got
+                .into_iter()
+                .map(|event| SseEvent { id: None, ..event })
+                .collect();
+            assert_eq!(got, want, "chunks: {:?}", t.chunks);
+        }
+
+        let mut parser = SseParser::new();
+        let events = parser.push(b"id: 7\ndata: 1\n\ndata: 2\n\n");
+        assert_eq!(events[0].id, Some("7".to_string()));
+        assert_eq!(events[1].id, None);
+    }
+
+    #[test]
+    fn test_sse_event_to_bytes() {
+        assert_eq!(
+            SseEvent::new("endpoint", "/message?sessionId=1").to_bytes(),
+            Bytes::from("event: endpoint\ndata: /message?sessionId=1\n\n")
+        );
+        assert_eq!(
+            SseEvent {
+                id: Some("7".to_string()),
+                event: "message".to_string(),
+                data: "a\nb".to_string(),
+            }
+            .to_bytes(),
+            Bytes::from("id: 7\nevent: message\ndata: a\ndata: b\n\n")
+        );
+
+        let mut parser = SseParser::new();
+        let event = SseEvent {
+            id: Some("7".to_string()),
+            event: "message".to_string(),
+            data: "a\nb".to_string(),
+        };
+        assert_eq!(parser.push(&event.to_bytes()), vec![event]);
+    }
+}
@@ -0,0 +1,489 @@
+use crate::reverse_proxy::connection::build_proxy_message_path;
+use crate::reverse_proxy::policy::{PolicyGuard, ServerPolicy, SessionGuards, merge_denied};
+use crate::reverse_proxy::streamable::{HEADER_MCP_SESSION_ID, session_id};
+use crate::reverse_proxy::{ProxyResponse, build_error_stream_response};
+use crate::stdio::{METHOD_INITIALIZE, StdioManager, StdioSession};
+use axum::body::Body;
+use axum::extract::Request;
+use axum::response::Response;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use mc_common::app::cache::McpServerInfo;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+
+const MESSAGE_PATH: &str = "/message";
+const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+// how long a streamable POST waits for the process to answer its requests
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Bridges HTTP clients to locally spawned stdio MCP servers.
+///
+/// A GET on the connect endpoint without `Mcp-Session-Id` opens a legacy SSE session whose
+/// messages are posted to the message endpoint; every other request follows the Streamable
+/// HTTP transport.
+#[derive(Clone)]
+pub struct StdioService {
+    manager: StdioManager,
+    guards: SessionGuards,
+}
+
+impl StdioService {
+    pub fn new(manager: StdioManager, guards: SessionGuards) -> Self {
+        Self { manager, guards }
+    }
+
+    pub async fn connect(
+        &self,
+        req: Request<Body>,
+        name: &str,
+        tag: &str,
+        mcp_server: &McpServerInfo,
+        policy: &ServerPolicy,
+    ) -> ProxyResponse {
+        let method = req.method().clone();
+        match (method, session_id(req.headers())) {
+            (Method::GET, None) => self.open_sse(name, tag, mcp_server, policy),
+            (Method::GET, Some(sid)) => self.open_stream(name, tag, &sid),
+            (Method::POST, sid) => self.post(req, name, tag, mcp_server, sid, policy).await,
+            (Method::DELETE, Some(sid)) => {
+                if self.manager.session(name, tag, &sid).is_some() {
+                    self.manager.close_session(&sid);
+                    build_body_response(StatusCode::OK, None, None, Bytes::new())
+                } else {
+                    error_response(format!("Session {sid} not found"), StatusCode::NOT_FOUND)
+                }
+            }
+            (Method::DELETE, None) => error_response(
+                "Missing Mcp-Session-Id header".to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
+            (method, _) => error_response(
+                format!("Method {method} is not allowed"),
+                StatusCode::METHOD_NOT_ALLOWED,
+            ),
+        }
+    }
+
+    /// Handles a POST to the legacy SSE message endpoint, answers arrive on the SSE stream.
+    pub async fn message(&self, req: Request<Body>, name: &str, tag: &str) -> ProxyResponse {
+        let session = match query_session_id(req.uri().query())
+            .and_then(|sid| self.manager.session(name, tag, &sid))
+        {
+            Some(session) => session,
+            None => {
+                return error_response("Session not found".to_string(), StatusCode::NOT_FOUND);
+            }
+        };
+
+        let messages = match read_messages(req).await {
+            Ok((messages, _)) => messages,
+            Err(err) => return error_response(err, StatusCode::BAD_REQUEST),
+        };
+
+        for message in messages {
+            if let Err(err) = session.send(message).await {
+                tracing::error!("Failed to send message to {name} {tag}, error {err}");
+                return error_response(err, StatusCode::BAD_GATEWAY);
+            }
+        }
+
+        build_body_response(
+            StatusCode::ACCEPTED,
+            None,
+            None,
+            Bytes::from_static(b"Accepted"),
+        )
+    }
+
+    fn open_sse(
+        &self,
+        name: &str,
+        tag: &str,
+        mcp_server: &McpServerInfo,
+        policy: &ServerPolicy,
+    ) -> ProxyResponse {
+        let session = match self.manager.open_session(name, tag, mcp_server) {
+            Ok(session) => session,
+            Err(err) => {
+                tracing::error!("Failed to open stdio session for {name} {tag}, error {err}");
+                return error_response(err, StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        let endpoint = build_proxy_message_path(name, tag, MESSAGE_PATH, &session.id);
+        let messages = session.open_stream();
+        let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(100);
+        let manager = self.manager.clone();
+        let guards = self.guards.clone();
+
+        // the message endpoint finds the guard of a restricted session by its id
+        let guard = (!policy.is_unrestricted()).then(|| {
+            let (guard, denials) = PolicyGuard::with_injector(policy.clone());
+            let guard = Arc::new(guard);
+            guards.insert(&session.id, guard.clone());
+            (guard, denials)
+        });
+
+        tokio::task::spawn(async move {
+            if tx
+                .send(Ok(Frame::data(sse_event("endpoint", &endpoint))))
+                .await
+                .is_ok()
+            {
+                forward_stream(&tx, messages, guard).await;
+            }
+            // the SSE connection is the session, end it with the connection
+            guards.remove(&session.id);
+            manager.close_session(&session.id);
+        });
+
+        build_stream_response(rx, None)
+    }
+
+    fn open_stream(&self, name: &str, tag: &str, sid: &str) -> ProxyResponse {
+        let session = match self.manager.session(name, tag, sid) {
+            Some(session) => session,
+            None => {
+                return error_response(format!("Session {sid} not found"), StatusCode::NOT_FOUND);
+            }
+        };
+
+        let messages = session.open_stream();
+        let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(100);
+        tokio::task::spawn(async move {
+            forward_stream(&tx, messages, None).await;
+        });
+
+        build_stream_response(rx, Some(&session.id))
+    }
+
+    async fn post(
+        &self,
+        req: Request<Body>,
+        name: &str,
+        tag: &str,
+        mcp_server: &McpServerInfo,
+        sid: Option<String>,
+        policy: &ServerPolicy,
+    ) -> ProxyResponse {
+        let (messages, batch) = match read_messages(req).await {
+            Ok(res) => res,
+            Err(err) => return error_response(err, StatusCode::BAD_REQUEST),
+        };
+
+        let guard = PolicyGuard::new(policy.clone());
+        let (messages, denied) = if policy.is_unrestricted() {
+            (messages, vec![])
+        } else {
+            guard.check(messages)
+        };
+
+        let session: Arc<StdioSession> = match sid {
+            Some(sid) => match self.manager.session(name, tag, &sid) {
+                Some(session) => session,
+                None => {
+                    return error_response(
+                        format!("Session {sid} not found"),
+                        StatusCode::NOT_FOUND,
+                    );
+                }
+            },
+            None if messages.iter().any(is_initialize) => {
+                match self.manager.open_session(name, tag, mcp_server) {
+                    Ok(session) => session,
+                    Err(err) => {
+                        tracing::error!(
+                            "Failed to open stdio session for {name} {tag}, error {err}"
+                        );
+                        return error_response(err, StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                }
+            }
+            None => {
+                return error_response(
+                    "Missing Mcp-Session-Id header".to_string(),
+                    StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+
+        let waiters: Vec<_> = messages
+            .iter()
+            .filter(|message| message.get("method").is_some())
+            .filter_map(|message| message.get("id"))
+            .map(|id| session.wait_response(id))
+            .collect();
+
+        for message in messages {
+            if let Err(err) = session.send(message).await {
+                tracing::error!("Failed to send message to {name} {tag}, error {err}");
+                return error_response(err, StatusCode::BAD_GATEWAY);
+            }
+        }
+
+        if waiters.is_empty() {
+            return match merge_denied(denied) {
+                Some(body) => build_body_response(
+                    StatusCode::OK,
+                    Some(&session.id),
+                    Some(CONTENT_TYPE_JSON),
+                    Bytes::from(body.to_string()),
+                ),
+                None => {
+                    build_body_response(StatusCode::ACCEPTED, Some(&session.id), None, Bytes::new())
+                }
+            };
+        }
+
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut responses = Vec::with_capacity(waiters.len());
+        for waiter in waiters {
+            match tokio::time::timeout_at(deadline, waiter).await {
+                Ok(Ok(mut response)) => {
+                    guard.filter(&mut response);
+                    responses.push(response);
+                }
+                _ => {
+                    tracing::error!("Timed out waiting for response from {name} {tag}");
+                    return error_response(
+                        format!("Timed out waiting for response from {name} {tag}"),
+                        StatusCode::GATEWAY_TIMEOUT,
+                    );
+                }
+            }
+        }
+
+        responses.extend(denied);
+        let body = if batch {
+            Value::Array(responses)
+        } else {
+            responses.remove(0)
+        };
+
+        build_body_response(
+            StatusCode::OK,
+            Some(&session.id),
+            Some(CONTENT_TYPE_JSON),
+            Bytes::from(body.to_string()),
+        )
+    }
+}
+
+// a guarded stream filters tools/list responses and carries the denials of the message endpoint
+async fn forward_stream(
+    tx: &mpsc::Sender<Result<Frame<Bytes>, std::io::Error>>,
+    mut messages: mpsc::Receiver<Value>,
+    mut guard: Option<(Arc<PolicyGuard>, mpsc::Receiver<Value>)>,
+) {
+    loop {
+        let message = tokio::select! {
+            _ = tx.closed() => break,
+            message = messages.recv() => match message {
+                Some(mut message) => {
+                    if let Some((guard, _)) = &guard {
+                        guard.filter(&mut message);
+                    }
+                    message
+                }
+                None => break,
+            },
+            Some(message) = async {
+                match &mut guard {
+                    Some((_, denials)) => denials.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => message,
+        };
+
+        let event = sse_event("message", &message.to_string());
+        if tx.send(Ok(Frame::data(event))).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn is_initialize(message: &Value) -> bool {
+    message.get("method").and_then(Value::as_str) == Some(METHOD_INITIALIZE)
+}
+
+pub(crate) async fn read_messages(req: Request<Body>) -> Result<(Vec<Value>, bool), String> {
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|err| format!("Failed to read request body: {err}"))?
+        .to_bytes();
+    parse_messages(&body)
+}
+
+// returns the JSON-RPC messages of a body and whether it was a batch
+pub(crate) fn parse_messages(body: &[u8]) -> Result<(Vec<Value>, bool), String> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(messages)) if !messages.is_empty() => Ok((messages, true)),
+        Ok(message @ Value::Object(_)) => Ok((vec![message], false)),
+        Ok(_) => Err("Request body is not a JSON-RPC message".to_string()),
+        Err(err) => Err(format!("Failed to parse JSON-RPC message: {err}")),
+    }
+}
+
+pub(crate) fn query_session_id(query: Option<&str>) -> Option<String> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("sessionId="))
+        .filter(|sid| !sid.is_empty())
+        .map(|sid| sid.to_string())
+}
+
+fn sse_event(event: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {event}\ndata: {data}\n\n"))
+}
+
+pub(crate) fn error_response(msg: String, status: StatusCode) -> ProxyResponse {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(1);
+    build_error_stream_response(tx, ReceiverStream::new(rx), msg, status)
+}
+
+pub(crate) fn build_body_response(
+    status: StatusCode,
+    sid: Option<&str>,
+    content_type: Option<&str>,
+    body: Bytes,
+) -> ProxyResponse {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(1);
+    if !body.is_empty() {
+        let _ = tx.try_send(Ok(Frame::data(body)));
+    }
+
+    let mut response_builder = Response::builder().status(status);
+    if let Some(sid) = sid {
+        response_builder = response_builder.header(HEADER_MCP_SESSION_ID, sid);
+    }
+    if let Some(content_type) = content_type {
+        response_builder = response_builder.header(http::header::CONTENT_TYPE, content_type);
+    }
+    response_builder
+        .body(StreamBody::new(ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+fn build_stream_response(
+    rx: mpsc::Receiver<Result<Frame<Bytes>, std::io::Error>>,
+    sid: Option<&str>,
+) -> ProxyResponse {
+    let mut response_builder = Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, CONTENT_TYPE_EVENT_STREAM)
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        .header("connection", "keep-alive");
+    if let Some(sid) = sid {
+        response_builder = response_builder.header(HEADER_MCP_SESSION_ID, sid);
+    }
+    response_builder
+        .body(StreamBody::new(ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_messages() {
+        struct TestCase {
+            body: &'static str,
+            want: Result<(Vec<Value>, bool), ()>,
+        }
+
+        let tests = vec![
+            TestCase {
+                body: r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+                want: Ok((vec![json!({"jsonrpc":"2.0","id":1,"method":"ping"})], false)),
+            },
+            TestCase {
+                body: r#"[{"jsonrpc":"2.0","id":1,"method":"ping"},{"jsonrpc":"2.0","method":"notifications/initialized"}]"#,
+                want: Ok((
+                    vec![
+                        json!({"jsonrpc":"2.0","id":1,"method":"ping"}),
+                        json!({"jsonrpc":"2.0","method":"notifications/initialized"}),
+                    ],
+                    true,
+                )),
+            },
+            TestCase {
+                body: "[]",
+                want: Err(()),
+            },
+            TestCase {
+                body: "\"ping\"",
+                want: Err(()),
+            },
+            TestCase {
+                body: "not json",
+                want: Err(()),
+            },
+        ];
+
+        for t in tests {
+            let got = parse_messages(t.body.as_bytes()).map_err(|_| ());
+            assert_eq!(got, t.want, "body: {}", t.body);
+        }
+    }
+
+    #[test]
+    fn test_query_session_id() {
+        struct TestCase {
+            query: Option<&'static str>,
+            want: Option<&'static str>,
+        }
+
+        let tests = vec![
+            TestCase {
+                query: Some("sessionId=36f34c7e-ec0c-4f6d-8451-38b4488ff4e4"),
+                want: Some("36f34c7e-ec0c-4f6d-8451-38b4488ff4e4"),
+            },
+            TestCase {
+                query: Some("foo=bar&sessionId=abc"),
+                want: Some("abc"),
+            },
+            TestCase {
+                query: Some("sessionId="),
+                want: None,
+            },
+            TestCase {
+                query: Some("foo=bar"),
+                want: None,
+            },
+            TestCase {
+                query: None,
+                want: None,
+            },
+        ];
+
+        for t in tests {
+            assert_eq!(
+                query_session_id(t.query).as_deref(),
+                t.want,
+                "query: {:?}",
+                t.query
+            );
+        }
+    }
+
+    #[test]
+    fn test_sse_event() {
+        assert_eq!(
+            sse_event("endpoint", "/proxy/message/a/1/message?sessionId=x"),
+            Bytes::from("event: endpoint\ndata: /proxy/message/a/1/message?sessionId=x\n\n")
+        );
+    }
+}
@@ -0,0 +1,506 @@
+use crate::reverse_proxy::headers::{HeaderRules, client_addr};
+use crate::reverse_proxy::inspect::{Inspection, StreamInspector, inspect, inspect_request};
+use crate::reverse_proxy::policy::{
+    PolicyGuard, ResponseFilter, ServerPolicy, guard_body, merge_denied,
+};
+use crate::reverse_proxy::upstream::{UpstreamBody, UpstreamClient, UpstreamError};
+use crate::reverse_proxy::{
+    ProxyResponse, build_error_stream_response, set_upstream_credentials, upstream_result,
+};
+use crate::telemetry;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::response::Response;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use http_body_util::StreamBody;
+use hyper::body::Frame;
+use mc_common::app::balancer::Lease;
+use mc_common::app::cache::{Cache, McpServerInfo};
+use mc_common::metrics;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::Instrument;
+
+pub const HEADER_MCP_SESSION_ID: &str = "mcp-session-id";
+
+const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+/// Proxies the MCP Streamable HTTP transport, where a single endpoint accepts
+/// POST (JSON-RPC messages), GET (server-initiated SSE stream) and DELETE
+/// (session termination), and the session is carried in `Mcp-Session-Id`.
+#[derive(Clone)]
+pub struct StreamableService {
+    client: UpstreamClient,
+    cache: Arc<Cache>,
+}
+
+impl StreamableService {
+    pub fn new(client: UpstreamClient, cache: Arc<Cache>) -> Self {
+        Self { client, cache }
+    }
+
+    /// `lease` counts the request as active on the instance until the response ends.
+    pub async fn proxy(
+        &self,
+        mut req: Request<Body>,
+        name: &str,
+        tag: &str,
+        mcp_server: &McpServerInfo,
+        policy: &ServerPolicy,
+        lease: Lease,
+    ) -> ProxyResponse {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(100);
+        let stream = ReceiverStream::new(rx);
+
+        let method = req.method().clone();
+        if method != Method::POST && method != Method::GET && method != Method::DELETE {
+            tracing::warn!("Unsupported method {method} for streamable server {name} {tag}");
+            return build_error_stream_response(
+                tx,
+                stream,
+                format!("Method {method} is not allowed"),
+                StatusCode::METHOD_NOT_ALLOWED,
+            );
+        }
+
+        // denied tool calls of a restricted key are answered here, the rest is forwarded
+        let mut guarded = None;
+        // GET and DELETE carry no messages, a session may be initialized more than once
+        let mut idempotent = method != Method::POST;
+        if method == Method::POST {
+            let (mut parts, mut body) = match inspect_request(req, name, tag).await {
+                Ok(res) => res,
+                Err(err) => {
+                    tracing::error!("Failed to inspect message for {name} {tag}, error {err}");
+                    return build_error_stream_response(tx, stream, err, StatusCode::BAD_REQUEST);
+                }
+            };
+
+            if !policy.is_unrestricted() {
+                let guard = Arc::new(PolicyGuard::new(policy.clone()));
+                let (forward, denied) = guard_body(&guard, body);
+                body = match forward {
+                    Some(forward) => forward,
+                    None => {
+                        return match merge_denied(denied) {
+                            Some(denied) => build_json_response(tx, stream, denied),
+                            None => build_error_stream_response(
+                                tx,
+                                stream,
+                                String::new(),
+                                StatusCode::ACCEPTED,
+                            ),
+                        };
+                    }
+                };
+                guarded = Some((guard, denied));
+            }
+            idempotent = session_id(&parts.headers).is_none() && is_initialize(&body);
+            telemetry::link_meta(&body);
+            let body = telemetry::inject_meta(body);
+            // the body may have been rewritten, let the client compute its length
+            parts.headers.remove(http::header::CONTENT_LENGTH);
+            req = Request::from_parts(parts, Body::from(body));
+        }
+
+        let request_session_id = session_id(req.headers());
+
+        let rules = match HeaderRules::from_server(mcp_server) {
+            Ok(rules) => rules,
+            Err(err) => {
+                tracing::error!("Failed to load header rules of {name} {tag}, error {err}");
+                return build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("Invalid header rules of {name} {tag}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        };
+
+        let policy = match self.client.policy(mcp_server) {
+            Ok(policy) => policy,
+            Err(err) => {
+                tracing::error!("Failed to load upstream policy of {name} {tag}, error {err}");
+                return build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("Invalid upstream policy of {name} {tag}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        };
+
+        *req.uri_mut() = match Uri::try_from(build_streamable_uri(mcp_server, req.uri().query())) {
+            Ok(uri) => uri,
+            Err(err) => {
+                tracing::error!("Failed to convert endpoint to uri for {name} {tag}, error {err}");
+                return build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("Failed to convert endpoint to uri for {name} {tag}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        };
+
+        let peer = client_addr(req.extensions());
+        rules.rewrite_request(req.headers_mut(), peer);
+        if let Ok(host) = HeaderValue::from_str(mcp_server.host.as_str()) {
+            req.headers_mut().insert(http::header::HOST, host);
+        };
+        set_upstream_credentials(req.headers_mut(), mcp_server);
+
+        let started = Instant::now();
+        let response = match self
+            .client
+            .request(req, mcp_server, &policy, idempotent)
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::error!("Failed to request streamable server {name} {tag}, error {err}");
+                metrics::upstream_error(name, tag, err.kind());
+                if method == Method::POST {
+                    metrics::message(name, tag, err.status().as_u16(), started);
+                }
+                if err != UpstreamError::CircuitOpen {
+                    lease.report(Err(err.to_string()));
+                }
+                return build_error_stream_response(
+                    tx,
+                    stream,
+                    format!("Failed to request upstream server for {name} {tag}: {err}"),
+                    err.status(),
+                );
+            }
+        };
+
+        let mut status_code = response.status();
+        if method == Method::POST {
+            metrics::message(name, tag, status_code.as_u16(), started);
+        }
+        lease.report(upstream_result(status_code));
+        let mut headers = response.headers().clone();
+        rules.rewrite_response(&mut headers);
+
+        // an accepted body of notifications still has to carry the denials
+        let answer_denied = status_code == StatusCode::ACCEPTED
+            && guarded
+                .as_ref()
+                .is_some_and(|(_, denied)| !denied.is_empty());
+        if answer_denied {
+            status_code = StatusCode::OK;
+        }
+
+        match (&method, session_id(&headers)) {
+            (&Method::DELETE, _) => {
+                if let Some(sid) = &request_session_id {
+                    self.cache.unbind_session(name, tag, sid);
+                }
+                tracing::info!(
+                    "terminate mcp session sessionId={}, name={}, tag={}, status={}",
+                    request_session_id.unwrap_or_default(),
+                    name,
+                    tag,
+                    status_code
+                );
+            }
+            (_, Some(sid)) if request_session_id.as_deref() != Some(sid.as_str()) => {
+                // later requests of the session must reach the same instance
+                self.cache
+                    .bind_session(name, tag, &sid, &mcp_server.endpoint);
+                tracing::info!(
+                    "connect mcp success sessionId={}, name={}, tag={}",
+                    sid,
+                    name,
+                    tag
+                );
+            }
+            _ => {}
+        }
+
+        let event_stream = is_event_stream(&headers);
+        let mut inspector = StreamInspector::new(event_stream);
+        let mut filter =
+            guarded.map(|(guard, denied)| ResponseFilter::new(guard, event_stream, denied));
+        let (name, tag) = (name.to_string(), tag.to_string());
+        let idle = policy.idle_timeout();
+
+        let stream_span = tracing::info_span!("stream", name = %name, tag = %tag);
+        tokio::task::spawn(
+            async move {
+                let _lease = lease;
+                let mut response_stream = UpstreamBody::new(response.into_body(), idle);
+
+                while let Some(chunk_result) = response_stream.next().await {
+                    match chunk_result {
+                        Ok(chunk) => {
+                            tracing::trace!("chunk: {:?}", String::from_utf8_lossy(&chunk));
+                            for inspection in inspector.push(&chunk) {
+                                inspection.log(&name, &tag, "response");
+                            }
+
+                            let chunk = match &mut filter {
+                                Some(filter) => filter.push(&chunk),
+                                None => chunk,
+                            };
+                            if chunk.is_empty() {
+                                continue;
+                            }
+
+                            if let Err(e) = tx.send(Ok(Frame::data(chunk))).await {
+                                tracing::warn!("connection closed: {:?}", e);
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("connection error: {:?}", e);
+                            let _ = tx.send(Err(e)).await;
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(inspection) = inspector.finish() {
+                    inspection.log(&name, &tag, "response");
+                }
+
+                if let Some(filter) = filter {
+                    let chunk = filter.finish();
+                    if !chunk.is_empty() {
+                        let _ = tx.send(Ok(Frame::data(chunk))).await;
+                    }
+                }
+
+                let _ = tx.send(Ok(Frame::trailers(HeaderMap::new()))).await;
+            }
+            .instrument(stream_span),
+        );
+
+        let mut response_builder = Response::builder().status(status_code);
+
+        for (name, value) in &headers {
+            response_builder = response_builder.header(name, value);
+        }
+
+        if event_stream {
+            response_builder = response_builder.header("connection", "keep-alive");
+        }
+
+        if answer_denied {
+            response_builder =
+                response_builder.header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON);
+        }
+
+        response_builder.body(StreamBody::new(stream)).unwrap()
+    }
+}
+
+fn build_json_response(
+    tx: Sender<Result<Frame<Bytes>, std::io::Error>>,
+    stream: ReceiverStream<Result<Frame<Bytes>, std::io::Error>>,
+    body: Value,
+) -> ProxyResponse {
+    tokio::task::spawn(async move {
+        let _ = tx
+            .send(Ok(Frame::data(Bytes::from(body.to_string()))))
+            .await;
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
+        .body(StreamBody::new(stream))
+        .unwrap()
+}
+
+// the handshake opening a session, safe to send again when no response arrived
+fn is_initialize(body: &[u8]) -> bool {
+    match inspect(body) {
+        Inspection::JsonRpc {
+            messages,
+            batch: false,
+        } => messages[0].method.as_deref() == Some("initialize"),
+        _ => false,
+    }
+}
+
+pub(crate) fn session_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(HEADER_MCP_SESSION_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+pub(crate) fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(CONTENT_TYPE_EVENT_STREAM))
+}
+
+fn build_streamable_uri(mcp_server: &McpServerInfo, path_query: Option<&str>) -> String {
+    match path_query {
+        Some(query) if !query.is_empty() => {
+            let separator = if mcp_server.endpoint.contains('?') {
+                '&'
+            } else {
+                '?'
+            };
+            format!("{}{separator}{query}", mcp_server.endpoint)
+        }
+        _ => mcp_server.endpoint.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::types::{HttpScheme, TransportType};
+
+    fn server(endpoint: &str) -> McpServerInfo {
+        McpServerInfo {
+            endpoint: endpoint.to_string(),
+            host: "example.com".to_string(),
+            port: "443".to_string(),
+            path: "/mcp".to_string(),
+            scheme: HttpScheme::Https,
+            transport_type: TransportType::Streamable,
+            extra: None,
+            credential: None,
+        }
+    }
+
+    #[test]
+    fn test_build_streamable_uri() {
+        struct TestCase {
+            endpoint: &'static str,
+            path_query: Option<&'static str>,
+            want: &'static str,
+        }
+
+        let tests = vec![
+            TestCase {
+                endpoint: "https://example.com/mcp",
+                path_query: None,
+                want: "https://example.com/mcp",
+            },
+            TestCase {
+                endpoint: "https://example.com/mcp",
+                path_query: Some(""),
+                want: "https://example.com/mcp",
+            },
+            TestCase {
+                endpoint: "https://example.com/mcp",
+                path_query: Some("foo=bar"),
+                want: "https://example.com/mcp?foo=bar",
+            },
+            TestCase {
+                endpoint: "https://example.com/mcp?region=eu",
+                path_query: Some("foo=bar"),
+                want: "https://example.com/mcp?region=eu&foo=bar",
+            },
+        ];
+
+        for t in tests {
+            let got = build_streamable_uri(&server(t.endpoint), t.path_query);
+            assert_eq!(
+                got, t.want,
+                "endpoint: {}, query: {:?}",
+                t.endpoint, t.path_query
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_event_stream() {
+        struct TestCase {
+            content_type: Option<&'static str>,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                content_type: Some("text/event-stream"),
+                want: true,
+            },
+            TestCase {
+                content_type: Some("text/event-stream; charset=utf-8"),
+                want: true,
+            },
+            TestCase {
+                content_type: Some("application/json"),
+                want: false,
+            },
+            TestCase {
+                content_type: None,
+                want: false,
+            },
+        ];
+
+        for t in tests {
+            let mut headers = HeaderMap::new();
+            if let Some(content_type) = t.content_type {
+                headers.insert(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static(content_type),
+                );
+            }
+            assert_eq!(
+                is_event_stream(&headers),
+                t.want,
+                "content_type: {:?}",
+                t.content_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_session_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_id(&headers), None);
+
+        headers.insert(
+            "Mcp-Session-Id",
+            HeaderValue::from_static("36f34c7e-ec0c-4f6d-8451-38b4488ff4e4"),
+        );
+        assert_eq!(
+            session_id(&headers),
+            Some("36f34c7e-ec0c-4f6d-8451-38b4488ff4e4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_initialize() {
+        let cases = [
+            (
+                r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{}}"#,
+                true,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{}}"#,
+                false,
+            ),
+            (
+                r#"[{"jsonrpc":"2.0","id":0,"method":"initialize","params":{}}]"#,
+                false,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                false,
+            ),
+            ("not json", false),
+        ];
+
+        for (body, want) in cases {
+            assert_eq!(is_initialize(body.as_bytes()), want, "{body}");
+        }
+    }
+}
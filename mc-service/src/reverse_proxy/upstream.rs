@@ -0,0 +1,540 @@
+use crate::telemetry;
+use axum::body::Body;
+use axum::extract::Request;
+use bytes::Bytes;
+use http::request::Parts;
+use http::{Response, StatusCode};
+use http_body_util::{BodyDataStream, BodyExt};
+use hyper::body::Incoming;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use mc_common::app::cache::McpServerInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::{Sleep, sleep, timeout};
+use tokio_stream::Stream;
+
+// pause before the first retry, growing with each attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Timeouts, retries and circuit breaking of requests to a server. The defaults
+/// come from `mcp_center.upstream`, `upstream` of the registry `extra` column
+/// overrides them per server. Durations are in seconds, `0` disables a timeout.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct UpstreamPolicy {
+    /// Until the response headers arrive.
+    pub first_byte_timeout: u64,
+    /// Between two chunks of a response body. SSE streams quiet for longer are cut.
+    pub idle_timeout: u64,
+    /// Extra attempts of idempotent handshakes failing to connect. Requests which
+    /// may have reached the server, timed out ones included, are not sent again.
+    pub retries: u32,
+    /// Failures in a row opening the circuit of an instance, `0` disables the breaker.
+    pub failure_threshold: u32,
+    /// How long an open circuit fails requests before letting a trial through.
+    pub open_timeout: u64,
+}
+
+impl Default for UpstreamPolicy {
+    fn default() -> Self {
+        Self {
+            first_byte_timeout: 30,
+            idle_timeout: 0,
+            retries: 2,
+            failure_threshold: 5,
+            open_timeout: 30,
+        }
+    }
+}
+
+impl UpstreamPolicy {
+    pub fn from_extra(
+        extra: Option<&serde_json::Value>,
+        defaults: &UpstreamPolicy,
+    ) -> Result<Self, String> {
+        let Some(overrides) = extra.and_then(|extra| extra.get("upstream")) else {
+            return Ok(defaults.clone());
+        };
+        let Some(overrides) = overrides.as_object() else {
+            return Err("invalid upstream config: expected an object".to_string());
+        };
+
+        let mut policy = serde_json::to_value(defaults).map_err(|err| err.to_string())?;
+        let fields = policy
+            .as_object_mut()
+            .ok_or("invalid upstream config defaults")?;
+        for (key, value) in overrides {
+            if !fields.contains_key(key) {
+                return Err(format!("invalid upstream config: unknown field `{key}`"));
+            }
+            fields.insert(key.clone(), value.clone());
+        }
+        serde_json::from_value(policy).map_err(|err| format!("invalid upstream config: {err}"))
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        seconds(self.idle_timeout)
+    }
+
+    fn first_byte_timeout(&self) -> Option<Duration> {
+        seconds(self.first_byte_timeout)
+    }
+}
+
+fn seconds(value: u64) -> Option<Duration> {
+    (value > 0).then(|| Duration::from_secs(value))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpstreamError {
+    /// The instance failed too often, requests are refused without trying it.
+    CircuitOpen,
+    /// No connection to the instance could be opened, the request was not sent.
+    Connect(String),
+    Timeout,
+    Request(String),
+}
+
+impl UpstreamError {
+    /// Label of the error in metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UpstreamError::CircuitOpen => "circuit_open",
+            UpstreamError::Connect(_) => "connect",
+            UpstreamError::Timeout => "timeout",
+            UpstreamError::Request(_) => "request",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UpstreamError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            UpstreamError::CircuitOpen | UpstreamError::Connect(_) | UpstreamError::Request(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+        }
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::CircuitOpen => write!(f, "circuit breaker is open"),
+            UpstreamError::Timeout => write!(f, "upstream did not respond in time"),
+            UpstreamError::Connect(err) | UpstreamError::Request(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// The HTTP client of the proxy, applying the [`UpstreamPolicy`] of each server.
+#[derive(Clone)]
+pub struct UpstreamClient {
+    client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+    defaults: UpstreamPolicy,
+    breakers: CircuitBreakers,
+}
+
+impl UpstreamClient {
+    pub fn new(
+        client: Arc<Client<HttpsConnector<HttpConnector>, Body>>,
+        defaults: UpstreamPolicy,
+    ) -> Self {
+        Self {
+            client,
+            defaults,
+            breakers: CircuitBreakers::default(),
+        }
+    }
+
+    /// The shared client, for callers with their own timeouts.
+    pub fn http_client(&self) -> Arc<Client<HttpsConnector<HttpConnector>, Body>> {
+        self.client.clone()
+    }
+
+    pub fn policy(&self, server: &McpServerInfo) -> Result<UpstreamPolicy, String> {
+        UpstreamPolicy::from_extra(server.extra.as_ref(), &self.defaults)
+    }
+
+    /// Sends `req` to `server`. Only `idempotent` requests are retried, their body is buffered.
+    #[tracing::instrument(
+        name = "upstream.request",
+        skip_all,
+        fields(
+            otel.kind = "client",
+            server.endpoint = %server.endpoint,
+            http.response.status_code = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+        )
+    )]
+    pub async fn request(
+        &self,
+        mut req: Request<Body>,
+        server: &McpServerInfo,
+        policy: &UpstreamPolicy,
+        idempotent: bool,
+    ) -> Result<Response<Incoming>, UpstreamError> {
+        telemetry::inject(req.headers_mut());
+
+        if !self
+            .breakers
+            .allow(&server.endpoint, policy, Instant::now())
+        {
+            return Err(UpstreamError::CircuitOpen);
+        }
+
+        let result = if idempotent && policy.retries > 0 {
+            self.send_with_retries(req, server, policy).await
+        } else {
+            self.send(req, policy).await
+        };
+
+        let succeeded = result
+            .as_ref()
+            .is_ok_and(|response| !response.status().is_server_error());
+        self.breakers
+            .record(&server.endpoint, policy, succeeded, Instant::now());
+
+        let span = tracing::Span::current();
+        match &result {
+            Ok(response) => span.record("http.response.status_code", response.status().as_u16()),
+            Err(_) => span.record("otel.status_code", "error"),
+        };
+        result
+    }
+
+    async fn send(
+        &self,
+        req: Request<Body>,
+        policy: &UpstreamPolicy,
+    ) -> Result<Response<Incoming>, UpstreamError> {
+        let response = self.client.request(req);
+        let response = match policy.first_byte_timeout() {
+            None => response.await,
+            Some(first_byte) => timeout(first_byte, response)
+                .await
+                .map_err(|_| UpstreamError::Timeout)?,
+        };
+        response.map_err(|err| {
+            if err.is_connect() {
+                UpstreamError::Connect(err.to_string())
+            } else {
+                UpstreamError::Request(err.to_string())
+            }
+        })
+    }
+
+    async fn send_with_retries(
+        &self,
+        req: Request<Body>,
+        server: &McpServerInfo,
+        policy: &UpstreamPolicy,
+    ) -> Result<Response<Incoming>, UpstreamError> {
+        let (parts, body) = req.into_parts();
+        let body = body
+            .collect()
+            .await
+            .map_err(|err| UpstreamError::Request(format!("Failed to read request body: {err}")))?
+            .to_bytes();
+
+        let mut attempt = 0;
+        loop {
+            match self.send(clone_request(&parts, body.clone()), policy).await {
+                // a connect error is the only failure proving the request was never delivered
+                Err(err @ UpstreamError::Connect(_)) if attempt < policy.retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Request to {} failed, retrying ({}/{}), error: {}",
+                        server.endpoint,
+                        attempt,
+                        policy.retries,
+                        err
+                    );
+                    sleep(RETRY_BACKOFF * attempt).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn clone_request(parts: &Parts, body: Bytes) -> Request<Body> {
+    let mut req = Request::new(Body::from(body));
+    *req.method_mut() = parts.method.clone();
+    *req.uri_mut() = parts.uri.clone();
+    *req.version_mut() = parts.version;
+    *req.headers_mut() = parts.headers.clone();
+    req
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Circuit breakers of the upstream instances by endpoint. An open circuit lets
+/// one trial request through per `open_timeout`, its success closes the circuit.
+#[derive(Clone, Default)]
+struct CircuitBreakers {
+    breakers: Arc<Mutex<HashMap<String, Breaker>>>,
+}
+
+impl CircuitBreakers {
+    fn allow(&self, endpoint: &str, policy: &UpstreamPolicy, now: Instant) -> bool {
+        if policy.failure_threshold == 0 {
+            return true;
+        }
+        let mut breakers = self.breakers.lock().unwrap();
+        let Some(breaker) = breakers.get_mut(endpoint) else {
+            return true;
+        };
+        match breaker.open_until {
+            Some(until) if now < until => false,
+            Some(_) => {
+                // half open, a failing trial keeps the circuit open for another period
+                breaker.open_until = Some(now + Duration::from_secs(policy.open_timeout));
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record(&self, endpoint: &str, policy: &UpstreamPolicy, succeeded: bool, now: Instant) {
+        if policy.failure_threshold == 0 {
+            return;
+        }
+        let mut breakers = self.breakers.lock().unwrap();
+        if succeeded {
+            if breakers
+                .remove(endpoint)
+                .is_some_and(|b| b.open_until.is_some())
+            {
+                tracing::info!("Circuit of {} is closed", endpoint);
+            }
+            return;
+        }
+
+        let breaker = breakers.entry(endpoint.to_string()).or_default();
+        breaker.failures = breaker.failures.saturating_add(1);
+        if breaker.open_until.is_some() || breaker.failures >= policy.failure_threshold {
+            if breaker.open_until.is_none() {
+                tracing::warn!(
+                    "Circuit of {} is open after {} failures",
+                    endpoint,
+                    breaker.failures
+                );
+            }
+            breaker.open_until = Some(now + Duration::from_secs(policy.open_timeout));
+        }
+    }
+}
+
+/// Body of an upstream response, failing once it stalls longer than the idle timeout.
+pub struct UpstreamBody {
+    inner: BodyDataStream<Incoming>,
+    idle: Option<Duration>,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl UpstreamBody {
+    pub fn new(body: Incoming, idle: Option<Duration>) -> Self {
+        Self {
+            inner: body.into_data_stream(),
+            idle,
+            timer: idle.map(|idle| Box::pin(sleep(idle))),
+        }
+    }
+}
+
+impl Stream for UpstreamBody {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Poll::Ready(item) = Pin::new(&mut this.inner).poll_next(cx) {
+            if let (Some(timer), Some(idle)) = (&mut this.timer, this.idle) {
+                timer.as_mut().reset(tokio::time::Instant::now() + idle);
+            }
+            return Poll::Ready(item.map(|chunk| chunk.map_err(std::io::Error::other)));
+        }
+
+        let stalled = match &mut this.timer {
+            Some(timer) => timer.as_mut().poll(cx).is_ready(),
+            None => false,
+        };
+        if !stalled {
+            return Poll::Pending;
+        }
+        this.timer = None;
+        this.idle = None;
+        Poll::Ready(Some(Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "upstream body is idle",
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper_rustls::HttpsConnectorBuilder;
+    use hyper_util::rt::TokioExecutor;
+    use mc_common::types::{HttpScheme, TransportType};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    fn upstream_client() -> UpstreamClient {
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .unwrap()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        UpstreamClient::new(
+            Arc::new(Client::builder(TokioExecutor::new()).build(https)),
+            UpstreamPolicy::default(),
+        )
+    }
+
+    fn server(port: u16) -> McpServerInfo {
+        McpServerInfo {
+            endpoint: format!("http://127.0.0.1:{port}/sse"),
+            host: "127.0.0.1".to_string(),
+            port: port.to_string(),
+            path: "/sse".to_string(),
+            scheme: HttpScheme::Http,
+            transport_type: TransportType::Sse,
+            extra: None,
+            credential: None,
+        }
+    }
+
+    fn get(server: &McpServerInfo) -> Request<Body> {
+        Request::get(&server.endpoint).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_from_extra() {
+        let defaults = UpstreamPolicy::default();
+        assert_eq!(
+            UpstreamPolicy::from_extra(None, &defaults).unwrap(),
+            defaults
+        );
+        assert_eq!(
+            UpstreamPolicy::from_extra(Some(&json!({"headers": {}})), &defaults).unwrap(),
+            defaults
+        );
+
+        let policy = UpstreamPolicy::from_extra(
+            Some(&json!({"upstream": {"first_byte_timeout": 5, "retries": 0}})),
+            &defaults,
+        )
+        .unwrap();
+        assert_eq!(policy.first_byte_timeout, 5);
+        assert_eq!(policy.retries, 0);
+        assert_eq!(policy.failure_threshold, defaults.failure_threshold);
+        assert_eq!(policy.idle_timeout(), None);
+
+        let invalid = [
+            json!({"upstream": []}),
+            json!({"upstream": {"connect_timeout": 5}}),
+            json!({"upstream": {"retries": -1}}),
+        ];
+        for extra in invalid {
+            assert!(
+                UpstreamPolicy::from_extra(Some(&extra), &defaults).is_err(),
+                "{extra}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let client = upstream_client();
+        let policy = UpstreamPolicy {
+            first_byte_timeout: 1,
+            retries: 2,
+            failure_threshold: 0,
+            ..Default::default()
+        };
+
+        // nothing listens on the port, every attempt fails to connect
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = server(listener.local_addr().unwrap().port());
+        drop(listener);
+        let err = client
+            .request(get(&closed), &closed, &policy, true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UpstreamError::Connect(_)), "{err:?}");
+
+        // the request reached a server which never answers, it is not sent again
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent = server(listener.local_addr().unwrap().port());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                connections.push(stream);
+            }
+        });
+        let err = client
+            .request(get(&silent), &silent, &policy, true)
+            .await
+            .unwrap_err();
+        assert_eq!(err, UpstreamError::Timeout);
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let policy = UpstreamPolicy {
+            failure_threshold: 2,
+            open_timeout: 10,
+            ..Default::default()
+        };
+        let breakers = CircuitBreakers::default();
+        let now = Instant::now();
+        let endpoint = "http://mcp-a/sse";
+
+        breakers.record(endpoint, &policy, false, now);
+        assert!(breakers.allow(endpoint, &policy, now));
+        breakers.record(endpoint, &policy, false, now);
+        assert!(!breakers.allow(endpoint, &policy, now));
+        assert!(!breakers.allow(endpoint, &policy, now + Duration::from_secs(9)));
+        // other instances are not affected
+        assert!(breakers.allow("http://mcp-b/sse", &policy, now));
+
+        // one trial after the open timeout, its failure opens the circuit again
+        let later = now + Duration::from_secs(10);
+        assert!(breakers.allow(endpoint, &policy, later));
+        assert!(!breakers.allow(endpoint, &policy, later));
+        breakers.record(endpoint, &policy, false, later);
+        assert!(!breakers.allow(endpoint, &policy, later + Duration::from_secs(9)));
+
+        let later = later + Duration::from_secs(10);
+        assert!(breakers.allow(endpoint, &policy, later));
+        breakers.record(endpoint, &policy, true, later);
+        assert!(breakers.allow(endpoint, &policy, later));
+        assert!(breakers.allow(endpoint, &policy, later));
+
+        let disabled = UpstreamPolicy {
+            failure_threshold: 0,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            breakers.record(endpoint, &disabled, false, now);
+        }
+        assert!(breakers.allow(endpoint, &disabled, now));
+    }
+}
@@ -1,32 +1,58 @@
+use crate::aggregate::client::McpClient;
+use crate::audit;
+use crate::audit::{Actor, AuditLog};
 use crate::config::{AppConfig, McpRegistry};
+use crate::health::HealthChecker;
+use crate::oauth;
+use crate::oauth::ResourceServer;
+use crate::rate_limit;
+use crate::rate_limit::RateLimiter;
 use crate::reverse_proxy;
+use crate::reverse_proxy::policy::AccessPolicy;
+use crate::reverse_proxy::upstream::UpstreamClient;
+use crate::stdio::StdioManager;
+use crate::sync::LoaderSync;
+use crate::telemetry;
 use axum::extract::{Request, State};
 use axum::middleware;
 use axum::middleware::Next;
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 use hyper_rustls::HttpsConnectorBuilder;
 use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::rt::TokioExecutor;
 use mc_booter::app::application::Application;
 use mc_common::app::cache::Cache;
 use mc_common::app::event::Event;
-use mc_common::app::{AppState, HandlerManager};
+use mc_common::app::{Admin, AppState, HandlerManager};
+use mc_common::credential::CredentialCipher;
+use mc_common::metrics;
 use mc_common::router;
 use mc_common::router::RouterHandler;
 use mc_db::DBClient;
+use mc_db::model::{CreateFrom, ROLE_ADMIN};
+use mc_loader::Loader;
+use mc_loader::external_api::{ExternalApiLoader, ExternalApiOptions};
+use mc_loader::kubernetes::KubernetesLoader;
+use mc_loader::local::LocalFileLoader;
+use mc_token::jwt::{self, Authenticator, TokenType};
 use std::error::Error;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::sync::broadcast;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
 
 pub enum Registry {
-    #[allow(dead_code)]
     Memory(String),
-    #[allow(dead_code)]
     ExternalAPI(ExternalApiConfig),
+    Kubernetes(KubernetesConfig),
 }
 
 impl Default for Registry {
@@ -35,23 +61,62 @@ impl Default for Registry {
     }
 }
 
+impl Registry {
+    fn build_loader(&self) -> Result<Arc<dyn Loader>, Box<dyn Error>> {
+        Ok(match self {
+            Registry::Memory(path) => Arc::new(LocalFileLoader::new(path.clone())),
+            Registry::ExternalAPI(config) => Arc::new(ExternalApiLoader::new(
+                config.url.as_str(),
+                config.authorization.clone(),
+                (*config.options).clone(),
+            )?),
+            Registry::Kubernetes(config) => Arc::new(KubernetesLoader::new(
+                &config.api_server,
+                &config.namespace,
+                &config.label_selector,
+            )?),
+        })
+    }
+
+    /// Stdio servers are spawned on this host, only the admin-controlled local file defines them.
+    fn allows_stdio(&self) -> bool {
+        matches!(self, Registry::Memory(_))
+    }
+
+    /// Marks the rows of the loaded servers, discovered services are told apart.
+    fn create_from(&self) -> CreateFrom {
+        match self {
+            Registry::Kubernetes(_) => CreateFrom::KubernetesService,
+            _ => CreateFrom::Manual,
+        }
+    }
+}
+
 pub struct ExternalApiConfig {
-    #[allow(dead_code)]
     pub url: String,
-    #[allow(dead_code)]
     pub authorization: Option<String>,
+    pub options: Box<ExternalApiOptions>,
+}
+
+pub struct KubernetesConfig {
+    pub api_server: String,
+    pub namespace: String,
+    pub label_selector: String,
 }
 
 #[derive(Default)]
 struct Bootstrap {
     pub port: u16,
     pub registry: Registry,
+    pub registry_sync_interval: u64,
 }
 
 pub struct McpCenterServer {
     bootstrap: Bootstrap,
     config: AppConfig,
     state: Option<AppState>,
+    audit: Option<Arc<AuditLog>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 impl McpCenterServer {
     pub fn new() -> Self {
@@ -59,6 +124,8 @@ impl McpCenterServer {
             bootstrap: Default::default(),
             config: Default::default(),
             state: None,
+            audit: None,
+            rate_limiter: None,
         }
     }
 
@@ -68,15 +135,73 @@ impl McpCenterServer {
         runtime: Arc<Runtime>,
     ) -> Result<(), Box<dyn Error>> {
         let state = self.state.clone().unwrap();
+        let rate_limiter = self.rate_limiter.clone().unwrap();
+        let auth = Arc::new(Authenticator::new(
+            self.config.mcp_center.jwt.clone(),
+            self.config.mcp_center.admin_token.clone(),
+        ));
 
-        let builder = router::RouterBuilder::<AppState>::new()
+        let resource_server = ResourceServer::new(
+            self.config.mcp_center.oauth.clone(),
+            state.https_client.clone(),
+        )?
+        .map(Arc::new);
+
+        let mut builder = router::RouterBuilder::<AppState>::new()
             .with_register(reverse_proxy::register_router(
-                state.https_client.clone(),
+                UpstreamClient::new(
+                    state.https_client.clone(),
+                    self.config.mcp_center.upstream.policy.clone(),
+                ),
                 state.mcp_cache.clone(),
+                state.handlers().aggregate_handler.clone().unwrap(),
+                &self.config.mcp_center.stdio,
+                runtime.clone(),
             ))
             .with_register(mc_registry::register_router())
-            .with_register(mc_token::register_router())
-            .with_layer(layer_authorization(self.config.clone(), state.clone()));
+            .with_register(mc_token::register_router(auth.clone()))
+            .with_register(audit::register_router())
+            .with_register(rate_limit::register_router(rate_limiter.clone()));
+        if let Some(resource_server) = &resource_server {
+            tracing::info!(
+                "Accepting OAuth tokens of {}",
+                self.config.mcp_center.oauth.issuer
+            );
+            builder = builder.with_register(oauth::register_router(resource_server.clone()));
+        }
+        let metrics_port = self.config.mcp_center.metrics.port;
+        if metrics_port == 0 {
+            builder = builder.with_register(metrics::register_router());
+        } else {
+            // scraped from the internal network, the admin port is not authorized
+            let admin = router::RouterBuilder::<AppState>::new()
+                .with_register(metrics::register_router())
+                .build(state.clone());
+            let shutdown = shutdown_signal.clone();
+            runtime.spawn(async move {
+                let listener =
+                    match tokio::net::TcpListener::bind(format!("0.0.0.0:{metrics_port}")).await {
+                        Ok(listener) => listener,
+                        Err(err) => {
+                            tracing::error!("Can't bind metrics port {metrics_port}, error: {err}");
+                            return;
+                        }
+                    };
+                tracing::info!("starting metrics server on port {}", metrics_port);
+                if let Err(err) = axum::serve(listener, admin)
+                    .with_graceful_shutdown(async move { shutdown.cancelled().await })
+                    .await
+                {
+                    tracing::error!("Metrics server failed, error: {err}");
+                }
+            });
+        }
+        // audited and limited requests carry the actor found by the authorization,
+        // rejections of the limits are audited
+        let builder = builder
+            .with_layer(rate_limit::layer(rate_limiter))
+            .with_layer(audit::layer(self.audit.clone().unwrap()))
+            .with_layer(layer_authorization(auth, resource_server, state.clone()));
 
         let app = builder.build(state);
 
@@ -94,10 +219,14 @@ impl McpCenterServer {
 
             tracing::info!("starting HTTP server on port {}", self.bootstrap.port);
 
-            axum::serve(listener, app)
-                .with_graceful_shutdown(shutdown())
-                .await
-                .unwrap();
+            // the peer address is forwarded to upstream servers
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown())
+            .await
+            .unwrap();
         });
         Ok(())
     }
@@ -117,23 +246,57 @@ impl Application for McpCenterServer {
     ) -> Result<(), Box<dyn Error>> {
         self.config = config.clone();
 
+        telemetry::init(&config.mcp_center.telemetry)?;
+
         self.bootstrap.port = config.mcp_center.http_port;
 
-        self.bootstrap.registry = match config.mcp_registry {
+        (
+            self.bootstrap.registry,
+            self.bootstrap.registry_sync_interval,
+        ) = match config.mcp_registry {
             McpRegistry::LocalMemory {
                 mcp_definition_path,
-            } => Registry::Memory(mcp_definition_path),
-            McpRegistry::External { url, token } => build_external_api_registry(url, token),
+                sync_interval,
+            } => (Registry::Memory(mcp_definition_path), sync_interval),
+            McpRegistry::External {
+                url,
+                token,
+                sync_interval,
+                options,
+            } => (
+                build_external_api_registry(url, token, options),
+                sync_interval,
+            ),
+            McpRegistry::Kubernetes {
+                api_server,
+                namespace,
+                label_selector,
+                sync_interval,
+            } => (
+                Registry::Kubernetes(KubernetesConfig {
+                    api_server,
+                    namespace,
+                    label_selector,
+                }),
+                sync_interval,
+            ),
         };
 
         let (tx, _) = broadcast::channel::<Event>(100);
 
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        if config.mcp_center.upstream.connect_timeout > 0 {
+            http.set_connect_timeout(Some(Duration::from_secs(
+                config.mcp_center.upstream.connect_timeout,
+            )));
+        }
         let https = HttpsConnectorBuilder::new()
             .with_native_roots()
             .unwrap()
             .https_or_http()
             .enable_http1()
-            .build();
+            .wrap_connector(http);
 
         let client = Arc::new(Client::builder(TokioExecutor::new()).build(https));
 
@@ -157,24 +320,77 @@ impl Application for McpCenterServer {
 
         let db_client = Arc::new(db_client);
 
+        let credential_cipher = match config.mcp_center.credential_key.as_str() {
+            "" => {
+                tracing::warn!("Credential key is not set, upstream credentials are unavailable");
+                None
+            }
+            key => Some(Arc::new(CredentialCipher::new(key)?)),
+        };
+
         // mcp cache for reverse proxy, load mcp servers from postgres
         let cache = Arc::new(Cache::new(
             db_client.clone(),
             tx.subscribe(),
             runtime.clone(),
-            100,
+            config.mcp_center.cache.poll_interval,
+            config.mcp_center.cache.listen,
+            credential_cipher.clone(),
+            config.mcp_center.health_check.thresholds,
         ));
 
+        // sync mcp servers from the configured registry into postgres
+        LoaderSync::new(
+            self.bootstrap.registry.build_loader()?,
+            self.bootstrap.registry.create_from(),
+            self.bootstrap.registry.allows_stdio(),
+            db_client.clone(),
+            tx.clone(),
+            runtime.clone(),
+        )
+        .start(self.bootstrap.registry_sync_interval);
+
+        // probes skip stdio servers, their processes are not shared with the proxy
+        HealthChecker::new(
+            cache.clone(),
+            McpClient::new(client.clone(), StdioManager::new()),
+            config.mcp_center.health_check.clone(),
+            runtime.clone(),
+        )
+        .start();
+
         let manager = HandlerManager::new(db_client.clone())
             .with_mcp_handler()
             .with_system_settings_handler()
-            .with_api_keys_handler();
+            .with_api_keys_handler()
+            .with_aggregate_handler()
+            .with_policy_handler()
+            .with_user_handler()
+            .with_audit_handler()
+            .with_rate_limit_handler();
+
+        let audit = Arc::new(AuditLog::new(
+            config.mcp_center.audit.clone(),
+            manager.audit_handler.clone(),
+            runtime.clone(),
+        ));
+        audit.start();
+        self.audit = Some(audit);
+
+        let rate_limiter = Arc::new(RateLimiter::new(
+            config.mcp_center.rate_limit.clone(),
+            manager.rate_limit_handler.clone(),
+            runtime.clone(),
+        ));
+        rate_limiter.start();
+        self.rate_limiter = Some(rate_limiter);
 
         let state = AppState::new(
             db_client.clone(),
             tx.clone(),
             client.clone(),
             cache.clone(),
+            credential_cipher,
             manager,
         );
 
@@ -188,45 +404,204 @@ impl Application for McpCenterServer {
         shutdown: CancellationToken,
         runtime: Arc<Runtime>,
     ) -> Result<(), Box<dyn Error>> {
-        self.start(shutdown, runtime)?;
-        Ok(())
+        let result = self.start(shutdown, runtime);
+        telemetry::shutdown();
+        result
     }
 }
-fn build_external_api_registry(url: String, token: Option<String>) -> Registry {
+fn build_external_api_registry(
+    url: String,
+    token: Option<String>,
+    options: Box<ExternalApiOptions>,
+) -> Registry {
     let config = ExternalApiConfig {
         url,
         authorization: token,
+        options,
     };
     Registry::ExternalAPI(config)
 }
 
-fn layer_authorization(config: AppConfig, state: AppState) -> RouterHandler<AppState> {
+type AuthorizationState = (Arc<Authenticator>, Option<Arc<ResourceServer>>, AppState);
+
+fn layer_authorization(
+    auth: Arc<Authenticator>,
+    resource_server: Option<Arc<ResourceServer>>,
+    state: AppState,
+) -> RouterHandler<AppState> {
     Box::new(move |router| {
         router.layer(middleware::from_fn_with_state(
-            (config.clone(), state.clone()),
+            (auth.clone(), resource_server.clone(), state.clone()),
             authorization,
         ))
     })
 }
 
+// failures on the proxy endpoints challenge OAuth clients to find the authorization server
 async fn authorization(
-    State((config, state)): State<(AppConfig, AppState)>,
+    State(state): State<AuthorizationState>,
     req: Request,
     next: Next,
-) -> Result<Response, (StatusCode, String)> {
-    if req.uri() == "/api/user/admin/login" {
-        return Ok(next.run(req).await);
+) -> Response {
+    let challenge = state
+        .1
+        .as_ref()
+        .filter(|_| req.uri().path().starts_with("/proxy/"))
+        .map(|resource_server| resource_server.metadata_url());
+    let has_credentials = req.headers().contains_key(http::header::AUTHORIZATION);
+    let scope = mc_token::required_scope(req.method(), req.uri().path());
+
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        url.path = req.uri().path(),
+        http.response.status_code = tracing::field::Empty,
+    );
+    span.set_parent(telemetry::extract(req.headers()));
+
+    async move {
+        let response = match authenticate(state, req)
+            .instrument(tracing::info_span!("authorization"))
+            .await
+        {
+            Ok(req) => next.run(req).await,
+            Err((status, msg)) => {
+                let mut response = (status, msg).into_response();
+                if let Some(value) = challenge.and_then(|metadata_url| {
+                    oauth::www_authenticate(status, has_credentials, &metadata_url, scope)
+                }) {
+                    response
+                        .headers_mut()
+                        .insert(http::header::WWW_AUTHENTICATE, value);
+                }
+                response
+            }
+        };
+        tracing::Span::current().record("http.response.status_code", response.status().as_u16());
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+async fn authenticate(
+    (auth, resource_server, state): AuthorizationState,
+    mut req: Request,
+) -> Result<Request, (StatusCode, String)> {
+    if mc_token::PUBLIC_ROUTES.contains(&req.uri().path())
+        || req.uri().path().starts_with(oauth::METADATA_PATH)
+    {
+        return Ok(req);
     }
 
     if let Some(key) = req.headers().get(http::header::AUTHORIZATION) {
-        let mut apikey = key.to_str().unwrap();
-        apikey = apikey.strip_prefix("Bearer ").unwrap_or(apikey);
+        let Ok(apikey) = key.to_str() else {
+            tracing::error!("Authorization header is not visible ASCII");
+            metrics::auth_failure("invalid_header");
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                String::from("Invalid Authorization header."),
+            ));
+        };
+        let apikey = apikey.strip_prefix("Bearer ").unwrap_or(apikey);
+
+        if auth.is_admin_token(apikey) {
+            req.extensions_mut().insert(Actor::admin());
+            req.extensions_mut().insert(Admin);
+            return Ok(req);
+        }
 
-        tracing::debug!("Authorization header set to: {apikey}");
+        let oauth_server = resource_server
+            .as_ref()
+            .filter(|_| jwt::is_jwt(apikey) && oauth::is_oauth_token(apikey));
 
-        if apikey == config.mcp_center.admin_token.as_str() {
-            return Ok(next.run(req).await);
+        if jwt::is_jwt(apikey) && oauth_server.is_none() {
+            let claims = auth.verify(apikey, TokenType::Access).map_err(|err| {
+                tracing::error!("Invalid access token: {}", err);
+                metrics::auth_failure("invalid_token");
+                (
+                    StatusCode::UNAUTHORIZED,
+                    String::from("Invalid access token."),
+                )
+            })?;
+            if mc_token::is_revoked(&state, &claims).await? {
+                tracing::error!("The access token of {} is revoked", claims.sub);
+                metrics::auth_failure("revoked_token");
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    String::from("The access token is revoked."),
+                ));
+            }
+            if !jwt::role_permits(&claims.role, req.method(), req.uri().path()) {
+                tracing::error!(
+                    "The role {} of {} is not permitted to {} {}",
+                    claims.role,
+                    claims.sub,
+                    req.method(),
+                    req.uri().path()
+                );
+                metrics::auth_failure("role_not_permitted");
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    String::from("The role is not permitted."),
+                ));
+            }
+
+            if claims.role == ROLE_ADMIN {
+                req.extensions_mut().insert(Admin);
+            }
+            req.extensions_mut().insert(Actor::user(&claims.sub));
+            req.extensions_mut().insert(claims);
+            return Ok(req);
+        }
+
+        if mc_token::ADMIN_ROUTE_PREFIXES
+            .iter()
+            .any(|prefix| req.uri().path().starts_with(prefix))
+        {
+            tracing::error!("Only the admin token may call {}", req.uri().path());
+            metrics::auth_failure("admin_only");
+            return Err((
+                StatusCode::FORBIDDEN,
+                String::from("Only the admin token is permitted."),
+            ));
+        }
+
+        if let Some(resource_server) = oauth_server {
+            let principal = resource_server.verify(apikey).await.map_err(|err| {
+                tracing::error!("Invalid OAuth token: {}", err);
+                metrics::auth_failure("invalid_oauth_token");
+                (
+                    StatusCode::UNAUTHORIZED,
+                    String::from("Invalid access token."),
+                )
+            })?;
+            if let Some(scope) = mc_token::required_scope(req.method(), req.uri().path())
+                && !principal.has_scope(scope)
+            {
+                tracing::error!(
+                    "The OAuth token of {} lacks the scope {scope}",
+                    principal.subject
+                );
+                metrics::auth_failure("missing_scope");
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("The access token lacks the scope {scope}."),
+                ));
+            }
+
+            // a principal reaches only what is granted to its subject or groups
+            let policy = match load_access_policy(&state, None, &principal.policy_names()).await? {
+                AccessPolicy::Unrestricted => AccessPolicy::Restricted(vec![]),
+                policy => policy,
+            };
+            req.extensions_mut().insert(policy);
+            req.extensions_mut()
+                .insert(Actor::oauth(&principal.subject));
+            return Ok(req);
         }
+
         let handler = match &state.handlers().api_keys_handler {
             None => {
                 return Err((
@@ -238,9 +613,36 @@ async fn authorization(
         };
 
         let res = match handler.find(apikey).await {
-            Ok(_) => Ok(next.run(req).await),
+            Ok(key) => {
+                if let Some(scope) = mc_token::required_scope(req.method(), req.uri().path())
+                    && !key.has_scope(scope)
+                {
+                    tracing::error!("The API key {} lacks the scope {scope}", key.name);
+                    metrics::auth_failure("missing_scope");
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!("The API key lacks the scope {scope}."),
+                    ));
+                }
+
+                // last use is informational, it must not delay the request
+                let touch = handler.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = touch.touch(key.id).await {
+                        tracing::warn!("failed to record api key use: {}", err);
+                    }
+                });
+
+                let policy = load_access_policy(&state, Some(key.id), &[]).await?;
+                req.extensions_mut().insert(policy);
+                req.extensions_mut()
+                    .insert(Actor::api_key(key.id, &key.name));
+                Ok(req)
+            }
+            // unknown, revoked and expired keys alike
             Err(sqlx::Error::RowNotFound) => {
                 tracing::error!("The API key is not permitted.");
+                metrics::auth_failure("invalid_api_key");
                 Err((
                     StatusCode::UNAUTHORIZED,
                     String::from("The API key is not permitted."),
@@ -257,8 +659,35 @@ async fn authorization(
         return res;
     }
     tracing::error!("Authorization header not found");
+    metrics::auth_failure("missing_credentials");
     Err((
         StatusCode::UNAUTHORIZED,
         String::from("Authorization header not found"),
     ))
 }
+
+// API keys hold the grants bound to their id, OAuth principals those of their names
+async fn load_access_policy(
+    state: &AppState,
+    api_key_id: Option<Uuid>,
+    names: &[String],
+) -> Result<AccessPolicy, (StatusCode, String)> {
+    let handler = state.handlers().policy_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("no policy handler found"),
+        )
+    })?;
+
+    let policies = handler
+        .list_for_caller(api_key_id, names)
+        .await
+        .map_err(|err| {
+            tracing::error!("failed to select policies: {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("Failed to select policies"),
+            )
+        })?;
+    Ok(AccessPolicy::from_policies(policies))
+}
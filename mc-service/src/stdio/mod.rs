@@ -0,0 +1,396 @@
+mod process;
+
+pub use mc_common::app::stdio::{ProcessMode, StdioConfig};
+pub use process::*;
+
+use mc_common::app::cache::{Cache, McpServerInfo};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::interval;
+use uuid::Uuid;
+
+// processes of removed servers are stopped, and idle sessions closed, within this time
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A client session bridged to a [`StdioProcess`].
+pub struct StdioSession {
+    pub id: String,
+    pub name: String,
+    pub tag: String,
+    process: Arc<StdioProcess>,
+    owns_process: bool,
+    waiters: Mutex<HashMap<String, oneshot::Sender<Value>>>,
+    stream: Mutex<Option<mpsc::Sender<Value>>>,
+    last_active: Mutex<Instant>,
+}
+
+impl StdioSession {
+    pub async fn send(&self, message: Value) -> Result<(), String> {
+        self.touch();
+        self.process.send(&self.id, message).await
+    }
+
+    /// Registers interest in the response to the request with the given id.
+    pub fn wait_response(&self, id: &Value) -> oneshot::Receiver<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().insert(id.to_string(), tx);
+        rx
+    }
+
+    /// Opens the stream receiving every message nobody waits for, replacing any previous one.
+    pub fn open_stream(&self) -> mpsc::Receiver<Value> {
+        let (tx, rx) = mpsc::channel(100);
+        *self.stream.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    // a session is in use while a stream is open or a request waits for its response
+    fn is_idle(&self, timeout: Duration) -> bool {
+        let streaming = self
+            .stream
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|stream| !stream.is_closed());
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.retain(|_, waiter| !waiter.is_closed());
+        !streaming && waiters.is_empty() && self.last_active.lock().unwrap().elapsed() >= timeout
+    }
+
+    async fn route(&self, message: Value) {
+        self.touch();
+        if is_response(&message)
+            && let Some(id) = message.get("id").map(|id| id.to_string())
+        {
+            let waiter = self.waiters.lock().unwrap().remove(&id);
+            if let Some(waiter) = waiter {
+                let _ = waiter.send(message);
+                return;
+            }
+        }
+
+        let stream = self.stream.lock().unwrap().clone();
+        match stream {
+            Some(stream) => {
+                if stream.send(message).await.is_err() {
+                    tracing::debug!("stream of stdio session {} is closed", self.id);
+                }
+            }
+            None => tracing::debug!("Drop message for stdio session {} without stream", self.id),
+        }
+    }
+}
+
+// shared processes are keyed by server name and tag
+type ProcessKey = (String, String);
+
+/// Keeps track of running stdio processes and the sessions attached to them.
+#[derive(Clone, Default)]
+pub struct StdioManager {
+    processes: Arc<Mutex<HashMap<ProcessKey, Arc<StdioProcess>>>>,
+    sessions: Arc<Mutex<HashMap<String, Arc<StdioSession>>>>,
+}
+
+impl StdioManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open_session(
+        &self,
+        name: &str,
+        tag: &str,
+        server: &McpServerInfo,
+    ) -> Result<Arc<StdioSession>, String> {
+        let config = StdioConfig::from_extra(server.extra.as_ref())?;
+        let owns_process = config.mode == ProcessMode::Session;
+        let process = if owns_process {
+            StdioProcess::spawn(name, tag, config)
+        } else {
+            self.shared_process(name, tag, config)
+        };
+
+        let id = Uuid::new_v4().to_string();
+        let mut receiver = process.attach(&id);
+        let session = Arc::new(StdioSession {
+            id: id.clone(),
+            name: name.to_string(),
+            tag: tag.to_string(),
+            process,
+            owns_process,
+            waiters: Mutex::new(HashMap::new()),
+            stream: Mutex::new(None),
+            last_active: Mutex::new(Instant::now()),
+        });
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.clone(), session.clone());
+
+        let manager = self.clone();
+        let routed = session.clone();
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                routed.route(message).await;
+            }
+            // the process detached the session, e.g. it was replaced or stopped
+            manager.close_session(&routed.id);
+            *routed.stream.lock().unwrap() = None;
+        });
+
+        tracing::info!(
+            "open stdio session sessionId={}, name={}, tag={}",
+            id,
+            name,
+            tag
+        );
+        Ok(session)
+    }
+
+    pub fn session(&self, name: &str, tag: &str, session_id: &str) -> Option<Arc<StdioSession>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .filter(|session| session.name == name && session.tag == tag)
+            .cloned()
+    }
+
+    pub fn close_session(&self, session_id: &str) -> bool {
+        let session = self.sessions.lock().unwrap().remove(session_id);
+        match session {
+            Some(session) => {
+                session.process.detach(&session.id);
+                if session.owns_process {
+                    session.process.shutdown();
+                }
+                tracing::info!(
+                    "close stdio session sessionId={}, name={}, tag={}",
+                    session.id,
+                    session.name,
+                    session.tag
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Periodically stops the processes and sessions of servers which are deleted, disabled
+    /// or reconfigured, and closes sessions idle for longer than `idle_timeout`, if any.
+    pub fn start_reaper(
+        &self,
+        cache: Arc<Cache>,
+        idle_timeout: Option<Duration>,
+        runtime: Arc<Runtime>,
+    ) {
+        let manager = self.clone();
+        runtime.spawn(async move {
+            let mut ticker = interval(REAP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let mut configs = HashMap::new();
+                for (name, tag) in manager.servers() {
+                    let config = cache
+                        .load_server_info(&name, &tag)
+                        .await
+                        .filter(|server| server.transport_type.is_stdio())
+                        .and_then(|server| StdioConfig::from_extra(server.extra.as_ref()).ok());
+                    configs.insert((name, tag), config);
+                }
+                manager.reap(&configs, idle_timeout);
+            }
+        });
+    }
+
+    fn servers(&self) -> HashSet<ProcessKey> {
+        let mut servers: HashSet<ProcessKey> =
+            self.processes.lock().unwrap().keys().cloned().collect();
+        servers.extend(
+            self.sessions
+                .lock()
+                .unwrap()
+                .values()
+                .map(|session| (session.name.clone(), session.tag.clone())),
+        );
+        servers
+    }
+
+    // `configs` holds the current config of each server, `None` once it is no longer served;
+    // servers missing from it started after the lookup and are left alone
+    fn reap(
+        &self,
+        configs: &HashMap<ProcessKey, Option<StdioConfig>>,
+        idle_timeout: Option<Duration>,
+    ) {
+        let is_current = |name: &str, tag: &str, config: &StdioConfig| {
+            configs
+                .get(&(name.to_string(), tag.to_string()))
+                .is_none_or(|current| current.as_ref() == Some(config))
+        };
+
+        let sessions: Vec<_> = self.sessions.lock().unwrap().values().cloned().collect();
+        for session in sessions {
+            if !is_current(&session.name, &session.tag, session.process.config()) {
+                tracing::info!(
+                    "stdio server {}/{} is removed or changed, closing session {}",
+                    session.name,
+                    session.tag,
+                    session.id
+                );
+                self.close_session(&session.id);
+            } else if idle_timeout.is_some_and(|timeout| session.is_idle(timeout)) {
+                tracing::info!("stdio session {} is idle, closing it", session.id);
+                self.close_session(&session.id);
+            }
+        }
+
+        self.processes
+            .lock()
+            .unwrap()
+            .retain(|(name, tag), process| {
+                if !process.is_shutdown() && is_current(name, tag, process.config()) {
+                    return true;
+                }
+                tracing::info!("stop stdio process of {}/{}", name, tag);
+                process.shutdown();
+                false
+            });
+    }
+
+    fn shared_process(&self, name: &str, tag: &str, config: StdioConfig) -> Arc<StdioProcess> {
+        let mut processes = self.processes.lock().unwrap();
+        let key = (name.to_string(), tag.to_string());
+
+        if let Some(process) = processes.get(&key)
+            && !process.is_shutdown()
+            && process.config() == &config
+        {
+            return process.clone();
+        }
+
+        // the registry entry changed, replace the running process
+        if let Some(process) = processes.remove(&key) {
+            process.shutdown();
+        }
+
+        let process = StdioProcess::spawn(name, tag, config);
+        processes.insert(key, process.clone());
+        process
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::types::{HttpScheme, TransportType};
+    use serde_json::json;
+
+    // `sed` answers every request like a minimal MCP server, see the process tests
+    fn echo_server() -> McpServerInfo {
+        McpServerInfo {
+            endpoint: "stdio://echo".to_string(),
+            host: String::new(),
+            port: String::new(),
+            path: String::new(),
+            scheme: HttpScheme::Http,
+            transport_type: TransportType::Stdio,
+            extra: Some(json!({
+                "command": "sed",
+                "args": ["-u", r#"s/"method":"[^"]*","params":{}/"result":{}/"#],
+            })),
+            credential: None,
+        }
+    }
+
+    fn key() -> ProcessKey {
+        ("echo".to_string(), "1.0.0".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_reap_idle_sessions() {
+        let manager = StdioManager::new();
+        let server = echo_server();
+        let config = StdioConfig::from_extra(server.extra.as_ref()).unwrap();
+        let configs = HashMap::from([(key(), Some(config))]);
+
+        let idle = manager.open_session("echo", "1.0.0", &server).unwrap();
+        let streaming = manager.open_session("echo", "1.0.0", &server).unwrap();
+        let _stream = streaming.open_stream();
+
+        manager.reap(&configs, None);
+        assert!(manager.session("echo", "1.0.0", &idle.id).is_some());
+
+        manager.reap(&configs, Some(Duration::from_secs(60)));
+        assert!(manager.session("echo", "1.0.0", &idle.id).is_some());
+
+        // the open stream keeps its session, the shared process stays up
+        manager.reap(&configs, Some(Duration::ZERO));
+        assert!(manager.session("echo", "1.0.0", &idle.id).is_none());
+        assert!(manager.session("echo", "1.0.0", &streaming.id).is_some());
+        assert!(!idle.process.is_shutdown());
+        assert!(manager.processes.lock().unwrap().contains_key(&key()));
+
+        manager.close_session(&streaming.id);
+    }
+
+    #[tokio::test]
+    async fn test_reap_removed_servers() {
+        struct TestCase {
+            name: &'static str,
+            mode: &'static str,
+            current: Option<Value>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "removed shared server",
+                mode: "shared",
+                current: None,
+            },
+            TestCase {
+                name: "changed shared server",
+                mode: "shared",
+                current: Some(json!({"command": "cat"})),
+            },
+            TestCase {
+                name: "removed session server",
+                mode: "session",
+                current: None,
+            },
+        ];
+
+        for t in tests {
+            let manager = StdioManager::new();
+            let mut server = echo_server();
+            server.extra.as_mut().unwrap()["mode"] = json!(t.mode);
+
+            let session = manager.open_session("echo", "1.0.0", &server).unwrap();
+            let _stream = session.open_stream();
+            let current = t
+                .current
+                .map(|extra| StdioConfig::from_extra(Some(&extra)).unwrap());
+            manager.reap(&HashMap::from([(key(), current)]), None);
+
+            assert!(
+                manager.session("echo", "1.0.0", &session.id).is_none(),
+                "case: {}",
+                t.name
+            );
+            assert!(session.process.is_shutdown(), "case: {}", t.name);
+            assert!(
+                manager.processes.lock().unwrap().is_empty(),
+                "case: {}",
+                t.name
+            );
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use crate::stdio::StdioConfig;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub const METHOD_INITIALIZE: &str = "initialize";
+const NOTIFICATION_INITIALIZED: &str = "notifications/initialized";
+const REPLAY_INITIALIZE_ID: &str = "mc-replay-initialize";
+const JSONRPC_INTERNAL_ERROR: i64 = -32603;
+
+// the environment of the proxy holds its secrets, children only inherit these variables
+const INHERITED_ENV: [&str; 4] = ["PATH", "HOME", "LANG", "TMPDIR"];
+// a process which stayed up this long is considered healthy and resets the restart backoff
+const STABLE_RUNTIME: Duration = Duration::from_secs(30);
+
+struct PendingRequest {
+    session_id: String,
+    id: Value,
+    method: String,
+}
+
+/// A supervised stdio MCP server process.
+///
+/// Several sessions can be attached to one process. Request ids are rewritten on the way in
+/// so responses can be routed back to the session which sent the request, while notifications
+/// and server-initiated requests are delivered to every attached session. The first
+/// `initialize` handshake is cached and replayed after a restart, so later sessions and a
+/// restarted process both see an initialized server.
+pub struct StdioProcess {
+    name: String,
+    tag: String,
+    config: StdioConfig,
+    stdin: mpsc::Sender<String>,
+    sessions: Mutex<HashMap<String, mpsc::Sender<Value>>>,
+    pending: Mutex<HashMap<String, PendingRequest>>,
+    next_id: AtomicU64,
+    initialize_request: Mutex<Option<Value>>,
+    initialize_result: Mutex<Option<Value>>,
+    initialized: AtomicBool,
+    shutdown: CancellationToken,
+}
+
+impl StdioProcess {
+    pub fn spawn(name: &str, tag: &str, config: StdioConfig) -> Arc<Self> {
+        let (stdin, stdin_rx) = mpsc::channel::<String>(100);
+        let process = Arc::new(Self {
+            name: name.to_string(),
+            tag: tag.to_string(),
+            config,
+            stdin,
+            sessions: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            initialize_request: Mutex::new(None),
+            initialize_result: Mutex::new(None),
+            initialized: AtomicBool::new(false),
+            shutdown: CancellationToken::new(),
+        });
+        tokio::spawn(process.clone().supervise(stdin_rx));
+        process
+    }
+
+    pub fn config(&self) -> &StdioConfig {
+        &self.config
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Stops the process and detaches every session, which ends their message streams.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.sessions.lock().unwrap().clear();
+    }
+
+    pub fn attach(&self, session_id: &str) -> mpsc::Receiver<Value> {
+        let (tx, rx) = mpsc::channel::<Value>(100);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), tx);
+        rx
+    }
+
+    pub fn detach(&self, session_id: &str) {
+        self.sessions.lock().unwrap().remove(session_id);
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.session_id != session_id);
+    }
+
+    pub async fn send(&self, session_id: &str, mut message: Value) -> Result<(), String> {
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        match (method, message.get("id").cloned()) {
+            (Some(method), Some(id)) => {
+                if method == METHOD_INITIALIZE {
+                    let cached = self.initialize_result.lock().unwrap().clone();
+                    if let Some(result) = cached {
+                        // the process is already initialized, answer from the first handshake
+                        self.deliver(
+                            session_id,
+                            json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        )
+                        .await;
+                        return Ok(());
+                    }
+                }
+
+                let proxy_id = format!("mc-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+                message["id"] = Value::String(proxy_id.clone());
+
+                if method == METHOD_INITIALIZE {
+                    let mut replay = message.clone();
+                    replay["id"] = Value::String(REPLAY_INITIALIZE_ID.to_string());
+                    self.initialize_request
+                        .lock()
+                        .unwrap()
+                        .get_or_insert(replay);
+                }
+
+                self.pending.lock().unwrap().insert(
+                    proxy_id,
+                    PendingRequest {
+                        session_id: session_id.to_string(),
+                        id,
+                        method,
+                    },
+                );
+            }
+            (Some(method), None)
+                if method == NOTIFICATION_INITIALIZED
+                    && self.initialized.swap(true, Ordering::SeqCst) =>
+            {
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        self.stdin.send(message.to_string()).await.map_err(|_| {
+            format!(
+                "MCP server process {}/{} is stopped",
+                self.name.as_str(),
+                self.tag.as_str()
+            )
+        })
+    }
+
+    async fn deliver(&self, session_id: &str, message: Value) {
+        let sender = self.sessions.lock().unwrap().get(session_id).cloned();
+        if let Some(sender) = sender
+            && sender.send(message).await.is_err()
+        {
+            tracing::debug!("stdio session {} is closed", session_id);
+        }
+    }
+
+    async fn broadcast(&self, message: Value) {
+        let senders: Vec<mpsc::Sender<Value>> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        for sender in senders {
+            let _ = sender.send(message.clone()).await;
+        }
+    }
+
+    async fn dispatch(&self, line: &str) {
+        let mut message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => {
+                tracing::debug!(
+                    "Ignore non JSON-RPC output of {}/{}: {}",
+                    self.name,
+                    self.tag,
+                    line
+                );
+                return;
+            }
+        };
+
+        if is_response(&message) {
+            let proxy_id = message
+                .get("id")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if proxy_id == REPLAY_INITIALIZE_ID {
+                return;
+            }
+
+            let pending = self.pending.lock().unwrap().remove(&proxy_id);
+            if let Some(pending) = pending {
+                if pending.method == METHOD_INITIALIZE
+                    && let Some(result) = message.get("result")
+                {
+                    self.initialize_result
+                        .lock()
+                        .unwrap()
+                        .get_or_insert(result.clone());
+                }
+                message["id"] = pending.id;
+                self.deliver(&pending.session_id, message).await;
+                return;
+            }
+        }
+
+        // notifications and requests from the server go to every attached session
+        self.broadcast(message).await;
+    }
+
+    async fn fail_pending(&self) {
+        let pending: Vec<PendingRequest> = self
+            .pending
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, pending)| pending)
+            .collect();
+        for request in pending {
+            self.deliver(
+                &request.session_id,
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": request.id,
+                    "error": {"code": JSONRPC_INTERNAL_ERROR, "message": "MCP server process exited"},
+                }),
+            )
+            .await;
+        }
+    }
+
+    async fn supervise(self: Arc<Self>, mut stdin_rx: mpsc::Receiver<String>) {
+        let initial_backoff = Duration::from_millis(self.config.restart_backoff_ms);
+        let max_backoff = Duration::from_millis(self.config.max_restart_backoff_ms);
+        let mut backoff = initial_backoff;
+        let mut restarted = false;
+
+        while !self.shutdown.is_cancelled() {
+            let started = Instant::now();
+            match self.run(&mut stdin_rx, restarted).await {
+                Ok(()) => break,
+                Err(err) => tracing::warn!(
+                    "MCP server process {}/{} exited: {}",
+                    self.name,
+                    self.tag,
+                    err
+                ),
+            }
+
+            self.fail_pending().await;
+
+            if started.elapsed() >= STABLE_RUNTIME {
+                backoff = initial_backoff;
+            }
+            tracing::warn!(
+                "Restart MCP server process {}/{} in {:?}",
+                self.name,
+                self.tag,
+                backoff
+            );
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = next_backoff(backoff, max_backoff);
+            restarted = true;
+        }
+
+        tracing::info!("MCP server process {}/{} stopped", self.name, self.tag);
+    }
+
+    // runs the process until it exits, returns Ok when the process was asked to stop
+    async fn run(
+        &self,
+        stdin_rx: &mut mpsc::Receiver<String>,
+        replay_initialize: bool,
+    ) -> Result<(), String> {
+        let mut child = build_command(&self.config)
+            .spawn()
+            .map_err(|err| format!("failed to spawn {}: {err}", self.config.command))?;
+
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| "stdin is not piped".to_string())?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "stdout is not piped".to_string())?;
+
+        if let Some(stderr) = child.stderr.take() {
+            let name = self.name.clone();
+            let tag = self.tag.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::info!(mcp_name = %name, tag = %tag, "stderr: {}", line);
+                }
+            });
+        }
+
+        tracing::info!(
+            "MCP server process {}/{} started, pid: {:?}",
+            self.name,
+            self.tag,
+            child.id()
+        );
+
+        if replay_initialize {
+            let request = self.initialize_request.lock().unwrap().clone();
+            if let Some(request) = request {
+                write_line(&mut stdin, &request.to_string()).await?;
+                let initialized = json!({"jsonrpc": "2.0", "method": NOTIFICATION_INITIALIZED});
+                write_line(&mut stdin, &initialized.to_string()).await?;
+            }
+        }
+
+        let mut lines = BufReader::new(stdout).lines();
+        loop {
+            tokio::select! {
+                // the child is killed on drop
+                _ = self.shutdown.cancelled() => return Ok(()),
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => self.dispatch(&line).await,
+                    Ok(None) => break,
+                    Err(err) => return Err(format!("failed to read stdout: {err}")),
+                },
+                message = stdin_rx.recv() => match message {
+                    Some(message) => write_line(&mut stdin, &message).await?,
+                    None => return Ok(()),
+                },
+            }
+        }
+
+        let status = child.wait().await.map_err(|err| err.to_string())?;
+        Err(format!("stdout closed, {status}"))
+    }
+}
+
+pub fn is_response(message: &Value) -> bool {
+    message.get("method").is_none()
+        && message.get("id").is_some()
+        && (message.get("result").is_some() || message.get("error").is_some())
+}
+
+fn build_command(config: &StdioConfig) -> Command {
+    let mut command = Command::new(&config.command);
+    command.env_clear();
+    for key in INHERITED_ENV {
+        if let Some(value) = std::env::var_os(key) {
+            command.env(key, value);
+        }
+    }
+    command
+        .args(&config.args)
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(working_dir) = &config.working_dir {
+        command.current_dir(working_dir);
+    }
+    command
+}
+
+async fn write_line(stdin: &mut ChildStdin, line: &str) -> Result<(), String> {
+    stdin
+        .write_all(format!("{line}\n").as_bytes())
+        .await
+        .map_err(|err| format!("failed to write stdin: {err}"))?;
+    stdin
+        .flush()
+        .await
+        .map_err(|err| format!("failed to flush stdin: {err}"))
+}
+
+fn next_backoff(current: Duration, max: Duration) -> Duration {
+    current.saturating_mul(2).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdio::ProcessMode;
+
+    #[test]
+    fn test_next_backoff() {
+        let max = Duration::from_secs(30);
+        assert_eq!(
+            next_backoff(Duration::from_millis(500), max),
+            Duration::from_secs(1)
+        );
+        assert_eq!(next_backoff(Duration::from_secs(20), max), max);
+        assert_eq!(next_backoff(max, max), max);
+    }
+
+    #[test]
+    fn test_is_response() {
+        struct TestCase {
+            message: Value,
+            want: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                message: json!({"jsonrpc": "2.0", "id": 1, "result": {}}),
+                want: true,
+            },
+            TestCase {
+                message: json!({"jsonrpc": "2.0", "id": "a", "error": {"code": -1}}),
+                want: true,
+            },
+            TestCase {
+                message: json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}),
+                want: false,
+            },
+            TestCase {
+                message: json!({"jsonrpc": "2.0", "method": "notifications/progress"}),
+                want: false,
+            },
+        ];
+
+        for t in tests {
+            assert_eq!(is_response(&t.message), t.want, "message: {}", t.message);
+        }
+    }
+
+    // `sed` turns every request into a response with the same id, like a minimal MCP server
+    fn echo_config() -> StdioConfig {
+        StdioConfig {
+            command: "sed".to_string(),
+            args: vec![
+                "-u".to_string(),
+                r#"s/"method":"[^"]*","params":{}/"result":{}/"#.to_string(),
+            ],
+            env: Default::default(),
+            working_dir: None,
+            mode: ProcessMode::Shared,
+            restart_backoff_ms: 100,
+            max_restart_backoff_ms: 100,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_command_env() {
+        let mut config = echo_config();
+        config.command = "env".to_string();
+        config.args = vec![];
+        config.env = HashMap::from([("MC_TEST".to_string(), "1".to_string())]);
+
+        let output = build_command(&config).output().await.unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        let keys: Vec<&str> = output
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(key, _)| key))
+            .collect();
+        assert!(keys.contains(&"MC_TEST"));
+        assert!(keys.contains(&"PATH"));
+        // nothing else of the proxy environment, e.g. what cargo sets for the test, leaks
+        assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+        assert!(!keys.contains(&"CARGO_MANIFEST_DIR"));
+        for key in keys {
+            assert!(key == "MC_TEST" || INHERITED_ENV.contains(&key), "{key}");
+        }
+    }
+
+    async fn recv(rx: &mut mpsc::Receiver<Value>) -> Value {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("timed out waiting for message")
+            .expect("session closed")
+    }
+
+    #[tokio::test]
+    async fn test_shared_process_routes_responses() {
+        let process = StdioProcess::spawn("echo", "1.0.0", echo_config());
+        let mut first = process.attach("first");
+        let mut second = process.attach("second");
+
+        process
+            .send(
+                "first",
+                json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            recv(&mut first).await,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {}})
+        );
+
+        // the second session is answered from the cached handshake
+        process
+            .send(
+                "second",
+                json!({"jsonrpc": "2.0", "id": 7, "method": "initialize", "params": {}}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            recv(&mut second).await,
+            json!({"jsonrpc": "2.0", "id": 7, "result": {}})
+        );
+
+        // both sessions use the same request id, each gets its own response
+        for session in ["first", "second"] {
+            process
+                .send(
+                    session,
+                    json!({"jsonrpc": "2.0", "id": 2, "method": "ping", "params": {}}),
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            recv(&mut first).await,
+            json!({"jsonrpc": "2.0", "id": 2, "result": {}})
+        );
+        assert_eq!(
+            recv(&mut second).await,
+            json!({"jsonrpc": "2.0", "id": 2, "result": {}})
+        );
+
+        process.shutdown();
+        assert!(first.recv().await.is_none());
+    }
+}
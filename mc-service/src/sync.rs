@@ -0,0 +1,351 @@
+use mc_common::app::event::Event;
+use mc_db::model::{CreateFrom, McpServers};
+use mc_db::{DBClient, McpDBHandler};
+use mc_loader::{Loader, McpServer};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::Sender;
+use tokio::time::interval;
+use uuid::Uuid;
+
+const DEFAULT_TRANSPORT_TYPE: &str = "sse";
+
+/// Periodically pulls servers from the configured [`Loader`] into `tb_mcp_servers`,
+/// and whenever the loader reports a change.
+///
+/// Rows written by the sync are marked with the `create_from` of the loader; only those
+/// rows are updated or soft-deleted, so servers registered through the API are never touched.
+pub struct LoaderSync {
+    loader: Arc<dyn Loader>,
+    create_from: String,
+    /// Only the local file may define stdio servers, their command runs on this host.
+    allow_stdio: bool,
+    db_client: Arc<DBClient>,
+    event_sender: Sender<Event>,
+    runtime: Arc<Runtime>,
+}
+
+#[derive(Debug, Default)]
+struct SyncPlan {
+    upserts: Vec<McpServers>,
+    deletes: Vec<(String, String)>,
+}
+
+impl LoaderSync {
+    pub fn new(
+        loader: Arc<dyn Loader>,
+        create_from: CreateFrom,
+        allow_stdio: bool,
+        db_client: Arc<DBClient>,
+        event_sender: Sender<Event>,
+        runtime: Arc<Runtime>,
+    ) -> Self {
+        Self {
+            loader,
+            create_from: create_from.to_string(),
+            allow_stdio,
+            db_client,
+            event_sender,
+            runtime,
+        }
+    }
+
+    pub fn start(&self, sync_interval: u64) {
+        let loader = self.loader.clone();
+        let create_from = self.create_from.clone();
+        let allow_stdio = self.allow_stdio;
+        let db_client = self.db_client.clone();
+        let event_sender = self.event_sender.clone();
+
+        self.runtime.spawn(async move {
+            let mut ticker = interval(Duration::from_secs(sync_interval.max(1)));
+            let handler = McpDBHandler::new(db_client);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = loader.changed() => {}
+                }
+
+                // a failed load is never taken as an empty registry, nothing is removed
+                let mut desired = match loader.list_mcp().await {
+                    Ok(servers) => servers,
+                    Err(err) => {
+                        tracing::error!("Can't load mcp servers from registry, error: {}", err);
+                        continue;
+                    }
+                };
+
+                if !allow_stdio {
+                    desired.retain(|server| {
+                        if server.is_stdio() {
+                            tracing::warn!(
+                                "Skip stdio mcp server {}, only the local file may define them",
+                                server.name
+                            );
+                        }
+                        !server.is_stdio()
+                    });
+                }
+
+                let existing = match handler.list_by_create_from(&create_from).await {
+                    Ok(results) => results,
+                    Err(err) => {
+                        tracing::error!("Can't list mcp servers, error: {}", err);
+                        continue;
+                    }
+                };
+
+                let plan = plan_sync(desired, existing, &create_from);
+                let upsert_count = plan.upserts.len();
+                let delete_count = plan.deletes.len();
+
+                for server in plan.upserts {
+                    match handler.upsert(&server).await {
+                        Ok(Some(row)) => {
+                            tracing::info!(
+                                "Sync mcp server {}/{} success, endpoint: {}",
+                                row.name,
+                                row.tag,
+                                row.endpoint
+                            );
+                            // servers disabled by an admin stay out of the proxy cache
+                            send_event(&event_sender, Event::from(&row));
+                        }
+                        Ok(None) => {
+                            tracing::warn!(
+                                "Skip syncing mcp server {}/{}, registered from another source",
+                                server.name,
+                                server.tag
+                            );
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to sync mcp server {}/{}, error: {}",
+                                server.name,
+                                server.tag,
+                                err
+                            );
+                        }
+                    }
+                }
+
+                for (name, tag) in plan.deletes {
+                    match handler.delete(&name, &tag).await {
+                        Ok(None) => {
+                            tracing::debug!("Mcp server {}/{} is already removed", name, tag);
+                        }
+                        Ok(Some(_)) => {
+                            tracing::info!("Remove mcp server {}/{} from registry", name, tag);
+                            send_event(
+                                &event_sender,
+                                Event::Delete {
+                                    mcp_name: name,
+                                    tag,
+                                },
+                            );
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to remove mcp server {}/{}, error: {}",
+                                name,
+                                tag,
+                                err
+                            );
+                        }
+                    }
+                }
+
+                tracing::info!(
+                    upsert_count = upsert_count,
+                    delete_count = delete_count,
+                    "sync registry done"
+                );
+            }
+        });
+    }
+}
+
+fn send_event(sender: &Sender<Event>, event: Event) {
+    if let Err(err) = sender.send(event) {
+        tracing::error!("Failed to send event {}", err);
+    }
+}
+
+// compare the loader result with the rows previously synced, only changed entries are upserted
+fn plan_sync(desired: Vec<McpServer>, existing: Vec<McpServers>, create_from: &str) -> SyncPlan {
+    let mut wanted: HashMap<(String, String), McpServers> = HashMap::new();
+    for server in desired {
+        let row = build_mcp_server(server, create_from);
+        wanted.insert((row.name.clone(), row.tag.clone()), row);
+    }
+
+    let mut plan = SyncPlan::default();
+
+    for row in &existing {
+        let key = (row.name.clone(), row.tag.clone());
+        match wanted.get(&key) {
+            None => plan.deletes.push(key),
+            Some(server)
+                if server.endpoint == row.endpoint
+                    && server.transport_type == row.transport_type
+                    && server.description == row.description
+                    && server.extra == row.extra =>
+            {
+                wanted.remove(&key);
+            }
+            Some(_) => {}
+        }
+    }
+
+    plan.upserts = wanted.into_values().collect();
+    plan.upserts
+        .sort_by(|a, b| (&a.name, &a.tag).cmp(&(&b.name, &b.tag)));
+    plan.deletes.sort();
+    plan
+}
+
+fn build_mcp_server(server: McpServer, create_from: &str) -> McpServers {
+    McpServers {
+        id: Uuid::new_v4(),
+        tag: server.resolved_tag(),
+        name: server.name,
+        endpoint: server.endpoint,
+        transport_type: server
+            .transport_type
+            .unwrap_or_else(|| DEFAULT_TRANSPORT_TYPE.to_string()),
+        description: server.description.unwrap_or_default(),
+        create_from: create_from.to_string(),
+        // stored as `{}` when missing, compared as such to avoid rewriting unchanged rows
+        extra: Some(server.extra.unwrap_or_else(|| json!({}))),
+        disabled: Default::default(),
+        credential_type: None,
+        credential: None,
+        created_at: Default::default(),
+        updated_at: Default::default(),
+        deleted_at: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(name: &str, tag: Option<&str>, endpoint: &str) -> McpServer {
+        McpServer {
+            endpoint: endpoint.to_string(),
+            name: name.to_string(),
+            version: None,
+            tag: tag.map(|t| t.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn stored(name: &str, tag: &str, endpoint: &str) -> McpServers {
+        build_mcp_server(loaded(name, Some(tag), endpoint), "manual")
+    }
+
+    #[test]
+    fn test_build_mcp_server_tag() {
+        let server = build_mcp_server(
+            McpServer {
+                endpoint: "http://127.0.0.1:8080/sse".to_string(),
+                name: "example".to_string(),
+                version: Some("2.0.0".to_string()),
+                ..Default::default()
+            },
+            "manual",
+        );
+        assert_eq!(server.tag, "2.0.0");
+        assert_eq!(server.create_from, "manual");
+        assert_eq!(server.transport_type, "sse");
+
+        let server = build_mcp_server(
+            loaded("example", None, "http://127.0.0.1:8080/sse"),
+            "manual",
+        );
+        assert_eq!(server.tag, "latest");
+
+        let mut discovered = loaded("example", None, "http://example.mcp.svc:8080/mcp");
+        discovered.transport_type = Some("streamable".to_string());
+        let server = build_mcp_server(discovered, "kubernetes-service");
+        assert_eq!(server.transport_type, "streamable");
+        assert_eq!(server.create_from, "kubernetes-service");
+    }
+
+    #[test]
+    fn test_plan_sync() {
+        struct TestCase {
+            name: &'static str,
+            desired: Vec<McpServer>,
+            existing: Vec<McpServers>,
+            want_upserts: Vec<(&'static str, &'static str)>,
+            want_deletes: Vec<(&'static str, &'static str)>,
+        }
+
+        let tests = vec![
+            TestCase {
+                name: "new servers are upserted",
+                desired: vec![
+                    loaded("a", Some("1.0.0"), "http://a/sse"),
+                    loaded("b", Some("1.0.0"), "http://b/sse"),
+                ],
+                existing: vec![],
+                want_upserts: vec![("a", "1.0.0"), ("b", "1.0.0")],
+                want_deletes: vec![],
+            },
+            TestCase {
+                name: "unchanged servers are skipped",
+                desired: vec![loaded("a", Some("1.0.0"), "http://a/sse")],
+                existing: vec![stored("a", "1.0.0", "http://a/sse")],
+                want_upserts: vec![],
+                want_deletes: vec![],
+            },
+            TestCase {
+                name: "changed endpoint is upserted",
+                desired: vec![loaded("a", Some("1.0.0"), "http://a2/sse")],
+                existing: vec![stored("a", "1.0.0", "http://a/sse")],
+                want_upserts: vec![("a", "1.0.0")],
+                want_deletes: vec![],
+            },
+            TestCase {
+                name: "changed extra is upserted",
+                desired: vec![McpServer {
+                    extra: Some(json!({"owner": "platform"})),
+                    ..loaded("a", Some("1.0.0"), "http://a/sse")
+                }],
+                existing: vec![stored("a", "1.0.0", "http://a/sse")],
+                want_upserts: vec![("a", "1.0.0")],
+                want_deletes: vec![],
+            },
+            TestCase {
+                name: "disappeared servers are deleted",
+                desired: vec![loaded("a", Some("1.0.0"), "http://a/sse")],
+                existing: vec![
+                    stored("a", "1.0.0", "http://a/sse"),
+                    stored("a", "0.9.0", "http://a/sse"),
+                ],
+                want_upserts: vec![],
+                want_deletes: vec![("a", "0.9.0")],
+            },
+        ];
+
+        for t in tests {
+            let plan = plan_sync(t.desired, t.existing, "manual");
+            let upserts: Vec<(&str, &str)> = plan
+                .upserts
+                .iter()
+                .map(|s| (s.name.as_str(), s.tag.as_str()))
+                .collect();
+            let deletes: Vec<(&str, &str)> = plan
+                .deletes
+                .iter()
+                .map(|(n, t)| (n.as_str(), t.as_str()))
+                .collect();
+            assert_eq!(upserts, t.want_upserts, "case: {}", t.name);
+            assert_eq!(deletes, t.want_deletes, "case: {}", t.name);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use crate::config::Telemetry;
+use bytes::Bytes;
+use http::HeaderMap;
+use once_cell::sync::{Lazy, OnceCell};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{
+    Link, SamplingResult, SpanKind, TraceContextExt, TraceId, TracerProvider as _,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{
+    BatchSpanProcessor, Sampler, SdkTracer, SdkTracerProvider, ShouldSample, Span, SpanData,
+    SpanProcessor,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// Spans of the whole process. The layer is installed with the subscriber in
+/// `main`, before the config is read, and exports nothing until [`init`].
+static TRACING: Lazy<Tracing> = Lazy::new(Tracing::new);
+
+pub fn layer<S>() -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    TRACING.layer()
+}
+
+/// Exports spans to the configured OTLP collector, returns whether it is enabled.
+pub fn init(config: &Telemetry) -> Result<bool, Box<dyn Error>> {
+    if config.otlp_endpoint.is_empty() {
+        tracing::info!("OpenTelemetry tracing is disabled");
+        return Ok(false);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.otlp_endpoint)
+        .build()?;
+    // callers' sampling decisions are kept, traces started here are sampled by ratio
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    TRACING.export(
+        sampler,
+        BatchSpanProcessor::builder(exporter).build(),
+        resource,
+    )?;
+
+    tracing::info!(
+        "Exporting traces of {} to {}",
+        config.service_name,
+        config.otlp_endpoint
+    );
+    Ok(true)
+}
+
+/// Flushes the spans not exported yet.
+pub fn shutdown() {
+    if let Err(err) = TRACING.provider.shutdown() {
+        tracing::warn!("Failed to shut down tracing, error: {err}");
+    }
+}
+
+/// Context of the caller from the W3C `traceparent` and `tracestate` headers.
+pub fn extract(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Sets the current span as the parent of the upstream request. Headers of the
+/// caller are forwarded unchanged while the span is not recorded.
+pub fn inject(headers: &mut HeaderMap) {
+    let cx = tracing::Span::current().context();
+    if cx.span().span_context().is_sampled() {
+        TraceContextPropagator::new().inject_context(&cx, &mut HeaderInjector(headers));
+    }
+}
+
+/// Context in `params._meta` of the first JSON-RPC request carrying one, sent by
+/// MCP clients which can't set headers.
+pub fn extract_meta(body: &[u8]) -> Option<Context> {
+    let value = serde_json::from_slice::<Value>(body).ok()?;
+    messages(&value).find_map(|message| {
+        let meta = message.get("params")?.get("_meta")?.as_object()?;
+        let carrier = [TRACEPARENT, TRACESTATE]
+            .into_iter()
+            .filter_map(|key| Some((key.to_string(), meta.get(key)?.as_str()?.to_string())))
+            .collect::<HashMap<_, _>>();
+        let cx = TraceContextPropagator::new().extract(&carrier);
+        cx.span().span_context().is_valid().then_some(cx)
+    })
+}
+
+/// Replaces the trace fields in `params._meta` of the JSON-RPC requests with the
+/// current span, bodies without them are returned as they are.
+pub fn inject_meta(body: Bytes) -> Bytes {
+    let cx = tracing::Span::current().context();
+    if !cx.span().span_context().is_sampled() {
+        return body;
+    }
+    let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+
+    let mut changed = false;
+    let messages = match &mut value {
+        Value::Array(messages) => messages.iter_mut().collect::<Vec<_>>(),
+        message => vec![message],
+    };
+    for message in messages {
+        let Some(meta) = message
+            .get_mut("params")
+            .and_then(|params| params.get_mut("_meta"))
+            .and_then(Value::as_object_mut)
+            .filter(|meta| meta.contains_key(TRACEPARENT))
+        else {
+            continue;
+        };
+        meta.remove(TRACESTATE);
+        for (key, value) in carrier.iter().filter(|(_, value)| !value.is_empty()) {
+            meta.insert(key.clone(), Value::String(value.clone()));
+        }
+        changed = true;
+    }
+
+    match changed {
+        true => Bytes::from(value.to_string()),
+        false => body,
+    }
+}
+
+/// Links the current span to the trace of the caller found in `params._meta`.
+pub fn link_meta(body: &[u8]) {
+    if let Some(cx) = extract_meta(body) {
+        tracing::Span::current().add_link(cx.span().span_context().clone());
+    }
+}
+
+fn messages(value: &Value) -> Box<dyn Iterator<Item = &Value> + '_> {
+    match value {
+        Value::Array(messages) => Box::new(messages.iter()),
+        message => Box::new(std::iter::once(message)),
+    }
+}
+
+struct Tracing {
+    export: Export,
+    provider: SdkTracerProvider,
+}
+
+impl Tracing {
+    fn new() -> Self {
+        let export = Export::default();
+        let provider = SdkTracerProvider::builder()
+            .with_sampler(export.clone())
+            .with_span_processor(export.clone())
+            .build();
+        Self { export, provider }
+    }
+
+    fn layer<S>(&self) -> OpenTelemetryLayer<S, SdkTracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("mcp-center"))
+    }
+
+    fn export(
+        &self,
+        sampler: Sampler,
+        mut processor: impl SpanProcessor + 'static,
+        resource: Resource,
+    ) -> Result<(), String> {
+        processor.set_resource(&resource);
+        self.export
+            .0
+            .set((sampler, Box::new(processor)))
+            .map_err(|_| "tracing is already initialized".to_string())
+    }
+}
+
+/// Sampler and processor of the provider, dropping all spans until they are set.
+#[derive(Clone, Debug, Default)]
+struct Export(Arc<OnceCell<(Sampler, Box<dyn SpanProcessor>)>>);
+
+impl ShouldSample for Export {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        match self.0.get() {
+            Some((sampler, _)) => {
+                sampler.should_sample(parent_context, trace_id, name, span_kind, attributes, links)
+            }
+            None => Sampler::AlwaysOff.should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            ),
+        }
+    }
+}
+
+impl SpanProcessor for Export {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if let Some((_, processor)) = self.0.get() {
+            processor.on_start(span, cx);
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if let Some((_, processor)) = self.0.get() {
+            processor.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.0
+            .get()
+            .map_or(Ok(()), |(_, processor)| processor.force_flush())
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.0.get().map_or(Ok(()), |(_, processor)| {
+            processor.shutdown_with_timeout(timeout)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SimpleSpanProcessor};
+    use serde_json::json;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const CALLER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    const CALLER_TRACE: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    // spans are exported to memory while `run` executes
+    fn traced<T>(run: impl FnOnce() -> T) -> (T, Vec<SpanData>) {
+        let tracing = Tracing::new();
+        let exporter = InMemorySpanExporter::default();
+        tracing
+            .export(
+                Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+                SimpleSpanProcessor::new(exporter.clone()),
+                Resource::builder().with_service_name("test").build(),
+            )
+            .unwrap();
+        let subscriber = tracing_subscriber::registry().with(tracing.layer());
+        let result = tracing::subscriber::with_default(subscriber, run);
+        (result, exporter.get_finished_spans().unwrap())
+    }
+
+    fn traceparent(headers: &HeaderMap) -> Option<&str> {
+        headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    #[test]
+    fn test_inject() {
+        let (injected, spans) = traced(|| {
+            let mut incoming = HeaderMap::new();
+            incoming.insert(TRACEPARENT, CALLER.parse().unwrap());
+
+            let request = tracing::info_span!("request");
+            request.set_parent(extract(&incoming));
+            let _request = request.enter();
+            let upstream = tracing::info_span!("upstream.request");
+            let _upstream = upstream.enter();
+
+            let mut outgoing = incoming.clone();
+            inject(&mut outgoing);
+            traceparent(&outgoing).unwrap().to_string()
+        });
+
+        assert_eq!(spans.len(), 2);
+        let upstream = spans.iter().find(|s| s.name == "upstream.request").unwrap();
+        let request = spans.iter().find(|s| s.name == "request").unwrap();
+        for span in [upstream, request] {
+            assert_eq!(span.span_context.trace_id().to_string(), CALLER_TRACE);
+        }
+        assert_eq!(request.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert_eq!(upstream.parent_span_id, request.span_context.span_id());
+        assert_eq!(
+            injected,
+            format!("00-{CALLER_TRACE}-{}-01", upstream.span_context.span_id())
+        );
+    }
+
+    #[test]
+    fn test_inject_disabled() {
+        // the layer of a process without exporter keeps the caller's headers
+        let tracing = Tracing::new();
+        let subscriber = tracing_subscriber::registry().with(tracing.layer());
+        let outgoing = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            let _span = span.enter();
+            let mut headers = HeaderMap::new();
+            headers.insert(TRACEPARENT, CALLER.parse().unwrap());
+            inject(&mut headers);
+            headers
+        });
+        assert_eq!(traceparent(&outgoing), Some(CALLER));
+    }
+
+    #[test]
+    fn test_meta() {
+        struct TestCase {
+            body: Value,
+            extracted: bool,
+            injected: bool,
+        }
+
+        let tests = vec![
+            TestCase {
+                body: json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                    "params": {"name": "echo", "_meta": {"traceparent": CALLER, "tracestate": "a=b"}}}),
+                extracted: true,
+                injected: true,
+            },
+            TestCase {
+                body: json!([
+                    {"jsonrpc": "2.0", "method": "notifications/initialized"},
+                    {"jsonrpc": "2.0", "id": 2, "method": "ping",
+                        "params": {"_meta": {"traceparent": CALLER}}},
+                ]),
+                extracted: true,
+                injected: true,
+            },
+            TestCase {
+                body: json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                    "params": {"name": "echo", "_meta": {"progressToken": 1}}}),
+                extracted: false,
+                injected: false,
+            },
+            TestCase {
+                body: json!({"jsonrpc": "2.0", "id": 1, "method": "ping",
+                    "params": {"_meta": {"traceparent": "invalid"}}}),
+                extracted: false,
+                injected: true,
+            },
+        ];
+
+        for t in tests {
+            let body = Bytes::from(t.body.to_string());
+            assert_eq!(extract_meta(&body).is_some(), t.extracted, "{}", t.body);
+
+            let (forwarded, spans) = traced(|| {
+                let span = tracing::info_span!("upstream.request");
+                let _span = span.enter();
+                inject_meta(body.clone())
+            });
+            if !t.injected {
+                assert_eq!(forwarded, body, "{}", t.body);
+                continue;
+            }
+
+            let span_id = spans[0].span_context.span_id().to_string();
+            let forwarded = serde_json::from_slice::<Value>(&forwarded).unwrap();
+            let meta = messages(&forwarded)
+                .find_map(|message| message.get("params")?.get("_meta"))
+                .unwrap();
+            assert!(
+                meta[TRACEPARENT].as_str().unwrap().contains(&span_id),
+                "{}",
+                t.body
+            );
+            assert!(meta.get(TRACESTATE).is_none(), "{}", t.body);
+        }
+    }
+
+    #[test]
+    fn test_link_meta() {
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": "ping",
+            "params": {"_meta": {"traceparent": CALLER}}})
+        .to_string();
+        let (_, spans) = traced(|| {
+            let span = tracing::info_span!("request");
+            let _span = span.enter();
+            link_meta(body.as_bytes());
+        });
+        assert_eq!(
+            spans[0].links.links[0].span_context.trace_id().to_string(),
+            CALLER_TRACE
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::{Method, StatusCode};
+use chrono::{NaiveDateTime, Utc};
+use mc_common::app::{AppState, Response};
+use mc_db::ApiKeyDBHandler;
+use mc_db::model::{ApiKeys, SCOPE_PROXY, SCOPE_REGISTRY_READ, SCOPE_REGISTRY_WRITE, SCOPES};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct ListApiKeysRequest {
+    #[serde(default)]
+    include_revoked: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListApiKeysResponse {
+    api_keys: Vec<ApiKeys>,
+    count: usize,
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    Query(request): Query<ListApiKeysRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let api_key_handler = get_api_key_handler(&state)?;
+
+    let api_keys = api_key_handler
+        .list(request.include_revoked)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list api keys {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to list api keys".to_string(),
+            )
+        })?;
+
+    let count = api_keys.len();
+    build_response(ListApiKeysResponse { api_keys, count })
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ApiKeyCreateRequest {
+    pub name: String,
+    pub scopes: Option<Vec<String>>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// The key is only part of the create and rotate responses, it can't be read afterward.
+#[derive(Serialize)]
+pub struct ApiKeyCreatedResponse {
+    api_key: ApiKeys,
+    key: String,
+}
+
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Json(request): Json<ApiKeyCreateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let api_key_handler = get_api_key_handler(&state)?;
+
+    if request.name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "name must not be empty".to_string(),
+        ));
+    }
+    let scopes = request
+        .scopes
+        .unwrap_or_else(|| vec![SCOPE_PROXY.to_string()]);
+    validate_scopes(&scopes)?;
+    validate_expires_at(request.expires_at, Utc::now().naive_utc())?;
+
+    let (api_key, key) = api_key_handler
+        .create(&request.name, &scopes, request.expires_at)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create api key {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create api key".to_string(),
+            )
+        })?
+        .ok_or_else(|| name_taken(&request.name))?;
+    tracing::info!("Api key {} created", api_key.name);
+
+    build_response(ApiKeyCreatedResponse { api_key, key })
+}
+
+pub async fn get_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let api_key_handler = get_api_key_handler(&state)?;
+
+    let res = api_key_handler
+        .get(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get api key {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get api key".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+
+    build_response(res)
+}
+
+pub async fn rotate_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let api_key_handler = get_api_key_handler(&state)?;
+
+    let (api_key, key) = api_key_handler
+        .rotate(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to rotate api key {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to rotate api key".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Api key {} rotated", api_key.name);
+
+    build_response(ApiKeyCreatedResponse { api_key, key })
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let api_key_handler = get_api_key_handler(&state)?;
+
+    let res = api_key_handler
+        .revoke(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to revoke api key {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to revoke api key".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Api key {} revoked", res.name);
+
+    build_response(res)
+}
+
+/// Scope an API key needs to call the route, `None` when any key may call it.
+pub fn required_scope(method: &Method, path: &str) -> Option<&'static str> {
+    if path.starts_with("/proxy/") {
+        Some(SCOPE_PROXY)
+    } else if path.starts_with("/api/registry/") {
+        if method == Method::GET {
+            Some(SCOPE_REGISTRY_READ)
+        } else {
+            Some(SCOPE_REGISTRY_WRITE)
+        }
+    } else {
+        None
+    }
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), (StatusCode, String)> {
+    match scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        Some(scope) => Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown scope {scope}, expected one of {}",
+                SCOPES.join(", ")
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// a key expiring before it is created could never be used
+fn validate_expires_at(
+    expires_at: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> Result<(), (StatusCode, String)> {
+    match expires_at {
+        Some(expires_at) if expires_at <= now => Err((
+            StatusCode::BAD_REQUEST,
+            format!("expires_at {expires_at} is not in the future"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn get_api_key_handler(state: &AppState) -> Result<&Arc<ApiKeyDBHandler>, (StatusCode, String)> {
+    state.handlers().api_keys_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get api key handler not found".to_string(),
+        )
+    })
+}
+
+fn not_found(id: Uuid) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Api key {id} not found"))
+}
+
+fn name_taken(name: &str) -> (StatusCode, String) {
+    (
+        StatusCode::CONFLICT,
+        format!("An active api key named {name} already exists"),
+    )
+}
+
+fn build_response<T: Serialize>(res: T) -> Result<Json<Response>, (StatusCode, String)> {
+    let data = serde_json::to_value(res).map_err(|e| {
+        tracing::error!("Failed to parse api key {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        let cases = [
+            (
+                Method::GET,
+                "/proxy/connect/github/1.0.0",
+                Some(SCOPE_PROXY),
+            ),
+            (
+                Method::POST,
+                "/proxy/message/github/1.0.0/message",
+                Some(SCOPE_PROXY),
+            ),
+            (Method::POST, "/proxy/aggregate/dev", Some(SCOPE_PROXY)),
+            (
+                Method::GET,
+                "/api/registry/mcp-server",
+                Some(SCOPE_REGISTRY_READ),
+            ),
+            (
+                Method::POST,
+                "/api/registry/mcp-server",
+                Some(SCOPE_REGISTRY_WRITE),
+            ),
+            (
+                Method::DELETE,
+                "/api/registry/aggregate/dev",
+                Some(SCOPE_REGISTRY_WRITE),
+            ),
+            (Method::GET, "/api/unknown", None),
+        ];
+
+        for (method, path, expected) in cases {
+            assert_eq!(required_scope(&method, path), expected, "{method} {path}");
+        }
+    }
+
+    #[test]
+    fn test_validate_scopes() {
+        assert!(validate_scopes(&[]).is_ok());
+        assert!(validate_scopes(&SCOPES.map(String::from)).is_ok());
+        assert!(validate_scopes(&["proxy".to_string(), "admin".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_create_rejections() {
+        let now = Utc::now().naive_utc();
+        assert!(validate_expires_at(None, now).is_ok());
+        assert!(validate_expires_at(Some(now + chrono::Duration::hours(1)), now).is_ok());
+        for expires_at in [now, now - chrono::Duration::seconds(1)] {
+            let (status, _) = validate_expires_at(Some(expires_at), now).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{expires_at}");
+        }
+
+        let (status, message) = name_taken("ci");
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "An active api key named ci already exists");
+    }
+}
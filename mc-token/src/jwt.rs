@@ -0,0 +1,282 @@
+use axum::http::Method;
+use jsonwebtoken::errors::{Error, ErrorKind};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use mc_db::model::ROLE_ADMIN;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::ADMIN_ROUTE_PREFIXES;
+
+/// Path any logged in user may call to end the session.
+pub const LOGOUT_PATH: &str = "/api/user/logout";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct JwtConfig {
+    /// HMAC key signing the tokens. A random key is generated when empty, so
+    /// tokens don't survive a restart.
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    /// Lifetime of access tokens in seconds.
+    #[serde(default = "default_access_token_ttl")]
+    pub access_token_ttl: u64,
+    /// Lifetime of refresh tokens in seconds.
+    #[serde(default = "default_refresh_token_ttl")]
+    pub refresh_token_ttl: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            secret: String::new(),
+            issuer: default_issuer(),
+            access_token_ttl: default_access_token_ttl(),
+            refresh_token_ttl: default_refresh_token_ttl(),
+        }
+    }
+}
+
+fn default_issuer() -> String {
+    "mcp-center".to_string()
+}
+
+fn default_access_token_ttl() -> u64 {
+    15 * 60
+}
+
+fn default_refresh_token_ttl() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub role: String,
+    pub typ: TokenType,
+    pub jti: Uuid,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    pub role: String,
+}
+
+/// Issues and verifies the JWTs of logged in users, and knows the admin
+/// token the bootstrap `admin` user logs in with.
+pub struct Authenticator {
+    config: JwtConfig,
+    admin_token: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+}
+
+impl Authenticator {
+    pub fn new(config: JwtConfig, admin_token: String) -> Self {
+        let secret = if config.secret.is_empty() {
+            tracing::warn!("JWT secret is not set, tokens are signed with a random key");
+            format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+        } else {
+            config.secret.clone()
+        };
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[config.issuer.as_str()]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        validation.leeway = 0;
+
+        Self {
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            config,
+            admin_token,
+            validation,
+        }
+    }
+
+    /// An empty admin token never matches.
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        !self.admin_token.is_empty() && token == self.admin_token
+    }
+
+    pub fn issue(&self, username: &str, role: &str) -> Result<TokenPair, Error> {
+        Ok(TokenPair {
+            access_token: self.encode(username, role, TokenType::Access)?,
+            refresh_token: self.encode(username, role, TokenType::Refresh)?,
+            token_type: "Bearer",
+            expires_in: self.config.access_token_ttl,
+            role: role.to_string(),
+        })
+    }
+
+    /// Verifies signature, issuer and expiry, and that the token is of the expected type.
+    pub fn verify(&self, token: &str, typ: TokenType) -> Result<Claims, Error> {
+        let claims =
+            jsonwebtoken::decode::<Claims>(token, &self.decoding, &self.validation)?.claims;
+        if claims.typ != typ {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+        Ok(claims)
+    }
+
+    fn encode(&self, username: &str, role: &str, typ: TokenType) -> Result<String, Error> {
+        let ttl = match typ {
+            TokenType::Access => self.config.access_token_ttl,
+            TokenType::Refresh => self.config.refresh_token_ttl,
+        };
+        let iat = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            sub: username.to_string(),
+            role: role.to_string(),
+            typ,
+            jti: Uuid::new_v4(),
+            iss: self.config.issuer.clone(),
+            iat,
+            exp: iat + ttl as i64,
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+    }
+}
+
+/// API keys never contain dots, JWTs are three dot separated segments.
+pub fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+/// Admins may call every route, read-only users only read outside the admin routes.
+pub fn role_permits(role: &str, method: &Method, path: &str) -> bool {
+    if role == ROLE_ADMIN || path == LOGOUT_PATH {
+        return true;
+    }
+    (method == Method::GET || method == Method::HEAD)
+        && !ADMIN_ROUTE_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_db::model::ROLE_READ_ONLY;
+
+    fn authenticator(secret: &str) -> Authenticator {
+        Authenticator::new(
+            JwtConfig {
+                secret: secret.to_string(),
+                ..Default::default()
+            },
+            "admin-token".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let auth = authenticator("secret");
+        let pair = auth.issue("alice", ROLE_READ_ONLY).unwrap();
+        assert_eq!(pair.expires_in, default_access_token_ttl());
+
+        let claims = auth.verify(&pair.access_token, TokenType::Access).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.role, ROLE_READ_ONLY);
+        assert_eq!(claims.exp - claims.iat, default_access_token_ttl() as i64);
+
+        let refresh = auth
+            .verify(&pair.refresh_token, TokenType::Refresh)
+            .unwrap();
+        assert_ne!(refresh.jti, claims.jti);
+
+        // a token is only valid as its own type
+        assert!(auth.verify(&pair.refresh_token, TokenType::Access).is_err());
+        assert!(auth.verify(&pair.access_token, TokenType::Refresh).is_err());
+
+        // nor with another key
+        assert!(
+            authenticator("other")
+                .verify(&pair.access_token, TokenType::Access)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let auth = authenticator("secret");
+        let iat = chrono::Utc::now().timestamp() - 60;
+        let claims = Claims {
+            sub: "alice".to_string(),
+            role: ROLE_ADMIN.to_string(),
+            typ: TokenType::Access,
+            jti: Uuid::new_v4(),
+            iss: default_issuer(),
+            iat,
+            exp: iat + 30,
+        };
+        let token =
+            jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &auth.encoding).unwrap();
+        assert!(auth.verify(&token, TokenType::Access).is_err());
+    }
+
+    #[test]
+    fn test_is_admin_token() {
+        assert!(authenticator("secret").is_admin_token("admin-token"));
+        assert!(!authenticator("secret").is_admin_token("other"));
+        assert!(!Authenticator::new(JwtConfig::default(), String::new()).is_admin_token(""));
+    }
+
+    #[test]
+    fn test_is_jwt() {
+        assert!(is_jwt("aaa.bbb.ccc"));
+        assert!(!is_jwt("mck_0123456789abcdef"));
+        assert!(!is_jwt("c3a1b2d4-0000-4000-8000-000000000000"));
+    }
+
+    #[test]
+    fn test_role_permits() {
+        let cases = [
+            (ROLE_ADMIN, Method::POST, "/api/policy", true),
+            (
+                ROLE_ADMIN,
+                Method::DELETE,
+                "/api/registry/aggregate/dev",
+                true,
+            ),
+            (
+                ROLE_READ_ONLY,
+                Method::GET,
+                "/api/registry/mcp-server",
+                true,
+            ),
+            (
+                ROLE_READ_ONLY,
+                Method::POST,
+                "/api/registry/mcp-server",
+                false,
+            ),
+            (ROLE_READ_ONLY, Method::GET, "/api/policy", false),
+            (ROLE_READ_ONLY, Method::GET, "/api/key", false),
+            (ROLE_READ_ONLY, Method::POST, LOGOUT_PATH, true),
+        ];
+
+        for (role, method, path, expected) in cases {
+            assert_eq!(
+                role_permits(role, &method, path),
+                expected,
+                "{role} {method} {path}"
+            );
+        }
+    }
+}
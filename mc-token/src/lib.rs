@@ -1,9 +1,71 @@
-use axum::routing::post;
+use axum::routing::{get, post, put};
+use axum::{Extension, Router};
 use mc_common::app::AppState;
 use mc_common::router;
+use std::sync::Arc;
 
+mod apikey;
+pub mod jwt;
+mod policy;
 mod token;
+mod user;
 
-pub fn register_router() -> router::RouterHandler<AppState> {
-    Box::new(|router| router.route("/api/user/admin/login", post(token::admin_login)))
+pub use apikey::required_scope;
+pub use token::is_revoked;
+
+/// Prefixes of the routes only admins may call.
+pub const ADMIN_ROUTE_PREFIXES: [&str; 6] = [
+    "/api/policy",
+    "/api/key",
+    "/api/user/account",
+    "/api/audit",
+    "/api/rate-limit",
+    "/metrics",
+];
+
+/// Routes callable without credentials, they issue the credentials.
+pub const PUBLIC_ROUTES: [&str; 3] = [
+    "/api/user/login",
+    "/api/user/admin/login",
+    "/api/user/refresh",
+];
+
+pub fn register_router(auth: Arc<jwt::Authenticator>) -> router::RouterHandler<AppState> {
+    Box::new(move |router| {
+        let routes = Router::new()
+            .route("/api/user/login", post(token::login))
+            // kept for clients of the admin token login
+            .route("/api/user/admin/login", post(token::login))
+            .route("/api/user/refresh", post(token::refresh))
+            .route("/api/user/logout", post(token::logout))
+            .route(
+                "/api/user/account",
+                get(user::list_users).post(user::create_user),
+            )
+            .route(
+                "/api/user/account/{username}",
+                put(user::update_user).delete(user::delete_user),
+            )
+            .route(
+                "/api/key",
+                get(apikey::list_api_keys).post(apikey::create_api_key),
+            )
+            .route(
+                "/api/key/{id}",
+                get(apikey::get_api_key).delete(apikey::revoke_api_key),
+            )
+            .route("/api/key/{id}/rotate", post(apikey::rotate_api_key))
+            .route(
+                "/api/policy",
+                get(policy::list_policies).post(policy::create_policy),
+            )
+            .route(
+                "/api/policy/{id}",
+                get(policy::get_policy)
+                    .put(policy::update_policy)
+                    .delete(policy::delete_policy),
+            )
+            .layer(Extension(auth.clone()));
+        router.merge(routes)
+    })
 }
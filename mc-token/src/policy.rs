@@ -0,0 +1,226 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use mc_common::app::{AppState, Response};
+use mc_db::PolicyDBHandler;
+use mc_db::model::ApiKeyPolicies;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct ListPoliciesRequest {
+    api_key_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListPoliciesResponse {
+    policies: Vec<ApiKeyPolicies>,
+    count: usize,
+}
+
+pub async fn list_policies(
+    State(state): State<AppState>,
+    Query(request): Query<ListPoliciesRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let policy_handler = get_policy_handler(&state)?;
+
+    let policies = policy_handler
+        .list(request.api_key_name.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list policies {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to list policies".to_string(),
+            )
+        })?;
+
+    let count = policies.len();
+    let data = serde_json::to_value(ListPoliciesResponse { policies, count }).map_err(|e| {
+        tracing::error!("Failed to parse policies {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PolicyRequest {
+    pub api_key_name: String,
+    pub server_name: String,
+    pub tag_pattern: Option<String>,
+    #[serde(default)]
+    pub allow_tools: Vec<String>,
+    #[serde(default)]
+    pub deny_tools: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl PolicyRequest {
+    fn into_policy(self, id: Uuid) -> Result<ApiKeyPolicies, (StatusCode, String)> {
+        if self.api_key_name.is_empty() || self.server_name.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "api_key_name and server_name must not be empty".to_string(),
+            ));
+        }
+
+        Ok(ApiKeyPolicies {
+            id,
+            api_key_id: None,
+            api_key_name: self.api_key_name,
+            server_name: self.server_name,
+            tag_pattern: self.tag_pattern.unwrap_or_else(|| "*".to_string()),
+            allow_tools: self.allow_tools,
+            deny_tools: self.deny_tools,
+            description: self.description,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        })
+    }
+}
+
+pub async fn create_policy(
+    State(state): State<AppState>,
+    Json(request): Json<PolicyRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let policy_handler = get_policy_handler(&state)?;
+
+    let mut policy = request.into_policy(Uuid::new_v4())?;
+    policy.api_key_id = find_api_key(&state, &policy.api_key_name).await?;
+
+    let res = policy_handler.create(&policy).await.map_err(|e| {
+        tracing::error!("Failed to create policy {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create policy".to_string(),
+        )
+    })?;
+    tracing::info!("Policy {} of api key {} created", res.id, res.api_key_name);
+
+    build_response(res)
+}
+
+pub async fn get_policy(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let policy_handler = get_policy_handler(&state)?;
+
+    let res = policy_handler
+        .get(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get policy {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get policy".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+
+    build_response(res)
+}
+
+pub async fn update_policy(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PolicyRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let policy_handler = get_policy_handler(&state)?;
+
+    let mut policy = request.into_policy(id)?;
+    policy.api_key_id = find_api_key(&state, &policy.api_key_name).await?;
+
+    let res = policy_handler
+        .update(&policy)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update policy {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update policy".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Policy {} of api key {} updated", res.id, res.api_key_name);
+
+    build_response(res)
+}
+
+pub async fn delete_policy(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let policy_handler = get_policy_handler(&state)?;
+
+    let res = policy_handler
+        .delete(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete policy {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete policy".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(id))?;
+    tracing::info!("Policy {} of api key {} deleted", res.id, res.api_key_name);
+
+    build_response(res)
+}
+
+// a grant is bound to the active key of its name, other names are OAuth principals
+async fn find_api_key(state: &AppState, name: &str) -> Result<Option<Uuid>, (StatusCode, String)> {
+    // policies of OAuth subjects and groups are never bound to a key
+    if name.starts_with("sub:") || name.starts_with("group:") {
+        return Ok(None);
+    }
+
+    let handler = state.handlers().api_keys_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get api key handler not found".to_string(),
+        )
+    })?;
+
+    let key = handler.find_by_name(name).await.map_err(|e| {
+        tracing::error!("Failed to find api key {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to find api key".to_string(),
+        )
+    })?;
+    Ok(key.map(|key| key.id))
+}
+
+fn get_policy_handler(state: &AppState) -> Result<&Arc<PolicyDBHandler>, (StatusCode, String)> {
+    state.handlers().policy_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get policy handler not found".to_string(),
+        )
+    })
+}
+
+fn not_found(id: Uuid) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Policy {id} not found"))
+}
+
+fn build_response(policy: ApiKeyPolicies) -> Result<Json<Response>, (StatusCode, String)> {
+    let data = serde_json::to_value(policy).map_err(|e| {
+        tracing::error!("Failed to parse policy {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
@@ -1,64 +1,217 @@
-use axum::Json;
+use crate::jwt::{Authenticator, Claims, TokenType};
+use crate::user::{get_user_handler, verify_password};
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::{Extension, Json};
 use mc_common::app::{AppState, Response};
+use mc_db::model::ROLE_ADMIN;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The bootstrap user logging in with the admin token.
+const ADMIN_USERNAME: &str = "admin";
 
 #[derive(Deserialize, Serialize, Clone)]
-pub struct AdminLoginRequest {
+pub struct LoginRequest {
     pub username: String,
+    pub password: Option<String>,
     pub token: Option<String>,
 }
 
+pub async fn login(
+    State(state): State<AppState>,
+    Extension(auth): Extension<Arc<Authenticator>>,
+    Json(request): Json<LoginRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    if let Some(token) = request.token {
+        if request.username != ADMIN_USERNAME || !auth.is_admin_token(&token) {
+            tracing::error!("Invalid admin token for user {}", request.username);
+            return Err((StatusCode::UNAUTHORIZED, String::from("Invalid token")));
+        }
+        tracing::info!("User {} logged in with the admin token", request.username);
+        return issue(&auth, ADMIN_USERNAME, ROLE_ADMIN);
+    }
+
+    let Some(password) = request.password else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("password or token is required"),
+        ));
+    };
+
+    let user = get_user_handler(&state)?
+        .get(&request.username)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get user {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get user".to_string(),
+            )
+        })?
+        .filter(|user| verify_password(&password, &user.password_hash))
+        .ok_or_else(|| {
+            tracing::error!("Invalid username or password of user {}", request.username);
+            (
+                StatusCode::UNAUTHORIZED,
+                String::from("Invalid username or password"),
+            )
+        })?;
+
+    tracing::info!("User {} logged in", user.username);
+    issue(&auth, &user.username, &user.role)
+}
+
 #[derive(Deserialize, Serialize, Clone)]
-pub struct AdminLoginResponse {
-    pub code: Option<u16>,
-    pub message: Option<String>,
+pub struct RefreshRequest {
+    pub refresh_token: String,
 }
 
-pub async fn admin_login(
-    State(_state): State<AppState>,
-    Json(request): Json<AdminLoginRequest>,
+/// Trades a refresh token for a new token pair, the refresh token can't be used twice.
+pub async fn refresh(
+    State(state): State<AppState>,
+    Extension(auth): Extension<Arc<Authenticator>>,
+    Json(request): Json<RefreshRequest>,
 ) -> Result<Json<Response>, (StatusCode, String)> {
-    if request.username != "admin" {
+    let claims = auth
+        .verify(&request.refresh_token, TokenType::Refresh)
+        .map_err(|e| {
+            tracing::error!("Invalid refresh token {}", e);
+            (
+                StatusCode::UNAUTHORIZED,
+                String::from("Invalid refresh token"),
+            )
+        })?;
+
+    // revoking claims the token, of concurrent refreshes with it only one gets a pair
+    if !revoke(&state, &claims).await? {
+        tracing::error!("Refresh token of {} is already used", claims.sub);
         return Err((
             StatusCode::UNAUTHORIZED,
-            String::from("only admin can login"),
+            String::from("Invalid refresh token"),
         ));
     }
-    let env_token = std::env::var("MCP_ADMIN_TOKEN").unwrap_or_else(|e| {
-        tracing::warn!("MCP_ADMIN_TOKEN environment variable is not set: {}", e);
-        String::from("")
-    });
 
-    if let Some(token) = request.token {
-        if token != env_token {
-            tracing::error!(
-                "MCP_ADMIN_TOKEN environment variable is {}, request token is {}",
-                env_token,
-                token
-            );
-            return Err((StatusCode::UNAUTHORIZED, String::from("Invalid token")));
+    // the role may have changed since login, the bootstrap admin has no user record
+    let user = get_user_handler(&state)?
+        .get(&claims.sub)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get user {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get user".to_string(),
+            )
+        })?;
+    let role = match user {
+        Some(user) => user.role,
+        None if claims.sub == ADMIN_USERNAME => ROLE_ADMIN.to_string(),
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                String::from("Invalid refresh token"),
+            ));
+        }
+    };
+
+    issue(&auth, &claims.sub, &role)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+/// Revokes the access token of the request and the refresh token of the body.
+pub async fn logout(
+    State(state): State<AppState>,
+    Extension(auth): Extension<Arc<Authenticator>>,
+    claims: Option<Extension<Claims>>,
+    Json(request): Json<LogoutRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let Some(Extension(claims)) = claims else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Logout requires a JWT access token"),
+        ));
+    };
+    revoke(&state, &claims).await?;
+
+    if let Some(refresh_token) = request.refresh_token {
+        match auth.verify(&refresh_token, TokenType::Refresh) {
+            Ok(refresh) if refresh.sub == claims.sub => {
+                revoke(&state, &refresh).await?;
+            }
+            _ => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("Invalid refresh token"),
+                ));
+            }
         }
-        let response = AdminLoginResponse {
-            code: Some(u16::from(StatusCode::OK)),
-            message: Some(String::from("successfully logged in")),
-        };
+    }
 
-        let data = serde_json::to_value(response).map_err(|e| {
-            tracing::error!("Failed to parse response {}", e);
+    tracing::info!("User {} logged out", claims.sub);
+    Ok(Json(Response::new(None)))
+}
+
+pub async fn is_revoked(state: &AppState, claims: &Claims) -> Result<bool, (StatusCode, String)> {
+    get_user_handler(state)?
+        .is_token_revoked(claims.jti)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to select revoked token {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to select revoked token".to_string(),
+            )
+        })
+}
+
+/// `false` when the token was already revoked.
+async fn revoke(state: &AppState, claims: &Claims) -> Result<bool, (StatusCode, String)> {
+    let handler = get_user_handler(state)?;
+    let revoked = handler
+        .revoke_token(claims.jti, claims.exp)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to revoke token {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
+                "Failed to revoke token".to_string(),
             )
         })?;
 
-        return Ok(Json(Response::new(Some(data))));
-    }
+    // expired rows are dropped off the request path
+    let purge = handler.clone();
+    tokio::spawn(async move {
+        if let Err(err) = purge.purge_revoked_tokens().await {
+            tracing::warn!("failed to purge revoked tokens: {}", err);
+        }
+    });
+    Ok(revoked)
+}
+
+fn issue(
+    auth: &Authenticator,
+    username: &str,
+    role: &str,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let pair = auth.issue(username, role).map_err(|e| {
+        tracing::error!("Failed to issue token {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to issue token".to_string(),
+        )
+    })?;
+
+    let data = serde_json::to_value(pair).map_err(|e| {
+        tracing::error!("Failed to parse response {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
 
-    tracing::warn!("only support admin token in current version");
-    Err((
-        StatusCode::UNAUTHORIZED,
-        String::from("only support admin token in current version"),
-    ))
+    Ok(Json(Response::new(Some(data))))
 }
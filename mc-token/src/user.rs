@@ -0,0 +1,212 @@
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use mc_common::app::{AppState, Response};
+use mc_db::UserDBHandler;
+use mc_db::model::{ROLE_ADMIN, ROLE_READ_ONLY, Users};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListUsersResponse {
+    users: Vec<Users>,
+    count: usize,
+}
+
+pub async fn list_users(
+    State(state): State<AppState>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let users = get_user_handler(&state)?.list().await.map_err(|e| {
+        tracing::error!("Failed to list users {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to list users".to_string(),
+        )
+    })?;
+
+    let count = users.len();
+    build_response(ListUsersResponse { users, count })
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct UserCreateRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Option<String>,
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    Json(request): Json<UserCreateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    if request.username.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "username must not be empty".to_string(),
+        ));
+    }
+    let role = request.role.unwrap_or_else(|| ROLE_READ_ONLY.to_string());
+    validate_role(&role)?;
+
+    let res = get_user_handler(&state)?
+        .create(&Users {
+            id: Uuid::new_v4(),
+            username: request.username,
+            password_hash: hash_password(&request.password)?,
+            role,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create user {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create user".to_string(),
+            )
+        })?;
+    tracing::info!("User {} created with role {}", res.username, res.role);
+
+    build_response(res)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct UserUpdateRequest {
+    pub password: Option<String>,
+    pub role: Option<String>,
+}
+
+pub async fn update_user(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(request): Json<UserUpdateRequest>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    if let Some(role) = &request.role {
+        validate_role(role)?;
+    }
+    let password_hash = request.password.as_deref().map(hash_password).transpose()?;
+
+    let res = get_user_handler(&state)?
+        .update(&username, password_hash.as_deref(), request.role.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update user {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update user".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&username))?;
+    tracing::info!("User {} updated", res.username);
+
+    build_response(res)
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<Response>, (StatusCode, String)> {
+    let res = get_user_handler(&state)?
+        .delete(&username)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete user {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete user".to_string(),
+            )
+        })?
+        .ok_or_else(|| not_found(&username))?;
+    tracing::info!("User {} deleted", res.username);
+
+    build_response(res)
+}
+
+fn hash_password(password: &str) -> Result<String, (StatusCode, String)> {
+    if password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "password must not be empty".to_string(),
+        ));
+    }
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes()).map_err(|e| {
+        tracing::error!("Failed to generate salt {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            tracing::error!("Failed to hash password {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            )
+        })
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+fn validate_role(role: &str) -> Result<(), (StatusCode, String)> {
+    if role != ROLE_ADMIN && role != ROLE_READ_ONLY {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown role {role}, expected {ROLE_ADMIN} or {ROLE_READ_ONLY}"),
+        ));
+    }
+    Ok(())
+}
+
+pub fn get_user_handler(state: &AppState) -> Result<&Arc<UserDBHandler>, (StatusCode, String)> {
+    state.handlers().user_handler.as_ref().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Can't get user handler not found".to_string(),
+        )
+    })
+}
+
+fn not_found(username: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("User {username} not found"))
+}
+
+fn build_response<T: Serialize>(res: T) -> Result<Json<Response>, (StatusCode, String)> {
+    let data = serde_json::to_value(res).map_err(|e| {
+        tracing::error!("Failed to parse user {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        )
+    })?;
+
+    Ok(Json(Response::new(Some(data))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("secret").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert_ne!(hash, hash_password("secret").unwrap());
+
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("other", &hash));
+        assert!(!verify_password("secret", "not a hash"));
+        assert!(hash_password("").is_err());
+    }
+}